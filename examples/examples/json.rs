@@ -0,0 +1,48 @@
+use async_trait::async_trait;
+use http::{Request, StatusCode};
+use izanami::{
+    extract::{EventsExt, RequestExt},
+    Events,
+};
+use izanami_hyper::{Events as HyperEvents, Server};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Greeting {
+    name: String,
+}
+
+#[derive(Clone)]
+struct Greet;
+
+#[async_trait]
+impl<'a> izanami::App<HyperEvents<'a>> for Greet {
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    async fn call(&self, mut req: Request<HyperEvents<'a>>) -> Result<(), Self::Error> {
+        let greeting = req.json::<Greeting>(4096).await;
+        let mut events = req.into_body();
+        match greeting {
+            Ok(greeting) => {
+                events
+                    .send_json(
+                        StatusCode::OK,
+                        &Greeting {
+                            name: format!("Hello, {}!", greeting.name),
+                        },
+                    )
+                    .await?
+            }
+            Err(rejection) => events.send_response(rejection.to_response()).await?,
+        }
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let server = Server::bind("127.0.0.1:4000").await?;
+    server.serve(Greet).await?;
+
+    Ok(())
+}
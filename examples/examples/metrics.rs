@@ -0,0 +1,27 @@
+use izanami::{
+    app_fn,
+    metrics::{Metrics, MetricsApp},
+    DynApp,
+};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let metrics = Metrics::new();
+
+    let exporter = izanami_hyper::Server::bind("127.0.0.1:9100").await?;
+    let app = MetricsApp::new(metrics.clone());
+    tokio::spawn(async move {
+        if let Err(err) = exporter.serve(app).await {
+            eprintln!("metrics server error: {}", err);
+        }
+    });
+
+    let server = izanami_hyper::Server::bind("127.0.0.1:4000")
+        .await?
+        .record_metrics(metrics);
+    server
+        .serve(DynApp::new(app_fn(izanami_examples::hello)))
+        .await?;
+
+    Ok(())
+}
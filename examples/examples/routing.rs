@@ -1,68 +1,53 @@
-#![allow(clippy::type_complexity)]
-
 use async_trait::async_trait;
-use http::{Request, Response};
-use izanami::{App, Events};
-use izanami_hyper::Events as HyperEvents;
+use http::{Request, Response, StatusCode};
+use izanami::{app_fn, App, BoxApp, BoxEvents, DynApp};
 use regex::{Regex, RegexSet};
 
 type BoxedError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 struct Router {
-    routes: Vec<(
-        Regex,
-        Box<dyn for<'a> App<HyperEvents<'a>, Error = BoxedError> + Send + Sync + 'static>,
-    )>,
+    routes: Vec<(Regex, BoxApp)>,
     re_set: RegexSet,
 }
 
-impl<'a> App<HyperEvents<'a>> for Router {
+#[async_trait]
+impl<'a> App<BoxEvents<'a>> for Router {
     type Error = BoxedError;
 
-    fn call<'l1, 'async_trait>(
-        &'l1 self,
-        request: Request<HyperEvents<'a>>,
-    ) -> std::pin::Pin<
-        Box<dyn std::future::Future<Output = Result<(), Self::Error>> + Send + 'async_trait>,
-    >
+    async fn call(&self, request: Request<BoxEvents<'a>>) -> Result<(), Self::Error>
     where
-        'l1: 'async_trait,
-        HyperEvents<'a>: 'async_trait,
+        BoxEvents<'a>: 'async_trait,
     {
-        match self
+        let route = self
             .re_set
             .matches(request.uri().path())
             .iter()
             .next()
-            .and_then(|index| self.routes.get(index))
-        {
-            Some((_re, app)) => app.call(request),
-            None => Box::pin(async move {
+            .and_then(|index| self.routes.get(index));
+        match route {
+            Some((_re, app)) => app.call(request).await,
+            None => {
+                let mut response = Response::new(());
+                *response.status_mut() = StatusCode::NOT_FOUND;
                 let mut events = request.into_body();
-                events
-                    .start_send_response(Response::new(()), true)
-                    .await
-                    .map_err(Into::into)
-            }),
+                events.start_send_response(response, true).await
+            }
         }
     }
 }
 
 #[derive(Default)]
 struct RouterBuilder {
-    routes: Vec<(
-        Regex,
-        Box<dyn for<'a> App<HyperEvents<'a>, Error = BoxedError> + Send + Sync + 'static>,
-    )>,
+    routes: Vec<(Regex, BoxApp)>,
 }
 
 impl RouterBuilder {
     fn add<T>(&mut self, pattern: &str, app: T) -> anyhow::Result<&mut Self>
     where
-        T: for<'a> App<HyperEvents<'a>, Error = BoxedError> + Send + Sync + 'static,
+        T: for<'a> App<BoxEvents<'a>> + Send + Sync + 'static,
     {
         let pattern = Regex::new(pattern)?;
-        self.routes.push((pattern, Box::new(app)));
+        self.routes.push((pattern, BoxApp::new(app)));
         Ok(self)
     }
 
@@ -71,7 +56,7 @@ impl RouterBuilder {
         let re_set = RegexSet::new(patterns)?;
 
         Ok(Router {
-            routes: std::mem::replace(&mut self.routes, vec![]),
+            routes: std::mem::take(&mut self.routes),
             re_set,
         })
     }
@@ -80,38 +65,12 @@ impl RouterBuilder {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let mut router = RouterBuilder::default();
-    router.add("/", Hello)?;
+    router.add("/", app_fn(izanami_examples::hello))?;
     let router = router.build()?;
 
     let server = izanami_hyper::Server::bind("127.0.0.1:4000").await?;
-    server.serve(std::sync::Arc::new(router)).await?;
+    server
+        .serve(DynApp::new(std::sync::Arc::new(router)))
+        .await?;
     Ok(())
 }
-
-struct Hello;
-
-#[async_trait]
-impl<E: Events> App<E> for Hello
-where
-    E: Send,
-    E::Data: Send,
-    &'static str: Into<E::Data>,
-{
-    type Error = BoxedError;
-
-    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
-    where
-        E: 'async_trait,
-    {
-        let mut events = request.into_body();
-        events
-            .start_send_response(Response::new(()), false)
-            .await
-            .map_err(Into::into)?;
-        events
-            .send_data("Hello, world!\n".into(), true)
-            .await
-            .map_err(Into::into)?;
-        Ok(())
-    }
-}
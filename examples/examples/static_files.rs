@@ -0,0 +1,13 @@
+use izanami_static::ServeDir;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let root = std::env::args().nth(1).unwrap_or_else(|| ".".into());
+
+    let server = izanami_hyper::Server::bind("127.0.0.1:4000").await?;
+    server
+        .serve(ServeDir::new(root).precompressed(true))
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,312 @@
+//! An adapter for running synchronous handlers on a blocking thread pool.
+
+#![deny(
+    missing_debug_implementations,
+    nonstandard_style,
+    rust_2018_idioms,
+    rust_2018_compatibility,
+    unused
+)]
+
+use async_trait::async_trait;
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{
+    channel::{mpsc, oneshot},
+    executor::block_on,
+    SinkExt, StreamExt,
+};
+use http::{Request, Response};
+use izanami::{App, Events};
+use std::{cmp, error, fmt, io, sync::Arc};
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+const DEFAULT_BUFFER: usize = 8;
+const DEFAULT_WRITE_BUFFER: usize = 8 * 1024;
+
+/// An `App` that runs a synchronous handler on a blocking thread pool.
+///
+/// The handler receives the request with `SyncEvents`, whose blocking methods
+/// exchange the messages with the asynchronous `Events` through a bounded
+/// channel. The handler blocks only its own thread when the channel is full,
+/// and the runtime threads are never blocked.
+///
+/// ```ignore
+/// let app = BlockingApp::new(|request: Request<SyncEvents>| -> io::Result<()> {
+///     let mut events = request.into_body();
+///     let rows = db.query_blocking()?;
+///     events.start_response(Response::new(()))?;
+///     serde_json::to_writer(&mut events, &rows)?;
+///     Ok(())
+/// });
+/// ```
+pub struct BlockingApp<F> {
+    handler: Arc<F>,
+    buffer: usize,
+    write_buffer: usize,
+}
+
+impl<F> fmt::Debug for BlockingApp<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingApp")
+            .field("buffer", &self.buffer)
+            .field("write_buffer", &self.write_buffer)
+            .finish()
+    }
+}
+
+impl<F> Clone for BlockingApp<F> {
+    fn clone(&self) -> Self {
+        Self {
+            handler: self.handler.clone(),
+            buffer: self.buffer,
+            write_buffer: self.write_buffer,
+        }
+    }
+}
+
+impl<F> BlockingApp<F> {
+    /// Create a new `BlockingApp` that runs `handler` for each request.
+    pub fn new(handler: F) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            buffer: DEFAULT_BUFFER,
+            write_buffer: DEFAULT_WRITE_BUFFER,
+        }
+    }
+
+    /// Set the number of messages buffered between the handler and the client.
+    ///
+    /// The default value is 8.
+    pub fn buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer;
+        self
+    }
+
+    /// Set the number of bytes written to `SyncEvents` accumulated before
+    /// sending them as a chunk of the response body.
+    ///
+    /// If the value is zero, each write is sent as a chunk.
+    /// The default value is 8 KiB.
+    pub fn write_buffer(mut self, write_buffer: usize) -> Self {
+        self.write_buffer = write_buffer;
+        self
+    }
+}
+
+#[async_trait]
+impl<E, F, R> App<E> for BlockingApp<F>
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+    F: Fn(Request<SyncEvents>) -> Result<(), R> + Send + Sync + 'static,
+    R: Into<BoxError> + 'static,
+{
+    type Error = BoxError;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+        let (tx, mut rx) = mpsc::channel(self.buffer);
+        let request = Request::from_parts(
+            parts,
+            SyncEvents {
+                tx,
+                buf: Bytes::new(),
+                out: BytesMut::new(),
+                write_buffer: self.write_buffer,
+                started: false,
+            },
+        );
+
+        let handler = self.handler.clone();
+        let handled =
+            tokio_executor::blocking::run(move || (*handler)(request).map_err(Into::into));
+
+        // The channel is closed when the handler drops `SyncEvents`.
+        let mut started = false;
+        let mut failed = None;
+        while let Some(command) = rx.next().await {
+            let result = match command {
+                Command::Read(reply) => {
+                    let data = match events.data().await {
+                        Some(Ok(data)) => Some(Ok(data.collect::<Bytes>())),
+                        Some(Err(err)) => Some(Err(err.into())),
+                        None => None,
+                    };
+                    let _ = reply.send(data);
+                    Ok(())
+                }
+                Command::StartResponse(response) => {
+                    started = true;
+                    events.start_send_response(response, false).await
+                }
+                Command::Write(data) => events.send_data(data.into(), false).await,
+                Command::Flush(reply) => {
+                    // The preceding chunks have been sent unless one of them failed.
+                    if failed.is_none() {
+                        let _ = reply.send(());
+                    }
+                    Ok(())
+                }
+            };
+            if let Err(err) = result {
+                // The subsequent calls of the handler fail with the closed channel.
+                rx.close();
+                failed = Some(err.into());
+            }
+        }
+
+        handled.await?;
+        if let Some(err) = failed {
+            return Err(err);
+        }
+        if !started {
+            return Err("the handler returned without the response".into());
+        }
+        events
+            .send_data(Bytes::new().into(), true)
+            .await
+            .map_err(Into::into)
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Read(oneshot::Sender<Option<Result<Bytes, BoxError>>>),
+    StartResponse(Response<()>),
+    Write(Bytes),
+    Flush(oneshot::Sender<()>),
+}
+
+/// The blocking counterpart of `Events` passed to the handlers of `BlockingApp`.
+///
+/// The request body is read through `io::Read`, and the response body is
+/// written through `io::Write` after `start_response`. The written bytes
+/// are buffered up to the size set by `BlockingApp::write_buffer`, and sent
+/// when the buffer is full, on `flush`, or when the handler returns, which
+/// ends the response.
+#[derive(Debug)]
+pub struct SyncEvents {
+    tx: mpsc::Sender<Command>,
+    buf: Bytes,
+    out: BytesMut,
+    write_buffer: usize,
+    started: bool,
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "the exchange has been closed")
+}
+
+impl SyncEvents {
+    fn send(&mut self, command: Command) -> io::Result<()> {
+        block_on(self.tx.send(command)).map_err(|_| closed())
+    }
+
+    fn send_buffered(&mut self) -> io::Result<()> {
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        let chunk = self.out.take().freeze();
+        self.send(Command::Write(chunk))
+    }
+
+    /// Send the response head, blocking the current thread while the buffer is full.
+    pub fn start_response(&mut self, response: Response<()>) -> io::Result<()> {
+        if self.started {
+            return Err(io::Error::other("the response has already been started"));
+        }
+        self.started = true;
+        self.send(Command::StartResponse(response))
+    }
+
+    /// Send a complete response.
+    pub fn send_response<T>(&mut self, response: Response<T>) -> io::Result<()>
+    where
+        T: Into<Bytes>,
+    {
+        let (parts, body) = response.into_parts();
+        self.start_response(Response::from_parts(parts, ()))?;
+        let body = body.into();
+        if !body.is_empty() {
+            self.send(Command::Write(body))?;
+        }
+        Ok(())
+    }
+
+    /// Receive the next chunk of the request body, or `None` at the end of the body.
+    pub fn data(&mut self) -> Option<io::Result<Bytes>> {
+        if !self.buf.is_empty() {
+            return Some(Ok(std::mem::take(&mut self.buf)));
+        }
+        let (reply, rx) = oneshot::channel();
+        if let Err(err) = self.send(Command::Read(reply)) {
+            return Some(Err(err));
+        }
+        match block_on(rx) {
+            Ok(Some(Ok(data))) => Some(Ok(data)),
+            Ok(Some(Err(err))) => Some(Err(io::Error::other(err))),
+            Ok(None) => None,
+            Err(..) => Some(Err(closed())),
+        }
+    }
+}
+
+impl io::Read for SyncEvents {
+    fn read(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        while self.buf.is_empty() {
+            match self.data() {
+                Some(data) => self.buf = data?,
+                None => return Ok(0),
+            }
+        }
+        let len = cmp::min(dst.len(), self.buf.len());
+        dst[..len].copy_from_slice(&self.buf.split_to(len));
+        Ok(len)
+    }
+}
+
+impl io::Write for SyncEvents {
+    /// Append the bytes to the response body, sending the buffered bytes as
+    /// a chunk once they reach the size of the buffer. It blocks the current
+    /// thread while the channel is full.
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if !self.started {
+            return Err(io::Error::other("the response head has not been sent"));
+        }
+        if self.out.len() + data.len() > self.write_buffer {
+            self.send_buffered()?;
+        }
+        if data.len() >= self.write_buffer {
+            // Too large to be buffered.
+            if !data.is_empty() {
+                self.send(Command::Write(Bytes::from(data)))?;
+            }
+        } else {
+            self.out.extend_from_slice(data);
+            if self.out.len() >= self.write_buffer {
+                self.send_buffered()?;
+            }
+        }
+        Ok(data.len())
+    }
+
+    /// Send the buffered bytes, and wait until all the written bytes are
+    /// passed to the client.
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffered()?;
+        let (reply, rx) = oneshot::channel();
+        self.send(Command::Flush(reply))?;
+        block_on(rx).map_err(|_| closed())
+    }
+}
+
+impl Drop for SyncEvents {
+    fn drop(&mut self) {
+        // The rest of the response body is sent when the handler returns.
+        let _ = self.send_buffered();
+    }
+}
@@ -0,0 +1,90 @@
+use bytes::Bytes;
+use http::{Request, Response, StatusCode};
+use izanami::App;
+use izanami_blocking::{BlockingApp, SyncEvents};
+use izanami_test::MockEvents;
+use std::io::{self, Read, Write};
+
+#[tokio::test]
+async fn test_read_and_write() {
+    let app = BlockingApp::new(|request: Request<SyncEvents>| -> io::Result<()> {
+        let mut events = request.into_body();
+        let mut body = String::new();
+        events.read_to_string(&mut body)?;
+
+        let response = Response::builder()
+            .status(StatusCode::CREATED)
+            .body(())
+            .unwrap();
+        events.start_response(response)?;
+        for word in body.split(' ') {
+            events.write_all(word.to_uppercase().as_bytes())?;
+        }
+        Ok(())
+    });
+
+    let mut events = MockEvents::default();
+    events.request_body.push_back(Bytes::from("hello "));
+    events.request_body.push_back(Bytes::from("blocking world"));
+    app.call(Request::new(&mut events)).await.unwrap();
+
+    assert_eq!(events.response.unwrap().status(), StatusCode::CREATED);
+    assert_eq!(events.chunks, vec!["HELLOBLOCKINGWORLD", ""]);
+    assert!(events.end_of_stream);
+}
+
+#[tokio::test]
+async fn test_write_buffer_and_flush() {
+    let app = BlockingApp::new(|request: Request<SyncEvents>| -> io::Result<()> {
+        let mut events = request.into_body();
+        events.start_response(Response::new(()))?;
+        events.write_all(b"ab")?;
+        events.write_all(b"cd")?;
+        events.write_all(b"e")?;
+        events.flush()?;
+        events.write_all(b"fghij")?;
+        events.write_all(b"k")?;
+        Ok(())
+    })
+    .write_buffer(4);
+
+    let mut events = MockEvents::default();
+    app.call(Request::new(&mut events)).await.unwrap();
+    assert_eq!(events.chunks, vec!["abcd", "e", "fghij", "k", ""]);
+    assert!(events.end_of_stream);
+}
+
+#[tokio::test]
+async fn test_write_fails_after_disconnect() {
+    let app = BlockingApp::new(|request: Request<SyncEvents>| -> io::Result<()> {
+        let mut events = request.into_body();
+        events.start_response(Response::new(()))?;
+        loop {
+            if let Err(err) = events.write_all(b"chunk") {
+                assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+                return Ok(());
+            }
+        }
+    })
+    .buffer(1);
+
+    let mut events = MockEvents {
+        max_chunks: Some(3),
+        ..MockEvents::default()
+    };
+    let err = app.call(Request::new(&mut events)).await.unwrap_err();
+    let err = err.downcast::<io::Error>().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    assert_eq!(events.chunks.len(), 3);
+}
+
+#[tokio::test]
+async fn test_handler_error() {
+    let app = BlockingApp::new(|_: Request<SyncEvents>| -> io::Result<()> {
+        Err(io::Error::other("database is down"))
+    });
+    let mut events = MockEvents::default();
+    let err = app.call(Request::new(&mut events)).await.unwrap_err();
+    assert_eq!(err.to_string(), "database is down");
+    assert!(events.response.is_none());
+}
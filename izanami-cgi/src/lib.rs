@@ -0,0 +1,464 @@
+//! An `App` that runs CGI scripts.
+
+#![deny(
+    missing_debug_implementations,
+    nonstandard_style,
+    rust_2018_idioms,
+    rust_2018_compatibility,
+    unused
+)]
+
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use http::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    request::Parts,
+    Request, Response, StatusCode, Version,
+};
+use izanami::{App, Events, RemoteAddr};
+use std::{
+    error,
+    ffi::OsString,
+    io, mem,
+    path::PathBuf,
+    process::{ExitStatus, Stdio},
+    str,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::process::{ChildStdin, ChildStdout, Command},
+};
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+const MAX_HEAD_LEN: usize = 64 * 1024;
+
+/// An `App` that runs a CGI script for each request, as specified in RFC 3875.
+///
+/// The request body is streamed into the standard input of the script, and
+/// its standard output is sent to the client as the response. `CONTENT_LENGTH`
+/// is set only if the request has `content-length`, and the script reads the
+/// standard input until the end otherwise.
+///
+/// The script is killed when the client cancels the request.
+#[derive(Debug, Clone)]
+pub struct Cgi {
+    program: PathBuf,
+    args: Vec<OsString>,
+    script_name: String,
+    current_dir: Option<PathBuf>,
+    envs: Vec<(OsString, OsString)>,
+    chunk_size: usize,
+}
+
+impl Cgi {
+    /// Create a new `Cgi` that runs the executable at `program`.
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+            args: vec![],
+            script_name: String::new(),
+            current_dir: None,
+            envs: vec![],
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Append an argument passed to the executable.
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Set the path prefix at which the script is mounted.
+    ///
+    /// It is passed as `SCRIPT_NAME`, and the rest of the request path as
+    /// `PATH_INFO`. The requests outside of the prefix are responded with
+    /// `404 Not Found`. The default value is empty.
+    pub fn script_name(mut self, script_name: impl Into<String>) -> Self {
+        self.script_name = script_name.into();
+        self
+    }
+
+    /// Set the working directory of the script.
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+
+    /// Add an environment variable passed to the script.
+    ///
+    /// The script runs with the meta-variables of the request and `PATH`,
+    /// and the environment of the server is not inherited otherwise.
+    pub fn env(mut self, key: impl Into<OsString>, value: impl Into<OsString>) -> Self {
+        self.envs.push((key.into(), value.into()));
+        self
+    }
+
+    /// Set the maximum size of the chunks passed to `send_data`.
+    ///
+    /// The default value is 16 KiB.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `chunk_size` is zero.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "the chunk size must be greater than zero");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    fn command(&self, parts: &Parts, path_info: &str) -> Command {
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .env_clear()
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        if let Some(ref dir) = self.current_dir {
+            command.current_dir(dir);
+        }
+        if let Some(path) = std::env::var_os("PATH") {
+            command.env("PATH", path);
+        }
+
+        command
+            .env("GATEWAY_INTERFACE", "CGI/1.1")
+            .env("SERVER_SOFTWARE", "izanami")
+            .env("SERVER_PROTOCOL", protocol(parts.version))
+            .env("REQUEST_METHOD", parts.method.as_str())
+            .env("REQUEST_URI", parts.uri.to_string())
+            .env("SCRIPT_NAME", &self.script_name)
+            .env("PATH_INFO", path_info)
+            .env("QUERY_STRING", parts.uri.query().unwrap_or(""));
+
+        let host = parts
+            .uri
+            .authority_part()
+            .map(|authority| authority.as_str())
+            .or_else(|| header_str(&parts.headers, header::HOST));
+        if let Some(host) = host {
+            let (name, port) = match host.rfind(':') {
+                Some(i) if !host[i..].contains(']') => (&host[..i], &host[i + 1..]),
+                _ => (
+                    host,
+                    if parts.uri.scheme_str() == Some("https") {
+                        "443"
+                    } else {
+                        "80"
+                    },
+                ),
+            };
+            command.env("SERVER_NAME", name).env("SERVER_PORT", port);
+        }
+        if let Some(RemoteAddr(remote_addr)) = parts.extensions.get::<RemoteAddr>() {
+            command
+                .env("REMOTE_ADDR", remote_addr.ip().to_string())
+                .env("REMOTE_PORT", remote_addr.port().to_string());
+        }
+        if let Some(content_length) = header_str(&parts.headers, header::CONTENT_LENGTH) {
+            command.env("CONTENT_LENGTH", content_length);
+        }
+        if let Some(content_type) = header_str(&parts.headers, header::CONTENT_TYPE) {
+            command.env("CONTENT_TYPE", content_type);
+        }
+
+        for name in parts.headers.keys() {
+            // `Proxy` must not be exposed as `HTTP_PROXY` (httpoxy).
+            if name == header::CONTENT_LENGTH || name == header::CONTENT_TYPE || name == "proxy" {
+                continue;
+            }
+            let values: Vec<&str> = parts
+                .headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect();
+            let key = format!(
+                "HTTP_{}",
+                name.as_str().to_ascii_uppercase().replace('-', "_")
+            );
+            command.env(key, values.join(", "));
+        }
+
+        for (key, value) in &self.envs {
+            command.env(key, value);
+        }
+
+        command
+    }
+}
+
+#[async_trait]
+impl<E> App<E> for Cgi
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    type Error = BoxError;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+
+        let path_info = match parts.uri.path().strip_prefix(self.script_name.as_str()) {
+            Some(path_info) if path_info.is_empty() || path_info.starts_with('/') => {
+                path_info.to_owned()
+            }
+            _ => return send_status(&mut events, StatusCode::NOT_FOUND).await,
+        };
+
+        // The child is killed when it is dropped before exiting.
+        let mut child = self.command(&parts, &path_info).spawn()?;
+        let stdin = child.stdin().take().expect("the standard input is piped");
+        let stdout = child.stdout().take().expect("the standard output is piped");
+
+        // The request body is written while the output is forwarded, since
+        // the script may not read the whole body before writing the response.
+        let mut input = Input::Receiving(stdin);
+        let mut reading = read_output(BufReader::new(stdout), false, self.chunk_size);
+        let mut head_sent = false;
+        loop {
+            let event = match input {
+                // The future of `data` is dropped if the output comes first, which
+                // loses nothing since the received data is buffered by the backends.
+                // The disconnection of the client is notified through `data` here.
+                Input::Receiving(..) => match future::select(&mut reading, events.data()).await {
+                    Either::Left(((stdout, output), _)) => Event::Output(stdout, output),
+                    Either::Right((data, _)) => {
+                        Event::Received(data.map(|data| data.map(Buf::collect).map_err(Into::into)))
+                    }
+                },
+                Input::Writing(ref mut writing) => {
+                    let cancelled = events.cancelled();
+                    match future::select(&mut reading, future::select(writing, cancelled)).await {
+                        Either::Left(((stdout, output), _)) => Event::Output(stdout, output),
+                        Either::Right((Either::Left(((stdin, written), _)), _)) => {
+                            Event::Written(stdin, written)
+                        }
+                        Either::Right((Either::Right(..), _)) => Event::Cancelled,
+                    }
+                }
+                Input::Closed => match future::select(&mut reading, events.cancelled()).await {
+                    Either::Left(((stdout, output), _)) => Event::Output(stdout, output),
+                    Either::Right(..) => Event::Cancelled,
+                },
+            };
+
+            match event {
+                Event::Output(stdout, output) => {
+                    match output {
+                        Ok(Output::Head(response)) => {
+                            events
+                                .start_send_response(response, false)
+                                .await
+                                .map_err(Into::into)?;
+                            head_sent = true;
+                        }
+                        Ok(Output::Data(data)) => {
+                            events
+                                .send_data(data.into(), false)
+                                .await
+                                .map_err(Into::into)?;
+                        }
+                        Ok(Output::End) => break,
+                        Err(err) => {
+                            if !head_sent {
+                                send_status(&mut events, StatusCode::BAD_GATEWAY).await?;
+                            }
+                            return Err(err);
+                        }
+                    }
+                    reading = read_output(stdout, true, self.chunk_size);
+                }
+                Event::Received(data) => {
+                    let stdin = match mem::replace(&mut input, Input::Closed) {
+                        Input::Receiving(stdin) => stdin,
+                        _ => unreachable!(),
+                    };
+                    // The standard input is closed by dropping it at the end of the body.
+                    if let Some(data) = data {
+                        input = Input::Writing(write_input(stdin, data?));
+                    }
+                }
+                Event::Written(stdin, written) => match written {
+                    Ok(()) => input = Input::Receiving(stdin),
+                    // The script has stopped reading the body.
+                    Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => {
+                        input = Input::Closed;
+                    }
+                    Err(err) => return Err(err.into()),
+                },
+                Event::Cancelled => return Ok(()),
+            }
+        }
+        drop(input);
+
+        events
+            .send_data(Bytes::new().into(), true)
+            .await
+            .map_err(Into::into)?;
+
+        let status: ExitStatus = child.await?;
+        if !status.success() {
+            return Err(format!("the CGI script exited with {}", status).into());
+        }
+        Ok(())
+    }
+}
+
+/// The progress of streaming the request body into the standard input of the script.
+enum Input {
+    /// Waiting for the next chunk of the request body.
+    Receiving(ChildStdin),
+    /// Writing a chunk into the standard input.
+    Writing(BoxFuture<'static, (ChildStdin, io::Result<()>)>),
+    /// The standard input has been closed.
+    Closed,
+}
+
+/// An item read from the standard output of the script.
+enum Output {
+    Head(Response<()>),
+    Data(Bytes),
+    End,
+}
+
+/// What has happened while running the script.
+enum Event {
+    Output(BufReader<ChildStdout>, Result<Output, BoxError>),
+    Received(Option<Result<Bytes, BoxError>>),
+    Written(ChildStdin, io::Result<()>),
+    Cancelled,
+}
+
+type Reading = BoxFuture<'static, (BufReader<ChildStdout>, Result<Output, BoxError>)>;
+
+/// Read the response head, or the next chunk of the body after the head has been read.
+///
+/// The standard output is moved into the future and returned with the item,
+/// so that the future is kept while the request body is being written.
+fn read_output(mut stdout: BufReader<ChildStdout>, head_read: bool, chunk_size: usize) -> Reading {
+    async move {
+        let output = if head_read {
+            let mut buf = vec![0; chunk_size];
+            match stdout.read(&mut buf).await {
+                Ok(0) => Ok(Output::End),
+                Ok(n) => {
+                    buf.truncate(n);
+                    Ok(Output::Data(buf.into()))
+                }
+                Err(err) => Err(err.into()),
+            }
+        } else {
+            read_head(&mut stdout).await.map(Output::Head)
+        };
+        (stdout, output)
+    }
+    .boxed()
+}
+
+/// Write a chunk of the request body into the standard input of the script.
+fn write_input(
+    mut stdin: ChildStdin,
+    data: Bytes,
+) -> BoxFuture<'static, (ChildStdin, io::Result<()>)> {
+    async move {
+        let written = stdin.write_all(&data).await;
+        (stdin, written)
+    }
+    .boxed()
+}
+
+/// Read the header fields of the CGI response, and convert them to the response head.
+async fn read_head(stdout: &mut BufReader<ChildStdout>) -> Result<Response<()>, BoxError> {
+    let mut response = Response::new(());
+    let mut status = None;
+    let mut head_len = 0;
+    let mut line = vec![];
+    loop {
+        line.clear();
+        let n = stdout.read_until(b'\n', &mut line).await?;
+        if n == 0 {
+            return Err("the CGI script exited without the response head".into());
+        }
+        head_len += n;
+        if head_len > MAX_HEAD_LEN {
+            return Err("too large CGI response head".into());
+        }
+
+        let line = trim_newline(&line);
+        if line.is_empty() {
+            break;
+        }
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or("malformed CGI header field")?;
+        let name = HeaderName::from_bytes(&line[..colon])?;
+        let value = HeaderValue::from_bytes(trim_whitespace(&line[colon + 1..]))?;
+
+        if name == "status" {
+            let code = value.as_bytes().get(..3).ok_or("malformed CGI Status")?;
+            status = Some(StatusCode::from_bytes(code)?);
+        } else {
+            response.headers_mut().append(name, value);
+        }
+    }
+
+    // The redirect to the local path is also sent to the client, instead of
+    // being processed within the server.
+    *response.status_mut() = match status {
+        Some(status) => status,
+        None if response.headers().contains_key(header::LOCATION) => StatusCode::FOUND,
+        None => StatusCode::OK,
+    };
+    Ok(response)
+}
+
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn trim_whitespace(value: &[u8]) -> &[u8] {
+    let start = value
+        .iter()
+        .position(|&b| b != b' ' && b != b'\t')
+        .unwrap_or(value.len());
+    let end = value
+        .iter()
+        .rposition(|&b| b != b' ' && b != b'\t')
+        .map_or(start, |i| i + 1);
+    &value[start..end]
+}
+
+fn protocol(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2.0",
+        _ => "HTTP/1.1",
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+async fn send_status<E>(events: &mut E, status: StatusCode) -> Result<(), BoxError>
+where
+    E: Events + Send,
+{
+    let mut response = Response::new(());
+    *response.status_mut() = status;
+    events
+        .start_send_response(response, true)
+        .await
+        .map_err(Into::into)
+}
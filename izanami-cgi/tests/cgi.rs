@@ -0,0 +1,144 @@
+use bytes::Bytes;
+use futures::channel::oneshot;
+use http::{Request, StatusCode};
+use izanami::{App, RemoteAddr};
+use izanami_cgi::Cgi;
+use izanami_test::MockEvents;
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+fn script(name: &str, content: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("izanami-cgi-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+async fn call(app: &Cgi, mut request: http::request::Builder, events: &mut MockEvents) {
+    let mut request = request.body(events).unwrap();
+    request
+        .extensions_mut()
+        .insert(RemoteAddr(([192, 0, 2, 1], 4321).into()));
+    app.call(request).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_environment_and_body() {
+    let app = Cgi::new(script(
+        "echo.sh",
+        "#!/bin/sh\n\
+         printf 'Status: 201 Created\\r\\nContent-Type: text/plain\\r\\nX-Method: %s\\r\\n\\r\\n' \"$REQUEST_METHOD\"\n\
+         echo \"$SCRIPT_NAME|$PATH_INFO|$QUERY_STRING|$CONTENT_LENGTH|$REMOTE_ADDR|$HTTP_X_FOO|$HTTP_PROXY\"\n\
+         cat\n",
+    ))
+    .script_name("/cgi-bin/echo")
+    .chunk_size(4);
+
+    let mut events = MockEvents::default();
+    events.request_body.push_back(Bytes::from("Hello, "));
+    events.request_body.push_back(Bytes::from("world"));
+    let mut request = Request::post("/cgi-bin/echo/foo/bar?x=1");
+    request
+        .header("content-length", "12")
+        .header("x-foo", "a")
+        .header("x-foo", "b")
+        .header("proxy", "http://evil.example.com");
+    call(&app, request, &mut events).await;
+
+    assert_eq!(events.status(), StatusCode::CREATED);
+    assert_eq!(events.header("content-type"), Some("text/plain"));
+    assert_eq!(events.header("x-method"), Some("POST"));
+    assert_eq!(
+        events.body(),
+        b"/cgi-bin/echo|/foo/bar|x=1|12|192.0.2.1|a, b|\nHello, world"
+    );
+    assert!(events.chunks.iter().all(|chunk| chunk.len() <= 4));
+    assert!(events.end_of_stream);
+
+    let mut events = MockEvents::default();
+    call(&app, Request::get("/other"), &mut events).await;
+    assert_eq!(events.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_location() {
+    let app = Cgi::new(script(
+        "redirect.sh",
+        "#!/bin/sh\nprintf 'Location: http://example.com/\\n\\n'\n",
+    ));
+    let mut events = MockEvents::default();
+    call(&app, Request::get("/"), &mut events).await;
+    assert_eq!(events.status(), StatusCode::FOUND);
+    assert_eq!(events.header("location"), Some("http://example.com/"));
+    assert!(events.end_of_stream);
+}
+
+#[tokio::test]
+async fn test_kill_on_disconnect() {
+    let pid_file = std::env::temp_dir().join(format!("izanami-cgi-{}.pid", std::process::id()));
+    let _ = std::fs::remove_file(&pid_file);
+    let app = Cgi::new(script(
+        "sleep.sh",
+        "#!/bin/sh\necho $$ > \"$PID_FILE.tmp\"\nmv \"$PID_FILE.tmp\" \"$PID_FILE\"\nexec sleep 30\n",
+    ))
+    .env("PID_FILE", &pid_file);
+
+    // The client disconnects once the file is created.
+    let (disconnect, disconnected) = oneshot::channel();
+    let path = pid_file.clone();
+    tokio::spawn(async move {
+        while !path.exists() {
+            tokio::timer::delay_for(Duration::from_millis(10)).await;
+        }
+        let _ = disconnect.send(());
+    });
+    let mut events = MockEvents {
+        disconnected: Some(disconnected),
+        ..MockEvents::default()
+    };
+    let started = Instant::now();
+    call(&app, Request::get("/"), &mut events).await;
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert!(events.response.is_none());
+
+    // The script is killed, and then reaped in the background.
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    let pid = pid.trim();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(ref stat) if !stat.contains(") Z ") => {}
+            _ => break,
+        }
+        assert!(Instant::now() < deadline, "the script is still running");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[tokio::test]
+async fn test_stream_large_body() {
+    let app = Cgi::new(script(
+        "cat.sh",
+        "#!/bin/sh\nprintf 'Content-Type: application/octet-stream\\n\\n'\nexec cat\n",
+    ));
+
+    // Larger than the pipe buffers, so that the script blocks on writing
+    // the output unless it is read while the body is being written.
+    let chunk: Vec<u8> = (0..16 * 1024).map(|i| b'a' + (i % 26) as u8).collect();
+    let mut events = MockEvents::default();
+    for _ in 0..64 {
+        events.request_body.push_back(Bytes::from(&chunk[..]));
+    }
+    call(&app, Request::post("/"), &mut events).await;
+
+    assert_eq!(events.status(), StatusCode::OK);
+    let body = events.body();
+    assert_eq!(body.len(), 1024 * 1024);
+    assert!(body.chunks(chunk.len()).all(|c| c == &chunk[..]));
+    assert!(events.end_of_stream);
+}
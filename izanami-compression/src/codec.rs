@@ -0,0 +1,261 @@
+use crate::PayloadTooLarge;
+use std::io::{self, Write};
+
+/// The content codings supported by this crate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Coding {
+    /// `gzip`
+    #[cfg(feature = "gzip")]
+    Gzip,
+
+    /// `deflate` (zlib format)
+    #[cfg(feature = "deflate")]
+    Deflate,
+
+    /// `br`
+    #[cfg(feature = "br")]
+    Brotli,
+
+    /// `zstd`
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Coding {
+    /// All of the available codings, in the default order of preference.
+    pub const ALL: &'static [Coding] = &[
+        #[cfg(feature = "br")]
+        Coding::Brotli,
+        #[cfg(feature = "zstd")]
+        Coding::Zstd,
+        #[cfg(feature = "gzip")]
+        Coding::Gzip,
+        #[cfg(feature = "deflate")]
+        Coding::Deflate,
+    ];
+
+    /// Return the token used in `Content-Encoding` and `Accept-Encoding`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gzip")]
+            Coding::Gzip => "gzip",
+            #[cfg(feature = "deflate")]
+            Coding::Deflate => "deflate",
+            #[cfg(feature = "br")]
+            Coding::Brotli => "br",
+            #[cfg(feature = "zstd")]
+            Coding::Zstd => "zstd",
+        }
+    }
+
+    pub(crate) fn from_token(token: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|coding| {
+            token.eq_ignore_ascii_case(coding.as_str())
+                || (coding.as_str() == "gzip" && token.eq_ignore_ascii_case("x-gzip"))
+        })
+    }
+}
+
+/// A streaming compressor that accumulates its output in a buffer.
+pub(crate) enum Encoder {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    #[cfg(feature = "deflate")]
+    Deflate(flate2::write::ZlibEncoder<Vec<u8>>),
+    #[cfg(feature = "br")]
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    pub(crate) fn new(coding: Coding) -> io::Result<Self> {
+        Ok(match coding {
+            #[cfg(feature = "gzip")]
+            Coding::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "deflate")]
+            Coding::Deflate => Encoder::Deflate(flate2::write::ZlibEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "br")]
+            Coding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                5,
+                22,
+            ))),
+            #[cfg(feature = "zstd")]
+            Coding::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), 0)?),
+        })
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder,
+            #[cfg(feature = "deflate")]
+            Encoder::Deflate(encoder) => encoder,
+            #[cfg(feature = "br")]
+            Encoder::Brotli(encoder) => &mut **encoder,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder,
+        }
+    }
+
+    fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            #[cfg(feature = "deflate")]
+            Encoder::Deflate(encoder) => encoder.get_mut(),
+            #[cfg(feature = "br")]
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+        }
+    }
+
+    /// Compress `data` and return the compressed bytes produced so far.
+    ///
+    /// If `flush` is `true`, all of the pending input is flushed
+    /// so that the client can decode it immediately.
+    pub(crate) fn encode(&mut self, data: &[u8], flush: bool) -> io::Result<Vec<u8>> {
+        let writer = self.writer();
+        writer.write_all(data)?;
+        if flush {
+            writer.flush()?;
+        }
+        Ok(std::mem::take(self.output()))
+    }
+
+    /// Finish the compressed stream and return the remaining bytes.
+    pub(crate) fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "deflate")]
+            Encoder::Deflate(encoder) => encoder.finish(),
+            #[cfg(feature = "br")]
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// The buffer of the decompressed bytes, which refuses to grow beyond the limit.
+pub(crate) struct Sink {
+    buf: Vec<u8>,
+    remaining: u64,
+}
+
+impl Write for Sink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.len() as u64 > self.remaining {
+            return Err(io::Error::other(PayloadTooLarge::new()));
+        }
+        self.remaining -= data.len() as u64;
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A streaming decompressor that accumulates its output in a buffer.
+pub(crate) enum Decoder {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzDecoder<Sink>),
+    #[cfg(feature = "deflate")]
+    Deflate(flate2::write::ZlibDecoder<Sink>),
+    #[cfg(feature = "br")]
+    Brotli(Box<brotli::DecompressorWriter<Sink>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Decoder<'static, Sink>),
+}
+
+impl Decoder {
+    /// Create a decompressor that fails with `PayloadTooLarge` once
+    /// the decompressed bytes exceed `limit` in total.
+    pub(crate) fn new(coding: Coding, limit: u64) -> io::Result<Self> {
+        let sink = Sink {
+            buf: Vec::new(),
+            remaining: limit,
+        };
+        Ok(match coding {
+            #[cfg(feature = "gzip")]
+            Coding::Gzip => Decoder::Gzip(flate2::write::GzDecoder::new(sink)),
+            #[cfg(feature = "deflate")]
+            Coding::Deflate => Decoder::Deflate(flate2::write::ZlibDecoder::new(sink)),
+            #[cfg(feature = "br")]
+            Coding::Brotli => {
+                Decoder::Brotli(Box::new(brotli::DecompressorWriter::new(sink, 4096)))
+            }
+            #[cfg(feature = "zstd")]
+            Coding::Zstd => Decoder::Zstd(zstd::stream::write::Decoder::new(sink)?),
+        })
+    }
+
+    fn output(&mut self) -> &mut Vec<u8> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(decoder) => &mut decoder.get_mut().buf,
+            #[cfg(feature = "deflate")]
+            Decoder::Deflate(decoder) => &mut decoder.get_mut().buf,
+            #[cfg(feature = "br")]
+            Decoder::Brotli(decoder) => &mut decoder.get_mut().buf,
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(decoder) => &mut decoder.get_mut().buf,
+        }
+    }
+
+    /// Decompress `data` and return the decompressed bytes produced so far.
+    pub(crate) fn decode(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(decoder) => decoder.write_all(data)?,
+            #[cfg(feature = "deflate")]
+            Decoder::Deflate(decoder) => decoder.write_all(data)?,
+            #[cfg(feature = "br")]
+            Decoder::Brotli(decoder) => decoder.write_all(data)?,
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(decoder) => decoder.write_all(data)?,
+        }
+        Ok(std::mem::take(self.output()))
+    }
+
+    /// Finish the decompressed stream and return the remaining bytes.
+    pub(crate) fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(decoder) => decoder.finish().map(|sink| sink.buf),
+            #[cfg(feature = "deflate")]
+            Decoder::Deflate(decoder) => decoder.finish().map(|sink| sink.buf),
+            #[cfg(feature = "br")]
+            Decoder::Brotli(mut decoder) => {
+                decoder.close()?;
+                decoder
+                    .into_inner()
+                    .map(|sink| sink.buf)
+                    .map_err(|_| invalid_data())
+            }
+            #[cfg(feature = "zstd")]
+            Decoder::Zstd(mut decoder) => {
+                decoder.flush()?;
+                Ok(decoder.into_inner().buf)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "br")]
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid brotli stream")
+}
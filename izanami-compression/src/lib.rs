@@ -0,0 +1,644 @@
+//! A middleware that compresses the response bodies and
+//! decompresses the request bodies.
+
+#![deny(
+    missing_debug_implementations,
+    nonstandard_style,
+    rust_2018_idioms,
+    rust_2018_compatibility,
+    unused
+)]
+
+#[cfg(not(any(
+    feature = "gzip",
+    feature = "deflate",
+    feature = "br",
+    feature = "zstd"
+)))]
+compile_error!("at least one of the features `gzip`, `deflate`, `br` and `zstd` must be enabled");
+
+mod codec;
+
+pub use crate::codec::Coding;
+
+use crate::codec::{Decoder, Encoder};
+use async_trait::async_trait;
+use bytes::{Buf, Bytes, BytesMut};
+use http::{
+    header::{self, HeaderMap, HeaderValue},
+    Method, Request, Response, StatusCode,
+};
+use izanami::{App, Events, Reason};
+use std::{
+    cmp, error, fmt, io,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+const DEFAULT_MAX_DECODED_CHUNK: usize = 64 * 1024;
+const DEFAULT_MAX_DECODED_LEN: u64 = 64 * 1024 * 1024;
+
+/// The amount of the compressed bytes fed to the decompressor at once,
+/// which keeps the output of a single step small.
+const DECODE_STEP: usize = 1024;
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+/// The policy to flush the compressor after each call of `send_data`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Flush {
+    /// Flush only when the response is a stream of events (`text/event-stream`).
+    Auto,
+
+    /// Flush every chunk, so that the client receives the data without delay.
+    ///
+    /// This is suitable for bidirectional streaming, at the cost of the
+    /// compression ratio.
+    Always,
+
+    /// Never flush. The compressor decides when to emit the data.
+    Never,
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    codings: Vec<Coding>,
+    flush: Flush,
+    min_size: u64,
+    decompress_requests: bool,
+    max_decoded_chunk: usize,
+    max_decoded_len: u64,
+}
+
+/// A middleware that negotiates the content coding with the client.
+///
+/// The response body is compressed with the coding selected from
+/// `Accept-Encoding`, and the request body is decompressed according
+/// to `Content-Encoding`.
+#[derive(Debug, Clone)]
+pub struct Compression<A> {
+    app: A,
+    config: Arc<Config>,
+}
+
+impl<A> Compression<A> {
+    /// Wrap an `App` with the default configuration.
+    pub fn new(app: A) -> Self {
+        Self {
+            app,
+            config: Arc::new(Config {
+                codings: Coding::ALL.to_vec(),
+                flush: Flush::Auto,
+                min_size: 256,
+                decompress_requests: true,
+                max_decoded_chunk: DEFAULT_MAX_DECODED_CHUNK,
+                max_decoded_len: DEFAULT_MAX_DECODED_LEN,
+            }),
+        }
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        Arc::make_mut(&mut self.config)
+    }
+
+    /// Set the codings used for compressing the response bodies,
+    /// in the order of preference.
+    ///
+    /// By default, all of the codings enabled by the feature flags are used.
+    pub fn codings<I>(mut self, codings: I) -> Self
+    where
+        I: IntoIterator<Item = Coding>,
+    {
+        self.config_mut().codings = codings.into_iter().collect();
+        self
+    }
+
+    /// Set the flush policy of the compressor.
+    ///
+    /// The default value is `Flush::Auto`.
+    pub fn flush(mut self, flush: Flush) -> Self {
+        self.config_mut().flush = flush;
+        self
+    }
+
+    /// Set the minimum value of `Content-Length` for compressing the response.
+    ///
+    /// The responses without `Content-Length` are always compressed.
+    /// The default value is 256.
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.config_mut().min_size = min_size;
+        self
+    }
+
+    /// Specify whether to decompress the request bodies.
+    ///
+    /// The default value is `true`.
+    pub fn decompress_requests(mut self, enabled: bool) -> Self {
+        self.config_mut().decompress_requests = enabled;
+        self
+    }
+
+    /// Set the maximum number of the decompressed bytes returned from a call of `data`.
+    ///
+    /// The default value is 64 KiB.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `max` is zero.
+    pub fn max_decoded_chunk(mut self, max: usize) -> Self {
+        assert!(max > 0, "the maximum chunk size must be positive");
+        self.config_mut().max_decoded_chunk = max;
+        self
+    }
+
+    /// Set the maximum length of the decompressed request body.
+    ///
+    /// When the body exceeds the limit, `data` returns `PayloadTooLarge`,
+    /// after sending `413 Payload Too Large` if the response has not been
+    /// started yet. The default value is 64 MiB.
+    pub fn max_decoded_len(mut self, max: u64) -> Self {
+        self.config_mut().max_decoded_len = max;
+        self
+    }
+}
+
+/// The error returned from `data` when the decompressed request body exceeds
+/// the limit set by `max_decoded_len`.
+#[derive(Debug)]
+pub struct PayloadTooLarge {
+    _priv: (),
+}
+
+impl PayloadTooLarge {
+    pub(crate) fn new() -> Self {
+        Self { _priv: () }
+    }
+}
+
+impl fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the decompressed request body is too large")
+    }
+}
+
+impl error::Error for PayloadTooLarge {}
+
+#[async_trait]
+impl<A, E> App<E> for Compression<A>
+where
+    A: App<CompressionEvents<E>> + Send + Sync,
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    type Error = BoxError;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (mut parts, mut events) = request.into_parts();
+
+        let mut decoder = None;
+        if self.config.decompress_requests {
+            if let Some(content_encoding) = parts.headers.get(header::CONTENT_ENCODING) {
+                let coding = content_encoding
+                    .to_str()
+                    .ok()
+                    .map(str::trim)
+                    .filter(|coding| !coding.eq_ignore_ascii_case("identity"))
+                    .map(|coding| Coding::from_token(coding).ok_or(()));
+                match coding {
+                    Some(Ok(coding)) => {
+                        decoder = Some(Decoder::new(coding, self.config.max_decoded_len)?);
+                        parts.headers.remove(header::CONTENT_ENCODING);
+                        parts.headers.remove(header::CONTENT_LENGTH);
+                    }
+                    Some(Err(())) => {
+                        let response = Response::builder()
+                            .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                            .header(header::ACCEPT_ENCODING, accept_encoding(&self.config))
+                            .body(())?;
+                        return events
+                            .start_send_response(response, true)
+                            .await
+                            .map_err(Into::into);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        let events = CompressionEvents {
+            inner: events,
+            config: self.config.clone(),
+            coding: negotiate(&parts.headers, &self.config.codings),
+            is_head: parts.method == Method::HEAD,
+            encoder: None,
+            flush: false,
+            decompressing: decoder.is_some(),
+            decoder,
+            pending: None,
+            decoded: BytesMut::new(),
+            manual_release: false,
+            response_started: false,
+        };
+
+        self.app
+            .call(Request::from_parts(parts, events))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// The `Events` passed to the inner `App` of `Compression`.
+///
+/// While decompressing the request body, the capacity of the received data
+/// is released as it is decompressed, so `release_capacity` does nothing.
+pub struct CompressionEvents<E: Events> {
+    inner: E,
+    config: Arc<Config>,
+    coding: Option<Coding>,
+    is_head: bool,
+    encoder: Option<Encoder>,
+    flush: bool,
+    decompressing: bool,
+    decoder: Option<Decoder>,
+    /// The compressed bytes not yet fed to the decoder.
+    pending: Option<E::Data>,
+    /// The decompressed bytes not yet returned from `data`.
+    decoded: BytesMut,
+    manual_release: bool,
+    response_started: bool,
+}
+
+impl<E: Events + fmt::Debug> fmt::Debug for CompressionEvents<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionEvents")
+            .field("inner", &self.inner)
+            .field("coding", &self.coding)
+            .field("compressing", &self.encoder.is_some())
+            .field("decompressing", &self.decompressing)
+            .finish()
+    }
+}
+
+impl<E: Events> CompressionEvents<E> {
+    /// Return the coding selected for the response body.
+    pub fn coding(&self) -> Option<Coding> {
+        self.coding
+    }
+
+    /// Return a reference to the underlying `Events`.
+    pub fn get_ref(&self) -> &E {
+        &self.inner
+    }
+
+    /// Return a mutable reference to the underlying `Events`.
+    pub fn get_mut(&mut self) -> &mut E {
+        &mut self.inner
+    }
+
+    /// Convert the error from the decoder, sending `413 Payload Too Large`
+    /// if the body exceeds the limit before the response is started.
+    async fn decode_error(&mut self, err: io::Error) -> BoxError {
+        if !err.get_ref().is_some_and(|err| err.is::<PayloadTooLarge>()) {
+            return err.into();
+        }
+
+        if !self.response_started {
+            self.response_started = true;
+            let mut response = Response::new(());
+            *response.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+            if let Err(err) = self.inner.start_send_response(response, true).await {
+                return err.into();
+            }
+        }
+        Box::new(PayloadTooLarge::new())
+    }
+}
+
+#[async_trait]
+impl<E> Events for CompressionEvents<E>
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    type Data = E::Data;
+    type Error = BoxError;
+
+    async fn data(&mut self) -> Option<Result<Self::Data, Self::Error>> {
+        if !self.decompressing {
+            return self.inner.data().await.map(|res| res.map_err(Into::into));
+        }
+
+        let max = self.config.max_decoded_chunk;
+        loop {
+            if self.decoded.len() >= max || (self.decoder.is_none() && !self.decoded.is_empty()) {
+                let len = cmp::min(self.decoded.len(), max);
+                return Some(Ok(self.decoded.split_to(len).freeze().into()));
+            }
+
+            let decoder = self.decoder.as_mut()?;
+            match self.pending {
+                Some(ref mut data) if data.has_remaining() => {
+                    let bytes = data.bytes();
+                    let len = cmp::min(bytes.len(), DECODE_STEP);
+                    let res = decoder.decode(&bytes[..len]);
+                    data.advance(len);
+                    match res {
+                        Ok(output) => self.decoded.extend_from_slice(&output),
+                        Err(err) => return Some(Err(self.decode_error(err).await)),
+                    }
+                    if self.manual_release {
+                        if let Err(err) = self.inner.release_capacity(len) {
+                            return Some(Err(err.into()));
+                        }
+                    }
+                    continue;
+                }
+                _ => self.pending = None,
+            }
+
+            if !self.decoded.is_empty() {
+                return Some(Ok(self.decoded.take().freeze().into()));
+            }
+
+            let data = match self.inner.data().await {
+                Some(Ok(data)) => Some(data),
+                Some(Err(err)) => return Some(Err(err.into())),
+                None => None,
+            };
+            match data {
+                Some(data) => self.pending = Some(data),
+                None => {
+                    let decoder = self.decoder.take().unwrap();
+                    match decoder.finish() {
+                        Ok(output) => self.decoded.extend_from_slice(&output),
+                        Err(err) => return Some(Err(self.decode_error(err).await)),
+                    }
+                    if self.decoded.is_empty() {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn trailers(&mut self) -> Result<Option<HeaderMap>, Self::Error> {
+        self.inner.trailers().await.map_err(Into::into)
+    }
+
+    async fn start_send_response(
+        &mut self,
+        mut response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        self.response_started = true;
+
+        if !self.config.codings.is_empty() && is_compressible(&response) {
+            response
+                .headers_mut()
+                .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+
+            let too_small = response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<u64>().ok())
+                .is_some_and(|len| len < self.config.min_size);
+
+            if let Some(coding) = self.coding {
+                if !end_of_stream && !self.is_head && !too_small {
+                    let headers = response.headers_mut();
+                    headers.insert(
+                        header::CONTENT_ENCODING,
+                        HeaderValue::from_static(coding.as_str()),
+                    );
+                    headers.remove(header::CONTENT_LENGTH);
+                    headers.remove(header::ACCEPT_RANGES);
+                    if let Some(etag) = headers.get(header::ETAG).and_then(weaken) {
+                        headers.insert(header::ETAG, etag);
+                    }
+
+                    self.flush = match self.config.flush {
+                        Flush::Always => true,
+                        Flush::Never => false,
+                        Flush::Auto => headers
+                            .get(header::CONTENT_TYPE)
+                            .and_then(|value| value.to_str().ok())
+                            .is_some_and(|mime| mime.starts_with("text/event-stream")),
+                    };
+                    self.encoder = Some(Encoder::new(coding)?);
+                }
+            }
+        }
+
+        self.inner
+            .start_send_response(response, end_of_stream)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn send_data(
+        &mut self,
+        mut data: Self::Data,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        let encoder = match self.encoder {
+            Some(ref mut encoder) => encoder,
+            None => {
+                return self
+                    .inner
+                    .send_data(data, end_of_stream)
+                    .await
+                    .map_err(Into::into)
+            }
+        };
+
+        let mut encoded = vec![];
+        while data.has_remaining() {
+            let len = {
+                let bytes = data.bytes();
+                encoded.extend_from_slice(&encoder.encode(bytes, false)?);
+                bytes.len()
+            };
+            data.advance(len);
+        }
+        if end_of_stream {
+            let encoder = self.encoder.take().unwrap();
+            encoded.extend_from_slice(&encoder.finish()?);
+        } else if self.flush {
+            encoded.extend_from_slice(&encoder.encode(&[], true)?);
+        }
+
+        if encoded.is_empty() && !end_of_stream {
+            return Ok(());
+        }
+
+        self.inner
+            .send_data(Bytes::from(encoded).into(), end_of_stream)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error> {
+        if let Some(encoder) = self.encoder.take() {
+            let encoded = encoder.finish()?;
+            self.inner
+                .send_data(Bytes::from(encoded).into(), false)
+                .await
+                .map_err(Into::into)?;
+        }
+        self.inner.send_trailers(trailers).await.map_err(Into::into)
+    }
+
+    fn reset(&mut self, reason: Reason) {
+        self.inner.reset(reason)
+    }
+
+    async fn cancelled(&mut self) -> Reason {
+        self.inner.cancelled().await
+    }
+
+    fn reserve_send_capacity(&mut self, capacity: usize) {
+        self.inner.reserve_send_capacity(capacity)
+    }
+
+    fn poll_send_capacity(&mut self, cx: &mut Context<'_>) -> Poll<Result<usize, Self::Error>> {
+        self.inner.poll_send_capacity(cx).map_err(Into::into)
+    }
+
+    fn set_manual_release(&mut self, enabled: bool) -> bool {
+        let supported = self.inner.set_manual_release(enabled);
+        self.manual_release = supported && enabled;
+        supported
+    }
+
+    fn release_capacity(&mut self, len: usize) -> Result<(), Self::Error> {
+        if self.decompressing {
+            return Ok(());
+        }
+        self.inner.release_capacity(len).map_err(Into::into)
+    }
+
+    fn is_push_supported(&self) -> bool {
+        self.inner.is_push_supported()
+    }
+}
+
+/// Convert a strong entity tag into the weak one, since the compressed
+/// representation is not byte-for-byte identical to the original.
+fn weaken(etag: &HeaderValue) -> Option<HeaderValue> {
+    if etag.as_bytes().starts_with(b"W/") {
+        return None;
+    }
+    let mut weak = b"W/".to_vec();
+    weak.extend_from_slice(etag.as_bytes());
+    HeaderValue::from_bytes(&weak).ok()
+}
+
+/// Select the coding for the response from `Accept-Encoding`.
+///
+/// The explicit entries take precedence over `*`, and `q=0` refuses the coding.
+/// `None` is returned if no coding is acceptable, or `identity` is preferred
+/// to all of the acceptable ones.
+fn negotiate(headers: &HeaderMap, codings: &[Coding]) -> Option<Coding> {
+    let mut explicit: Vec<(Coding, f32)> = vec![];
+    let mut identity = None;
+    let mut any = None;
+    for item in headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+    {
+        let mut params = item.split(';');
+        let token = params.next().unwrap_or("").trim();
+        let q = params
+            .filter_map(|param| {
+                let mut kv = param.splitn(2, '=');
+                match (kv.next().map(str::trim), kv.next()) {
+                    (Some("q"), Some(q)) | (Some("Q"), Some(q)) => q.trim().parse::<f32>().ok(),
+                    _ => None,
+                }
+            })
+            .next()
+            .unwrap_or(1.0);
+
+        if token == "*" {
+            any = Some(q);
+        } else if token.eq_ignore_ascii_case("identity") {
+            identity = Some(q);
+        } else if let Some(coding) = Coding::from_token(token) {
+            explicit.push((coding, q));
+        }
+    }
+
+    let mut best: Option<(Coding, f32)> = None;
+    for &coding in codings {
+        let q = explicit
+            .iter()
+            .find(|(c, _)| *c == coding)
+            .map(|&(_, q)| q)
+            .or(any)
+            .unwrap_or(0.0);
+        // The earlier one in `codings` wins the tie.
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((coding, q));
+        }
+    }
+
+    // `identity` is acceptable unless it is refused explicitly or by `*;q=0`.
+    let identity = identity.or(any).unwrap_or(1.0);
+    best.filter(|&(_, q)| q >= identity)
+        .map(|(coding, _)| coding)
+}
+
+fn accept_encoding(config: &Config) -> String {
+    let mut codings: Vec<&str> = config.codings.iter().map(|c| c.as_str()).collect();
+    codings.push("identity");
+    codings.join(", ")
+}
+
+/// Determine whether the response may be compressed.
+fn is_compressible(response: &Response<()>) -> bool {
+    let status = response.status();
+    if status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+        || status == StatusCode::PARTIAL_CONTENT
+    {
+        return false;
+    }
+
+    let headers = response.headers();
+    if headers.contains_key(header::CONTENT_ENCODING) || headers.contains_key(header::CONTENT_RANGE)
+    {
+        return false;
+    }
+
+    let mime = match headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(mime) => mime
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_ascii_lowercase(),
+        None => return true,
+    };
+    if mime == "image/svg+xml" {
+        return true;
+    }
+    !(mime.starts_with("image/")
+        || mime.starts_with("audio/")
+        || mime.starts_with("video/")
+        || mime == "application/zip"
+        || mime == "application/gzip"
+        || mime == "application/x-gzip"
+        || mime == "application/zstd"
+        || mime == "application/octet-stream"
+        || mime == "application/grpc")
+}
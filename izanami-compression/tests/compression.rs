@@ -0,0 +1,257 @@
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::executor::block_on;
+use http::{Request, Response};
+use izanami::{App, Events};
+use izanami_compression::{Coding, Compression, Flush, PayloadTooLarge};
+use izanami_test::MockEvents;
+use std::io::{Read, Write};
+
+const TEXT: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ";
+
+/// Echo the request body three times, in separate chunks.
+struct Echo;
+
+#[async_trait]
+impl<E> App<E> for Echo
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    type Error = E::Error;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let mut events = request.into_body();
+        let mut body = vec![];
+        while let Some(data) = events.data().await {
+            body.extend_from_slice(Buf::bytes(&data?));
+        }
+
+        let response = Response::builder()
+            .header("content-type", "text/plain")
+            .body(())
+            .unwrap();
+        events.start_send_response(response, false).await?;
+        for i in 0..3 {
+            events
+                .send_data(Bytes::from(body.clone()).into(), i == 2)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn decode(coding: &str, data: &[u8]) -> Vec<u8> {
+    let mut decoded = vec![];
+    match coding {
+        "gzip" => flate2::read::GzDecoder::new(data)
+            .read_to_end(&mut decoded)
+            .unwrap(),
+        "deflate" => flate2::read::ZlibDecoder::new(data)
+            .read_to_end(&mut decoded)
+            .unwrap(),
+        "br" => brotli::Decompressor::new(data, 4096)
+            .read_to_end(&mut decoded)
+            .unwrap(),
+        "zstd" => zstd::stream::read::Decoder::new(data)
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap(),
+        _ => unreachable!(),
+    };
+    decoded
+}
+
+fn call<A>(app: &A, request: http::request::Builder, events: &mut MockEvents)
+where
+    A: for<'a> App<&'a mut MockEvents>,
+{
+    let mut request = request;
+    let request = request.body(events).unwrap();
+    block_on(app.call(request)).map_err(Into::into).unwrap();
+}
+
+#[test]
+fn test_compress_response() {
+    for coding in &["gzip", "deflate", "br", "zstd"] {
+        let app = Compression::new(Echo).min_size(0);
+        let mut events = MockEvents::default();
+        events.request_body.push_back(Bytes::from(TEXT));
+
+        let mut request = Request::post("/");
+        request.header("accept-encoding", format!("identity;q=0.5, {}", coding));
+        call(&app, request, &mut events);
+
+        assert_eq!(events.header("content-encoding"), Some(*coding));
+        assert_eq!(events.header("vary"), Some("accept-encoding"));
+        assert_eq!(decode(coding, &events.body()), TEXT.repeat(3).as_bytes());
+    }
+}
+
+#[test]
+fn test_negotiation() {
+    let app = Compression::new(Echo).codings(vec![Coding::Gzip, Coding::Brotli]);
+    for (accept, expected) in &[
+        ("gzip, br", Some("gzip")),
+        ("gzip;q=0.5, br", Some("br")),
+        ("*", Some("gzip")),
+        ("zstd", None),
+        ("gzip;q=0", None),
+        ("gzip;q=0, *", Some("br")),
+        ("br;q=0, *;q=0.5", Some("gzip")),
+        ("*, gzip;q=0, br;q=0", None),
+        ("gzip;q=0.5, identity", None),
+        ("gzip;q=0.5, identity;q=0.5", Some("gzip")),
+        ("gzip;q=0.5, *;q=0", Some("gzip")),
+        ("", None),
+    ] {
+        let mut events = MockEvents::default();
+        let mut request = Request::get("/");
+        request.header("accept-encoding", *accept);
+        call(&app, request, &mut events);
+        assert_eq!(events.header("content-encoding"), *expected, "{}", accept);
+    }
+}
+
+#[test]
+fn test_flush_always() {
+    let app = Compression::new(Echo).flush(Flush::Always);
+    let mut events = MockEvents::default();
+    events.request_body.push_back(Bytes::from(TEXT));
+
+    let mut request = Request::post("/");
+    request.header("accept-encoding", "gzip");
+    call(&app, request, &mut events);
+
+    // Every chunk is flushed, so that the decoder can read the first chunk alone.
+    assert_eq!(events.chunks.len(), 3);
+    let mut decoder = flate2::write::GzDecoder::new(vec![]);
+    decoder.write_all(&events.chunks[0]).unwrap();
+    decoder.flush().unwrap();
+    assert_eq!(decoder.get_ref().as_slice(), TEXT.as_bytes());
+}
+
+#[test]
+fn test_decompress_request() {
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(TEXT.as_bytes()).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    let app = Compression::new(Echo);
+    let mut events = MockEvents::default();
+    for chunk in compressed.chunks(5) {
+        events.request_body.push_back(Bytes::from(chunk));
+    }
+
+    let mut request = Request::post("/");
+    request.header("content-encoding", "gzip");
+    call(&app, request, &mut events);
+
+    assert_eq!(events.header("content-encoding"), None);
+    assert_eq!(events.body(), TEXT.repeat(3).as_bytes());
+
+    let mut events = MockEvents::default();
+    let mut request = Request::post("/");
+    request.header("content-encoding", "compress");
+    call(&app, request, &mut events);
+    assert_eq!(events.response.unwrap().status(), 415);
+}
+
+/// Receive the request body, and check the size of each chunk.
+struct Chunks(usize);
+
+#[async_trait]
+impl<E> App<E> for Chunks
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    type Error = E::Error;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let mut events = request.into_body();
+        let mut len = 0;
+        while let Some(data) = events.data().await {
+            let data = data?;
+            assert!(data.remaining() <= self.0);
+            len += data.remaining();
+        }
+        events
+            .send_response(Response::new(Bytes::from(len.to_string())))
+            .await
+    }
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn test_decompression_limits() {
+    let compressed = gzip(&[0; 1024 * 1024]);
+
+    let app = Compression::new(Chunks(4096)).max_decoded_chunk(4096);
+    let mut events = MockEvents::default();
+    events
+        .request_body
+        .push_back(Bytes::from(compressed.clone()));
+    let mut request = Request::post("/");
+    request.header("content-encoding", "gzip");
+    call(&app, request, &mut events);
+    assert_eq!(events.body(), b"1048576");
+
+    let app = Compression::new(Chunks(4096)).max_decoded_len(64 * 1024);
+    let mut events = MockEvents::default();
+    events.request_body.push_back(Bytes::from(compressed));
+    let mut request = Request::post("/");
+    request.header("content-encoding", "gzip");
+    let err = block_on(app.call(request.body(&mut events).unwrap())).unwrap_err();
+    assert!(err.is::<PayloadTooLarge>());
+    assert_eq!(events.response.unwrap().status(), 413);
+}
+
+/// Send a response with a strong entity tag.
+struct Tagged;
+
+#[async_trait]
+impl<E> App<E> for Tagged
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    type Error = E::Error;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let response = Response::builder()
+            .header("etag", "\"abc\"")
+            .body(Bytes::from(TEXT.repeat(10)))
+            .unwrap();
+        request.into_body().send_response(response).await
+    }
+}
+
+#[test]
+fn test_weaken_etag() {
+    let app = Compression::new(Tagged);
+    let mut events = MockEvents::default();
+    let mut request = Request::get("/");
+    request.header("accept-encoding", "gzip");
+    call(&app, request, &mut events);
+    assert_eq!(events.header("content-encoding"), Some("gzip"));
+    assert_eq!(events.header("etag"), Some("W/\"abc\""));
+
+    let mut events = MockEvents::default();
+    call(&app, Request::get("/"), &mut events);
+    assert_eq!(events.header("etag"), Some("\"abc\""));
+}
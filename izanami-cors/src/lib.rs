@@ -0,0 +1,489 @@
+//! A middleware that implements Cross-Origin Resource Sharing (CORS).
+
+#![deny(
+    missing_debug_implementations,
+    nonstandard_style,
+    rust_2018_idioms,
+    rust_2018_compatibility,
+    unused
+)]
+
+use async_trait::async_trait;
+use http::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    Method, Request, Response, StatusCode,
+};
+use izanami::{App, Events, Reason};
+use regex::RegexSet;
+use std::{
+    error, fmt,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+/// The set of origins allowed to access the resources.
+#[derive(Clone)]
+pub struct AllowOrigin(OriginKind);
+
+#[derive(Clone)]
+enum OriginKind {
+    Any,
+    Exact(Vec<HeaderValue>),
+    Regex(RegexSet),
+    Predicate(Arc<dyn Fn(&HeaderValue) -> bool + Send + Sync + 'static>),
+}
+
+impl fmt::Debug for AllowOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            OriginKind::Any => f.write_str("Any"),
+            OriginKind::Exact(ref origins) => f.debug_tuple("Exact").field(origins).finish(),
+            OriginKind::Regex(ref set) => f.debug_tuple("Regex").field(&set.patterns()).finish(),
+            OriginKind::Predicate(..) => f.write_str("Predicate"),
+        }
+    }
+}
+
+impl AllowOrigin {
+    /// Allow any origin.
+    pub fn any() -> Self {
+        AllowOrigin(OriginKind::Any)
+    }
+
+    /// Allow the origins that exactly match one of the specified values.
+    pub fn exact<I>(origins: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderValue>,
+    {
+        AllowOrigin(OriginKind::Exact(origins.into_iter().collect()))
+    }
+
+    /// Allow the origins that match one of the regular expressions.
+    ///
+    /// Note that the patterns should be anchored (e.g. `^https://.*\.example\.com$`)
+    /// to avoid matching unintended origins.
+    pub fn regex<I>(patterns: I) -> Result<Self, regex::Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        Ok(AllowOrigin(OriginKind::Regex(RegexSet::new(patterns)?)))
+    }
+
+    /// Allow the origins for which the predicate returns `true`.
+    pub fn predicate<F>(f: F) -> Self
+    where
+        F: Fn(&HeaderValue) -> bool + Send + Sync + 'static,
+    {
+        AllowOrigin(OriginKind::Predicate(Arc::new(f)))
+    }
+
+    fn is_any(&self) -> bool {
+        matches!(self.0, OriginKind::Any)
+    }
+
+    fn is_allowed(&self, origin: &HeaderValue) -> bool {
+        match self.0 {
+            OriginKind::Any => true,
+            OriginKind::Exact(ref origins) => origins.contains(origin),
+            OriginKind::Regex(ref set) => origin.to_str().is_ok_and(|o| set.is_match(o)),
+            OriginKind::Predicate(ref f) => f(origin),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum AllowHeaders {
+    Mirror,
+    List(Vec<HeaderName>),
+}
+
+#[derive(Debug, Clone)]
+struct Config {
+    allow_origin: AllowOrigin,
+    allow_methods: Vec<Method>,
+    allow_headers: AllowHeaders,
+    expose_headers: Vec<HeaderName>,
+    allow_credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Config {
+    /// Return the value of `Access-Control-Allow-Origin` for the request.
+    fn allow_origin_value(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        if !self.allow_origin.is_allowed(origin) {
+            return None;
+        }
+        if self.allow_origin.is_any() {
+            Some(HeaderValue::from_static("*"))
+        } else {
+            Some(origin.clone())
+        }
+    }
+
+    fn append_common_headers(&self, headers: &mut HeaderMap, allow_origin: HeaderValue) {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    fn vary_origin(&self) -> bool {
+        !self.allow_origin.is_any()
+    }
+}
+
+/// A middleware that adds the CORS headers to the responses.
+///
+/// The preflight requests are answered by this middleware itself,
+/// without calling the inner `App`.
+#[derive(Debug, Clone)]
+pub struct Cors<A> {
+    app: A,
+    config: Arc<Config>,
+}
+
+impl<A> Cors<A> {
+    /// Wrap an `App` with the default configuration.
+    ///
+    /// By default, no origins are allowed.
+    pub fn new(app: A) -> Self {
+        Self {
+            app,
+            config: Arc::new(Config {
+                allow_origin: AllowOrigin::exact(None),
+                allow_methods: vec![Method::GET, Method::HEAD, Method::POST],
+                allow_headers: AllowHeaders::List(vec![]),
+                expose_headers: vec![],
+                allow_credentials: false,
+                max_age: None,
+            }),
+        }
+    }
+
+    fn config_mut(&mut self) -> &mut Config {
+        Arc::make_mut(&mut self.config)
+    }
+
+    fn check_credentials(&self) {
+        assert!(
+            !(self.config.allow_origin.is_any() && self.config.allow_credentials),
+            "the credentials cannot be allowed for any origin; \
+             use an explicit list or a predicate instead"
+        );
+    }
+
+    /// Set the origins allowed to access the resources.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `AllowOrigin::any()` is given while the credentials are allowed.
+    pub fn allow_origin(mut self, allow_origin: AllowOrigin) -> Self {
+        self.config_mut().allow_origin = allow_origin;
+        self.check_credentials();
+        self
+    }
+
+    /// Set the methods allowed in the actual requests.
+    ///
+    /// The default value is `GET`, `HEAD` and `POST`.
+    pub fn allow_methods<I>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = Method>,
+    {
+        self.config_mut().allow_methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Set the request headers allowed in the actual requests.
+    pub fn allow_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.config_mut().allow_headers = AllowHeaders::List(headers.into_iter().collect());
+        self
+    }
+
+    /// Allow any request headers requested by the preflight requests.
+    pub fn allow_any_headers(mut self) -> Self {
+        self.config_mut().allow_headers = AllowHeaders::Mirror;
+        self
+    }
+
+    /// Set the response headers exposed to the client scripts.
+    pub fn expose_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        self.config_mut().expose_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Specify whether to allow the requests with credentials.
+    ///
+    /// The default value is `false`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if enabled while any origin is allowed, since it would let every
+    /// site make requests with the credentials of the user.
+    pub fn allow_credentials(mut self, enabled: bool) -> Self {
+        self.config_mut().allow_credentials = enabled;
+        self.check_credentials();
+        self
+    }
+
+    /// Set how long the results of a preflight request can be cached.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.config_mut().max_age = Some(max_age);
+        self
+    }
+
+    fn preflight_response(&self, headers: &HeaderMap, origin: &HeaderValue) -> Response<()> {
+        let config = &*self.config;
+
+        let mut response = Response::new(());
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        let response_headers = response.headers_mut();
+        response_headers.append(
+            header::VARY,
+            HeaderValue::from_static(
+                "origin, access-control-request-method, access-control-request-headers",
+            ),
+        );
+
+        let allow_origin = match config.allow_origin_value(origin) {
+            Some(allow_origin) => allow_origin,
+            None => return response,
+        };
+
+        let method_allowed = headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+            .is_some_and(|method| config.allow_methods.contains(&method));
+        if !method_allowed {
+            return response;
+        }
+
+        let requested_headers: Vec<&str> = headers
+            .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect();
+        let allow_headers = match config.allow_headers {
+            AllowHeaders::Mirror => requested_headers.join(", "),
+            AllowHeaders::List(ref allowed) => {
+                let all_allowed = requested_headers.iter().all(|name| {
+                    allowed
+                        .iter()
+                        .any(|allowed| allowed.as_str().eq_ignore_ascii_case(name))
+                });
+                if !all_allowed {
+                    return response;
+                }
+                join(allowed.iter().map(HeaderName::as_str))
+            }
+        };
+
+        config.append_common_headers(response_headers, allow_origin);
+        if let Ok(methods) =
+            HeaderValue::from_str(&join(config.allow_methods.iter().map(Method::as_str)))
+        {
+            response_headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+        if !allow_headers.is_empty() {
+            if let Ok(allow_headers) = HeaderValue::from_str(&allow_headers) {
+                response_headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+            }
+        }
+        if let Some(max_age) = config.max_age {
+            response_headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+        }
+
+        response
+    }
+}
+
+#[async_trait]
+impl<A, E> App<E> for Cors<A>
+where
+    A: App<CorsEvents<E>> + Send + Sync,
+    E: Events + Send,
+    E::Data: Send,
+{
+    type Error = BoxError;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+
+        let origin = parts.headers.get(header::ORIGIN).cloned();
+
+        if let Some(ref origin) = origin {
+            let is_preflight = parts.method == Method::OPTIONS
+                && parts
+                    .headers
+                    .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+            if is_preflight {
+                let response = self.preflight_response(&parts.headers, origin);
+                return events
+                    .start_send_response(response, true)
+                    .await
+                    .map_err(Into::into);
+            }
+        }
+
+        let allow_origin = origin
+            .as_ref()
+            .and_then(|origin| self.config.allow_origin_value(origin));
+
+        let events = CorsEvents {
+            inner: events,
+            config: self.config.clone(),
+            has_origin: origin.is_some(),
+            allow_origin,
+        };
+
+        self.app
+            .call(Request::from_parts(parts, events))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// The `Events` passed to the inner `App` of `Cors`.
+pub struct CorsEvents<E> {
+    inner: E,
+    config: Arc<Config>,
+    has_origin: bool,
+    allow_origin: Option<HeaderValue>,
+}
+
+impl<E: fmt::Debug> fmt::Debug for CorsEvents<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CorsEvents")
+            .field("inner", &self.inner)
+            .field("allow_origin", &self.allow_origin)
+            .finish()
+    }
+}
+
+impl<E> CorsEvents<E> {
+    /// Return whether the origin of the request is allowed.
+    pub fn is_origin_allowed(&self) -> bool {
+        self.allow_origin.is_some()
+    }
+
+    /// Return a reference to the underlying `Events`.
+    pub fn get_ref(&self) -> &E {
+        &self.inner
+    }
+
+    /// Return a mutable reference to the underlying `Events`.
+    pub fn get_mut(&mut self) -> &mut E {
+        &mut self.inner
+    }
+}
+
+#[async_trait]
+impl<E> Events for CorsEvents<E>
+where
+    E: Events + Send,
+    E::Data: Send,
+{
+    type Data = E::Data;
+    type Error = E::Error;
+
+    #[inline]
+    async fn data(&mut self) -> Option<Result<Self::Data, Self::Error>> {
+        self.inner.data().await
+    }
+
+    #[inline]
+    async fn trailers(&mut self) -> Result<Option<HeaderMap>, Self::Error> {
+        self.inner.trailers().await
+    }
+
+    async fn start_send_response(
+        &mut self,
+        mut response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        if self.has_origin {
+            let headers = response.headers_mut();
+            if self.config.vary_origin() {
+                headers.append(header::VARY, HeaderValue::from_static("origin"));
+            }
+            if let Some(ref allow_origin) = self.allow_origin {
+                self.config
+                    .append_common_headers(headers, allow_origin.clone());
+                if !self.config.expose_headers.is_empty() {
+                    let expose = join(self.config.expose_headers.iter().map(HeaderName::as_str));
+                    if let Ok(expose) = HeaderValue::from_str(&expose) {
+                        headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, expose);
+                    }
+                }
+            }
+        }
+        self.inner
+            .start_send_response(response, end_of_stream)
+            .await
+    }
+
+    #[inline]
+    async fn send_data(
+        &mut self,
+        data: Self::Data,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        self.inner.send_data(data, end_of_stream).await
+    }
+
+    #[inline]
+    async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error> {
+        self.inner.send_trailers(trailers).await
+    }
+
+    fn reset(&mut self, reason: Reason) {
+        self.inner.reset(reason)
+    }
+
+    async fn cancelled(&mut self) -> Reason {
+        self.inner.cancelled().await
+    }
+
+    fn reserve_send_capacity(&mut self, capacity: usize) {
+        self.inner.reserve_send_capacity(capacity)
+    }
+
+    fn poll_send_capacity(&mut self, cx: &mut Context<'_>) -> Poll<Result<usize, Self::Error>> {
+        self.inner.poll_send_capacity(cx)
+    }
+
+    fn set_manual_release(&mut self, enabled: bool) -> bool {
+        self.inner.set_manual_release(enabled)
+    }
+
+    fn release_capacity(&mut self, len: usize) -> Result<(), Self::Error> {
+        self.inner.release_capacity(len)
+    }
+
+    fn is_push_supported(&self) -> bool {
+        self.inner.is_push_supported()
+    }
+}
+
+fn join<'a>(items: impl Iterator<Item = &'a str>) -> String {
+    items.collect::<Vec<_>>().join(", ")
+}
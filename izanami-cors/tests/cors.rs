@@ -0,0 +1,214 @@
+use async_trait::async_trait;
+use futures::executor::block_on;
+use http::{header::HeaderValue, Method, Request, Response};
+use izanami::{App, Events};
+use izanami_cors::{AllowOrigin, Cors};
+use izanami_test::MockEvents;
+use std::time::Duration;
+
+/// Respond with `200 OK` and `x-inner: called`.
+#[derive(Clone)]
+struct Inner;
+
+#[async_trait]
+impl<E> App<E> for Inner
+where
+    E: Events + Send,
+{
+    type Error = E::Error;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let mut events = request.into_body();
+        let response = Response::builder()
+            .header("x-inner", "called")
+            .body(())
+            .unwrap();
+        events.start_send_response(response, true).await
+    }
+}
+
+fn call<A>(app: &A, mut request: http::request::Builder) -> Response<()>
+where
+    A: for<'a> App<&'a mut MockEvents>,
+{
+    let mut events = MockEvents::default();
+    let request = request.body(&mut events).unwrap();
+    block_on(app.call(request)).map_err(Into::into).unwrap();
+    events.response.unwrap()
+}
+
+fn header<'a>(response: &'a Response<()>, name: &str) -> Option<&'a str> {
+    response.headers().get(name).map(|v| v.to_str().unwrap())
+}
+
+#[test]
+fn test_simple_request() {
+    let app = Cors::new(Inner)
+        .allow_origin(AllowOrigin::exact(vec![HeaderValue::from_static(
+            "https://example.com",
+        )]))
+        .allow_credentials(true)
+        .expose_headers(vec!["x-inner".parse().unwrap()]);
+
+    let mut request = Request::get("/");
+    request.header("origin", "https://example.com");
+    let response = call(&app, request);
+    assert_eq!(header(&response, "x-inner"), Some("called"));
+    assert_eq!(
+        header(&response, "access-control-allow-origin"),
+        Some("https://example.com")
+    );
+    assert_eq!(
+        header(&response, "access-control-allow-credentials"),
+        Some("true")
+    );
+    assert_eq!(
+        header(&response, "access-control-expose-headers"),
+        Some("x-inner")
+    );
+    assert_eq!(header(&response, "vary"), Some("origin"));
+
+    let mut request = Request::get("/");
+    request.header("origin", "https://evil.example.com");
+    let response = call(&app, request);
+    assert_eq!(header(&response, "x-inner"), Some("called"));
+    assert_eq!(header(&response, "access-control-allow-origin"), None);
+
+    let response = call(&app, Request::get("/"));
+    assert_eq!(header(&response, "vary"), None);
+}
+
+#[test]
+fn test_allow_origin_kinds() {
+    let any = Cors::new(Inner).allow_origin(AllowOrigin::any());
+    let regex = Cors::new(Inner)
+        .allow_origin(AllowOrigin::regex(&[r"^https://[a-z]+\.example\.com$"]).unwrap());
+    let predicate = Cors::new(Inner).allow_origin(AllowOrigin::predicate(|origin| {
+        origin.as_bytes().ends_with(b".test")
+    }));
+
+    let mut request = Request::get("/");
+    request.header("origin", "https://a.example.com");
+    assert_eq!(
+        header(&call(&any, request), "access-control-allow-origin"),
+        Some("*")
+    );
+
+    let mut request = Request::get("/");
+    request.header("origin", "https://a.example.com");
+    assert_eq!(
+        header(&call(&regex, request), "access-control-allow-origin"),
+        Some("https://a.example.com")
+    );
+
+    let mut request = Request::get("/");
+    request.header("origin", "https://a.example.com.evil");
+    assert_eq!(
+        header(&call(&regex, request), "access-control-allow-origin"),
+        None
+    );
+
+    let mut request = Request::get("/");
+    request.header("origin", "http://localhost.test");
+    assert_eq!(
+        header(&call(&predicate, request), "access-control-allow-origin"),
+        Some("http://localhost.test")
+    );
+}
+
+#[test]
+fn test_preflight() {
+    let app = Cors::new(Inner)
+        .allow_origin(AllowOrigin::any())
+        .allow_methods(vec![Method::GET, Method::PUT])
+        .allow_headers(vec!["content-type".parse().unwrap()])
+        .max_age(Duration::from_secs(600));
+
+    let mut request = Request::builder();
+    request
+        .method(Method::OPTIONS)
+        .header("origin", "https://example.com")
+        .header("access-control-request-method", "PUT")
+        .header("access-control-request-headers", "Content-Type");
+    let response = call(&app, request);
+    assert_eq!(response.status(), 204);
+    assert_eq!(header(&response, "x-inner"), None);
+    assert_eq!(header(&response, "access-control-allow-origin"), Some("*"));
+    assert_eq!(
+        header(&response, "access-control-allow-methods"),
+        Some("GET, PUT")
+    );
+    assert_eq!(
+        header(&response, "access-control-allow-headers"),
+        Some("content-type")
+    );
+    assert_eq!(header(&response, "access-control-max-age"), Some("600"));
+
+    let mut request = Request::builder();
+    request
+        .method(Method::OPTIONS)
+        .header("origin", "https://example.com")
+        .header("access-control-request-method", "DELETE");
+    let response = call(&app, request);
+    assert_eq!(header(&response, "x-inner"), None);
+    assert_eq!(header(&response, "access-control-allow-origin"), None);
+
+    let mut request = Request::builder();
+    request
+        .method(Method::OPTIONS)
+        .header("origin", "https://example.com")
+        .header("access-control-request-method", "GET")
+        .header("access-control-request-headers", "x-custom");
+    let response = call(&app, request);
+    assert_eq!(header(&response, "access-control-allow-origin"), None);
+
+    let mut request = Request::builder();
+    request.method(Method::OPTIONS);
+    let response = call(&app, request);
+    assert_eq!(header(&response, "x-inner"), Some("called"));
+}
+
+#[test]
+fn test_configure_clone() {
+    let base = Cors::new(Inner).allow_origin(AllowOrigin::any());
+    let _shared = base.clone();
+    let derived = base
+        .clone()
+        .allow_origin(AllowOrigin::exact(vec![HeaderValue::from_static(
+            "https://a.example.com",
+        )]))
+        .allow_credentials(true);
+
+    let mut request = Request::get("/");
+    request.header("origin", "https://a.example.com");
+    assert_eq!(
+        header(&call(&base, request), "access-control-allow-origin"),
+        Some("*")
+    );
+
+    let mut request = Request::get("/");
+    request.header("origin", "https://a.example.com");
+    assert_eq!(
+        header(&call(&derived, request), "access-control-allow-origin"),
+        Some("https://a.example.com")
+    );
+}
+
+#[test]
+#[should_panic(expected = "the credentials cannot be allowed for any origin")]
+fn reject_credentials_with_any_origin() {
+    let _ = Cors::new(Inner)
+        .allow_origin(AllowOrigin::any())
+        .allow_credentials(true);
+}
+
+#[test]
+#[should_panic(expected = "the credentials cannot be allowed for any origin")]
+fn reject_any_origin_with_credentials() {
+    let _ = Cors::new(Inner)
+        .allow_credentials(true)
+        .allow_origin(AllowOrigin::any());
+}
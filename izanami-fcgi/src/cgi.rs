@@ -0,0 +1,137 @@
+//! The conversion between the CGI meta-variables and the HTTP messages.
+
+use bytes::Bytes;
+use http::{
+    header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE},
+    request, Method, Request, Response, Uri, Version,
+};
+use izanami::RemoteAddr;
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    str,
+};
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Build the request head from the meta-variables sent by the Web server.
+///
+/// The remote address is taken from `REMOTE_ADDR` and `REMOTE_PORT`,
+/// or `peer_addr` if they are missing.
+pub(crate) fn request_parts(
+    params: &[(Bytes, Bytes)],
+    peer_addr: SocketAddr,
+) -> io::Result<request::Parts> {
+    let (mut parts, ()) = Request::new(()).into_parts();
+    let mut request_uri = None;
+    let mut script_name: &[u8] = b"";
+    let mut path_info: &[u8] = b"";
+    let mut query_string: &[u8] = b"";
+    let mut remote_ip = None;
+    let mut remote_port = None;
+
+    for (name, value) in params {
+        match &name[..] {
+            b"REQUEST_METHOD" => {
+                parts.method = Method::from_bytes(value)
+                    .map_err(|_| invalid_data("invalid REQUEST_METHOD"))?;
+            }
+            b"REQUEST_URI" => request_uri = Some(value),
+            b"SCRIPT_NAME" => script_name = value,
+            b"PATH_INFO" => path_info = value,
+            b"QUERY_STRING" => query_string = value,
+            b"SERVER_PROTOCOL" => {
+                parts.version = match &value[..] {
+                    b"HTTP/0.9" => Version::HTTP_09,
+                    b"HTTP/1.0" => Version::HTTP_10,
+                    b"HTTP/2" | b"HTTP/2.0" => Version::HTTP_2,
+                    _ => Version::HTTP_11,
+                };
+            }
+            b"REMOTE_ADDR" => {
+                remote_ip = str::from_utf8(value)
+                    .ok()
+                    .and_then(|s| s.parse::<IpAddr>().ok());
+            }
+            b"REMOTE_PORT" => {
+                remote_port = str::from_utf8(value)
+                    .ok()
+                    .and_then(|s| s.parse::<u16>().ok());
+            }
+            b"CONTENT_TYPE" | b"CONTENT_LENGTH" if !value.is_empty() => {
+                let name = if &name[..] == b"CONTENT_TYPE" {
+                    CONTENT_TYPE
+                } else {
+                    CONTENT_LENGTH
+                };
+                parts.headers.append(name, header_value(value)?);
+            }
+            name if name.starts_with(b"HTTP_") => {
+                let name: Vec<u8> = name[5..]
+                    .iter()
+                    .map(|&b| match b {
+                        b'_' => b'-',
+                        b => b.to_ascii_lowercase(),
+                    })
+                    .collect();
+                let name = HeaderName::from_bytes(&name)
+                    .map_err(|_| invalid_data("invalid header name"))?;
+                parts.headers.append(name, header_value(value)?);
+            }
+            _ => {}
+        }
+    }
+
+    parts.uri = match request_uri {
+        Some(uri) => Uri::from_shared(uri.clone()),
+        None => {
+            let mut uri = Vec::with_capacity(script_name.len() + path_info.len() + 1);
+            uri.extend_from_slice(script_name);
+            uri.extend_from_slice(path_info);
+            if uri.is_empty() {
+                uri.push(b'/');
+            }
+            if !query_string.is_empty() {
+                uri.push(b'?');
+                uri.extend_from_slice(query_string);
+            }
+            Uri::from_shared(uri.into())
+        }
+    }
+    .map_err(|_| invalid_data("invalid request URI"))?;
+
+    let remote_addr = match remote_ip {
+        Some(ip) => SocketAddr::new(ip, remote_port.unwrap_or(0)),
+        None => peer_addr,
+    };
+    parts.extensions.insert(RemoteAddr(remote_addr));
+
+    Ok(parts)
+}
+
+fn header_value(value: &Bytes) -> io::Result<HeaderValue> {
+    HeaderValue::from_shared(value.clone()).map_err(|_| invalid_data("invalid header value"))
+}
+
+/// Encode the response head in the form of the CGI response.
+pub(crate) fn encode_response_head(response: &Response<()>) -> Bytes {
+    let status = response.status();
+    let mut head = Vec::with_capacity(1024);
+    head.extend_from_slice(b"Status: ");
+    head.extend_from_slice(status.as_str().as_bytes());
+    if let Some(reason) = status.canonical_reason() {
+        head.push(b' ');
+        head.extend_from_slice(reason.as_bytes());
+    }
+    head.extend_from_slice(b"\r\n");
+    for (name, value) in response.headers() {
+        head.extend_from_slice(name.as_str().as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+    head.into()
+}
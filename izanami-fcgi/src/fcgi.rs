@@ -0,0 +1,509 @@
+//! The responder of FastCGI, which serves the requests multiplexed on a connection.
+
+use crate::{cgi, run_app, Events, Transport};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{
+    future::{self, Either},
+    pin_mut,
+};
+use http::{request, Response, StatusCode};
+use izanami::{state::ConnectionState, App, Reason};
+use std::{collections::HashMap, io, net::SocketAddr};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+};
+use tracing::Instrument;
+
+const VERSION_1: u8 = 1;
+
+const BEGIN_REQUEST: u8 = 1;
+const ABORT_REQUEST: u8 = 2;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const DATA: u8 = 8;
+const GET_VALUES: u8 = 9;
+const GET_VALUES_RESULT: u8 = 10;
+const UNKNOWN_TYPE: u8 = 11;
+
+const RESPONDER: u16 = 1;
+const KEEP_CONN: u8 = 1;
+
+const REQUEST_COMPLETE: u8 = 0;
+const OVERLOADED: u8 = 2;
+const UNKNOWN_ROLE: u8 = 3;
+
+const MAX_CONTENT_LEN: usize = 0xffff;
+const MAX_PARAMS_LEN: usize = 64 * 1024;
+
+/// The number of the records buffered for the writer and for each request body.
+///
+/// FastCGI has no flow control, so the request body not read by the application
+/// eventually stops reading the records of the other requests on the connection.
+const BUFFER_LEN: usize = 16;
+
+/// The output to the Web server.
+#[derive(Debug)]
+enum Output {
+    Records(Bytes),
+    /// Close the connection after the request without `FCGI_KEEP_CONN`.
+    Close,
+}
+
+#[derive(Debug)]
+struct Record {
+    kind: u8,
+    id: u16,
+    content: Bytes,
+}
+
+/// Read a record, or return `None` at the end of the stream.
+async fn read_record<R>(reader: &mut R) -> io::Result<Option<Record>>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut header = [0; 8];
+    if reader.read(&mut header[..1]).await? == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut header[1..]).await?;
+    if header[0] != VERSION_1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unsupported FastCGI version",
+        ));
+    }
+    let content_len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let padding_len = header[6] as usize;
+    let mut content = vec![0; content_len + padding_len];
+    reader.read_exact(&mut content).await?;
+    content.truncate(content_len);
+    Ok(Some(Record {
+        kind: header[1],
+        id: u16::from_be_bytes([header[2], header[3]]),
+        content: content.into(),
+    }))
+}
+
+fn encode_record(dst: &mut BytesMut, kind: u8, id: u16, content: &[u8]) {
+    debug_assert!(content.len() <= MAX_CONTENT_LEN);
+    dst.reserve(8 + content.len());
+    dst.put_u8(VERSION_1);
+    dst.put_u8(kind);
+    dst.put_u16_be(id);
+    dst.put_u16_be(content.len() as u16);
+    dst.put_u8(0);
+    dst.put_u8(0);
+    dst.put_slice(content);
+}
+
+/// Encode the stream of `content`, split into the records of the maximum length.
+fn encode_stream(dst: &mut BytesMut, kind: u8, id: u16, content: &[u8]) {
+    for chunk in content.chunks(MAX_CONTENT_LEN) {
+        encode_record(dst, kind, id, chunk);
+    }
+}
+
+fn encode_end_request(dst: &mut BytesMut, id: u16, app_status: u32, protocol_status: u8) {
+    let mut body = [0; 8];
+    body[..4].copy_from_slice(&app_status.to_be_bytes());
+    body[4] = protocol_status;
+    encode_record(dst, END_REQUEST, id, &body);
+}
+
+fn decode_length(buf: &mut &[u8]) -> Option<usize> {
+    match buf.first()? {
+        &b if b & 0x80 == 0 => {
+            *buf = &buf[1..];
+            Some(b as usize)
+        }
+        _ if buf.len() >= 4 => {
+            let len = u32::from_be_bytes([buf[0] & 0x7f, buf[1], buf[2], buf[3]]) as usize;
+            *buf = &buf[4..];
+            Some(len)
+        }
+        _ => None,
+    }
+}
+
+/// Decode the name-value pairs in `FCGI_PARAMS` and `FCGI_GET_VALUES`.
+fn decode_pairs(content: &Bytes) -> io::Result<Vec<(Bytes, Bytes)>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed name-value pair");
+    let mut pairs = vec![];
+    let mut buf = &content[..];
+    while !buf.is_empty() {
+        let name_len = decode_length(&mut buf).ok_or_else(invalid)?;
+        let value_len = decode_length(&mut buf).ok_or_else(invalid)?;
+        if buf.len() < name_len + value_len {
+            return Err(invalid());
+        }
+        let start = content.len() - buf.len();
+        let name = content.slice(start, start + name_len);
+        let value = content.slice(start + name_len, start + name_len + value_len);
+        pairs.push((name, value));
+        buf = &buf[name_len + value_len..];
+    }
+    Ok(pairs)
+}
+
+fn encode_pair(dst: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    for &len in &[name.len(), value.len()] {
+        if len < 0x80 {
+            dst.push(len as u8);
+        } else {
+            dst.extend_from_slice(&(len as u32 | 0x8000_0000).to_be_bytes());
+        }
+    }
+    dst.extend_from_slice(name);
+    dst.extend_from_slice(value);
+}
+
+/// The state of a request seen by the reader of the connection.
+#[derive(Debug)]
+struct Slot {
+    keep_conn: bool,
+    /// The parameters received so far, or `None` after the request has started.
+    params: Option<BytesMut>,
+    stdin: Option<mpsc::Sender<Bytes>>,
+    abort: Option<oneshot::Sender<()>>,
+}
+
+pub(crate) async fn serve_connection<T>(
+    io: TcpStream,
+    app: T,
+    peer_addr: SocketAddr,
+    state: ConnectionState,
+) -> io::Result<()>
+where
+    T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
+{
+    let (reader, writer) = tokio::io::split(io);
+    let (output, rx) = mpsc::channel(BUFFER_LEN);
+    let reading = read_requests(reader, output, app, peer_addr, state);
+    let writing = write_output(writer, rx);
+    pin_mut!(reading, writing);
+    match future::select(reading, writing).await {
+        // Finish the responses in flight after the Web server has stopped sending.
+        Either::Left((result, writing)) => {
+            result?;
+            writing.await
+        }
+        Either::Right((result, _)) => result,
+    }
+}
+
+async fn write_output<W>(mut writer: W, mut rx: mpsc::Receiver<Output>) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    while let Some(output) = rx.recv().await {
+        match output {
+            Output::Records(records) => writer.write_all(&records).await?,
+            Output::Close => break,
+        }
+    }
+    writer.flush().await
+}
+
+async fn read_requests<R, T>(
+    mut reader: R,
+    mut output: mpsc::Sender<Output>,
+    app: T,
+    peer_addr: SocketAddr,
+    state: ConnectionState,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
+{
+    let mut slots = HashMap::new();
+    while let Some(record) = read_record(&mut reader).await? {
+        match record.kind {
+            BEGIN_REQUEST => {
+                let body = &record.content[..];
+                if body.len() < 8 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "malformed FCGI_BEGIN_REQUEST",
+                    ));
+                }
+                if u16::from_be_bytes([body[0], body[1]]) != RESPONDER {
+                    let mut records = BytesMut::new();
+                    encode_end_request(&mut records, record.id, 0, UNKNOWN_ROLE);
+                    send_output(&mut output, Output::Records(records.freeze())).await?;
+                    continue;
+                }
+                slots.insert(
+                    record.id,
+                    Slot {
+                        keep_conn: body[2] & KEEP_CONN != 0,
+                        params: Some(BytesMut::new()),
+                        stdin: None,
+                        abort: None,
+                    },
+                );
+            }
+
+            PARAMS => {
+                let slot = match slots.get_mut(&record.id) {
+                    Some(slot) => slot,
+                    None => continue,
+                };
+                let params = match slot.params {
+                    Some(ref mut params) => params,
+                    None => continue,
+                };
+                if !record.content.is_empty() {
+                    if params.len() + record.content.len() > MAX_PARAMS_LEN {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "too large FCGI_PARAMS",
+                        ));
+                    }
+                    params.extend_from_slice(&record.content);
+                    continue;
+                }
+
+                let params = slot.params.take().unwrap().freeze();
+                let (stdin_tx, stdin) = mpsc::channel(BUFFER_LEN);
+                let (abort_tx, abort) = oneshot::channel();
+                slot.stdin = Some(stdin_tx);
+                slot.abort = Some(abort_tx);
+                let exchange = Exchange {
+                    id: record.id,
+                    keep_conn: slot.keep_conn,
+                    stdin,
+                    output: output.clone(),
+                    abort: Some(abort),
+                    head_sent: false,
+                    end: End::Open,
+                };
+                let parts = decode_pairs(&params)
+                    .and_then(|params| cgi::request_parts(&params, peer_addr))
+                    .map(|mut parts| {
+                        state.inject(&mut parts.extensions);
+                        parts
+                    });
+                let span = match parts {
+                    Ok(ref parts) => tracing::info_span!(
+                        "request",
+                        id = record.id,
+                        method = %parts.method,
+                        uri = %parts.uri,
+                    ),
+                    Err(..) => tracing::info_span!("request", id = record.id),
+                };
+                tokio::spawn(handle_request(app.clone(), parts, exchange).instrument(span));
+            }
+
+            STDIN => {
+                if let Some(slot) = slots.get_mut(&record.id) {
+                    if record.content.is_empty() {
+                        slot.stdin.take();
+                    } else if let Some(ref mut stdin) = slot.stdin {
+                        if stdin.send(record.content).await.is_err() {
+                            // The application has finished without reading the body.
+                            slot.stdin.take();
+                        }
+                    }
+                }
+            }
+
+            ABORT_REQUEST => {
+                if let Some(slot) = slots.get_mut(&record.id) {
+                    slot.stdin.take();
+                    if let Some(abort) = slot.abort.take() {
+                        let _ = abort.send(());
+                    }
+                }
+            }
+
+            GET_VALUES => {
+                let mut values = vec![];
+                for (name, _) in decode_pairs(&record.content)? {
+                    if &name[..] == b"FCGI_MPXS_CONNS" {
+                        encode_pair(&mut values, &name, b"1");
+                    }
+                }
+                let mut records = BytesMut::new();
+                encode_record(&mut records, GET_VALUES_RESULT, 0, &values);
+                send_output(&mut output, Output::Records(records.freeze())).await?;
+            }
+
+            // The responder does not use the filter data.
+            DATA => {}
+
+            kind => {
+                let mut records = BytesMut::new();
+                encode_record(&mut records, UNKNOWN_TYPE, 0, &[kind, 0, 0, 0, 0, 0, 0, 0]);
+                send_output(&mut output, Output::Records(records.freeze())).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn send_output(output: &mut mpsc::Sender<Output>, item: Output) -> io::Result<()> {
+    output
+        .send(item)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the connection has been closed"))
+}
+
+async fn handle_request<T>(app: T, parts: io::Result<request::Parts>, mut exchange: Exchange)
+where
+    T: for<'a> App<Events<'a>>,
+{
+    let parts = match parts {
+        Ok(parts) => parts,
+        Err(err) => {
+            tracing::debug!("invalid request: {}", err);
+            exchange.send_status(StatusCode::BAD_REQUEST).await;
+            return;
+        }
+    };
+
+    let panicked = run_app(&app, parts, Transport::FastCgi(&mut exchange)).await;
+
+    if let End::Open = exchange.end {
+        if panicked && !exchange.head_sent {
+            exchange
+                .send_status(StatusCode::INTERNAL_SERVER_ERROR)
+                .await;
+            return;
+        }
+        // The response has not been completed by the application.
+        exchange.end = End::Pending(1, REQUEST_COMPLETE);
+    }
+    if let End::Pending(app_status, protocol_status) = exchange.end {
+        let _ = exchange.end_request(app_status, protocol_status).await;
+    }
+    tracing::debug!("request completed");
+}
+
+/// The state of `FCGI_END_REQUEST` of a request.
+#[derive(Debug, Copy, Clone)]
+enum End {
+    Open,
+    /// The request is going to be ended with the application and protocol status.
+    Pending(u32, u8),
+    Sent,
+}
+
+#[derive(Debug)]
+pub(crate) struct Exchange {
+    id: u16,
+    keep_conn: bool,
+    stdin: mpsc::Receiver<Bytes>,
+    output: mpsc::Sender<Output>,
+    /// The notification of `FCGI_ABORT_REQUEST`, or `None` after it has been received.
+    abort: Option<oneshot::Receiver<()>>,
+    head_sent: bool,
+    end: End,
+}
+
+impl Exchange {
+    pub(crate) async fn data(&mut self) -> Option<io::Result<Bytes>> {
+        self.stdin.recv().await.map(Ok)
+    }
+
+    fn ensure_open(&self) -> io::Result<()> {
+        match self.end {
+            End::Open => Ok(()),
+            _ => Err(io::Error::other("the request has already been ended")),
+        }
+    }
+
+    pub(crate) async fn start_send_response(
+        &mut self,
+        head: Bytes,
+        end_of_stream: bool,
+    ) -> io::Result<()> {
+        self.ensure_open()?;
+        self.head_sent = true;
+        self.send_stdout(&head, end_of_stream).await
+    }
+
+    pub(crate) async fn send_data(&mut self, data: Bytes, end_of_stream: bool) -> io::Result<()> {
+        self.ensure_open()?;
+        self.send_stdout(&data, end_of_stream).await
+    }
+
+    async fn send_stdout(&mut self, data: &[u8], end_of_stream: bool) -> io::Result<()> {
+        let mut records = BytesMut::new();
+        encode_stream(&mut records, STDOUT, self.id, data);
+        if end_of_stream {
+            self.end = End::Pending(0, REQUEST_COMPLETE);
+            encode_record(&mut records, STDOUT, self.id, &[]);
+            encode_end_request(&mut records, self.id, 0, REQUEST_COMPLETE);
+        }
+        if !records.is_empty() {
+            send_output(&mut self.output, Output::Records(records.freeze())).await?;
+        }
+        if end_of_stream {
+            self.end = End::Sent;
+            if !self.keep_conn {
+                send_output(&mut self.output, Output::Close).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn end_request(&mut self, app_status: u32, protocol_status: u8) -> io::Result<()> {
+        let mut records = BytesMut::new();
+        encode_record(&mut records, STDOUT, self.id, &[]);
+        encode_end_request(&mut records, self.id, app_status, protocol_status);
+        self.end = End::Sent;
+        send_output(&mut self.output, Output::Records(records.freeze())).await?;
+        if !self.keep_conn {
+            send_output(&mut self.output, Output::Close).await?;
+        }
+        Ok(())
+    }
+
+    /// Send the response without the body in place of the application.
+    async fn send_status(&mut self, status: StatusCode) {
+        let mut response = Response::new(());
+        *response.status_mut() = status;
+        let _ = self
+            .start_send_response(cgi::encode_response_head(&response), true)
+            .await;
+    }
+
+    pub(crate) fn reset(&mut self, reason: Reason) {
+        if let End::Open = self.end {
+            let protocol_status = if reason == Reason::REFUSED_STREAM {
+                OVERLOADED
+            } else {
+                REQUEST_COMPLETE
+            };
+            self.end = End::Pending(u32::from(reason), protocol_status);
+            // The record is sent after the application returns if the writer is busy.
+            let mut records = BytesMut::new();
+            encode_record(&mut records, STDOUT, self.id, &[]);
+            encode_end_request(&mut records, self.id, u32::from(reason), protocol_status);
+            if self
+                .output
+                .try_send(Output::Records(records.freeze()))
+                .is_ok()
+            {
+                self.end = End::Sent;
+                if !self.keep_conn {
+                    let _ = self.output.try_send(Output::Close);
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn cancelled(&mut self) -> Reason {
+        if let Some(ref mut abort) = self.abort {
+            // The connection closed by the Web server drops the sender.
+            let _ = abort.await;
+            self.abort = None;
+        }
+        Reason::CANCEL
+    }
+}
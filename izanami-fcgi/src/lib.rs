@@ -0,0 +1,309 @@
+//! FastCGI and SCGI backend of izanami, used behind the Web servers
+//! that forward the requests over these protocols.
+
+mod cgi;
+mod fcgi;
+mod scgi;
+
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::FutureExt;
+use http::{request, HeaderMap, Request, Response};
+use izanami::{state::StateMap, App, Reason, RemoteAddr};
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    panic::AssertUnwindSafe,
+};
+use tokio::net::TcpListener;
+use tracing::Instrument;
+
+/// The protocol spoken by the Web server in front.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    /// FastCGI, whose requests may be multiplexed on a connection.
+    FastCgi,
+    /// SCGI, which carries a request per connection.
+    Scgi,
+}
+
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    protocol: Protocol,
+    states: StateMap,
+}
+
+impl Server {
+    pub async fn bind<A>(addr: A) -> io::Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let addr = addr.to_socket_addrs()?.next().unwrap();
+        let listener = TcpListener::bind(&addr).await?;
+        Ok(Self {
+            listener,
+            protocol: Protocol::FastCgi,
+            states: StateMap::new(),
+        })
+    }
+
+    /// Return the local address that this server is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Set the protocol spoken on the accepted connections.
+    ///
+    /// The default is `Protocol::FastCgi`.
+    pub fn protocol(mut self, protocol: Protocol) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Insert `value` into the extensions of each request as `izanami::state::State<T>`.
+    pub fn state<T>(mut self, value: T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.states.insert(value);
+        self
+    }
+
+    /// Build a value with `f` for each accepted connection, and insert it into
+    /// the extensions of the requests on that connection as `izanami::state::State<T>`.
+    ///
+    /// The connections are those from the Web server in front, so `f` receives
+    /// the address of the Web server rather than that of the client, and the
+    /// value is shared by the requests of different clients multiplexed on
+    /// the connection.
+    pub fn connection_state<F, T>(mut self, f: F) -> Self
+    where
+        F: Fn(&RemoteAddr) -> T + Send + Sync + 'static,
+        T: Send + Sync + 'static,
+    {
+        self.states.insert_per_connection(f);
+        self
+    }
+
+    pub async fn serve<T>(self, app: T) -> io::Result<()>
+    where
+        T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
+    {
+        let mut listener = self.listener;
+        loop {
+            let (socket, peer_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::error!("accept error: {}", err);
+                    continue;
+                }
+            };
+            let protocol = self.protocol;
+            let states = self.states.clone();
+            let app = app.clone();
+            let span = tracing::info_span!("connection", peer = %peer_addr);
+            tokio::spawn(
+                async move {
+                    let state = states.connect(&RemoteAddr(peer_addr));
+                    let result = match protocol {
+                        Protocol::FastCgi => {
+                            fcgi::serve_connection(socket, app, peer_addr, state).await
+                        }
+                        Protocol::Scgi => {
+                            scgi::serve_connection(socket, app, peer_addr, state).await
+                        }
+                    };
+                    match result {
+                        Ok(()) => tracing::debug!("connection closed"),
+                        Err(err) => tracing::debug!("connection error: {}", err),
+                    }
+                }
+                .instrument(span),
+            );
+        }
+    }
+}
+
+/// Call the application and return whether it panicked.
+async fn run_app<T>(app: &T, parts: request::Parts, transport: Transport<'_>) -> bool
+where
+    T: for<'a> App<Events<'a>>,
+{
+    let request = Request::from_parts(parts, Events { transport });
+    match AssertUnwindSafe(app.call(request)).catch_unwind().await {
+        Ok(Ok(())) => false,
+        Ok(Err(err)) => {
+            let err = err.into();
+            tracing::error!("app error: {}", err);
+            false
+        }
+        Err(..) => {
+            tracing::error!("app panicked");
+            true
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Events<'a> {
+    transport: Transport<'a>,
+}
+
+/// The connection that carries the request.
+#[derive(Debug)]
+enum Transport<'a> {
+    FastCgi(&'a mut fcgi::Exchange),
+    Scgi(&'a mut scgi::Exchange),
+}
+
+impl Events<'_> {
+    pub async fn data(&mut self) -> Option<Result<Data, io::Error>> {
+        let data = match self.transport {
+            Transport::FastCgi(ref mut exchange) => exchange.data().await,
+            Transport::Scgi(ref mut exchange) => exchange.data().await,
+        };
+        data.map(|result| result.map(Data))
+    }
+
+    /// Receive the trailers of the request.
+    ///
+    /// Neither FastCGI nor SCGI conveys the trailers, so this always returns `None`.
+    pub async fn trailers(&mut self) -> Result<Option<HeaderMap>, io::Error> {
+        Ok(None)
+    }
+
+    pub async fn start_send_response(
+        &mut self,
+        response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), io::Error> {
+        let head = cgi::encode_response_head(&response);
+        match self.transport {
+            Transport::FastCgi(ref mut exchange) => {
+                exchange.start_send_response(head, end_of_stream).await
+            }
+            Transport::Scgi(ref mut exchange) => {
+                exchange.start_send_response(head, end_of_stream).await
+            }
+        }
+    }
+
+    pub async fn send_data<T>(&mut self, data: T, end_of_stream: bool) -> Result<(), io::Error>
+    where
+        T: Into<Data>,
+    {
+        let data = data.into().0;
+        match self.transport {
+            Transport::FastCgi(ref mut exchange) => exchange.send_data(data, end_of_stream).await,
+            Transport::Scgi(ref mut exchange) => exchange.send_data(data, end_of_stream).await,
+        }
+    }
+
+    /// End the response.
+    ///
+    /// The CGI response cannot carry the trailers, so they are discarded.
+    pub async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), io::Error> {
+        drop(trailers);
+        self.send_data(Bytes::new(), true).await
+    }
+
+    /// Abort the request.
+    ///
+    /// On FastCGI, the request is ended with `reason` as the application status,
+    /// leaving the other requests on the connection intact. On SCGI, the
+    /// connection is closed.
+    pub fn reset(&mut self, reason: Reason) {
+        match self.transport {
+            Transport::FastCgi(ref mut exchange) => exchange.reset(reason),
+            Transport::Scgi(ref mut exchange) => exchange.reset(),
+        }
+    }
+
+    /// Wait for the Web server to abort the request.
+    ///
+    /// On FastCGI, it is notified by `FCGI_ABORT_REQUEST` or by closing the connection.
+    /// On SCGI, only the connection closed by the Web server is detected.
+    pub async fn cancelled(&mut self) -> Reason {
+        match self.transport {
+            Transport::FastCgi(ref mut exchange) => exchange.cancelled().await,
+            Transport::Scgi(ref mut exchange) => exchange.cancelled().await,
+        }
+    }
+}
+
+#[async_trait]
+#[allow(clippy::needless_lifetimes)]
+impl<'a> izanami::Events for Events<'a> {
+    type Data = Data;
+    type Error = io::Error;
+
+    #[inline]
+    async fn data(&mut self) -> Option<Result<Self::Data, Self::Error>> {
+        self.data().await
+    }
+
+    #[inline]
+    async fn trailers(&mut self) -> Result<Option<HeaderMap>, Self::Error> {
+        self.trailers().await
+    }
+
+    #[inline]
+    async fn start_send_response(
+        &mut self,
+        response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        self.start_send_response(response, end_of_stream).await
+    }
+
+    #[inline]
+    async fn send_data(
+        &mut self,
+        data: Self::Data,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        self.send_data(data, end_of_stream).await
+    }
+
+    #[inline]
+    async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error> {
+        self.send_trailers(trailers).await
+    }
+
+    #[inline]
+    fn reset(&mut self, reason: Reason) {
+        self.reset(reason)
+    }
+
+    #[inline]
+    async fn cancelled(&mut self) -> Reason {
+        self.cancelled().await
+    }
+}
+
+#[derive(Debug)]
+pub struct Data(Bytes);
+
+impl<T: Into<Bytes>> From<T> for Data {
+    fn from(bytes: T) -> Self {
+        Self(bytes.into())
+    }
+}
+
+impl Buf for Data {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    fn bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    #[inline]
+    fn advance(&mut self, amt: usize) {
+        self.0.advance(amt);
+    }
+}
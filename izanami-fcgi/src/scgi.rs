@@ -0,0 +1,229 @@
+//! The SCGI server, which serves a request per connection.
+
+use crate::{cgi, run_app, Events, Transport};
+use bytes::{Bytes, BytesMut};
+use futures::future;
+use http::{Response, StatusCode};
+use izanami::{state::ConnectionState, App, Reason};
+use std::{
+    io,
+    net::{Shutdown, SocketAddr},
+    str,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::Instrument;
+
+const MAX_HEADERS_LEN: usize = 64 * 1024;
+const READ_BUF_LEN: usize = 8 * 1024;
+/// The maximum length of the request body buffered while waiting for the cancellation.
+const MAX_DRAIN_LEN: usize = 64 * 1024;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) async fn serve_connection<T>(
+    mut io: TcpStream,
+    app: T,
+    peer_addr: SocketAddr,
+    state: ConnectionState,
+) -> io::Result<()>
+where
+    T: for<'a> App<Events<'a>>,
+{
+    let mut buf = BytesMut::new();
+    let headers = read_netstring(&mut io, &mut buf).await?;
+    let headers = decode_headers(&headers)?;
+
+    // The first header must be CONTENT_LENGTH, and SCGI must be 1.
+    let content_length = match headers.first() {
+        Some((name, value)) if &name[..] == b"CONTENT_LENGTH" => str::from_utf8(value)
+            .ok()
+            .and_then(|s| s.parse::<usize>().ok())
+            .ok_or_else(|| invalid_data("invalid CONTENT_LENGTH"))?,
+        _ => return Err(invalid_data("missing CONTENT_LENGTH")),
+    };
+    if !headers
+        .iter()
+        .any(|(name, value)| &name[..] == b"SCGI" && &value[..] == b"1")
+    {
+        return Err(invalid_data("missing SCGI header"));
+    }
+
+    let mut exchange = Exchange {
+        io,
+        buf,
+        remaining: content_length,
+        head_sent: false,
+        ended: false,
+    };
+    let mut parts = match cgi::request_parts(&headers, peer_addr) {
+        Ok(parts) => parts,
+        Err(err) => {
+            exchange.send_status(StatusCode::BAD_REQUEST).await?;
+            return Err(err);
+        }
+    };
+
+    state.inject(&mut parts.extensions);
+
+    let span = tracing::info_span!("request", method = %parts.method, uri = %parts.uri);
+    async {
+        let panicked = run_app(&app, parts, Transport::Scgi(&mut exchange)).await;
+        if panicked && !exchange.head_sent && !exchange.ended {
+            let _ = exchange
+                .send_status(StatusCode::INTERNAL_SERVER_ERROR)
+                .await;
+        }
+        tracing::debug!("request completed");
+    }
+    .instrument(span)
+    .await;
+
+    Ok(())
+}
+
+/// Read the netstring of the headers, leaving the rest of bytes in `buf`.
+async fn read_netstring(io: &mut TcpStream, buf: &mut BytesMut) -> io::Result<Bytes> {
+    loop {
+        if let Some(colon) = buf.iter().position(|&b| b == b':') {
+            let len = str::from_utf8(&buf[..colon])
+                .ok()
+                .and_then(|s| s.parse::<usize>().ok())
+                .filter(|&len| len <= MAX_HEADERS_LEN)
+                .ok_or_else(|| invalid_data("invalid netstring length"))?;
+            while buf.len() < colon + len + 2 {
+                fill_buf(io, buf).await?;
+            }
+            buf.advance(colon + 1);
+            let headers = buf.split_to(len).freeze();
+            if buf.split_to(1)[..] != b","[..] {
+                return Err(invalid_data("missing netstring terminator"));
+            }
+            return Ok(headers);
+        }
+        if buf.len() > 10 {
+            return Err(invalid_data("invalid netstring length"));
+        }
+        fill_buf(io, buf).await?;
+    }
+}
+
+async fn fill_buf(io: &mut TcpStream, buf: &mut BytesMut) -> io::Result<()> {
+    let mut chunk = [0; READ_BUF_LEN];
+    let n = io.read(&mut chunk).await?;
+    if n == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    buf.extend_from_slice(&chunk[..n]);
+    Ok(())
+}
+
+/// Decode the NUL-terminated names and values of the headers.
+fn decode_headers(headers: &Bytes) -> io::Result<Vec<(Bytes, Bytes)>> {
+    let mut fields = headers.split(|&b| b == 0);
+    let mut pairs = vec![];
+    let mut start = 0;
+    let mut field = |len: usize| {
+        let field = headers.slice(start, start + len);
+        start += len + 1;
+        field
+    };
+    loop {
+        let name = match fields.next() {
+            Some(name) if !name.is_empty() => field(name.len()),
+            // The headers end with NUL, which leaves the empty field at the end.
+            Some(..) if fields.next().is_none() => return Ok(pairs),
+            _ => return Err(invalid_data("malformed SCGI headers")),
+        };
+        let value = match fields.next() {
+            Some(value) => field(value.len()),
+            None => return Err(invalid_data("malformed SCGI headers")),
+        };
+        pairs.push((name, value));
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Exchange {
+    io: TcpStream,
+    buf: BytesMut,
+    /// The length of the request body not read yet.
+    remaining: usize,
+    head_sent: bool,
+    ended: bool,
+}
+
+impl Exchange {
+    pub(crate) async fn data(&mut self) -> Option<io::Result<Bytes>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        if self.buf.is_empty() {
+            if let Err(err) = fill_buf(&mut self.io, &mut self.buf).await {
+                return Some(Err(err));
+            }
+        }
+        let len = std::cmp::min(self.remaining, self.buf.len());
+        self.remaining -= len;
+        Some(Ok(self.buf.split_to(len).freeze()))
+    }
+
+    fn ensure_open(&self) -> io::Result<()> {
+        if self.ended {
+            return Err(io::Error::other("the response has already been ended"));
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn start_send_response(
+        &mut self,
+        head: Bytes,
+        end_of_stream: bool,
+    ) -> io::Result<()> {
+        self.ensure_open()?;
+        self.head_sent = true;
+        self.send_data(head, end_of_stream).await
+    }
+
+    pub(crate) async fn send_data(&mut self, data: Bytes, end_of_stream: bool) -> io::Result<()> {
+        self.ensure_open()?;
+        self.io.write_all(&data).await?;
+        if end_of_stream {
+            self.ended = true;
+            self.io.flush().await?;
+            self.io.shutdown(Shutdown::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Send the response without the body in place of the application.
+    async fn send_status(&mut self, status: StatusCode) -> io::Result<()> {
+        let mut response = Response::new(());
+        *response.status_mut() = status;
+        self.start_send_response(cgi::encode_response_head(&response), true)
+            .await
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.ended = true;
+        let _ = self.io.shutdown(Shutdown::Both);
+    }
+
+    /// Wait for the Web server to close the connection.
+    ///
+    /// The request body read meanwhile is kept in the buffer for `data`.
+    pub(crate) async fn cancelled(&mut self) -> Reason {
+        loop {
+            if self.buf.len() > MAX_DRAIN_LEN {
+                return future::pending().await;
+            }
+            if fill_buf(&mut self.io, &mut self.buf).await.is_err() {
+                return Reason::CANCEL;
+            }
+        }
+    }
+}
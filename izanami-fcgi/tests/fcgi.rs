@@ -0,0 +1,259 @@
+mod support;
+
+use izanami_fcgi::Protocol;
+use std::collections::HashMap;
+use support::{run, start_server};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+const BEGIN_REQUEST: u8 = 1;
+const ABORT_REQUEST: u8 = 2;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const GET_VALUES: u8 = 9;
+const GET_VALUES_RESULT: u8 = 10;
+
+/// A hand-written encoder of the records sent by the Web server.
+#[derive(Default)]
+struct Records(Vec<u8>);
+
+impl Records {
+    fn record(&mut self, kind: u8, id: u16, content: &[u8]) -> &mut Self {
+        // Add some padding to check that it is skipped.
+        let padding = (8 - content.len() % 8) % 8;
+        self.0.extend_from_slice(&[1, kind]);
+        self.0.extend_from_slice(&id.to_be_bytes());
+        self.0
+            .extend_from_slice(&(content.len() as u16).to_be_bytes());
+        self.0.extend_from_slice(&[padding as u8, 0]);
+        self.0.extend_from_slice(content);
+        self.0.resize(self.0.len() + padding, 0);
+        self
+    }
+
+    fn begin(&mut self, id: u16, keep_conn: bool) -> &mut Self {
+        self.record(BEGIN_REQUEST, id, &[0, 1, keep_conn as u8, 0, 0, 0, 0, 0])
+    }
+
+    fn params(&mut self, id: u16, params: &[(&str, &str)]) -> &mut Self {
+        let mut content = vec![];
+        for (name, value) in params {
+            encode_pair(&mut content, name.as_bytes(), value.as_bytes());
+        }
+        self.record(PARAMS, id, &content).record(PARAMS, id, &[])
+    }
+
+    fn stdin(&mut self, id: u16, data: &str) -> &mut Self {
+        self.record(STDIN, id, data.as_bytes())
+    }
+}
+
+fn encode_pair(dst: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    for &len in &[name.len(), value.len()] {
+        if len < 0x80 {
+            dst.push(len as u8);
+        } else {
+            dst.extend_from_slice(&(len as u32 | 0x8000_0000).to_be_bytes());
+        }
+    }
+    dst.extend_from_slice(name);
+    dst.extend_from_slice(value);
+}
+
+/// Read a record sent by the application, or return `None` at the end of the stream.
+async fn read_record(stream: &mut TcpStream) -> Option<(u8, u16, Vec<u8>)> {
+    let mut header = [0; 8];
+    if stream.read(&mut header[..1]).await.unwrap() == 0 {
+        return None;
+    }
+    stream.read_exact(&mut header[1..]).await.unwrap();
+    assert_eq!(header[0], 1);
+    let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let mut content = vec![0; len + header[6] as usize];
+    stream.read_exact(&mut content).await.unwrap();
+    content.truncate(len);
+    Some((
+        header[1],
+        u16::from_be_bytes([header[2], header[3]]),
+        content,
+    ))
+}
+
+/// Read the records until the requests of `ids` are ended, and return their output.
+async fn read_responses(stream: &mut TcpStream, ids: &[u16]) -> HashMap<u16, String> {
+    let mut stdout = HashMap::new();
+    let mut ended = vec![];
+    while ended.len() < ids.len() {
+        let (kind, id, content) = read_record(stream).await.unwrap();
+        assert!(ids.contains(&id), "unexpected request id {}", id);
+        match kind {
+            STDOUT => stdout
+                .entry(id)
+                .or_insert_with(String::new)
+                .push_str(std::str::from_utf8(&content).unwrap()),
+            END_REQUEST => {
+                assert_eq!(content[4], 0, "protocol status");
+                ended.push(id);
+            }
+            kind => panic!("unexpected record type {}", kind),
+        }
+    }
+    stdout
+}
+
+const PARAMS_ONE: &[(&str, &str)] = &[
+    ("REQUEST_METHOD", "POST"),
+    ("REQUEST_URI", "/one?x=1"),
+    ("SERVER_PROTOCOL", "HTTP/1.1"),
+    ("REMOTE_ADDR", "192.0.2.1"),
+    ("REMOTE_PORT", "4321"),
+    ("CONTENT_LENGTH", "7"),
+];
+
+const PARAMS_TWO: &[(&str, &str)] = &[
+    ("REQUEST_METHOD", "PUT"),
+    ("SCRIPT_NAME", "/two"),
+    ("PATH_INFO", "/three"),
+    ("QUERY_STRING", ""),
+    ("REMOTE_ADDR", "192.0.2.2"),
+    ("REMOTE_PORT", "5432"),
+    ("HTTP_X_FORWARDED_PROTO", "https"),
+];
+
+#[test]
+fn multiplexed_requests() {
+    run(async {
+        let addr = start_server(Protocol::FastCgi).await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+        let mut records = Records::default();
+        records
+            .begin(1, true)
+            .begin(2, true)
+            .params(2, PARAMS_TWO)
+            .params(1, PARAMS_ONE)
+            .stdin(2, "second")
+            .stdin(1, "first, ")
+            .stdin(2, "")
+            .stdin(1, "");
+        stream.write_all(&records.0).await.unwrap();
+
+        let stdout = read_responses(&mut stream, &[1, 2]).await;
+        assert_eq!(
+            stdout[&1],
+            "Status: 200 OK\r\ncontent-type: text/plain\r\n\r\n\
+             POST /one?x=1 192.0.2.1:4321 first, "
+        );
+        assert_eq!(
+            stdout[&2],
+            "Status: 200 OK\r\ncontent-type: text/plain\r\n\r\n\
+             PUT /two/three 192.0.2.2:5432 second"
+        );
+
+        // The connection is kept open, and the request ID can be reused.
+        let mut records = Records::default();
+        records.begin(1, true).params(1, PARAMS_ONE).stdin(1, "");
+        stream.write_all(&records.0).await.unwrap();
+        let stdout = read_responses(&mut stream, &[1]).await;
+        assert!(stdout[&1].ends_with("192.0.2.1:4321 "), "{:?}", stdout);
+    });
+}
+
+#[test]
+fn close_without_keep_conn() {
+    run(async {
+        let addr = start_server(Protocol::FastCgi).await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+        let mut records = Records::default();
+        records.begin(1, false).params(1, PARAMS_ONE).stdin(1, "");
+        stream.write_all(&records.0).await.unwrap();
+
+        let stdout = read_responses(&mut stream, &[1]).await;
+        assert!(stdout[&1].starts_with("Status: 200 OK\r\n"));
+        assert!(read_record(&mut stream).await.is_none());
+    });
+}
+
+#[test]
+fn abort_request() {
+    run(async {
+        let addr = start_server(Protocol::FastCgi).await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+        let mut records = Records::default();
+        records
+            .begin(1, true)
+            .params(1, &[("REQUEST_METHOD", "GET"), ("REQUEST_URI", "/wait")]);
+        stream.write_all(&records.0).await.unwrap();
+        let (kind, _, content) = read_record(&mut stream).await.unwrap();
+        assert_eq!(kind, STDOUT);
+        assert!(content.starts_with(b"Status: 200 OK\r\n"));
+
+        let mut records = Records::default();
+        records.record(ABORT_REQUEST, 1, &[]);
+        stream.write_all(&records.0).await.unwrap();
+        let stdout = read_responses(&mut stream, &[1]).await;
+        assert_eq!(stdout[&1], "cancelled");
+    });
+}
+
+#[test]
+fn wait_for_cancellation_repeatedly() {
+    run(async {
+        let addr = start_server(Protocol::FastCgi).await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+        let mut records = Records::default();
+        records
+            .begin(1, true)
+            .params(1, &[("REQUEST_METHOD", "POST"), ("REQUEST_URI", "/poll")])
+            .stdin(1, "hello")
+            .stdin(1, "");
+        stream.write_all(&records.0).await.unwrap();
+        let stdout = read_responses(&mut stream, &[1]).await;
+        assert!(stdout[&1].starts_with("Status: 200 OK\r\n"), "{:?}", stdout);
+        assert!(stdout[&1].ends_with("hello"), "{:?}", stdout);
+    });
+}
+
+#[test]
+fn panic_responds_internal_server_error() {
+    run(async {
+        let addr = start_server(Protocol::FastCgi).await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+        let mut records = Records::default();
+        records
+            .begin(1, true)
+            .params(1, &[("REQUEST_METHOD", "GET"), ("REQUEST_URI", "/panic")])
+            .stdin(1, "");
+        stream.write_all(&records.0).await.unwrap();
+        let stdout = read_responses(&mut stream, &[1]).await;
+        assert_eq!(stdout[&1], "Status: 500 Internal Server Error\r\n\r\n");
+    });
+}
+
+#[test]
+fn get_values() {
+    run(async {
+        let addr = start_server(Protocol::FastCgi).await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+        let mut content = vec![];
+        encode_pair(&mut content, b"FCGI_MPXS_CONNS", b"");
+        let mut records = Records::default();
+        records.record(GET_VALUES, 0, &content);
+        stream.write_all(&records.0).await.unwrap();
+
+        let (kind, id, content) = read_record(&mut stream).await.unwrap();
+        assert_eq!((kind, id), (GET_VALUES_RESULT, 0));
+        let mut expected = vec![];
+        encode_pair(&mut expected, b"FCGI_MPXS_CONNS", b"1");
+        assert_eq!(content, expected);
+    });
+}
@@ -0,0 +1,121 @@
+mod support;
+
+use izanami_fcgi::Protocol;
+use std::time::Duration;
+use support::{run, start_server};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Encode the headers of a request as the netstring.
+fn encode_headers(headers: &[(&str, &str)]) -> Vec<u8> {
+    let mut content = vec![];
+    for (name, value) in headers {
+        content.extend_from_slice(name.as_bytes());
+        content.push(0);
+        content.extend_from_slice(value.as_bytes());
+        content.push(0);
+    }
+    let mut netstring = format!("{}:", content.len()).into_bytes();
+    netstring.extend_from_slice(&content);
+    netstring.push(b',');
+    netstring
+}
+
+async fn request(headers: &[(&str, &str)], body: &str) -> String {
+    let addr = start_server(Protocol::Scgi).await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    let mut request = encode_headers(headers);
+    request.extend_from_slice(body.as_bytes());
+    stream.write_all(&request).await.unwrap();
+
+    let mut received = vec![];
+    stream.read_to_end(&mut received).await.unwrap();
+    String::from_utf8(received).unwrap()
+}
+
+#[test]
+fn echo_body() {
+    run(async {
+        let response = request(
+            &[
+                ("CONTENT_LENGTH", "5"),
+                ("SCGI", "1"),
+                ("REQUEST_METHOD", "POST"),
+                ("REQUEST_URI", "/echo?x=1"),
+                ("REMOTE_ADDR", "192.0.2.1"),
+                ("REMOTE_PORT", "4321"),
+                ("HTTP_HOST", "example.com"),
+            ],
+            "hello",
+        )
+        .await;
+        assert_eq!(
+            response,
+            "Status: 200 OK\r\ncontent-type: text/plain\r\n\r\n\
+             POST /echo?x=1 192.0.2.1:4321 hello"
+        );
+    });
+}
+
+#[test]
+fn keep_body_read_while_waiting_for_cancellation() {
+    run(async {
+        let addr = start_server(Protocol::Scgi).await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        let headers = encode_headers(&[
+            ("CONTENT_LENGTH", "5"),
+            ("SCGI", "1"),
+            ("REQUEST_METHOD", "POST"),
+            ("REQUEST_URI", "/poll"),
+            ("REMOTE_ADDR", "192.0.2.1"),
+            ("REMOTE_PORT", "4321"),
+        ]);
+        stream.write_all(&headers).await.unwrap();
+        // The body arrives while the application waits for the cancellation.
+        tokio::timer::delay_for(Duration::from_millis(20)).await;
+        stream.write_all(b"hello").await.unwrap();
+
+        let mut received = vec![];
+        stream.read_to_end(&mut received).await.unwrap();
+        assert_eq!(
+            String::from_utf8(received).unwrap(),
+            "Status: 200 OK\r\ncontent-type: text/plain\r\n\r\n\
+             POST /poll 192.0.2.1:4321 hello"
+        );
+    });
+}
+
+#[test]
+fn panic_responds_internal_server_error() {
+    run(async {
+        let response = request(
+            &[
+                ("CONTENT_LENGTH", "0"),
+                ("SCGI", "1"),
+                ("REQUEST_METHOD", "GET"),
+                ("REQUEST_URI", "/panic"),
+            ],
+            "",
+        )
+        .await;
+        assert_eq!(response, "Status: 500 Internal Server Error\r\n\r\n");
+    });
+}
+
+#[test]
+fn reject_without_content_length() {
+    run(async {
+        let response = request(
+            &[
+                ("SCGI", "1"),
+                ("REQUEST_METHOD", "GET"),
+                ("REQUEST_URI", "/"),
+            ],
+            "",
+        )
+        .await;
+        assert_eq!(response, "");
+    });
+}
@@ -0,0 +1,97 @@
+#![allow(dead_code)]
+
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::future::{self, Either};
+use http::{Request, Response};
+use izanami::{state::State, App, Events as _, RemoteAddr};
+use izanami_fcgi::{Events, Protocol, Server};
+use std::{future::Future, net::SocketAddr, time::Duration};
+use tokio::runtime::current_thread;
+
+pub fn run<Fut>(fut: Fut)
+where
+    Fut: Future<Output = ()>,
+{
+    let mut rt = current_thread::Runtime::new().unwrap();
+    rt.block_on(fut);
+}
+
+/// The per-connection state, which holds the address of the Web server.
+pub struct Frontend(SocketAddr);
+
+/// Respond with the request line and the body, or wait for the cancellation on `/wait`.
+///
+/// On `/poll`, it waits for the cancellation twice for a while before reading the body.
+#[derive(Clone)]
+pub struct Echo;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Echo {
+    type Error = std::io::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let RemoteAddr(remote_addr) = *request.extensions().get::<RemoteAddr>().unwrap();
+        assert_eq!(
+            *State::<&'static str>::from_request(&request).unwrap(),
+            "example"
+        );
+        assert!(State::<Frontend>::from_request(&request)
+            .unwrap()
+            .0
+            .ip()
+            .is_loopback());
+        let line = format!("{} {} {} ", request.method(), request.uri(), remote_addr);
+        match request.uri().path() {
+            "/panic" => panic!("explicit panic"),
+            "/wait" => {
+                let mut events = request.into_body();
+                events.start_send_response(Response::new(()), false).await?;
+                events.cancelled().await;
+                return events.send_data(Bytes::from("cancelled"), true).await;
+            }
+            _ => {}
+        }
+
+        let polls = if request.uri().path() == "/poll" {
+            2
+        } else {
+            0
+        };
+        let mut events = request.into_body();
+        for _ in 0..polls {
+            let cancelled = events.cancelled();
+            futures::pin_mut!(cancelled);
+            let delay = tokio::timer::delay_for(Duration::from_millis(50));
+            if let Either::Left(..) = future::select(cancelled, delay).await {
+                panic!("unexpected cancellation");
+            }
+        }
+        let mut body = line.into_bytes();
+        while let Some(data) = events.data().await {
+            body.extend_from_slice(data?.bytes());
+        }
+        let response = Response::builder()
+            .header("content-type", "text/plain")
+            .body(Bytes::from(body))
+            .unwrap();
+        events.send_response(response).await
+    }
+}
+
+pub async fn start_server(protocol: Protocol) -> SocketAddr {
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .protocol(protocol)
+        .state("example")
+        .connection_state(|remote_addr: &RemoteAddr| Frontend(remote_addr.0));
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        server.serve(Echo).await.unwrap();
+    });
+    addr
+}
@@ -0,0 +1,349 @@
+//! A middleware that resolves the client information forwarded by trusted proxies.
+
+#![deny(
+    missing_debug_implementations,
+    nonstandard_style,
+    rust_2018_idioms,
+    rust_2018_compatibility,
+    unused
+)]
+
+use async_trait::async_trait;
+use http::{
+    header::{self, HeaderMap},
+    Request, Uri,
+};
+use izanami::{App, Events, RemoteAddr};
+use std::{
+    error, fmt,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+/// A range of IP addresses, such as `10.0.0.0/8` or `2001:db8::/32`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Create a `Cidr` from the network address and the length of the prefix.
+    ///
+    /// Returns `None` if the prefix is longer than the address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<Self> {
+        let max_len = match addr {
+            IpAddr::V4(..) => 32,
+            IpAddr::V6(..) => 128,
+        };
+        if prefix_len <= max_len {
+            Some(Self { addr, prefix_len })
+        } else {
+            None
+        }
+    }
+
+    /// Return whether `addr` is in this range.
+    ///
+    /// IPv4-mapped IPv6 addresses are compared as IPv4 addresses.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, canonical(addr)) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                prefix_eq(&network.octets(), &addr.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                prefix_eq(&network.octets(), &addr.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        addr => addr,
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix_len: u8) -> bool {
+    let bytes = usize::from(prefix_len / 8);
+    let bits = prefix_len % 8;
+    if a[..bytes] != b[..bytes] {
+        return false;
+    }
+    if bits == 0 {
+        return true;
+    }
+    let mask = 0xff_u8 << (8 - bits);
+    a[bytes] & mask == b[bytes] & mask
+}
+
+impl From<IpAddr> for Cidr {
+    fn from(addr: IpAddr) -> Self {
+        match addr {
+            IpAddr::V4(..) => Self {
+                addr,
+                prefix_len: 32,
+            },
+            IpAddr::V6(..) => Self {
+                addr,
+                prefix_len: 128,
+            },
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.find('/') {
+            Some(pos) => {
+                let addr = s[..pos].parse().map_err(|_| InvalidCidr(()))?;
+                let prefix_len = s[pos + 1..].parse().map_err(|_| InvalidCidr(()))?;
+                Self::new(addr, prefix_len).ok_or(InvalidCidr(()))
+            }
+            None => s
+                .parse::<IpAddr>()
+                .map(Self::from)
+                .map_err(|_| InvalidCidr(())),
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// An error that a string is not a valid CIDR notation.
+#[derive(Debug)]
+pub struct InvalidCidr(());
+
+impl fmt::Display for InvalidCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid CIDR notation")
+    }
+}
+
+impl error::Error for InvalidCidr {}
+
+/// The information about the client, stored in the request extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The IP address of the client.
+    pub ip: IpAddr,
+
+    /// The scheme of the request sent by the client, such as `https`.
+    pub scheme: String,
+
+    /// The host requested by the client.
+    pub host: Option<String>,
+}
+
+/// A middleware that resolves `ClientInfo` of each request.
+///
+/// The `Forwarded` header (RFC 7239), or `X-Forwarded-For`, `X-Forwarded-Proto`
+/// and `X-Forwarded-Host` if it is missing, are used only if the peer is one
+/// of the trusted proxies. The proxy chain is followed from the nearest hop, and
+/// the client is the first address that is not trusted. Otherwise, the values are
+/// taken from the connection (`RemoteAddr`) and the request itself.
+///
+/// The request is passed to the inner `App` without `ClientInfo` if the server
+/// does not provide `RemoteAddr`.
+#[derive(Debug, Clone)]
+pub struct TrustedProxies<A> {
+    app: A,
+    trusted: Vec<Cidr>,
+}
+
+impl<A> TrustedProxies<A> {
+    /// Wrap an `App`, trusting no proxies.
+    pub fn new(app: A) -> Self {
+        Self {
+            app,
+            trusted: vec![],
+        }
+    }
+
+    /// Add the ranges of the trusted proxies.
+    pub fn trust<I>(mut self, proxies: I) -> Self
+    where
+        I: IntoIterator<Item = Cidr>,
+    {
+        self.trusted.extend(proxies);
+        self
+    }
+
+    fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.trusted.iter().any(|cidr| cidr.contains(addr))
+    }
+
+    /// Resolve the client information from the peer address and the request headers.
+    pub fn resolve(&self, peer: IpAddr, uri: &Uri, headers: &HeaderMap) -> ClientInfo {
+        let mut client = ClientInfo {
+            ip: peer,
+            scheme: uri.scheme_str().unwrap_or("http").to_owned(),
+            host: headers
+                .get(header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .or_else(|| uri.authority_part().map(|authority| authority.as_str()))
+                .map(ToOwned::to_owned),
+        };
+        if !self.is_trusted(peer) {
+            return client;
+        }
+
+        let forwarded = header_list(headers, header::FORWARDED.as_str());
+        if !forwarded.is_empty() {
+            let elements: Vec<Element> = forwarded.iter().map(|e| Element::parse(e)).collect();
+            for element in elements.iter().rev() {
+                let ip = match element.for_ip() {
+                    Some(ip) if self.is_trusted(client.ip) => ip,
+                    _ => break,
+                };
+                client.ip = ip;
+                if let Some(ref proto) = element.proto {
+                    client.scheme = proto.to_ascii_lowercase();
+                }
+                if let Some(ref host) = element.host {
+                    client.host = Some(host.clone());
+                }
+            }
+            return client;
+        }
+
+        for node in header_list(headers, "x-forwarded-for").iter().rev() {
+            match parse_node(node) {
+                Some(ip) if self.is_trusted(client.ip) => client.ip = ip,
+                _ => break,
+            }
+        }
+        // The values added by the nearest proxy are used.
+        if let Some(proto) = header_list(headers, "x-forwarded-proto").pop() {
+            client.scheme = proto.to_ascii_lowercase();
+        }
+        if let Some(host) = header_list(headers, "x-forwarded-host").pop() {
+            client.host = Some(host);
+        }
+        client
+    }
+}
+
+#[async_trait]
+impl<A, E> App<E> for TrustedProxies<A>
+where
+    A: App<E> + Send + Sync,
+    E: Events + Send,
+{
+    type Error = A::Error;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (mut parts, events) = request.into_parts();
+        if let Some(&RemoteAddr(peer)) = parts.extensions.get::<RemoteAddr>() {
+            let client = self.resolve(peer.ip(), &parts.uri, &parts.headers);
+            parts.extensions.insert(client);
+        }
+        self.app.call(Request::from_parts(parts, events)).await
+    }
+}
+
+/// Split the values of the header into the comma-separated elements,
+/// keeping the commas in quoted strings.
+fn header_list(headers: &HeaderMap, name: &str) -> Vec<String> {
+    let mut list = vec![];
+    for value in headers.get_all(name) {
+        let value = match value.to_str() {
+            Ok(value) => value,
+            Err(..) => continue,
+        };
+        let mut element = String::new();
+        let mut quoted = false;
+        let mut escaped = false;
+        for c in value.chars() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quoted => escaped = true,
+                '"' => quoted = !quoted,
+                ',' if !quoted => {
+                    list.push(element.trim().to_owned());
+                    element.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            element.push(c);
+        }
+        list.push(element.trim().to_owned());
+    }
+    list.retain(|element| !element.is_empty());
+    list
+}
+
+/// A forwarded element in the `Forwarded` header.
+#[derive(Debug, Default)]
+struct Element {
+    for_: Option<String>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+impl Element {
+    fn parse(s: &str) -> Self {
+        let mut element = Self::default();
+        for pair in s.split(';') {
+            let mut pair = pair.splitn(2, '=');
+            let name = pair.next().unwrap_or_default().trim();
+            let value = unquote(pair.next().unwrap_or_default().trim());
+            if name.eq_ignore_ascii_case("for") {
+                element.for_ = Some(value);
+            } else if name.eq_ignore_ascii_case("proto") {
+                element.proto = Some(value);
+            } else if name.eq_ignore_ascii_case("host") {
+                element.host = Some(value);
+            }
+        }
+        element
+    }
+
+    fn for_ip(&self) -> Option<IpAddr> {
+        self.for_.as_ref().and_then(|node| parse_node(node))
+    }
+}
+
+fn unquote(s: &str) -> String {
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        let mut unquoted = String::with_capacity(s.len() - 2);
+        let mut chars = s[1..s.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unquoted.extend(chars.next()),
+                c => unquoted.push(c),
+            }
+        }
+        unquoted
+    } else {
+        s.to_owned()
+    }
+}
+
+/// Parse a node, such as `192.0.2.43`, `192.0.2.43:47011` or `[2001:db8::1]:4711`.
+///
+/// Returns `None` for `unknown` and obfuscated identifiers.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim();
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    let v6 = node.strip_prefix('[')?;
+    let end = v6.find(']')?;
+    v6[..end].parse::<Ipv6Addr>().ok().map(IpAddr::V6)
+}
@@ -0,0 +1,120 @@
+use async_trait::async_trait;
+use futures::executor::block_on;
+use http::Request;
+use izanami::{App, Events, RemoteAddr};
+use izanami_forwarded::{Cidr, ClientInfo, TrustedProxies};
+use izanami_test::MockEvents;
+use std::sync::{Arc, Mutex};
+
+/// Record the `ClientInfo` passed to the app.
+#[derive(Clone, Default)]
+struct Inner(Arc<Mutex<Option<ClientInfo>>>);
+
+#[async_trait]
+impl<E> App<E> for Inner
+where
+    E: Events + Send,
+{
+    type Error = E::Error;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        *self.0.lock().unwrap() = request.extensions().get::<ClientInfo>().cloned();
+        Ok(())
+    }
+}
+
+fn resolve(peer: &str, headers: &[(&'static str, &str)]) -> ClientInfo {
+    let inner = Inner::default();
+    let app = TrustedProxies::new(inner.clone()).trust(vec![
+        "10.0.0.0/8".parse::<Cidr>().unwrap(),
+        "2001:db8::/32".parse().unwrap(),
+    ]);
+
+    let mut request = Request::get("/").body(MockEvents::default()).unwrap();
+    request
+        .headers_mut()
+        .insert("host", "internal.example".parse().unwrap());
+    for (name, value) in headers {
+        request.headers_mut().append(*name, value.parse().unwrap());
+    }
+    request
+        .extensions_mut()
+        .insert(RemoteAddr(peer.parse().unwrap()));
+    block_on(app.call(request)).unwrap();
+
+    let client = inner.0.lock().unwrap().take();
+    client.unwrap()
+}
+
+#[test]
+fn cidr() {
+    let cidr: Cidr = "192.168.0.0/20".parse().unwrap();
+    assert!(cidr.contains("192.168.15.255".parse().unwrap()));
+    assert!(!cidr.contains("192.168.16.0".parse().unwrap()));
+    assert!(cidr.contains("::ffff:192.168.1.1".parse().unwrap()));
+    assert!(!cidr.contains("2001:db8::1".parse().unwrap()));
+    assert_eq!(cidr.to_string(), "192.168.0.0/20");
+
+    let cidr: Cidr = "2001:db8::1".parse().unwrap();
+    assert!(cidr.contains("2001:db8::1".parse().unwrap()));
+    assert!(!cidr.contains("2001:db8::2".parse().unwrap()));
+
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+    assert!("example.com/8".parse::<Cidr>().is_err());
+}
+
+#[test]
+fn untrusted_peer() {
+    let client = resolve(
+        "198.51.100.1:1234",
+        &[
+            ("forwarded", "for=192.0.2.60;proto=https;host=example.com"),
+            ("x-forwarded-for", "192.0.2.60"),
+        ],
+    );
+    assert_eq!(client.ip.to_string(), "198.51.100.1");
+    assert_eq!(client.scheme, "http");
+    assert_eq!(client.host.as_deref(), Some("internal.example"));
+}
+
+#[test]
+fn forwarded_header() {
+    let client = resolve(
+        "10.0.0.2:1234",
+        &[
+            ("forwarded", "for=\"[2001:db8:cafe::17]:4711\";proto=http"),
+            (
+                "forwarded",
+                "for=192.0.2.60;proto=HTTPS;host=\"example.com\", for=10.0.0.1",
+            ),
+        ],
+    );
+    assert_eq!(client.ip.to_string(), "192.0.2.60");
+    assert_eq!(client.scheme, "https");
+    assert_eq!(client.host.as_deref(), Some("example.com"));
+
+    // The chain stops at an unknown node.
+    let client = resolve(
+        "10.0.0.2:1234",
+        &[("forwarded", "for=unknown, for=10.0.0.1")],
+    );
+    assert_eq!(client.ip.to_string(), "10.0.0.1");
+}
+
+#[test]
+fn x_forwarded_headers() {
+    let client = resolve(
+        "[2001:db8::1]:1234",
+        &[
+            ("x-forwarded-for", "203.0.113.1, 192.0.2.60, 10.1.2.3"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "example.com"),
+        ],
+    );
+    assert_eq!(client.ip.to_string(), "192.0.2.60");
+    assert_eq!(client.scheme, "https");
+    assert_eq!(client.host.as_deref(), Some("example.com"));
+}
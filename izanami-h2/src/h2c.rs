@@ -0,0 +1,441 @@
+//! Detection of the HTTP/2 connection preface and the upgrade from HTTP/1.1 (`h2c`),
+//! and the tracking of the settings sent by the client.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::task::{self, Poll};
+use http::{header, request, HeaderMap, Version};
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The connection preface sent by HTTP/2 clients.
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const FRAME_HEADER_LEN: usize = 9;
+const FRAME_HEADERS: u8 = 0x1;
+const FRAME_SETTINGS: u8 = 0x4;
+const FLAG_ACK: u8 = 0x1;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+
+/// The length of a setting in the payload of a `SETTINGS` frame.
+const SETTING_LEN: usize = 6;
+
+/// The identifier of `SETTINGS_ENABLE_PUSH`.
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+
+/// The initial value of `SETTINGS_MAX_FRAME_SIZE`.
+const MAX_FRAME_SIZE: usize = 16_384;
+
+/// Read the beginning of the connection into `buf`, until it is known
+/// whether it starts with the HTTP/2 connection preface.
+pub(crate) async fn read_preface<T>(io: &mut T, buf: &mut BytesMut) -> io::Result<bool>
+where
+    T: AsyncRead + Unpin,
+{
+    let mut chunk = [0; 4096];
+    while buf.len() < PREFACE.len() && PREFACE.starts_with(&buf[..]) {
+        let n = io.read(&mut chunk).await?;
+        if n == 0 {
+            break;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    Ok(buf.starts_with(PREFACE))
+}
+
+/// An I/O object that replays the specified bytes before reading from the inner one.
+#[derive(Debug)]
+pub(crate) struct Rewind<T> {
+    pre: Bytes,
+    inner: T,
+}
+
+impl<T> Rewind<T> {
+    pub(crate) fn new(inner: T, pre: Bytes) -> Self {
+        Self { pre, inner }
+    }
+}
+
+impl<T> AsyncRead for Rewind<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if !self.pre.is_empty() {
+            let n = self.pre.len().min(buf.len());
+            buf[..n].copy_from_slice(&self.pre.split_to(n));
+            return Poll::Ready(Ok(n));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for Rewind<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    #[inline]
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// An I/O object that follows the frames read from the client, to track
+/// `SETTINGS_ENABLE_PUSH`, which is neither exposed nor enforced by `h2`.
+///
+/// The stream read from the inner object must start with the connection preface.
+#[derive(Debug)]
+pub(crate) struct WatchSettings<T> {
+    inner: T,
+    state: FrameState,
+    push_enabled: Arc<AtomicBool>,
+}
+
+#[derive(Debug)]
+enum FrameState {
+    /// Skipping the remaining bytes of the preface or of a frame payload.
+    Skip(usize),
+    /// Reading a frame header.
+    Header([u8; FRAME_HEADER_LEN], usize),
+    /// Reading the payload of a `SETTINGS` frame.
+    Settings {
+        remaining: usize,
+        setting: [u8; SETTING_LEN],
+        len: usize,
+    },
+}
+
+impl<T> WatchSettings<T> {
+    /// Wrap `inner`, storing whether the client accepts the server push into `push_enabled`.
+    pub(crate) fn new(inner: T, push_enabled: Arc<AtomicBool>) -> Self {
+        Self {
+            inner,
+            state: FrameState::Skip(PREFACE.len()),
+            push_enabled,
+        }
+    }
+
+    fn observe(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            match self.state {
+                FrameState::Skip(ref mut remaining) => {
+                    let n = (*remaining).min(data.len());
+                    data = &data[n..];
+                    *remaining -= n;
+                    if *remaining == 0 {
+                        self.state = FrameState::Header([0; FRAME_HEADER_LEN], 0);
+                    }
+                }
+                FrameState::Header(ref mut header, ref mut len) => {
+                    let n = (FRAME_HEADER_LEN - *len).min(data.len());
+                    header[*len..*len + n].copy_from_slice(&data[..n]);
+                    data = &data[n..];
+                    *len += n;
+                    if *len < FRAME_HEADER_LEN {
+                        continue;
+                    }
+                    let payload_len = (usize::from(header[0]) << 16)
+                        | (usize::from(header[1]) << 8)
+                        | usize::from(header[2]);
+                    self.state = if payload_len == 0 {
+                        FrameState::Header([0; FRAME_HEADER_LEN], 0)
+                    } else if header[3] == FRAME_SETTINGS && header[4] & FLAG_ACK == 0 {
+                        FrameState::Settings {
+                            remaining: payload_len,
+                            setting: [0; SETTING_LEN],
+                            len: 0,
+                        }
+                    } else {
+                        FrameState::Skip(payload_len)
+                    };
+                }
+                FrameState::Settings {
+                    ref mut remaining,
+                    ref mut setting,
+                    ref mut len,
+                } => {
+                    let n = (SETTING_LEN - *len).min(*remaining).min(data.len());
+                    setting[*len..*len + n].copy_from_slice(&data[..n]);
+                    data = &data[n..];
+                    *len += n;
+                    *remaining -= n;
+                    if *len == SETTING_LEN {
+                        *len = 0;
+                        let id = u16::from_be_bytes([setting[0], setting[1]]);
+                        if id == SETTINGS_ENABLE_PUSH {
+                            let value = &setting[2..];
+                            let enabled = value.iter().any(|&b| b != 0);
+                            self.push_enabled.store(enabled, Ordering::Release);
+                        }
+                    }
+                    if *remaining == 0 {
+                        self.state = FrameState::Header([0; FRAME_HEADER_LEN], 0);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<T> AsyncRead for WatchSettings<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures::ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.observe(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<T> AsyncWrite for WatchSettings<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    #[inline]
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A request upgraded to `h2c`.
+#[derive(Debug)]
+pub(crate) struct Upgrade {
+    /// The payload of the `SETTINGS` frame carried by `HTTP2-Settings`.
+    settings: Bytes,
+    /// The `HEADERS` frame that carries the request as the stream 1.
+    headers: Bytes,
+}
+
+/// Return the upgrade to `h2c`, if the request asks for it and can be upgraded.
+///
+/// The requests with a body are served with HTTP/1.1 instead, as well as
+/// those whose header block does not fit in a frame or whose `HTTP2-Settings`
+/// is invalid.
+pub(crate) fn upgrade_request(parts: &request::Parts, has_body: bool) -> Option<Upgrade> {
+    if parts.version != Version::HTTP_11 || has_body {
+        return None;
+    }
+    if !has_token(&parts.headers, header::UPGRADE, "h2c")
+        || !has_token(&parts.headers, header::CONNECTION, "upgrade")
+        || !has_token(&parts.headers, header::CONNECTION, "http2-settings")
+    {
+        return None;
+    }
+    let mut values = parts.headers.get_all("http2-settings").iter();
+    let settings = match (values.next(), values.next()) {
+        (Some(value), None) => decode_settings(value.as_bytes())?,
+        _ => return None,
+    };
+
+    let block = encode_header_block(parts)?;
+    if block.len() > MAX_FRAME_SIZE {
+        return None;
+    }
+    let mut headers = BytesMut::with_capacity(FRAME_HEADER_LEN + block.len());
+    put_frame_header(
+        &mut headers,
+        block.len(),
+        FRAME_HEADERS,
+        FLAG_END_STREAM | FLAG_END_HEADERS,
+        1,
+    );
+    headers.extend_from_slice(&block);
+    Some(Upgrade {
+        settings,
+        headers: headers.freeze(),
+    })
+}
+
+/// Decode the value of `HTTP2-Settings`, the `SETTINGS` payload encoded with
+/// base64url without the padding.
+fn decode_settings(value: &[u8]) -> Option<Bytes> {
+    let value = match value.iter().position(|&b| b == b'=') {
+        Some(pos) => &value[..pos],
+        None => value,
+    };
+    let payload = URL_SAFE_NO_PAD.decode(value).ok()?;
+    if payload.len() % SETTING_LEN != 0 || payload.len() > MAX_FRAME_SIZE / 2 {
+        return None;
+    }
+    Some(payload.into())
+}
+
+/// The response to the upgrade request.
+const SWITCHING_PROTOCOLS: &[u8] =
+    b"HTTP/1.1 101 Switching Protocols\r\nconnection: Upgrade\r\nupgrade: h2c\r\n\r\n";
+
+/// Switch the connection to HTTP/2, and return the I/O object that emits
+/// the request as the stream 1 just after the preface of the client.
+///
+/// The settings in `HTTP2-Settings` are applied by prepending them to the
+/// first `SETTINGS` frame of the client, so that they are acknowledged
+/// together and overridden by the values sent in the frame.
+pub(crate) async fn upgrade<T>(
+    mut io: T,
+    mut buf: BytesMut,
+    upgrade: Upgrade,
+) -> io::Result<Rewind<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    io.write_all(SWITCHING_PROTOCOLS).await?;
+
+    let settings_start = PREFACE.len();
+    let payload_start = settings_start + FRAME_HEADER_LEN;
+    fill(&mut io, &mut buf, payload_start).await?;
+    let header = &buf[settings_start..payload_start];
+    if !buf.starts_with(PREFACE) || header[3] != FRAME_SETTINGS || header[4] & FLAG_ACK != 0 {
+        return Err(invalid_preface());
+    }
+    let len =
+        (usize::from(header[0]) << 16) | (usize::from(header[1]) << 8) | usize::from(header[2]);
+    let merged_len = upgrade.settings.len() + len;
+    if merged_len > MAX_FRAME_SIZE {
+        return Err(invalid_preface());
+    }
+    let settings_end = payload_start + len;
+    fill(&mut io, &mut buf, settings_end).await?;
+
+    let rest = buf.split_off(settings_end);
+    let payload = buf.split_off(payload_start);
+    buf.truncate(settings_start);
+    put_frame_header(&mut buf, merged_len, FRAME_SETTINGS, 0, 0);
+    buf.extend_from_slice(&upgrade.settings);
+    buf.extend_from_slice(&payload);
+    buf.extend_from_slice(&upgrade.headers);
+    buf.extend_from_slice(&rest);
+    Ok(Rewind::new(io, buf.freeze()))
+}
+
+fn invalid_preface() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "invalid HTTP/2 connection preface after the upgrade",
+    )
+}
+
+async fn fill<T>(io: &mut T, buf: &mut BytesMut, len: usize) -> io::Result<()>
+where
+    T: AsyncRead + Unpin,
+{
+    let mut chunk = [0; 4096];
+    while buf.len() < len {
+        let n = io.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+    Ok(())
+}
+
+fn put_frame_header(buf: &mut BytesMut, len: usize, kind: u8, flags: u8, stream_id: u32) {
+    buf.reserve(FRAME_HEADER_LEN);
+    buf.put_slice(&(len as u32).to_be_bytes()[1..]);
+    buf.put_u8(kind);
+    buf.put_u8(flags);
+    buf.put_slice(&stream_id.to_be_bytes());
+}
+
+fn has_token(headers: &HeaderMap, name: header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+/// Encode the request head with HPACK, using only the literals without indexing.
+fn encode_header_block(parts: &request::Parts) -> Option<Vec<u8>> {
+    let authority = parts
+        .headers
+        .get(header::HOST)
+        .map(|host| host.as_bytes())
+        .or_else(|| parts.uri.authority_part().map(|a| a.as_str().as_bytes()))?;
+    let path = parts.uri.path_and_query().map_or("/", |path| path.as_str());
+
+    let mut block = vec![];
+    encode_field(&mut block, b":method", parts.method.as_str().as_bytes());
+    encode_field(&mut block, b":scheme", b"http");
+    encode_field(&mut block, b":authority", authority);
+    encode_field(&mut block, b":path", path.as_bytes());
+    for (name, value) in &parts.headers {
+        match name.as_str() {
+            "connection" | "host" | "http2-settings" | "keep-alive" | "proxy-connection"
+            | "transfer-encoding" | "upgrade" => continue,
+            "te" if value != "trailers" => continue,
+            name => encode_field(&mut block, name.as_bytes(), value.as_bytes()),
+        }
+    }
+    Some(block)
+}
+
+fn encode_field(block: &mut Vec<u8>, name: &[u8], value: &[u8]) {
+    // Literal Header Field without Indexing, with a new name.
+    block.push(0x00);
+    encode_string(block, name);
+    encode_string(block, value);
+}
+
+fn encode_string(block: &mut Vec<u8>, s: &[u8]) {
+    // The length is encoded as an integer with a 7-bit prefix, without Huffman coding.
+    let mut len = s.len();
+    if len < 0x7f {
+        block.push(len as u8);
+    } else {
+        block.push(0x7f);
+        len -= 0x7f;
+        while len >= 0x80 {
+            block.push((len % 0x80) as u8 | 0x80);
+            len /= 0x80;
+        }
+        block.push(len as u8);
+    }
+    block.extend_from_slice(s);
+}
@@ -0,0 +1,687 @@
+//! A minimal HTTP/1.1 server used for the clients that do not speak HTTP/2.
+
+use crate::{
+    finish_request,
+    h2c::{self, Rewind},
+    run_app, ConnContext, Events, Transport,
+};
+use bytes::{Bytes, BytesMut};
+use futures::future::{self, Either};
+use http::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    request, Method, Request, Response, StatusCode, Version,
+};
+use izanami::{access_log::Record, limit::Shed, timeout::Watchdog, App, Reason, RemoteAddr};
+use std::{
+    io,
+    net::Shutdown,
+    str,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::{field, Instrument, Span};
+
+const MAX_HEADERS: usize = 100;
+const MAX_HEAD_LEN: usize = 64 * 1024;
+const MAX_LINE_LEN: usize = 8 * 1024;
+const MAX_DRAIN_LEN: usize = 64 * 1024;
+
+/// Serve the HTTP/1.1 requests on the connection, until it is closed
+/// or upgraded to HTTP/2.
+///
+/// `buf` is the bytes already read from the connection.
+pub(crate) async fn serve_connection<T>(
+    io: TcpStream,
+    buf: BytesMut,
+    app: &T,
+    cx: &ConnContext,
+) -> io::Result<Option<Rewind<TcpStream>>>
+where
+    T: for<'a> App<Events<'a>>,
+{
+    let mut conn = Conn { io, buf };
+    loop {
+        let parts = match conn.read_head_within(cx.timeouts.idle).await {
+            Ok(Some(parts)) => parts,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                if err.kind() == io::ErrorKind::InvalidData {
+                    let _ = conn.io.write_all(BAD_REQUEST).await;
+                }
+                return Err(err);
+            }
+        };
+
+        let body = match BodyState::from_headers(&parts.headers) {
+            Some(body) => body,
+            None => {
+                conn.io.write_all(BAD_REQUEST).await?;
+                return Ok(None);
+            }
+        };
+
+        let has_body = !matches!(body, BodyState::Done);
+        if let Some(upgrade) = h2c::upgrade_request(&parts, has_body) {
+            tracing::debug!("upgrading to h2c");
+            return h2c::upgrade(conn.io, conn.buf, upgrade).await.map(Some);
+        }
+
+        let span = tracing::info_span!(
+            "request",
+            peer = %cx.remote_addr,
+            method = %parts.method,
+            uri = %parts.uri,
+            version = ?parts.version,
+            status = field::Empty,
+            bytes_in = field::Empty,
+            bytes_out = field::Empty,
+            duration_ms = field::Empty,
+            request_id = field::Empty,
+        );
+        let exchange = Exchange::new(conn, &parts, body);
+        let exchange = handle_request(app, parts, exchange, cx)
+            .instrument(span)
+            .await;
+        if !exchange.is_reusable() {
+            return Ok(None);
+        }
+        conn = exchange.conn;
+    }
+}
+
+const BAD_REQUEST: &[u8] =
+    b"HTTP/1.1 400 Bad Request\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+
+async fn handle_request<T>(
+    app: &T,
+    mut parts: request::Parts,
+    mut exchange: Exchange,
+    cx: &ConnContext,
+) -> Exchange
+where
+    T: for<'a> App<Events<'a>>,
+{
+    let started = Instant::now();
+    let span = Span::current();
+    parts.extensions.insert(RemoteAddr(cx.remote_addr));
+    cx.state.inject(&mut parts.extensions);
+    let mut record = Record::new(&parts);
+    record.remote_addr = Some(cx.remote_addr);
+
+    match cx.control.admit(&cx.limiter).await {
+        Ok(guard) => {
+            let watchdog = Watchdog::new(cx.timeouts);
+            let request = Request::from_parts(
+                parts,
+                Events {
+                    transport: Transport::Http1(&mut exchange),
+                    watchdog: &watchdog,
+                    record: &mut record,
+                    span: &span,
+                },
+            );
+            if let Some((status, _)) = run_app(app, request, &watchdog, cx).await {
+                match status {
+                    Some(status) if !exchange.response_started() => {
+                        let _ = exchange.send_empty_response(status, None).await;
+                        record.status = Some(status);
+                    }
+                    // There is no way to abort the response but closing the connection.
+                    _ => exchange.keep_alive = false,
+                }
+            }
+            drop(guard);
+        }
+        Err(shed) => {
+            tracing::debug!("request rejected by the concurrency limit");
+            let retry_after = match shed {
+                Shed::Reject(retry_after) => retry_after,
+                Shed::Refuse => None,
+            };
+            let status = StatusCode::SERVICE_UNAVAILABLE;
+            let _ = exchange.send_empty_response(status, retry_after).await;
+            record.status = Some(status);
+        }
+    }
+
+    exchange.drain().await;
+    finish_request(&mut record, started, cx);
+    exchange
+}
+
+#[derive(Debug)]
+struct Conn {
+    io: TcpStream,
+    buf: BytesMut,
+}
+
+impl Conn {
+    /// Read more bytes into the buffer, and return the number of read bytes.
+    async fn fill(&mut self) -> io::Result<usize> {
+        let mut chunk = [0; 8192];
+        let n = self.io.read(&mut chunk).await?;
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n)
+    }
+
+    async fn fill_some(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() && self.fill().await? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+
+    /// Read a request head, or return `None` if the connection is closed before it.
+    async fn read_head(&mut self) -> io::Result<Option<request::Parts>> {
+        loop {
+            if let Some((parts, len)) = parse_head(&self.buf)? {
+                self.buf.advance(len);
+                return Ok(Some(parts));
+            }
+            if self.buf.len() > MAX_HEAD_LEN {
+                return Err(invalid_data("the request head is too large"));
+            }
+            if self.fill().await? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+
+    /// Read a request head as `read_head`, but return `None` if it does not
+    /// arrive within `timeout`, so that the idle connections are closed.
+    async fn read_head_within(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> io::Result<Option<request::Parts>> {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => return self.read_head().await,
+        };
+        let read_head = self.read_head();
+        futures::pin_mut!(read_head);
+        let delay = tokio::timer::delay(tokio::clock::now() + timeout);
+        match future::select(read_head, delay).await {
+            Either::Left((result, _)) => result,
+            Either::Right(..) => {
+                tracing::debug!("closing the idle connection");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Read a line, without the trailing CRLF.
+    async fn read_line(&mut self) -> io::Result<Bytes> {
+        loop {
+            if let Some(pos) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line = self.buf.split_to(pos).freeze();
+                self.buf.advance(2);
+                return Ok(line);
+            }
+            if self.buf.len() > MAX_LINE_LEN {
+                return Err(invalid_data("the line is too long"));
+            }
+            if self.fill().await? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_head(buf: &[u8]) -> io::Result<Option<(request::Parts, usize)>> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut req = httparse::Request::new(&mut headers);
+    let len = match req
+        .parse(buf)
+        .map_err(|_| invalid_data("invalid request head"))?
+    {
+        httparse::Status::Complete(len) => len,
+        httparse::Status::Partial => return Ok(None),
+    };
+
+    let mut request = Request::builder();
+    request
+        .method(req.method.unwrap_or_default())
+        .uri(req.path.unwrap_or_default())
+        .version(match req.version {
+            Some(1) => Version::HTTP_11,
+            _ => Version::HTTP_10,
+        });
+    for header in req.headers.iter() {
+        request.header(header.name, header.value);
+    }
+    let request = request
+        .body(())
+        .map_err(|_| invalid_data("invalid request head"))?;
+    Ok(Some((request.into_parts().0, len)))
+}
+
+/// The progress of reading the request body.
+#[derive(Debug)]
+enum BodyState {
+    Length(u64),
+    ChunkSize,
+    ChunkData(u64),
+    ChunkEnd,
+    Trailers,
+    Done,
+}
+
+impl BodyState {
+    /// Determine the framing of the request body, or return `None` if it is invalid.
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        if let Some(encoding) = headers
+            .get_all(header::TRANSFER_ENCODING)
+            .iter()
+            .next_back()
+        {
+            let chunked = encoding
+                .to_str()
+                .ok()?
+                .rsplit(',')
+                .next()?
+                .trim()
+                .eq_ignore_ascii_case("chunked");
+            return if chunked {
+                Some(BodyState::ChunkSize)
+            } else {
+                None
+            };
+        }
+        // A message with several lengths is rejected rather than guessing
+        // which one the other intermediaries have used.
+        let mut lengths = headers.get_all(header::CONTENT_LENGTH).iter();
+        match (lengths.next(), lengths.next()) {
+            (None, _) => Some(BodyState::Done),
+            (Some(len), None) => match parse_content_length(len.as_bytes())? {
+                0 => Some(BodyState::Done),
+                len => Some(BodyState::Length(len)),
+            },
+            (Some(..), Some(..)) => None,
+        }
+    }
+}
+
+/// Parse the value of `Content-Length`, which consists only of digits.
+fn parse_content_length(value: &[u8]) -> Option<u64> {
+    if value.is_empty() || !value.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    str::from_utf8(value).ok()?.parse().ok()
+}
+
+/// The framing of the response body.
+#[derive(Debug, PartialEq)]
+enum ResponseState {
+    Init,
+    /// The body must not be sent, e.g. for `HEAD` requests.
+    Omitted,
+    Length(u64),
+    Chunked,
+    /// The end of the body is indicated by closing the connection.
+    CloseDelimited,
+    Done,
+}
+
+/// A request and response exchanged on an HTTP/1.1 connection.
+#[derive(Debug)]
+pub(crate) struct Exchange {
+    conn: Conn,
+    version: Version,
+    is_head: bool,
+    body: BodyState,
+    trailers: Option<HeaderMap>,
+    expect_continue: bool,
+    response: ResponseState,
+    keep_alive: bool,
+    reset: Option<Reason>,
+}
+
+impl Exchange {
+    fn new(conn: Conn, parts: &request::Parts, body: BodyState) -> Self {
+        let connection = |token: &str| {
+            parts
+                .headers
+                .get_all(header::CONNECTION)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .any(|value| value.trim().eq_ignore_ascii_case(token))
+        };
+        // The body framed by `Transfer-Encoding` with `Content-Length` may be
+        // read differently by an intermediary, so the connection is not reused.
+        let ambiguous = parts.headers.contains_key(header::TRANSFER_ENCODING)
+            && parts.headers.contains_key(header::CONTENT_LENGTH);
+        let keep_alive = match parts.version {
+            Version::HTTP_11 => !connection("close") && !ambiguous,
+            _ => false,
+        };
+        let expect_continue = parts.version == Version::HTTP_11
+            && parts
+                .headers
+                .get(header::EXPECT)
+                .is_some_and(|expect| expect == "100-continue");
+        Self {
+            conn,
+            version: parts.version,
+            is_head: parts.method == Method::HEAD,
+            body,
+            trailers: None,
+            expect_continue,
+            response: ResponseState::Init,
+            keep_alive,
+            reset: None,
+        }
+    }
+
+    fn response_started(&self) -> bool {
+        self.response != ResponseState::Init
+    }
+
+    /// Return whether the connection can be used for the next request.
+    fn is_reusable(&self) -> bool {
+        self.keep_alive
+            && self.response == ResponseState::Done
+            && matches!(self.body, BodyState::Done)
+    }
+
+    pub(crate) async fn data(&mut self) -> Option<io::Result<Bytes>> {
+        self.read_data().await.transpose()
+    }
+
+    async fn read_data(&mut self) -> io::Result<Option<Bytes>> {
+        if self.expect_continue {
+            self.expect_continue = false;
+            if !self.response_started() {
+                self.conn
+                    .io
+                    .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                    .await?;
+            }
+        }
+
+        loop {
+            match self.body {
+                BodyState::Done => return Ok(None),
+                BodyState::Length(remaining) => {
+                    self.conn.fill_some().await?;
+                    let n = remaining.min(self.conn.buf.len() as u64);
+                    self.body = match remaining - n {
+                        0 => BodyState::Done,
+                        remaining => BodyState::Length(remaining),
+                    };
+                    return Ok(Some(self.conn.buf.split_to(n as usize).freeze()));
+                }
+                BodyState::ChunkSize => {
+                    let line = self.conn.read_line().await?;
+                    let size = str::from_utf8(&line)
+                        .ok()
+                        .and_then(|line| line.split(';').next())
+                        .and_then(|size| u64::from_str_radix(size.trim(), 16).ok())
+                        .ok_or_else(|| invalid_data("invalid chunk size"))?;
+                    self.body = match size {
+                        0 => BodyState::Trailers,
+                        size => BodyState::ChunkData(size),
+                    };
+                }
+                BodyState::ChunkData(remaining) => {
+                    self.conn.fill_some().await?;
+                    let n = remaining.min(self.conn.buf.len() as u64);
+                    self.body = match remaining - n {
+                        0 => BodyState::ChunkEnd,
+                        remaining => BodyState::ChunkData(remaining),
+                    };
+                    return Ok(Some(self.conn.buf.split_to(n as usize).freeze()));
+                }
+                BodyState::ChunkEnd => {
+                    if !self.conn.read_line().await?.is_empty() {
+                        return Err(invalid_data("invalid chunk"));
+                    }
+                    self.body = BodyState::ChunkSize;
+                }
+                BodyState::Trailers => {
+                    let line = self.conn.read_line().await?;
+                    if line.is_empty() {
+                        self.body = BodyState::Done;
+                        return Ok(None);
+                    }
+                    let (name, value) = parse_field(&line)?;
+                    let trailers = self.trailers.get_or_insert_with(HeaderMap::new);
+                    if trailers.len() >= MAX_HEADERS {
+                        return Err(invalid_data("too many trailer fields"));
+                    }
+                    trailers.append(name, value);
+                }
+            }
+        }
+    }
+
+    /// Discard the rest of the request body so that the connection can be reused,
+    /// unless the body is large or the client is waiting for `100 Continue`.
+    async fn drain(&mut self) {
+        if self.expect_continue || !self.keep_alive {
+            self.keep_alive = false;
+            return;
+        }
+        let mut drained = 0;
+        loop {
+            match self.read_data().await {
+                Ok(Some(data)) => {
+                    drained += data.len();
+                    if drained > MAX_DRAIN_LEN {
+                        self.keep_alive = false;
+                        return;
+                    }
+                }
+                Ok(None) => return,
+                Err(..) => {
+                    self.keep_alive = false;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Return the trailers of the request, discarding the rest of the body.
+    pub(crate) async fn trailers(&mut self) -> io::Result<Option<HeaderMap>> {
+        while self.read_data().await?.is_some() {}
+        Ok(self.trailers.take())
+    }
+
+    /// Abort the exchange by closing the connection.
+    pub(crate) fn reset(&mut self, reason: Reason) {
+        self.reset = Some(reason);
+        self.keep_alive = false;
+        let _ = self.conn.io.shutdown(Shutdown::Both);
+    }
+
+    /// Wait until the client closes the connection.
+    ///
+    /// The bytes received in the meantime are kept for the request body, up to the limit.
+    pub(crate) async fn cancelled(&mut self) -> Reason {
+        loop {
+            if let Some(reason) = self.reset {
+                return reason;
+            }
+            if self.conn.buf.len() > MAX_DRAIN_LEN {
+                return future::pending().await;
+            }
+            match self.conn.fill().await {
+                Ok(n) if n > 0 => {}
+                _ => self.reset = Some(Reason::CANCEL),
+            }
+        }
+    }
+
+    fn ensure_not_reset(&self) -> io::Result<()> {
+        match self.reset {
+            Some(..) => Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "the exchange has been reset",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) async fn start_send_response(
+        &mut self,
+        response: Response<()>,
+        end_of_stream: bool,
+    ) -> io::Result<()> {
+        self.ensure_not_reset()?;
+        let (mut parts, ()) = response.into_parts();
+        parts.headers.remove(header::TRANSFER_ENCODING);
+        parts.headers.remove(header::CONNECTION);
+
+        let status = parts.status;
+        let no_body = self.is_head
+            || status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED;
+        let content_length = parts
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse().ok());
+        self.response = if no_body {
+            ResponseState::Omitted
+        } else if let Some(len) = content_length {
+            ResponseState::Length(len)
+        } else if end_of_stream {
+            parts
+                .headers
+                .insert(header::CONTENT_LENGTH, HeaderValue::from_static("0"));
+            ResponseState::Length(0)
+        } else if self.version == Version::HTTP_11 {
+            parts.headers.insert(
+                header::TRANSFER_ENCODING,
+                HeaderValue::from_static("chunked"),
+            );
+            ResponseState::Chunked
+        } else {
+            self.keep_alive = false;
+            ResponseState::CloseDelimited
+        };
+        if !self.keep_alive {
+            parts
+                .headers
+                .insert(header::CONNECTION, HeaderValue::from_static("close"));
+        }
+
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            status.as_str(),
+            status.canonical_reason().unwrap_or("")
+        )
+        .into_bytes();
+        for (name, value) in &parts.headers {
+            head.extend_from_slice(name.as_str().as_bytes());
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(value.as_bytes());
+            head.extend_from_slice(b"\r\n");
+        }
+        head.extend_from_slice(b"\r\n");
+        self.conn.io.write_all(&head).await?;
+
+        if end_of_stream {
+            self.finish_response(None).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn send_data(&mut self, data: Bytes, end_of_stream: bool) -> io::Result<()> {
+        self.ensure_not_reset()?;
+        match self.response {
+            ResponseState::Length(remaining) => {
+                let n = remaining.min(data.len() as u64);
+                self.conn.io.write_all(&data[..n as usize]).await?;
+                self.response = ResponseState::Length(remaining - n);
+            }
+            ResponseState::Chunked if !data.is_empty() => {
+                let mut chunk = format!("{:x}\r\n", data.len()).into_bytes();
+                chunk.extend_from_slice(&data);
+                chunk.extend_from_slice(b"\r\n");
+                self.conn.io.write_all(&chunk).await?;
+            }
+            ResponseState::CloseDelimited => self.conn.io.write_all(&data).await?,
+            _ => {}
+        }
+        if end_of_stream {
+            self.finish_response(None).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn send_trailers(&mut self, trailers: HeaderMap) -> io::Result<()> {
+        self.ensure_not_reset()?;
+        self.finish_response(Some(trailers)).await
+    }
+
+    async fn finish_response(&mut self, trailers: Option<HeaderMap>) -> io::Result<()> {
+        match self.response {
+            ResponseState::Chunked => {
+                let mut last = b"0\r\n".to_vec();
+                for (name, value) in trailers.iter().flatten() {
+                    last.extend_from_slice(name.as_str().as_bytes());
+                    last.extend_from_slice(b": ");
+                    last.extend_from_slice(value.as_bytes());
+                    last.extend_from_slice(b"\r\n");
+                }
+                last.extend_from_slice(b"\r\n");
+                self.conn.io.write_all(&last).await?;
+            }
+            // The body is shorter than the declared length.
+            ResponseState::Length(remaining) if remaining > 0 => self.keep_alive = false,
+            _ => {}
+        }
+        self.response = ResponseState::Done;
+        Ok(())
+    }
+
+    async fn send_empty_response(
+        &mut self,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    ) -> io::Result<()> {
+        let mut response = Response::new(());
+        *response.status_mut() = status;
+        if let Some(retry_after) = retry_after {
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after.as_secs().max(1)),
+            );
+        }
+        self.start_send_response(response, true).await
+    }
+}
+
+fn parse_field(line: &[u8]) -> io::Result<(HeaderName, HeaderValue)> {
+    let pos = line
+        .iter()
+        .position(|&b| b == b':')
+        .ok_or_else(|| invalid_data("invalid trailer field"))?;
+    let name =
+        HeaderName::from_bytes(&line[..pos]).map_err(|_| invalid_data("invalid trailer field"))?;
+    let value = HeaderValue::from_bytes(trim(&line[pos + 1..]))
+        .map_err(|_| invalid_data("invalid trailer field"))?;
+    Ok((name, value))
+}
+
+fn trim(mut s: &[u8]) -> &[u8] {
+    while let [b' ', rest @ ..] | [b'\t', rest @ ..] = s {
+        s = rest;
+    }
+    while let [rest @ .., b' '] | [rest @ .., b'\t'] = s {
+        s = rest;
+    }
+    s
+}
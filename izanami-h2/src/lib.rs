@@ -1,19 +1,59 @@
+mod h2c;
+mod http1;
+
+pub use izanami::limit::{LoadMetrics, Overload};
+
+use crate::h2c::{Rewind, WatchSettings};
 use async_trait::async_trait;
-use bytes::{Buf, Bytes};
-use futures::future::poll_fn;
+use bytes::{Buf, Bytes, BytesMut};
+use futures::{
+    future::{self, poll_fn, Either, FutureExt},
+    task::{self, Poll},
+};
 use h2::{
-    server::{Connection, SendResponse},
-    RecvStream, SendStream,
+    server::{Connection, SendPushedResponse, SendResponse},
+    Reason, RecvStream, SendStream,
+};
+use http::{header, request, HeaderMap, HeaderValue, Request, Response, StatusCode};
+#[cfg(feature = "metrics")]
+use izanami::metrics::Metrics;
+use izanami::{
+    access_log::{AccessLog, Record},
+    limit::{Limiter, Limits, LoadControl, Shed},
+    proxy_protocol::{self, ProxyHeader, Status},
+    state::{ConnectionState, StateMap},
+    timeout::{Timeouts, Watchdog},
+    App, RemoteAddr,
 };
-use http::{HeaderMap, Request, Response};
-use izanami::App;
-use std::{io, net::ToSocketAddrs};
-use tokio::net::{TcpListener, TcpStream};
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs},
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpStream},
+};
+use tracing::{field, Instrument, Span};
 
 #[derive(Debug)]
 pub struct Server {
     listener: TcpListener,
     h2: h2::server::Builder,
+    timeouts: Timeouts,
+    limits: Limits,
+    metrics: LoadMetrics,
+    access_log: Option<Arc<AccessLog>>,
+    proxy_protocol: bool,
+    accept_http1: bool,
+    states: StateMap,
+    #[cfg(feature = "metrics")]
+    recorder: Option<Metrics>,
 }
 
 impl Server {
@@ -24,7 +64,150 @@ impl Server {
         let addr = addr.to_socket_addrs()?.next().unwrap();
         let listener = TcpListener::bind(&addr).await?;
         let h2 = h2::server::Builder::new();
-        Ok(Self { listener, h2 })
+        Ok(Self {
+            listener,
+            h2,
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            metrics: LoadMetrics::default(),
+            access_log: None,
+            proxy_protocol: false,
+            accept_http1: true,
+            states: StateMap::new(),
+            #[cfg(feature = "metrics")]
+            recorder: None,
+        })
+    }
+
+    /// Return the local address that this server is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Set the maximum duration from the start of a request until
+    /// the response head is sent.
+    ///
+    /// If the limit is exceeded, the server sends `503 Service Unavailable`
+    /// (or `408 Request Timeout` if the application is waiting for the
+    /// request body) instead of the application.
+    pub fn response_head_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.response_head = Some(timeout);
+        self
+    }
+
+    /// Set the maximum duration of a whole request.
+    ///
+    /// If the limit is exceeded after the response head has been sent,
+    /// the stream is reset.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.request = Some(timeout);
+        self
+    }
+
+    /// Set the maximum duration between the progress of a request, that is,
+    /// between the calls of `data` and `send_data`.
+    ///
+    /// It also limits the time to wait for the next request on an HTTP/1.1
+    /// connection, after which the connection is closed.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.idle = Some(timeout);
+        self
+    }
+
+    /// Set the maximum number of open connections.
+    ///
+    /// While the limit is reached, the server stops accepting new connections.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.limits.max_connections = Some(max);
+        self
+    }
+
+    /// Set the maximum number of requests processed concurrently by the application.
+    pub fn max_concurrent_requests(mut self, max: usize) -> Self {
+        self.limits.max_requests = Some(max);
+        self
+    }
+
+    /// Set the maximum number of requests processed concurrently on each connection.
+    ///
+    /// The value is also advertised to the clients as `SETTINGS_MAX_CONCURRENT_STREAMS`.
+    pub fn max_concurrent_requests_per_connection(mut self, max: usize) -> Self {
+        self.limits.max_requests_per_connection = Some(max);
+        self.h2.max_concurrent_streams(max as u32);
+        self
+    }
+
+    /// Set the behavior when the number of concurrent requests reaches the limit.
+    ///
+    /// The default is `Overload::Reject` with `Retry-After` of one second.
+    pub fn overload(mut self, overload: Overload) -> Self {
+        self.limits.overload = overload;
+        self
+    }
+
+    /// Return the handle of the counters that describe the load of this server.
+    pub fn metrics(&self) -> LoadMetrics {
+        self.metrics.clone()
+    }
+
+    /// Write a line to `access_log` for each completed request.
+    ///
+    /// Regardless of this setting, each connection and request is processed
+    /// within a `tracing` span that carries the same information.
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(Arc::new(access_log));
+        self
+    }
+
+    /// Specify whether to require the PROXY protocol header (version 1 or 2)
+    /// at the beginning of each connection.
+    ///
+    /// If enabled, the client address in the header is used as `RemoteAddr`
+    /// and in the logs, and the connections without a valid header are closed.
+    /// The idle timeout also limits the time to receive the header.
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Specify whether to serve the clients that do not start with
+    /// the HTTP/2 connection preface with HTTP/1.1.
+    ///
+    /// The HTTP/1.1 requests are passed to the same `App`, and those with
+    /// `Upgrade: h2c` are switched to HTTP/2. The default value is `true`.
+    /// If disabled, only HTTP/2 with prior knowledge is accepted.
+    pub fn accept_http1(mut self, enabled: bool) -> Self {
+        self.accept_http1 = enabled;
+        self
+    }
+
+    /// Insert `value` into the extensions of each request as `izanami::state::State<T>`.
+    pub fn state<T>(mut self, value: T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.states.insert(value);
+        self
+    }
+
+    /// Build a value with `f` for each accepted connection, and insert it into
+    /// the extensions of the requests on that connection as `izanami::state::State<T>`.
+    pub fn connection_state<F, T>(mut self, f: F) -> Self
+    where
+        F: Fn(&RemoteAddr) -> T + Send + Sync + 'static,
+        T: Send + Sync + 'static,
+    {
+        self.states.insert_per_connection(f);
+        self
+    }
+
+    /// Record the server and application events to `metrics`.
+    ///
+    /// Use `izanami::metrics::MetricsApp` to serve the recorded values.
+    #[cfg(feature = "metrics")]
+    pub fn record_metrics(mut self, metrics: Metrics) -> Self {
+        self.recorder = Some(metrics);
+        self
     }
 
     pub async fn serve<T>(self, app: T) -> io::Result<()>
@@ -32,32 +215,184 @@ impl Server {
         T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
     {
         let mut listener = self.listener;
+        let control = LoadControl::new(self.limits, self.metrics);
         loop {
-            if let Ok((socket, _)) = listener.accept().await {
-                let handshake = self.h2.handshake(socket);
+            let reservation = control.reserve().await;
+            if let Ok((mut socket, remote_addr)) = listener.accept().await {
+                let guard = reservation.accepted();
+                #[cfg(feature = "metrics")]
+                let recorded = self.recorder.as_ref().map(Metrics::connection);
+                let h2 = self.h2.clone();
+                let proxy_protocol = self.proxy_protocol;
+                let accept_http1 = self.accept_http1;
+                let states = self.states.clone();
                 let app = app.clone();
-                tokio::spawn(async move {
-                    match handshake.await {
-                        Ok(conn) => handle_connection(conn, app).await,
-                        Err(err) => {
-                            tracing::error!("handshake error: {}", err);
-                            return;
+                let mut cx = ConnContext {
+                    remote_addr,
+                    timeouts: self.timeouts,
+                    limiter: control.connection(),
+                    control: control.clone(),
+                    access_log: self.access_log.clone(),
+                    state: ConnectionState::default(),
+                    push_enabled: Arc::new(AtomicBool::new(true)),
+                    #[cfg(feature = "metrics")]
+                    recorder: self.recorder.clone(),
+                };
+                let span = tracing::info_span!("connection", peer = %remote_addr);
+                tokio::spawn(
+                    async move {
+                        if proxy_protocol {
+                            match read_proxy_header(&mut socket, cx.timeouts.idle).await {
+                                Ok(header) => {
+                                    if let Some(source) = header.source {
+                                        cx.remote_addr = source;
+                                    }
+                                }
+                                Err(err) => {
+                                    tracing::debug!("{}", err);
+                                    return;
+                                }
+                            }
                         }
+                        cx.state = states.connect(&RemoteAddr(cx.remote_addr));
+                        serve_connection(socket, h2, accept_http1, app, cx).await;
+                        drop(guard);
+                        #[cfg(feature = "metrics")]
+                        drop(recorded);
                     }
-                });
+                    .instrument(span),
+                );
             }
         }
     }
 }
 
-async fn handle_connection<T>(mut conn: Connection<TcpStream, Data>, app: T)
-where
+/// Read the PROXY protocol header, without consuming the bytes after it.
+async fn read_proxy_header(
+    socket: &mut TcpStream,
+    timeout: Option<Duration>,
+) -> io::Result<ProxyHeader> {
+    let read = async {
+        let mut buf = Vec::new();
+        loop {
+            match proxy_protocol::parse(&buf) {
+                Ok(Status::Complete(header)) => return Ok(header),
+                Ok(Status::Partial(n)) => {
+                    let len = buf.len();
+                    buf.resize(len + n, 0);
+                    socket.read_exact(&mut buf[len..]).await?;
+                }
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            }
+        }
+    };
+    futures::pin_mut!(read);
+
+    match timeout {
+        Some(timeout) => {
+            let delay = tokio::timer::delay(tokio::clock::now() + timeout);
+            match future::select(read, delay).await {
+                Either::Left((result, _)) => result,
+                Either::Right(..) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out while reading the PROXY protocol header",
+                )),
+            }
+        }
+        None => read.await,
+    }
+}
+
+/// The values shared by the requests on a connection.
+#[derive(Debug, Clone)]
+struct ConnContext {
+    remote_addr: SocketAddr,
+    timeouts: Timeouts,
+    control: LoadControl,
+    limiter: Limiter,
+    access_log: Option<Arc<AccessLog>>,
+    state: ConnectionState,
+    /// Whether the client accepts the server push, per `SETTINGS_ENABLE_PUSH`.
+    push_enabled: Arc<AtomicBool>,
+    #[cfg(feature = "metrics")]
+    recorder: Option<Metrics>,
+}
+
+impl ConnContext {
+    /// Record that the server has reset a stream.
+    fn stream_reset(&self) {
+        #[cfg(feature = "metrics")]
+        {
+            if let Some(ref recorder) = self.recorder {
+                recorder.stream_reset();
+            }
+        }
+    }
+}
+
+/// Serve the requests on the connection, detecting the protocol if HTTP/1.1 is accepted.
+async fn serve_connection<T>(
+    mut socket: TcpStream,
+    h2: h2::server::Builder,
+    accept_http1: bool,
+    app: T,
+    cx: ConnContext,
+) where
+    T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
+{
+    let io = if accept_http1 {
+        let mut buf = BytesMut::new();
+        match h2c::read_preface(&mut socket, &mut buf).await {
+            Ok(true) => Rewind::new(socket, buf.freeze()),
+            Ok(false) => match http1::serve_connection(socket, buf, &app, &cx).await {
+                Ok(Some(upgraded)) => upgraded,
+                Ok(None) => return,
+                Err(err) => {
+                    tracing::debug!("HTTP/1.1 connection error: {}", err);
+                    return;
+                }
+            },
+            Err(err) => {
+                tracing::debug!("read error: {}", err);
+                return;
+            }
+        }
+    } else {
+        Rewind::new(socket, Bytes::new())
+    };
+
+    let io = WatchSettings::new(io, cx.push_enabled.clone());
+    match h2.handshake(io).await {
+        Ok(conn) => handle_connection(conn, app, cx).await,
+        Err(err) => tracing::error!("handshake error: {}", err),
+    }
+}
+
+async fn handle_connection<T>(
+    mut conn: Connection<WatchSettings<Rewind<TcpStream>>, Data>,
+    app: T,
+    cx: ConnContext,
+) where
     T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
 {
     loop {
         match conn.accept().await {
             Some(Ok((request, sender))) => {
-                tokio::spawn(handle_request(app.clone(), request, sender));
+                let span = tracing::info_span!(
+                    "request",
+                    peer = %cx.remote_addr,
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    version = ?request.version(),
+                    status = field::Empty,
+                    bytes_in = field::Empty,
+                    bytes_out = field::Empty,
+                    duration_ms = field::Empty,
+                    request_id = field::Empty,
+                );
+                tokio::spawn(
+                    admit_request(app.clone(), request, sender, cx.clone()).instrument(span),
+                );
             }
             Some(Err(err)) => {
                 tracing::error!("accept error: {}", err);
@@ -71,65 +406,510 @@ where
     }
 }
 
-async fn handle_request<T>(app: T, request: Request<RecvStream>, mut sender: SendResponse<Data>)
-where
+async fn admit_request<T>(
+    app: T,
+    request: Request<RecvStream>,
+    mut sender: SendResponse<Data>,
+    cx: ConnContext,
+) where
     T: for<'a> App<Events<'a>>,
 {
-    let (parts, mut receiver) = request.into_parts();
-    let mut stream = None;
+    let started = Instant::now();
+    let span = Span::current();
+    let (mut parts, receiver) = request.into_parts();
+    parts.extensions.insert(RemoteAddr(cx.remote_addr));
+    cx.state.inject(&mut parts.extensions);
+    let mut record = Record::new(&parts);
+    record.remote_addr = Some(cx.remote_addr);
 
-    if let Err(err) = app
-        .call(Request::from_parts(
-            parts,
-            Events {
+    match cx.control.admit(&cx.limiter).await {
+        Ok(guard) => {
+            handle_request(app, parts, receiver, &mut sender, &cx, &mut record, &span).await;
+            drop(guard);
+        }
+        Err(Shed::Reject(retry_after)) => {
+            tracing::debug!("request rejected by the concurrency limit");
+            let mut response = Response::new(());
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            if let Some(retry_after) = retry_after {
+                response.headers_mut().insert(
+                    header::RETRY_AFTER,
+                    HeaderValue::from(retry_after.as_secs().max(1)),
+                );
+            }
+            let _ = sender.send_response(response, true);
+            record.status = Some(StatusCode::SERVICE_UNAVAILABLE);
+        }
+        Err(Shed::Refuse) => {
+            tracing::debug!("stream refused by the concurrency limit");
+            sender.send_reset(Reason::REFUSED_STREAM);
+            cx.stream_reset();
+        }
+    }
+
+    finish_request(&mut record, started, &cx);
+}
+
+/// Record the summary of a completed request to the current span, the access log
+/// and the metrics.
+fn finish_request(record: &mut Record, started: Instant, cx: &ConnContext) {
+    record.duration = started.elapsed();
+
+    let span = Span::current();
+    span.record("bytes_in", record.bytes_in);
+    span.record("bytes_out", record.bytes_out);
+    span.record("duration_ms", record.duration.as_secs_f64() * 1000.0);
+    if let Some(status) = record.status {
+        span.record("status", status.as_u16());
+    }
+    tracing::debug!("request completed");
+
+    if let Some(ref access_log) = cx.access_log {
+        access_log.log(record);
+    }
+    #[cfg(feature = "metrics")]
+    {
+        if let Some(ref recorder) = cx.recorder {
+            recorder.request_completed(record);
+        }
+    }
+}
+
+async fn handle_request<T>(
+    app: T,
+    parts: request::Parts,
+    mut receiver: RecvStream,
+    sender: &mut SendResponse<Data>,
+    cx: &ConnContext,
+    record: &mut Record,
+    span: &Span,
+) where
+    T: for<'a> App<Events<'a>>,
+{
+    let mut stream = None;
+    let watchdog = Watchdog::new(cx.timeouts);
+    let request = Request::from_parts(
+        parts,
+        Events {
+            transport: Transport::H2 {
                 receiver: &mut receiver,
-                sender: &mut sender,
+                sender: &mut *sender,
                 stream: &mut stream,
+                push_enabled: &cx.push_enabled,
+                manual_release: false,
+                reserved: 0,
             },
-        ))
-        .await
-    {
-        let err = err.into();
-        tracing::error!("app error: {}", err);
+            watchdog: &watchdog,
+            record: &mut *record,
+            span,
+        },
+    );
+
+    if let Some((status, reason)) = run_app(&app, request, &watchdog, cx).await {
+        match (status, stream) {
+            (Some(status), None) => {
+                let mut response = Response::new(());
+                *response.status_mut() = status;
+                let _ = sender.send_response(response, true);
+                record.status = Some(status);
+            }
+            (_, Some(mut stream)) => {
+                stream.send_reset(reason);
+                cx.stream_reset();
+            }
+            (None, None) => {}
+        }
     }
 
     drop(receiver);
 }
 
+/// Call the application within the limits of `watchdog`.
+///
+/// If the application failed, return the status of the response sent instead of
+/// the application and the reason of the reset used after the response head has been sent.
+async fn run_app<T>(
+    app: &T,
+    request: Request<Events<'_>>,
+    watchdog: &Watchdog,
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))] cx: &ConnContext,
+) -> Option<(Option<StatusCode>, Reason)>
+where
+    T: for<'a> App<Events<'a>>,
+{
+    match watchdog
+        .run(AssertUnwindSafe(app.call(request)).catch_unwind())
+        .await
+    {
+        Ok(Ok(Ok(()))) => None,
+        Ok(Ok(Err(err))) => {
+            let err = err.into();
+            tracing::error!("app error: {}", err);
+            #[cfg(feature = "metrics")]
+            {
+                if let Some(ref recorder) = cx.recorder {
+                    recorder.app_error();
+                }
+            }
+            None
+        }
+        Ok(Err(..)) => {
+            tracing::error!("app panicked");
+            #[cfg(feature = "metrics")]
+            {
+                if let Some(ref recorder) = cx.recorder {
+                    recorder.app_panic();
+                }
+            }
+            Some((
+                Some(StatusCode::INTERNAL_SERVER_ERROR),
+                Reason::INTERNAL_ERROR,
+            ))
+        }
+        Err(expired) => {
+            tracing::debug!("request timed out");
+            Some((expired.status(), Reason::CANCEL))
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Events<'a> {
-    receiver: &'a mut RecvStream,
-    sender: &'a mut SendResponse<Data>,
-    stream: &'a mut Option<SendStream<Data>>,
+    transport: Transport<'a>,
+    watchdog: &'a Watchdog,
+    record: &'a mut Record,
+    span: &'a Span,
+}
+
+/// The connection that carries the request.
+#[derive(Debug)]
+enum Transport<'a> {
+    H2 {
+        receiver: &'a mut RecvStream,
+        sender: &'a mut SendResponse<Data>,
+        stream: &'a mut Option<SendStream<Data>>,
+        /// Whether the client accepts the server push.
+        push_enabled: &'a AtomicBool,
+        /// Whether the capacity of the received data is released by the application.
+        manual_release: bool,
+        /// The capacity requested before the response head is sent.
+        reserved: usize,
+    },
+    Http1(&'a mut http1::Exchange),
 }
 
 impl Events<'_> {
     pub async fn data(&mut self) -> Option<Result<Data, h2::Error>> {
-        let data = self.receiver.data().await;
-        if let Some(Ok(ref data)) = data {
-            let release_capacity = self.receiver.release_capacity();
-            if let Err(err) = release_capacity.release_capacity(data.len()) {
-                return Some(Err(err));
+        self.watchdog.start_reading();
+        let data = match self.transport {
+            Transport::H2 {
+                ref mut receiver,
+                manual_release,
+                ..
+            } => {
+                let data = receiver.data().await;
+                if let Some(Ok(ref data)) = data.as_ref().filter(|_| !manual_release) {
+                    let release_capacity = receiver.release_capacity();
+                    if let Err(err) = release_capacity.release_capacity(data.len()) {
+                        return Some(Err(err));
+                    }
+                }
+                data
             }
+            Transport::Http1(ref mut exchange) => exchange
+                .data()
+                .await
+                .map(|res| res.map_err(h2::Error::from)),
+        };
+        self.watchdog.touch();
+        if let Some(Ok(ref data)) = data {
+            self.record.bytes_in += data.len() as u64;
         }
         data.map(|res| res.map(Data))
     }
 
     pub async fn trailers(&mut self) -> Result<Option<HeaderMap>, h2::Error> {
-        self.receiver.trailers().await
+        self.watchdog.start_reading();
+        let trailers = match self.transport {
+            Transport::H2 {
+                ref mut receiver, ..
+            } => receiver.trailers().await,
+            Transport::Http1(ref mut exchange) => exchange.trailers().await.map_err(Into::into),
+        };
+        self.watchdog.touch();
+        trailers
     }
 
-    pub async fn send_response<T>(&mut self, response: Response<T>) -> Result<(), h2::Error>
+    pub async fn start_send_response(
+        &mut self,
+        response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), h2::Error> {
+        let status = response.status();
+        match self.transport {
+            Transport::H2 {
+                ref mut sender,
+                ref mut stream,
+                reserved,
+                ..
+            } => {
+                let mut send_stream = sender.send_response(response, end_of_stream)?;
+                if reserved > 0 {
+                    send_stream.reserve_capacity(reserved);
+                }
+                stream.replace(send_stream);
+            }
+            Transport::Http1(ref mut exchange) => {
+                exchange
+                    .start_send_response(response, end_of_stream)
+                    .await?
+            }
+        }
+        self.record.status = Some(status);
+        self.record.head_duration = self.record.started.elapsed().ok();
+        self.span.record("status", status.as_u16());
+        self.watchdog.head_sent();
+        Ok(())
+    }
+
+    pub async fn send_data<T>(&mut self, data: T, end_of_stream: bool) -> Result<(), h2::Error>
     where
         T: Into<Data>,
     {
-        let (parts, body) = response.into_parts();
-        let response = Response::from_parts(parts, ());
-        self.start_send_response(response, false).await?;
-        self.send_data(body.into(), true).await?;
+        let data = data.into();
+        self.record.bytes_out += data.remaining() as u64;
+
+        match self.transport {
+            Transport::H2 { ref mut stream, .. } => {
+                let stream = stream.as_mut().ok_or_else(no_response_head)?;
+                send_h2_data(stream, data, end_of_stream).await?;
+            }
+            Transport::Http1(ref mut exchange) => {
+                exchange.send_data(data.0, end_of_stream).await?;
+            }
+        }
+        self.watchdog.touch();
+
         Ok(())
     }
 
+    pub async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), h2::Error> {
+        match self.transport {
+            Transport::H2 { ref mut stream, .. } => {
+                let stream = stream.as_mut().ok_or_else(no_response_head)?;
+                stream.send_trailers(trailers)
+            }
+            Transport::Http1(ref mut exchange) => {
+                exchange.send_trailers(trailers).await.map_err(Into::into)
+            }
+        }
+    }
+
+    /// Abort the exchange, by resetting the stream with `reason` on HTTP/2
+    /// or closing the connection on HTTP/1.x.
+    pub fn reset(&mut self, reason: izanami::Reason) {
+        match self.transport {
+            Transport::H2 {
+                ref mut sender,
+                ref mut stream,
+                ..
+            } => {
+                let reason = Reason::from(u32::from(reason));
+                match stream {
+                    Some(stream) => stream.send_reset(reason),
+                    None => sender.send_reset(reason),
+                }
+            }
+            Transport::Http1(ref mut exchange) => exchange.reset(reason),
+        }
+    }
+
+    /// Wait until the client cancels the request, and return the reason.
+    ///
+    /// The cancellation is signalled by `RST_STREAM` on HTTP/2, or by closing
+    /// the connection on HTTP/1.x.
+    pub async fn cancelled(&mut self) -> izanami::Reason {
+        match self.transport {
+            Transport::H2 {
+                ref mut sender,
+                ref mut stream,
+                ..
+            } => {
+                let reason = poll_fn(|cx| match stream {
+                    Some(stream) => stream.poll_reset(cx),
+                    None => sender.poll_reset(cx),
+                })
+                .await;
+                // The failure of the connection is regarded as the cancellation.
+                reason.map_or(izanami::Reason::CANCEL, |reason| {
+                    izanami::Reason::from(u32::from(reason))
+                })
+            }
+            Transport::Http1(ref mut exchange) => exchange.cancelled().await,
+        }
+    }
+
+    /// Request the capacity to send `capacity` bytes of the response body.
+    ///
+    /// If the response head has not been sent yet, the capacity is requested
+    /// when it is sent. It does nothing on HTTP/1.x.
+    pub fn reserve_send_capacity(&mut self, capacity: usize) {
+        if let Transport::H2 {
+            ref mut stream,
+            ref mut reserved,
+            ..
+        } = self.transport
+        {
+            match stream {
+                Some(stream) => stream.reserve_capacity(capacity),
+                None => *reserved = capacity,
+            }
+        }
+    }
+
+    /// Poll the number of bytes that can be sent without waiting for the client.
+    ///
+    /// On HTTP/2, the capacity is assigned after `reserve_send_capacity` is called.
+    /// It fails on HTTP/2 if the response head has not been sent yet, and
+    /// returns `usize::MAX` on HTTP/1.x, where the backpressure is applied
+    /// by `send_data` itself.
+    pub fn poll_send_capacity(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<usize, h2::Error>> {
+        match self.transport {
+            Transport::H2 { ref mut stream, .. } => match stream {
+                Some(stream) => poll_h2_capacity(stream, cx),
+                None => Poll::Ready(Err(no_response_head())),
+            },
+            Transport::Http1(..) => Poll::Ready(Ok(usize::MAX)),
+        }
+    }
+
+    /// Specify whether to release the capacity of the received data manually.
+    ///
+    /// Returns `false` on HTTP/1.x, where the request body is read only when
+    /// `data` is called.
+    pub fn set_manual_release(&mut self, enabled: bool) -> bool {
+        match self.transport {
+            Transport::H2 {
+                ref mut manual_release,
+                ..
+            } => {
+                *manual_release = enabled;
+                true
+            }
+            Transport::Http1(..) => false,
+        }
+    }
+
+    /// Release the capacity of `len` bytes of the received data.
+    pub fn release_capacity(&mut self, len: usize) -> Result<(), h2::Error> {
+        match self.transport {
+            Transport::H2 {
+                ref mut receiver, ..
+            } => receiver.release_capacity().release_capacity(len),
+            Transport::Http1(..) => Ok(()),
+        }
+    }
+
+    /// Return whether responses can be pushed to the client, that is, whether
+    /// the request is received over HTTP/2 and the client has not disabled
+    /// server push with `SETTINGS_ENABLE_PUSH`.
+    pub fn is_push_supported(&self) -> bool {
+        match self.transport {
+            Transport::H2 { push_enabled, .. } => push_enabled.load(Ordering::Acquire),
+            Transport::Http1(..) => false,
+        }
+    }
+
+    /// Promise the specified request to the client, and return the handle
+    /// to send the response to it.
+    ///
+    /// The promised request must be cacheable and safe without a body, such as `GET`,
+    /// and must be pushed before the response to the original request ends.
+    /// It fails if the client has disabled server push. The pushed response is
+    /// not sent until the client advertises `SETTINGS_MAX_CONCURRENT_STREAMS`.
+    pub fn push(&mut self, request: Request<()>) -> Result<PushedEvents, h2::Error> {
+        match self.transport {
+            Transport::H2 {
+                ref mut sender,
+                push_enabled,
+                ..
+            } => {
+                if !push_enabled.load(Ordering::Acquire) {
+                    return Err(io::Error::other("server push is disabled by the client").into());
+                }
+                let sender = sender.push_request(request)?;
+                Ok(PushedEvents {
+                    sender,
+                    stream: None,
+                })
+            }
+            Transport::Http1(..) => Err(io::Error::other("server push requires HTTP/2").into()),
+        }
+    }
+}
+
+/// Send `data` on the stream, splitting it into the chunks as large as
+/// the capacity assigned by the flow control.
+async fn send_h2_data(
+    stream: &mut SendStream<Data>,
+    data: Data,
+    end_of_stream: bool,
+) -> Result<(), h2::Error> {
+    let Data(mut data) = data;
+    // No capacity is assigned for an empty frame.
+    if data.is_empty() {
+        return stream.send_data(Data(data), end_of_stream);
+    }
+    while !data.is_empty() {
+        stream.reserve_capacity(data.len());
+        if stream.capacity() == 0 {
+            match poll_fn(|cx| stream.poll_capacity(cx)).await {
+                Some(capacity) => capacity?,
+                // The stream is no longer able to send data, so sending
+                // it results in the corresponding error.
+                None => return stream.send_data(Data(data), end_of_stream),
+            };
+            continue;
+        }
+        let chunk = data.split_to(stream.capacity().min(data.len()));
+        stream.send_data(Data(chunk), end_of_stream && data.is_empty())?;
+    }
+    Ok(())
+}
+
+/// Poll the capacity to send data, as `izanami::Events::poll_send_capacity`.
+fn poll_h2_capacity(
+    stream: &mut SendStream<Data>,
+    cx: &mut task::Context<'_>,
+) -> Poll<Result<usize, h2::Error>> {
+    if stream.capacity() > 0 {
+        return Poll::Ready(Ok(stream.capacity()));
+    }
+    match futures::ready!(stream.poll_capacity(cx)) {
+        Some(capacity) => Poll::Ready(capacity),
+        None => Poll::Ready(Err(Reason::STREAM_CLOSED.into())),
+    }
+}
+
+/// The error returned when the response body is sent before its head.
+fn no_response_head() -> h2::Error {
+    io::Error::other("the response head has not been sent").into()
+}
+
+/// The handle to send the response to a request promised by `Events::push`.
+///
+/// The pushed response is not limited by the timeouts of the original request,
+/// and is not recorded in the access log.
+#[derive(Debug)]
+pub struct PushedEvents {
+    sender: SendPushedResponse<Data>,
+    stream: Option<SendStream<Data>>,
+}
+
+impl PushedEvents {
     pub async fn start_send_response(
         &mut self,
         response: Response<()>,
@@ -144,20 +924,25 @@ impl Events<'_> {
     where
         T: Into<Data>,
     {
-        let stream = self.stream.as_mut().unwrap();
-        let data = data.into();
-
-        stream.reserve_capacity(data.remaining());
-        poll_fn(|cx| stream.poll_capacity(cx)).await.transpose()?;
-        stream.send_data(data, end_of_stream)?;
-
-        Ok(())
+        let stream = self.stream.as_mut().ok_or_else(no_response_head)?;
+        send_h2_data(stream, data.into(), end_of_stream).await
     }
 
     pub async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), h2::Error> {
-        let stream = self.stream.as_mut().unwrap();
+        let stream = self.stream.as_mut().ok_or_else(no_response_head)?;
         stream.send_trailers(trailers)
     }
+
+    /// Send a complete response to the promised request.
+    pub async fn send_response<T>(&mut self, response: Response<T>) -> Result<(), h2::Error>
+    where
+        T: Into<Data>,
+    {
+        let (parts, body) = response.into_parts();
+        self.start_send_response(Response::from_parts(parts, ()), false)
+            .await?;
+        self.send_data(body, true).await
+    }
 }
 
 #[async_trait]
@@ -198,6 +983,44 @@ impl<'a> izanami::Events for Events<'a> {
     async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error> {
         self.send_trailers(trailers).await
     }
+
+    #[inline]
+    fn reset(&mut self, reason: izanami::Reason) {
+        self.reset(reason)
+    }
+
+    #[inline]
+    async fn cancelled(&mut self) -> izanami::Reason {
+        self.cancelled().await
+    }
+
+    #[inline]
+    fn reserve_send_capacity(&mut self, capacity: usize) {
+        self.reserve_send_capacity(capacity)
+    }
+
+    #[inline]
+    fn poll_send_capacity(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<usize, Self::Error>> {
+        self.poll_send_capacity(cx)
+    }
+
+    #[inline]
+    fn set_manual_release(&mut self, enabled: bool) -> bool {
+        self.set_manual_release(enabled)
+    }
+
+    #[inline]
+    fn release_capacity(&mut self, len: usize) -> Result<(), Self::Error> {
+        self.release_capacity(len)
+    }
+
+    #[inline]
+    fn is_push_supported(&self) -> bool {
+        self.is_push_supported()
+    }
 }
 
 #[derive(Debug)]
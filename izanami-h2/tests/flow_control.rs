@@ -0,0 +1,132 @@
+mod support;
+
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::future::poll_fn;
+use http::{Request, Response};
+use izanami::App;
+use izanami_h2::{Events, Server};
+use support::run;
+use tokio::net::TcpStream;
+
+/// Larger than the initial window size of HTTP/2 (65535 bytes).
+const BODY_LEN: usize = 200_000;
+
+/// Count the request body, releasing its capacity manually, and send a large response body.
+#[derive(Clone)]
+struct Transfer;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Transfer {
+    type Error = h2::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        assert!(events.set_manual_release(true));
+        let mut received = 0;
+        while let Some(data) = events.data().await {
+            let len = data?.remaining();
+            received += len;
+            // The client would stall at the initial window without the release.
+            events.release_capacity(len)?;
+        }
+        assert_eq!(received, BODY_LEN);
+
+        events.start_send_response(Response::new(()), false).await?;
+        events.reserve_send_capacity(BODY_LEN);
+        let capacity = poll_fn(|cx| events.poll_send_capacity(cx)).await?;
+        assert!(capacity > 0 && capacity < BODY_LEN, "{}", capacity);
+
+        // The body is sent in the chunks within the capacity assigned by the client.
+        events.send_data(vec![b'x'; BODY_LEN], true).await
+    }
+}
+
+/// Request the capacity before sending the response head.
+#[derive(Clone)]
+struct Early;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Early {
+    type Error = h2::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        events.reserve_send_capacity(BODY_LEN);
+        assert!(poll_fn(|cx| events.poll_send_capacity(cx)).await.is_err());
+
+        events.start_send_response(Response::new(()), false).await?;
+        let capacity = poll_fn(|cx| events.poll_send_capacity(cx)).await?;
+        assert!(capacity > 0, "{}", capacity);
+        events.send_data(vec![b'x'; capacity], true).await
+    }
+}
+
+#[test]
+fn transfer_beyond_initial_window() {
+    run(|_| async {
+        let server = Server::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            server.serve(Transfer).await.unwrap();
+        });
+
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let (client, conn) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+        let mut client = client.ready().await.unwrap();
+        let request = Request::post("http://localhost/").body(()).unwrap();
+        let (response, mut send_stream) = client.send_request(request, false).unwrap();
+        send_stream
+            .send_data(Bytes::from(vec![b'x'; BODY_LEN]), true)
+            .unwrap();
+
+        let mut body = response.await.unwrap().into_body();
+        let mut received = 0;
+        while let Some(data) = body.data().await {
+            let data = data.unwrap();
+            received += data.len();
+            body.release_capacity()
+                .release_capacity(data.len())
+                .unwrap();
+        }
+        assert_eq!(received, BODY_LEN);
+    });
+}
+
+#[test]
+fn reserve_capacity_before_response_head() {
+    run(|_| async {
+        let server = Server::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            server.serve(Early).await.unwrap();
+        });
+
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let (client, conn) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+        let mut client = client.ready().await.unwrap();
+        let request = Request::get("http://localhost/").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+
+        let response = response.await.unwrap();
+        assert_eq!(response.status(), 200);
+        let mut body = response.into_body();
+        let mut received = 0;
+        while let Some(data) = body.data().await {
+            received += data.unwrap().len();
+        }
+        assert!(received > 0);
+    });
+}
@@ -0,0 +1,374 @@
+mod support;
+
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use http::{Request, Response};
+use izanami::App;
+use izanami_h2::{Events, Server};
+use std::{net::SocketAddr, time::Duration};
+use support::run;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Echo the request body in two chunks, followed by the trailers.
+#[derive(Clone)]
+struct Echo;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Echo {
+    type Error = h2::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+        let mut body = Vec::new();
+        while let Some(data) = events.data().await {
+            body.extend_from_slice(data?.bytes());
+        }
+        let trailers = events.trailers().await?;
+
+        let response = Response::builder()
+            .header("x-path", parts.uri.path())
+            .body(())
+            .unwrap();
+        events.start_send_response(response, false).await?;
+        events.send_data(Bytes::from("echo:"), false).await?;
+        match trailers {
+            Some(trailers) => {
+                events.send_data(Bytes::from(body), false).await?;
+                events.send_trailers(trailers).await
+            }
+            None => events.send_data(Bytes::from(body), true).await,
+        }
+    }
+}
+
+async fn start_server() -> SocketAddr {
+    let server = Server::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        server.serve(Echo).await.unwrap();
+    });
+    addr
+}
+
+/// Read from `stream` until `expected` bytes arrive.
+async fn read_until(stream: &mut TcpStream, received: &mut Vec<u8>, expected: &[u8]) {
+    let mut buf = [0; 4096];
+    while !received.windows(expected.len()).any(|w| w == expected) {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "{:?}", String::from_utf8_lossy(received));
+        received.extend_from_slice(&buf[..n]);
+    }
+}
+
+#[test]
+fn fallback_to_http1() {
+    run(|_| async {
+        let addr = start_server().await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+        stream
+            .write_all(b"POST /first HTTP/1.1\r\nhost: localhost\r\ncontent-length: 5\r\n\r\nhello")
+            .await
+            .unwrap();
+        let mut received = vec![];
+        read_until(&mut stream, &mut received, b"0\r\n\r\n").await;
+        let response = String::from_utf8(received).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\n"),
+            "{:?}",
+            response
+        );
+        assert!(response.contains("x-path: /first\r\n"), "{:?}", response);
+        assert!(
+            response.contains("transfer-encoding: chunked\r\n"),
+            "{:?}",
+            response
+        );
+        assert!(
+            response.ends_with("\r\n\r\n5\r\necho:\r\n5\r\nhello\r\n0\r\n\r\n"),
+            "{:?}",
+            response
+        );
+
+        // The connection is kept alive, and the chunked body with trailers is echoed.
+        stream
+            .write_all(
+                b"POST /second HTTP/1.1\r\nhost: localhost\r\ntransfer-encoding: chunked\r\n\r\n\
+                  3\r\nabc\r\n2\r\nde\r\n0\r\nx-checksum: 42\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut received = vec![];
+        read_until(&mut stream, &mut received, b"x-checksum: 42\r\n\r\n").await;
+        let response = String::from_utf8(received).unwrap();
+        assert!(response.contains("x-path: /second\r\n"), "{:?}", response);
+        assert!(
+            response.ends_with("5\r\necho:\r\n5\r\nabcde\r\n0\r\nx-checksum: 42\r\n\r\n"),
+            "{:?}",
+            response
+        );
+    });
+}
+
+#[test]
+fn http1_0_is_close_delimited() {
+    run(|_| async {
+        let addr = start_server().await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET /old HTTP/1.0\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = vec![];
+        stream.read_to_end(&mut received).await.unwrap();
+        let response = String::from_utf8(received).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 200 OK\r\n"),
+            "{:?}",
+            response
+        );
+        assert!(response.contains("connection: close\r\n"), "{:?}", response);
+        assert!(response.ends_with("\r\n\r\necho:"), "{:?}", response);
+    });
+}
+
+#[test]
+fn reject_ambiguous_content_length() {
+    run(|_| async {
+        let addr = start_server().await;
+        for head in &[
+            &b"POST / HTTP/1.1\r\nhost: localhost\r\ncontent-length: 5\r\ncontent-length: 5\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nhost: localhost\r\ncontent-length: 5, 5\r\n\r\n"[..],
+            &b"POST / HTTP/1.1\r\nhost: localhost\r\ncontent-length: +5\r\n\r\n"[..],
+        ] {
+            let mut stream = TcpStream::connect(&addr).await.unwrap();
+            stream.write_all(head).await.unwrap();
+            let mut received = vec![];
+            stream.read_to_end(&mut received).await.unwrap();
+            let response = String::from_utf8(received).unwrap();
+            assert!(
+                response.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                "{:?}",
+                response
+            );
+        }
+    });
+}
+
+#[test]
+fn reject_too_many_trailer_fields() {
+    run(|_| async {
+        let addr = start_server().await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        let mut request = b"POST / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\
+                            transfer-encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n"
+            .to_vec();
+        for i in 0..1000 {
+            request.extend_from_slice(format!("x-trailer-{}: {}\r\n", i, i).as_bytes());
+        }
+        request.extend_from_slice(b"\r\n");
+        let _ = stream.write_all(&request).await;
+        let mut received = vec![];
+        let _ = stream.read_to_end(&mut received).await;
+        let response = String::from_utf8(received).unwrap();
+        assert!(!response.contains("echo:"), "{:?}", response);
+    });
+}
+
+#[test]
+fn close_after_conflicting_framing() {
+    run(|_| async {
+        let addr = start_server().await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\nhost: localhost\r\ncontent-length: 4\r\n\
+                  transfer-encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n\
+                  GET /smuggled HTTP/1.1\r\nhost: localhost\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut received = vec![];
+        stream.read_to_end(&mut received).await.unwrap();
+        let response = String::from_utf8(received).unwrap();
+        assert!(response.contains("connection: close\r\n"), "{:?}", response);
+        assert!(
+            response.ends_with("5\r\necho:\r\n3\r\nabc\r\n0\r\n\r\n"),
+            "{:?}",
+            response
+        );
+        assert!(!response.contains("/smuggled"), "{:?}", response);
+    });
+}
+
+#[test]
+fn upgrade_to_h2c() {
+    run(|_| async {
+        let addr = start_server().await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(
+                b"GET /upgraded HTTP/1.1\r\nhost: localhost\r\n\
+                  connection: Upgrade, HTTP2-Settings\r\nupgrade: h2c\r\n\
+                  http2-settings: AAMAAABkAAQAAP__\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut received = vec![];
+        read_until(&mut stream, &mut received, b"\r\n\r\n").await;
+        assert!(
+            received.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"),
+            "{:?}",
+            String::from_utf8_lossy(&received)
+        );
+        let head_len = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let mut frames = received.split_off(head_len);
+
+        // The connection preface followed by an empty SETTINGS frame.
+        stream
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\x00\x00\x00\x04\x00\x00\x00\x00\x00")
+            .await
+            .unwrap();
+
+        // The response to the upgrade request arrives on the stream 1.
+        let mut buf = [0; 4096];
+        let mut data = vec![];
+        loop {
+            while frames.len() >= 9 {
+                let len = (usize::from(frames[0]) << 16)
+                    | (usize::from(frames[1]) << 8)
+                    | usize::from(frames[2]);
+                if frames.len() < 9 + len {
+                    break;
+                }
+                let kind = frames[3];
+                let flags = frames[4];
+                let stream_id = u32::from_be_bytes([frames[5], frames[6], frames[7], frames[8]]);
+                // DATA
+                if kind == 0x0 {
+                    assert_eq!(stream_id, 1);
+                    data.extend_from_slice(&frames[9..9 + len]);
+                    if flags & 0x1 != 0 {
+                        assert_eq!(data, b"echo:");
+                        return;
+                    }
+                }
+                frames.drain(..9 + len);
+            }
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0);
+            frames.extend_from_slice(&buf[..n]);
+        }
+    });
+}
+
+#[test]
+fn upgrade_applies_http2_settings() {
+    run(|_| async {
+        let addr = start_server().await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        // SETTINGS_INITIAL_WINDOW_SIZE = 3
+        stream
+            .write_all(
+                b"GET /upgraded HTTP/1.1\r\nhost: localhost\r\n\
+                  connection: Upgrade, HTTP2-Settings\r\nupgrade: h2c\r\n\
+                  http2-settings: AAQAAAAD\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut received = vec![];
+        read_until(&mut stream, &mut received, b"\r\n\r\n").await;
+        let head_len = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let mut frames = received.split_off(head_len);
+        stream
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\x00\x00\x00\x04\x00\x00\x00\x00\x00")
+            .await
+            .unwrap();
+
+        // Once the settings are acknowledged, the request on the stream 3 is
+        // answered within the window given by `HTTP2-Settings`.
+        let mut buf = [0; 4096];
+        let mut data = vec![];
+        loop {
+            while frames.len() >= 9 {
+                let len = (usize::from(frames[0]) << 16)
+                    | (usize::from(frames[1]) << 8)
+                    | usize::from(frames[2]);
+                if frames.len() < 9 + len {
+                    break;
+                }
+                let kind = frames[3];
+                let flags = frames[4];
+                let stream_id = u32::from_be_bytes([frames[5], frames[6], frames[7], frames[8]]);
+                match kind {
+                    // SETTINGS with ACK
+                    0x4 if flags & 0x1 != 0 => {
+                        // HEADERS of `GET http://localhost/` with END_STREAM and END_HEADERS
+                        stream
+                            .write_all(
+                                b"\x00\x00\x0e\x01\x05\x00\x00\x00\x03\
+                                  \x82\x86\x84\x01\x09localhost",
+                            )
+                            .await
+                            .unwrap();
+                    }
+                    // DATA
+                    0x0 if stream_id == 3 => {
+                        let payload = &frames[9..9 + len];
+                        if data.is_empty() {
+                            assert_eq!(payload, b"ech");
+                            // WINDOW_UPDATE on the stream 3
+                            stream
+                                .write_all(b"\x00\x00\x04\x08\x00\x00\x00\x00\x03\x00\x00\x00\x0a")
+                                .await
+                                .unwrap();
+                        }
+                        data.extend_from_slice(payload);
+                        if flags & 0x1 != 0 {
+                            assert_eq!(data, b"echo:");
+                            return;
+                        }
+                    }
+                    _ => {}
+                }
+                frames.drain(..9 + len);
+            }
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0);
+            frames.extend_from_slice(&buf[..n]);
+        }
+    });
+}
+
+#[test]
+fn close_idle_connection() {
+    run(|clock| async move {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .idle_timeout(Duration::from_secs(10));
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            server.serve(Echo).await.unwrap();
+        });
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = vec![];
+        read_until(&mut stream, &mut received, b"0\r\n\r\n").await;
+
+        // The server closes the connection while waiting for the next request.
+        clock.advance(Duration::from_secs(60));
+        let mut rest = vec![];
+        stream.read_to_end(&mut rest).await.unwrap();
+        assert!(rest.is_empty(), "{:?}", String::from_utf8_lossy(&rest));
+    });
+}
@@ -0,0 +1,93 @@
+mod support;
+
+use async_trait::async_trait;
+use futures::{channel::mpsc, future, StreamExt};
+use http::{Request, StatusCode};
+use izanami::App;
+use izanami_h2::{Events, Overload, Server};
+use std::time::Duration;
+use support::run;
+use tokio::net::TcpStream;
+
+/// An app that notifies when it is called, and never responds.
+#[derive(Clone)]
+struct TestApp {
+    notify: mpsc::UnboundedSender<()>,
+}
+
+#[async_trait]
+impl<'a> App<Events<'a>> for TestApp {
+    type Error = h2::Error;
+
+    async fn call(&self, _: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let _ = self.notify.unbounded_send(());
+        future::pending().await
+    }
+}
+
+/// Send two requests on a connection, and return the result of the second.
+async fn second_request(
+    server: Server,
+) -> (
+    Result<http::Response<h2::RecvStream>, h2::Error>,
+    izanami_h2::LoadMetrics,
+) {
+    let addr = server.local_addr().unwrap();
+    let metrics = server.metrics();
+    let (notify, mut notified) = mpsc::unbounded();
+    tokio::spawn(async move {
+        server.serve(TestApp { notify }).await.unwrap();
+    });
+
+    let stream = TcpStream::connect(&addr).await.unwrap();
+    let (client, conn) = h2::client::handshake(stream).await.unwrap();
+    tokio::spawn(async move {
+        let _ = conn.await;
+    });
+
+    let mut client = client.ready().await.unwrap();
+    let request = Request::get("http://localhost/").body(()).unwrap();
+    let (_first, _) = client.send_request(request, true).unwrap();
+    notified.next().await.unwrap();
+
+    let mut client = client.ready().await.unwrap();
+    let request = Request::get("http://localhost/").body(()).unwrap();
+    let (second, _) = client.send_request(request, true).unwrap();
+    (second.await, metrics)
+}
+
+#[test]
+fn reject_over_limit() {
+    run(|_| async {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .max_concurrent_requests(1)
+            .overload(Overload::Reject(Duration::from_secs(5)));
+        let (response, metrics) = second_request(server).await;
+        let response = response.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "5");
+        assert_eq!(metrics.requests_rejected(), 1);
+        assert_eq!(metrics.active_requests(), 1);
+    });
+}
+
+#[test]
+fn refuse_stream_over_limit() {
+    run(|_| async {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .max_concurrent_requests(1)
+            .overload(Overload::RefuseStream);
+        let (response, metrics) = second_request(server).await;
+        let err = response.unwrap_err();
+        assert_eq!(err.reason(), Some(h2::Reason::REFUSED_STREAM));
+        assert_eq!(metrics.streams_refused(), 1);
+        assert_eq!(metrics.active_connections(), 1);
+    });
+}
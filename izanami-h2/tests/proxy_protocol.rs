@@ -0,0 +1,61 @@
+mod support;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{Request, Response};
+use izanami::{App, RemoteAddr};
+use izanami_h2::{Events, Server};
+use support::run;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+/// Respond with the remote address of the request.
+#[derive(Clone)]
+struct Peer;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Peer {
+    type Error = h2::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+        let RemoteAddr(addr) = parts.extensions.get::<RemoteAddr>().cloned().unwrap();
+        events.start_send_response(Response::new(()), false).await?;
+        events.send_data(Bytes::from(addr.to_string()), true).await
+    }
+}
+
+#[test]
+fn use_source_address() {
+    run(|_| async {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .proxy_protocol(true);
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            server.serve(Peer).await.unwrap();
+        });
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        let mut header = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+        header.extend_from_slice(&[203, 0, 113, 7, 192, 0, 2, 1, 0xdc, 0x04, 0x01, 0xbb]);
+        stream.write_all(&header).await.unwrap();
+
+        let (client, conn) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+        let mut client = client.ready().await.unwrap();
+        let request = Request::get("http://localhost/").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        let mut body = response.await.unwrap().into_body();
+        let mut received = vec![];
+        while let Some(data) = body.data().await {
+            received.extend_from_slice(&data.unwrap());
+        }
+        assert_eq!(received, b"203.0.113.7:56324");
+    });
+}
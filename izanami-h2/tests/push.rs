@@ -0,0 +1,131 @@
+mod support;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{Request, Response};
+use izanami::{App, Events as _};
+use izanami_h2::{Events, Server};
+use std::net::SocketAddr;
+use support::run;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Push the stylesheet along with the page, if the server push is available.
+#[derive(Clone)]
+struct Page;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Page {
+    type Error = h2::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        if !events.is_push_supported() {
+            return events
+                .send_response(Response::new(Bytes::from("no push")))
+                .await;
+        }
+
+        let promised = Request::get("http://localhost/style.css").body(()).unwrap();
+        let mut pushed = events.push(promised)?;
+        events.start_send_response(Response::new(()), false).await?;
+        pushed
+            .send_response(Response::new(Bytes::from("body {}")))
+            .await?;
+        events.send_data(Bytes::from("<html>"), true).await
+    }
+}
+
+async fn start_server() -> SocketAddr {
+    let server = Server::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        server.serve(Page).await.unwrap();
+    });
+    addr
+}
+
+async fn read_body(mut body: h2::RecvStream) -> Vec<u8> {
+    let mut received = vec![];
+    while let Some(data) = body.data().await {
+        received.extend_from_slice(&data.unwrap());
+    }
+    received
+}
+
+#[test]
+fn push_stylesheet() {
+    run(|_| async {
+        let addr = start_server().await;
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        // The server opens the pushed streams within the concurrency limit of the client.
+        let (client, conn) = h2::client::Builder::new()
+            .max_concurrent_streams(10)
+            .handshake::<_, Bytes>(stream)
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+        let mut client = client.ready().await.unwrap();
+        let request = Request::get("http://localhost/").body(()).unwrap();
+        let (mut response, _) = client.send_request(request, true).unwrap();
+        let mut push_promises = response.push_promises();
+
+        let body = read_body(response.await.unwrap().into_body()).await;
+        assert_eq!(body, b"<html>");
+
+        let (promised, pushed) = push_promises
+            .push_promise()
+            .await
+            .unwrap()
+            .unwrap()
+            .into_parts();
+        assert_eq!(promised.uri().path(), "/style.css");
+        let body = read_body(pushed.await.unwrap().into_body()).await;
+        assert_eq!(body, b"body {}");
+    });
+}
+
+#[test]
+fn push_is_not_supported_when_disabled_by_client() {
+    run(|_| async {
+        let addr = start_server().await;
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let (client, conn) = h2::client::Builder::new()
+            .enable_push(false)
+            .handshake::<_, Bytes>(stream)
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+        let mut client = client.ready().await.unwrap();
+        let request = Request::get("http://localhost/").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+
+        let body = read_body(response.await.unwrap().into_body()).await;
+        assert_eq!(body, b"no push");
+    });
+}
+
+#[test]
+fn push_is_not_supported_over_http1() {
+    run(|_| async {
+        let addr = start_server().await;
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = vec![];
+        stream.read_to_end(&mut received).await.unwrap();
+        let response = String::from_utf8(received).unwrap();
+        assert!(response.contains("\r\nno push\r\n"), "{:?}", response);
+    });
+}
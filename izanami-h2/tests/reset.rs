@@ -0,0 +1,148 @@
+mod support;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{Request, Response};
+use izanami::{App, Reason};
+use izanami_h2::{Events, Server};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use support::run;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::oneshot,
+};
+
+/// Stream the response until the client cancels the request, and report the reason.
+#[derive(Clone)]
+struct WaitCancel(Arc<Mutex<Option<oneshot::Sender<Reason>>>>);
+
+#[async_trait]
+impl<'a> App<Events<'a>> for WaitCancel {
+    type Error = h2::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        events.start_send_response(Response::new(()), false).await?;
+        events.send_data(Bytes::from("partial"), false).await?;
+        let reason = events.cancelled().await;
+        let _ = self.0.lock().unwrap().take().unwrap().send(reason);
+        Ok(())
+    }
+}
+
+/// Abort every request without the response.
+#[derive(Clone)]
+struct Abort;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Abort {
+    type Error = h2::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        events.reset(Reason::ENHANCE_YOUR_CALM);
+        Ok(())
+    }
+}
+
+async fn start_server<T>(app: T) -> SocketAddr
+where
+    T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
+{
+    let server = Server::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        server.serve(app).await.unwrap();
+    });
+    addr
+}
+
+#[test]
+fn cancelled_by_client() {
+    run(|_| async {
+        let (tx, rx) = oneshot::channel();
+        let addr = start_server(WaitCancel(Arc::new(Mutex::new(Some(tx))))).await;
+
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let (client, conn) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+        let mut client = client.ready().await.unwrap();
+        let request = Request::get("http://localhost/").body(()).unwrap();
+        let (response, mut send_stream) = client.send_request(request, false).unwrap();
+        let mut body = response.await.unwrap().into_body();
+        assert_eq!(body.data().await.unwrap().unwrap(), "partial");
+
+        send_stream.send_reset(h2::Reason::CANCEL);
+        assert_eq!(rx.await.unwrap(), Reason::CANCEL);
+    });
+}
+
+#[test]
+fn cancelled_by_closing_http1_connection() {
+    run(|_| async {
+        let (tx, rx) = oneshot::channel();
+        let addr = start_server(WaitCancel(Arc::new(Mutex::new(Some(tx))))).await;
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0; 4096];
+        let mut received = vec![];
+        while !received.ends_with(b"partial\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0);
+            received.extend_from_slice(&buf[..n]);
+        }
+
+        drop(stream);
+        assert_eq!(rx.await.unwrap(), Reason::CANCEL);
+    });
+}
+
+#[test]
+fn reset_by_app() {
+    run(|_| async {
+        let addr = start_server(Abort).await;
+
+        let stream = TcpStream::connect(&addr).await.unwrap();
+        let (client, conn) = h2::client::handshake(stream).await.unwrap();
+        tokio::spawn(async move {
+            let _ = conn.await;
+        });
+        let mut client = client.ready().await.unwrap();
+        let request = Request::get("http://localhost/").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        let err = response.await.unwrap_err();
+        assert_eq!(err.reason(), Some(h2::Reason::ENHANCE_YOUR_CALM));
+    });
+}
+
+#[test]
+fn reset_closes_http1_connection() {
+    run(|_| async {
+        let addr = start_server(Abort).await;
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = vec![];
+        let _ = stream.read_to_end(&mut received).await;
+        assert!(received.is_empty(), "{:?}", received);
+    });
+}
@@ -0,0 +1,115 @@
+mod support;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{Request, Response};
+use izanami::{state::State, App, RemoteAddr};
+use izanami_h2::{Events, Server};
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use support::run;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+struct ConnId(usize);
+
+/// Respond with the shared and per-connection states.
+#[derive(Clone)]
+struct Echo;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Echo {
+    type Error = h2::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let name = State::<&'static str>::from_request(&request).unwrap();
+        let conn_id = State::<ConnId>::from_request(&request).unwrap();
+        let mut events = request.into_body();
+        events.start_send_response(Response::new(()), false).await?;
+        events
+            .send_data(Bytes::from(format!("{}/{}", *name, conn_id.0)), true)
+            .await
+    }
+}
+
+/// Send `requests` requests on a new connection, and return the response bodies.
+async fn send_requests(addr: &SocketAddr, requests: usize) -> Vec<String> {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (client, conn) = h2::client::handshake(stream).await.unwrap();
+    tokio::spawn(async move {
+        let _ = conn.await;
+    });
+    let mut bodies = vec![];
+    for _ in 0..requests {
+        let mut client = client.clone().ready().await.unwrap();
+        let request = Request::get("http://localhost/").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        let mut body = response.await.unwrap().into_body();
+        let mut received = vec![];
+        while let Some(data) = body.data().await {
+            received.extend_from_slice(&data.unwrap());
+        }
+        bodies.push(String::from_utf8(received).unwrap());
+    }
+    bodies
+}
+
+/// Send `requests` pipelined HTTP/1.1 requests on a new connection, and return the responses.
+async fn send_http1_requests(addr: &SocketAddr, requests: usize) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    for _ in 0..requests {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+    }
+    let mut received = vec![];
+    let mut buf = [0; 4096];
+    while received.windows(5).filter(|w| w == b"0\r\n\r\n").count() < requests {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0);
+        received.extend_from_slice(&buf[..n]);
+    }
+    String::from_utf8(received).unwrap()
+}
+
+#[test]
+fn inject_states() {
+    run(|_| async {
+        let next_id = AtomicUsize::new(1);
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .state("example")
+            .connection_state(move |remote_addr: &RemoteAddr| {
+                assert!(remote_addr.0.ip().is_loopback());
+                ConnId(next_id.fetch_add(1, Ordering::SeqCst))
+            });
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            server.serve(Echo).await.unwrap();
+        });
+
+        assert_eq!(
+            send_requests(&addr, 2).await,
+            vec!["example/1", "example/1"]
+        );
+        assert_eq!(send_requests(&addr, 1).await, vec!["example/2"]);
+
+        // The requests on an HTTP/1.1 connection share the per-connection state.
+        let responses = send_http1_requests(&addr, 2).await;
+        assert_eq!(
+            responses.matches("\r\nexample/3\r\n").count(),
+            2,
+            "{:?}",
+            responses
+        );
+    });
+}
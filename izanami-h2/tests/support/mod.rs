@@ -0,0 +1,38 @@
+#![allow(dead_code)]
+
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::runtime::current_thread;
+use tokio_timer::clock::{Clock, Now};
+
+/// A clock that advances only when the test says so.
+#[derive(Debug, Clone)]
+pub struct MockClock(Arc<Mutex<Instant>>);
+
+impl Now for MockClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl MockClock {
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+pub fn run<F, Fut>(f: F)
+where
+    F: FnOnce(MockClock) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let clock = MockClock(Arc::new(Mutex::new(Instant::now())));
+    let mut rt = current_thread::Builder::new()
+        .clock(Clock::new_with_now(clock.clone()))
+        .build()
+        .unwrap();
+    rt.block_on(f(clock));
+}
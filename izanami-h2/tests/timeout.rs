@@ -0,0 +1,91 @@
+mod support;
+
+use async_trait::async_trait;
+use futures::{channel::mpsc, future, StreamExt};
+use http::{Request, Response, StatusCode};
+use izanami::App;
+use izanami_h2::{Events, Server};
+use std::time::Duration;
+use support::{run, MockClock};
+use tokio::net::TcpStream;
+
+/// An app that notifies when it reaches the point to stall.
+#[derive(Clone)]
+struct TestApp {
+    send_head: bool,
+    notify: mpsc::UnboundedSender<()>,
+}
+
+#[async_trait]
+impl<'a> App<Events<'a>> for TestApp {
+    type Error = h2::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        if self.send_head {
+            events.start_send_response(Response::new(()), false).await?;
+        }
+        let _ = self.notify.unbounded_send(());
+        future::pending().await
+    }
+}
+
+async fn request(
+    server: Server,
+    send_head: bool,
+    clock: MockClock,
+) -> Result<(StatusCode, Option<Result<(), h2::Error>>), h2::Error> {
+    let addr = server.local_addr().unwrap();
+    let (notify, mut notified) = mpsc::unbounded();
+    tokio::spawn(async move {
+        server.serve(TestApp { send_head, notify }).await.unwrap();
+    });
+
+    let stream = TcpStream::connect(&addr).await.unwrap();
+    let (mut client, conn) = h2::client::handshake(stream).await?;
+    tokio::spawn(async move {
+        let _ = conn.await;
+    });
+
+    let request = Request::get("http://localhost/").body(()).unwrap();
+    let (response, _) = client.send_request(request, true)?;
+
+    notified.next().await.unwrap();
+    clock.advance(Duration::from_secs(60));
+
+    let response = response.await?;
+    let status = response.status();
+    let mut body = response.into_body();
+    let data = body.data().await.map(|data| data.map(drop));
+    Ok((status, data))
+}
+
+#[test]
+fn response_head_timeout() {
+    run(|clock| async move {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .response_head_timeout(Duration::from_secs(30));
+        let (status, data) = request(server, false, clock).await.unwrap();
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(data.is_none());
+    });
+}
+
+#[test]
+fn idle_timeout_after_response_head() {
+    run(|clock| async move {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .idle_timeout(Duration::from_secs(10));
+        let (status, data) = request(server, true, clock).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let err = data.unwrap().unwrap_err();
+        assert_eq!(err.reason(), Some(h2::Reason::CANCEL));
+    });
+}
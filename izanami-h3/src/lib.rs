@@ -0,0 +1,492 @@
+//! HTTP/3 backend of izanami, built on `quinn` and `h3`.
+//!
+//! The QUIC stack runs on tokio 1.x, so `Server::bind` and `Server::serve`
+//! must be called within its runtime.
+
+pub use h3::error::StreamError as Error;
+
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::FutureExt;
+use h3::{error::Code, server::RequestStream};
+use http::{HeaderMap, Request, Response};
+use izanami::{
+    state::{ConnectionState, StateMap},
+    App, Reason, RemoteAddr,
+};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::{
+    convert::TryFrom,
+    fmt, fs, io,
+    net::{SocketAddr, ToSocketAddrs},
+    panic::AssertUnwindSafe,
+    path::Path,
+    sync::Arc,
+};
+use tracing::Instrument;
+
+type Stream = RequestStream<h3_quinn::BidiStream<bytes_v1::Bytes>, bytes_v1::Bytes>;
+
+/// The certificate chain and the private key presented by the server.
+pub struct Identity {
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("chain", &self.chain)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Identity {
+    /// Load the PEM-encoded certificate chain and private key.
+    pub fn from_pem(cert: &[u8], key: &[u8]) -> io::Result<Self> {
+        let chain = rustls_pemfile::certs(&mut &cert[..]).collect::<Result<Vec<_>, _>>()?;
+        if chain.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no certificate in the PEM",
+            ));
+        }
+        let key = rustls_pemfile::private_key(&mut &key[..])?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no private key in the PEM")
+        })?;
+        Ok(Self { chain, key })
+    }
+
+    /// Load the PEM files of the certificate chain and private key,
+    /// such as `target/keys/server-crt.pem` and `target/keys/server-key.pem`
+    /// generated by `bin/gencert`.
+    pub fn from_pem_files(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_pem(&fs::read(cert)?, &fs::read(key)?)
+    }
+}
+
+#[derive(Debug)]
+pub struct Server {
+    endpoint: quinn::Endpoint,
+    states: StateMap,
+}
+
+impl Server {
+    /// Bind the UDP socket to `addr` and accept the QUIC connections
+    /// negotiating `h3` with `identity`.
+    pub async fn bind<A>(addr: A, identity: Identity) -> io::Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let addr = addr.to_socket_addrs()?.next().unwrap();
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .map_err(invalid_input)?
+            .with_no_client_auth()
+            .with_single_cert(identity.chain, identity.key)
+            .map_err(invalid_input)?;
+        tls.alpn_protocols = vec![b"h3".to_vec()];
+        let crypto =
+            quinn::crypto::rustls::QuicServerConfig::try_from(tls).map_err(invalid_input)?;
+
+        let config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        let endpoint = quinn::Endpoint::server(config, addr)?;
+        Ok(Self {
+            endpoint,
+            states: StateMap::new(),
+        })
+    }
+
+    /// Return the local address that this server is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    /// Insert `value` into the extensions of each request as `izanami::state::State<T>`.
+    pub fn state<T>(mut self, value: T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.states.insert(value);
+        self
+    }
+
+    /// Build a value with `f` for each accepted QUIC connection, and insert it into
+    /// the extensions of the requests on that connection as `izanami::state::State<T>`.
+    pub fn connection_state<F, T>(mut self, f: F) -> Self
+    where
+        F: Fn(&RemoteAddr) -> T + Send + Sync + 'static,
+        T: Send + Sync + 'static,
+    {
+        self.states.insert_per_connection(f);
+        self
+    }
+
+    pub async fn serve<T>(self, app: T) -> io::Result<()>
+    where
+        T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
+    {
+        while let Some(incoming) = self.endpoint.accept().await {
+            let remote_addr = incoming.remote_address();
+            let app = app.clone();
+            let states = self.states.clone();
+            let span = tracing::info_span!("connection", peer = %remote_addr);
+            tokio::spawn(
+                async move {
+                    if let Err(err) = serve_connection(incoming, app, states).await {
+                        tracing::debug!("connection error: {}", err);
+                    }
+                }
+                .instrument(span),
+            );
+        }
+        Ok(())
+    }
+}
+
+fn invalid_input<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidInput, err)
+}
+
+async fn serve_connection<T>(
+    incoming: quinn::Incoming,
+    app: T,
+    states: StateMap,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
+{
+    let conn = incoming.await?;
+    let remote_addr = conn.remote_address();
+    let state = states.connect(&RemoteAddr(remote_addr));
+    let mut conn =
+        h3::server::Connection::<_, bytes_v1::Bytes>::new(h3_quinn::Connection::new(conn)).await?;
+
+    loop {
+        match conn.accept().await {
+            Ok(Some(resolver)) => {
+                let app = app.clone();
+                let state = state.clone();
+                tokio::spawn(
+                    async move {
+                        match resolver.resolve_request().await {
+                            Ok((request, stream)) => {
+                                handle_request(&app, request, stream, remote_addr, &state).await
+                            }
+                            Err(err) => tracing::debug!("request error: {}", err),
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+            Ok(None) => return Ok(()),
+            Err(err) if err.is_h3_no_error() => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// The progress of the response on a request stream.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Progress {
+    Init,
+    Streaming,
+    Done,
+}
+
+async fn handle_request<T>(
+    app: &T,
+    request: http_v1::Request<()>,
+    mut stream: Stream,
+    remote_addr: SocketAddr,
+    state: &ConnectionState,
+) where
+    T: for<'a> App<Events<'a>>,
+{
+    let mut request = match request_from_v1(request) {
+        Some(request) => request,
+        None => {
+            tracing::debug!("the request head is not representable with http 0.1");
+            if !send_empty_response(&mut stream, http_v1::StatusCode::BAD_REQUEST).await {
+                stream.stop_stream(Code::H3_INTERNAL_ERROR);
+            }
+            return;
+        }
+    };
+    request.extensions_mut().insert(RemoteAddr(remote_addr));
+    state.inject(request.extensions_mut());
+    let (parts, ()) = request.into_parts();
+
+    let mut progress = Progress::Init;
+    let events = Events {
+        stream: &mut stream,
+        progress: &mut progress,
+    };
+    let request = Request::from_parts(parts, events);
+
+    let panicked = match AssertUnwindSafe(app.call(request)).catch_unwind().await {
+        Ok(Ok(())) => false,
+        Ok(Err(err)) => {
+            let err = err.into();
+            tracing::error!("app error: {}", err);
+            false
+        }
+        Err(..) => {
+            tracing::error!("app panicked");
+            true
+        }
+    };
+
+    if panicked
+        && progress == Progress::Init
+        && send_empty_response(&mut stream, http_v1::StatusCode::INTERNAL_SERVER_ERROR).await
+    {
+        progress = Progress::Done;
+    }
+
+    // Dropping the stream would finish it gracefully, which makes
+    // the incomplete response look like a complete one.
+    if progress != Progress::Done {
+        stream.stop_stream(Code::H3_INTERNAL_ERROR);
+    }
+    tracing::debug!("request completed");
+}
+
+/// Send a response without body, and return whether it is completed.
+async fn send_empty_response(stream: &mut Stream, status: http_v1::StatusCode) -> bool {
+    let mut response = http_v1::Response::new(());
+    *response.status_mut() = status;
+    stream.send_response(response).await.is_ok() && stream.finish().await.is_ok()
+}
+
+/// Convert the request head from http 1.x, or return `None` if it is not
+/// representable with http 0.1, e.g. the URI accepted only by the newer parser.
+fn request_from_v1(request: http_v1::Request<()>) -> Option<Request<()>> {
+    let (parts, ()) = request.into_parts();
+    let mut request = Request::new(());
+    *request.method_mut() = http::Method::from_bytes(parts.method.as_str().as_bytes()).ok()?;
+    *request.uri_mut() = http::Uri::from_shared(Bytes::from(parts.uri.to_string())).ok()?;
+    // http 0.1 does not define HTTP/3, so the closest version is reported.
+    *request.version_mut() = http::Version::HTTP_2;
+    *request.headers_mut() = headers_from_v1(&parts.headers);
+    Some(request)
+}
+
+fn headers_from_v1(headers: &http_v1::HeaderMap) -> HeaderMap {
+    headers
+        .iter()
+        .filter_map(|(name, value)| {
+            let name = http::header::HeaderName::from_bytes(name.as_str().as_bytes()).ok()?;
+            let value = http::HeaderValue::from_bytes(value.as_bytes()).ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+fn headers_to_v1(headers: &HeaderMap) -> http_v1::HeaderMap {
+    headers
+        .iter()
+        .filter(|(name, _)| !is_connection_specific(name))
+        .filter_map(|(name, value)| {
+            let name = http_v1::HeaderName::from_bytes(name.as_str().as_bytes()).ok()?;
+            let value = http_v1::HeaderValue::from_bytes(value.as_bytes()).ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+/// Whether the header field is prohibited in HTTP/3.
+fn is_connection_specific(name: &http::header::HeaderName) -> bool {
+    use http::header;
+    name == header::CONNECTION
+        || name == header::TRANSFER_ENCODING
+        || name == header::UPGRADE
+        || name == "keep-alive"
+        || name == "proxy-connection"
+}
+
+fn reason_to_code(reason: Reason) -> Code {
+    match reason {
+        Reason::NO_ERROR => Code::H3_NO_ERROR,
+        Reason::REFUSED_STREAM => Code::H3_REQUEST_REJECTED,
+        Reason::CANCEL => Code::H3_REQUEST_CANCELLED,
+        Reason::ENHANCE_YOUR_CALM => Code::H3_EXCESSIVE_LOAD,
+        _ => Code::H3_INTERNAL_ERROR,
+    }
+}
+
+pub struct Events<'a> {
+    stream: &'a mut Stream,
+    progress: &'a mut Progress,
+}
+
+impl fmt::Debug for Events<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events")
+            .field("progress", &self.progress)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Events<'_> {
+    pub async fn data(&mut self) -> Option<Result<Data, Error>> {
+        match self.stream.recv_data().await {
+            Ok(Some(mut data)) => {
+                let len = bytes_v1::Buf::remaining(&data);
+                let data = bytes_v1::Buf::copy_to_bytes(&mut data, len);
+                Some(Ok(Data(Bytes::from(&data[..]))))
+            }
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+
+    pub async fn trailers(&mut self) -> Result<Option<HeaderMap>, Error> {
+        let trailers = self.stream.recv_trailers().await?;
+        Ok(trailers.map(|trailers| headers_from_v1(&trailers)))
+    }
+
+    pub async fn start_send_response(
+        &mut self,
+        response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), Error> {
+        let (parts, ()) = response.into_parts();
+        let mut response = http_v1::Response::new(());
+        *response.status_mut() = http_v1::StatusCode::from_u16(parts.status.as_u16())
+            .unwrap_or(http_v1::StatusCode::INTERNAL_SERVER_ERROR);
+        *response.headers_mut() = headers_to_v1(&parts.headers);
+
+        self.stream.send_response(response).await?;
+        *self.progress = Progress::Streaming;
+        if end_of_stream {
+            self.finish().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn send_data<T>(&mut self, data: T, end_of_stream: bool) -> Result<(), Error>
+    where
+        T: Into<Data>,
+    {
+        let data = data.into();
+        if !data.0.is_empty() {
+            let data = bytes_v1::Bytes::copy_from_slice(&data.0);
+            self.stream.send_data(data).await?;
+        }
+        if end_of_stream {
+            self.finish().await?;
+        }
+        Ok(())
+    }
+
+    pub async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Error> {
+        self.stream.send_trailers(headers_to_v1(&trailers)).await?;
+        self.finish().await
+    }
+
+    async fn finish(&mut self) -> Result<(), Error> {
+        self.stream.finish().await?;
+        *self.progress = Progress::Done;
+        Ok(())
+    }
+
+    /// Abort the request stream in both directions with the HTTP/3 error code
+    /// corresponding to `reason`.
+    pub fn reset(&mut self, reason: Reason) {
+        let code = reason_to_code(reason);
+        self.stream.stop_sending(code);
+        self.stream.stop_stream(code);
+        // The stream is already aborted and must not be stopped again.
+        *self.progress = Progress::Done;
+    }
+
+    /// Wait for the client to cancel the request.
+    ///
+    /// The underlying HTTP/3 stack does not notify the cancellation by the client
+    /// until the server sends or receives data, so this future never completes.
+    pub async fn cancelled(&mut self) -> Reason {
+        futures::future::pending().await
+    }
+}
+
+#[async_trait]
+#[allow(clippy::needless_lifetimes)]
+impl<'a> izanami::Events for Events<'a> {
+    type Data = Data;
+    type Error = Error;
+
+    #[inline]
+    async fn data(&mut self) -> Option<Result<Self::Data, Self::Error>> {
+        self.data().await
+    }
+
+    #[inline]
+    async fn trailers(&mut self) -> Result<Option<HeaderMap>, Self::Error> {
+        self.trailers().await
+    }
+
+    #[inline]
+    async fn start_send_response(
+        &mut self,
+        response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        self.start_send_response(response, end_of_stream).await
+    }
+
+    #[inline]
+    async fn send_data(
+        &mut self,
+        data: Self::Data,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        self.send_data(data, end_of_stream).await
+    }
+
+    #[inline]
+    async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error> {
+        self.send_trailers(trailers).await
+    }
+
+    #[inline]
+    fn reset(&mut self, reason: Reason) {
+        self.reset(reason)
+    }
+
+    #[inline]
+    async fn cancelled(&mut self) -> Reason {
+        self.cancelled().await
+    }
+}
+
+#[derive(Debug)]
+pub struct Data(Bytes);
+
+impl<T: Into<Bytes>> From<T> for Data {
+    fn from(bytes: T) -> Self {
+        Self(bytes.into())
+    }
+}
+
+impl Buf for Data {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    fn bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    #[inline]
+    fn advance(&mut self, amt: usize) {
+        self.0.advance(amt);
+    }
+}
@@ -0,0 +1,165 @@
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use http::{Request, Response};
+use izanami::{state::State, App, RemoteAddr};
+use izanami_h3::{Events, Identity, Server};
+use std::{convert::TryFrom, net::SocketAddr, sync::Arc};
+
+/// The per-connection state, which holds the address of the client.
+struct Peer(SocketAddr);
+
+/// Echo the request body in chunks, followed by the request trailers.
+#[derive(Clone)]
+struct Echo;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Echo {
+    type Error = izanami_h3::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let remote_addr = request.extensions().get::<RemoteAddr>().unwrap().0;
+        let peer = State::<Peer>::from_request(&request).unwrap();
+        assert_eq!(peer.0, remote_addr);
+        assert_eq!(
+            *State::<&'static str>::from_request(&request).unwrap(),
+            "example"
+        );
+        if request.uri().path() == "/panic" {
+            panic!("explicit panic");
+        }
+
+        let mut events = request.into_body();
+        events.start_send_response(Response::new(()), false).await?;
+        while let Some(data) = events.data().await {
+            let data = data?;
+            events.send_data(Bytes::from(data.bytes()), false).await?;
+        }
+        match events.trailers().await? {
+            Some(trailers) => events.send_trailers(trailers).await,
+            None => events.send_data(Bytes::new(), true).await,
+        }
+    }
+}
+
+struct Client {
+    send_request: h3::client::SendRequest<h3_quinn::OpenStreams, bytes_v1::Bytes>,
+    _endpoint: quinn::Endpoint,
+}
+
+/// Start the server with a self-signed certificate and connect to it.
+async fn start(name: &str) -> Client {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+    let dir = std::env::temp_dir().join(format!("izanami-h3-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("server-crt.pem"), cert.cert.pem()).unwrap();
+    std::fs::write(dir.join("server-key.pem"), cert.key_pair.serialize_pem()).unwrap();
+    let identity =
+        Identity::from_pem_files(dir.join("server-crt.pem"), dir.join("server-key.pem")).unwrap();
+
+    let server = Server::bind("127.0.0.1:0", identity)
+        .await
+        .unwrap()
+        .state("example")
+        .connection_state(|remote_addr: &RemoteAddr| Peer(remote_addr.0));
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(async move {
+        server.serve(Echo).await.unwrap();
+    });
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(cert.cert.der().clone()).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut tls = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let crypto = quinn::crypto::rustls::QuicClientConfig::try_from(tls).unwrap();
+
+    let bind_addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let mut endpoint = quinn::Endpoint::client(bind_addr).unwrap();
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+    let conn = endpoint.connect(addr, "localhost").unwrap().await.unwrap();
+
+    let (mut driver, send_request) = h3::client::new(h3_quinn::Connection::new(conn))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        let _ = futures::future::poll_fn(|cx| driver.poll_close(cx)).await;
+    });
+
+    Client {
+        send_request,
+        _endpoint: endpoint,
+    }
+}
+
+#[tokio::test]
+async fn echo_streaming_body_and_trailers() {
+    let mut client = start("echo").await;
+
+    let request = http_v1::Request::post("https://localhost/echo")
+        .body(())
+        .unwrap();
+    let mut stream = client.send_request.send_request(request).await.unwrap();
+    for chunk in &["Hello", ", ", "world"] {
+        stream
+            .send_data(bytes_v1::Bytes::from_static(chunk.as_bytes()))
+            .await
+            .unwrap();
+    }
+    let mut trailers = http_v1::HeaderMap::new();
+    trailers.insert("x-checksum", "42".parse().unwrap());
+    stream.send_trailers(trailers).await.unwrap();
+    stream.finish().await.unwrap();
+
+    let response = stream.recv_response().await.unwrap();
+    assert_eq!(response.status(), http_v1::StatusCode::OK);
+    let mut body = vec![];
+    while let Some(mut data) = stream.recv_data().await.unwrap() {
+        while bytes_v1::Buf::has_remaining(&data) {
+            let chunk = bytes_v1::Buf::chunk(&data);
+            body.extend_from_slice(chunk);
+            let len = chunk.len();
+            bytes_v1::Buf::advance(&mut data, len);
+        }
+    }
+    assert_eq!(body, b"Hello, world");
+    let trailers = stream.recv_trailers().await.unwrap().unwrap();
+    assert_eq!(trailers["x-checksum"], "42");
+}
+
+#[tokio::test]
+async fn internal_server_error_on_panic() {
+    let mut client = start("panic").await;
+
+    let request = http_v1::Request::get("https://localhost/panic")
+        .body(())
+        .unwrap();
+    let mut stream = client.send_request.send_request(request).await.unwrap();
+    stream.finish().await.unwrap();
+
+    let response = stream.recv_response().await.unwrap();
+    assert_eq!(
+        response.status(),
+        http_v1::StatusCode::INTERNAL_SERVER_ERROR
+    );
+}
+
+#[tokio::test]
+async fn bad_request_on_unrepresentable_uri() {
+    let mut client = start("bad-request").await;
+
+    let request = http_v1::Request::get("https://localhost/a{b}")
+        .body(())
+        .unwrap();
+    let mut stream = client.send_request.send_request(request).await.unwrap();
+    stream.finish().await.unwrap();
+
+    let response = stream.recv_response().await.unwrap();
+    assert_eq!(response.status(), http_v1::StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,118 @@
+use futures::{
+    future::Future,
+    task::{self, Poll},
+};
+use http::HeaderMap;
+use http_body::{Body as HttpBody, SizeHint};
+use hyper::body::{Body, Chunk, Sender};
+use std::pin::Pin;
+use tokio::sync::oneshot;
+
+/// The body of responses, which can carry the trailers after the data.
+///
+/// Note that hyper sends the trailers only on HTTP/2 connections.
+#[derive(Debug)]
+pub(crate) struct ResponseBody {
+    body: Body,
+    trailers: Option<oneshot::Receiver<HeaderMap>>,
+}
+
+impl ResponseBody {
+    pub(crate) fn empty() -> Self {
+        Self::from(Body::empty())
+    }
+
+    pub(crate) fn channel() -> (BodySender, Self) {
+        let (data, body) = Body::channel();
+        let (trailers, rx) = oneshot::channel();
+        let sender = BodySender {
+            data,
+            trailers: Some(trailers),
+        };
+        let body = Self {
+            body,
+            trailers: Some(rx),
+        };
+        (sender, body)
+    }
+}
+
+impl From<Body> for ResponseBody {
+    fn from(body: Body) -> Self {
+        Self {
+            body,
+            trailers: None,
+        }
+    }
+}
+
+impl HttpBody for ResponseBody {
+    type Data = Chunk;
+    type Error = hyper::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.body).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = match self.trailers {
+            Some(ref mut trailers) => futures::ready!(Pin::new(trailers).poll(cx)).ok(),
+            None => None,
+        };
+        self.trailers = None;
+        Poll::Ready(Ok(trailers))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.trailers.is_none() && self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// The sending half of `ResponseBody::channel`.
+///
+/// The stream ends when this value is dropped, after the trailers if they were set.
+#[derive(Debug)]
+pub(crate) struct BodySender {
+    data: Sender,
+    trailers: Option<oneshot::Sender<HeaderMap>>,
+}
+
+impl BodySender {
+    pub(crate) async fn send_data(&mut self, chunk: Chunk) -> hyper::Result<()> {
+        self.data.send_data(chunk).await
+    }
+
+    pub(crate) fn send_trailers(mut self, trailers: HeaderMap) {
+        if let Some(sender) = self.trailers.take() {
+            let _ = sender.send(trailers);
+        }
+    }
+
+    pub(crate) fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<hyper::Result<()>> {
+        self.data.poll_ready(cx)
+    }
+
+    /// Abort the stream with an error.
+    pub(crate) fn abort(self) {
+        self.data.abort();
+    }
+
+    /// Wait until the receiving half is dropped before the end of the stream,
+    /// e.g. because the connection has been closed.
+    pub(crate) async fn closed(&mut self) {
+        match self.trailers {
+            Some(ref mut trailers) => trailers.closed().await,
+            None => futures::future::pending().await,
+        }
+    }
+}
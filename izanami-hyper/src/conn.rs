@@ -0,0 +1,208 @@
+use futures::{
+    future::{self, BoxFuture, Either},
+    ready,
+    stream::{FuturesUnordered, Stream},
+    task::{self, Poll},
+};
+use hyper::server::{
+    accept::Accept,
+    conn::{AddrIncoming, AddrStream},
+};
+#[cfg(feature = "metrics")]
+use izanami::metrics::{ConnectionGuard, Metrics};
+use izanami::{
+    limit::{Guard, LoadControl, Reservation},
+    proxy_protocol::{self, ProxyHeader, Status},
+};
+use std::{io, net::SocketAddr, pin::Pin, time::Duration};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+/// An `Accept` that stops accepting connections while the connection limit is reached.
+pub(crate) struct Incoming {
+    inner: AddrIncoming,
+    control: LoadControl,
+    pending: Option<BoxFuture<'static, Reservation>>,
+    reservation: Option<Reservation>,
+    proxy_protocol: bool,
+    header_timeout: Option<Duration>,
+    reading_headers: FuturesUnordered<BoxFuture<'static, io::Result<Conn>>>,
+    #[cfg(feature = "metrics")]
+    recorder: Option<Metrics>,
+}
+
+impl Incoming {
+    pub(crate) fn new(inner: AddrIncoming, control: LoadControl) -> Self {
+        Self {
+            inner,
+            control,
+            pending: None,
+            reservation: None,
+            proxy_protocol: false,
+            header_timeout: None,
+            reading_headers: FuturesUnordered::new(),
+            #[cfg(feature = "metrics")]
+            recorder: None,
+        }
+    }
+
+    /// Require the PROXY protocol header on the accepted connections, which is
+    /// read concurrently with accepting the next connections.
+    pub(crate) fn proxy_protocol(self, enabled: bool, header_timeout: Option<Duration>) -> Self {
+        Self {
+            proxy_protocol: enabled,
+            header_timeout,
+            ..self
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn record_metrics(self, recorder: Option<Metrics>) -> Self {
+        Self { recorder, ..self }
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Conn;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let me = self.get_mut();
+
+        loop {
+            while let Poll::Ready(Some(result)) = Pin::new(&mut me.reading_headers).poll_next(cx) {
+                match result {
+                    Ok(conn) => return Poll::Ready(Some(Ok(conn))),
+                    Err(err) => tracing::debug!("{}", err),
+                }
+            }
+
+            if me.reservation.is_none() {
+                let control = me.control.clone();
+                let pending = me
+                    .pending
+                    .get_or_insert_with(|| Box::pin(async move { control.reserve().await }));
+                let reservation = ready!(pending.as_mut().poll(cx));
+                me.pending = None;
+                me.reservation = Some(reservation);
+            }
+
+            let conn = match ready!(Pin::new(&mut me.inner).poll_accept(cx)) {
+                Some(Ok(io)) => Conn {
+                    remote_addr: io.remote_addr(),
+                    io,
+                    _guard: me.reservation.take().unwrap().accepted(),
+                    #[cfg(feature = "metrics")]
+                    _recorded: me.recorder.as_ref().map(Metrics::connection),
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            };
+            if !me.proxy_protocol {
+                return Poll::Ready(Some(Ok(conn)));
+            }
+
+            // The connection is handed to hyper after its header has been read.
+            let header_timeout = me.header_timeout;
+            me.reading_headers.push(Box::pin(async move {
+                let mut conn = conn;
+                let header = read_proxy_header(&mut conn.io, header_timeout).await?;
+                if let Some(source) = header.source {
+                    conn.remote_addr = source;
+                }
+                Ok(conn)
+            }));
+        }
+    }
+}
+
+/// Read the PROXY protocol header, without consuming the bytes after it.
+async fn read_proxy_header<R>(io: &mut R, timeout: Option<Duration>) -> io::Result<ProxyHeader>
+where
+    R: AsyncRead + Unpin,
+{
+    let read = async {
+        let mut buf = Vec::new();
+        loop {
+            match proxy_protocol::parse(&buf) {
+                Ok(Status::Complete(header)) => return Ok(header),
+                Ok(Status::Partial(n)) => {
+                    let len = buf.len();
+                    buf.resize(len + n, 0);
+                    io.read_exact(&mut buf[len..]).await?;
+                }
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            }
+        }
+    };
+    futures::pin_mut!(read);
+
+    match timeout {
+        Some(timeout) => {
+            let delay = tokio::timer::delay(tokio::clock::now() + timeout);
+            match future::select(read, delay).await {
+                Either::Left((result, _)) => result,
+                Either::Right(..) => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out while reading the PROXY protocol header",
+                )),
+            }
+        }
+        None => read.await,
+    }
+}
+
+/// An accepted connection, which holds its slot until closed.
+pub(crate) struct Conn {
+    io: AddrStream,
+    remote_addr: SocketAddr,
+    _guard: Guard,
+    #[cfg(feature = "metrics")]
+    _recorded: Option<ConnectionGuard>,
+}
+
+impl Conn {
+    /// Return the address of the client, which is conveyed by the PROXY protocol if enabled.
+    pub(crate) fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+}
+
+impl AsyncRead for Conn {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.io.prepare_uninitialized_buffer(buf)
+    }
+
+    #[inline]
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Conn {
+    #[inline]
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
@@ -1,23 +1,57 @@
+mod body;
+mod conn;
+
+pub use izanami::limit::{LoadMetrics, Overload};
+
+use crate::{
+    body::{BodySender, ResponseBody},
+    conn::{Conn, Incoming},
+};
 use async_trait::async_trait;
 use futures::{
-    future::{poll_fn, Future},
+    future::{self, poll_fn, Future, FutureExt},
     task::{self, Poll},
 };
-use http::{HeaderMap, Request, Response, StatusCode};
+use http::{header, HeaderMap, HeaderValue, Request, Response, StatusCode};
 use http_body::Body as _Body;
 use hyper::{
-    body::{Body, Chunk, Sender as BodySender},
-    server::{conn::AddrIncoming, Builder as ServerBuilder, Server as HyperServer},
+    body::{Body, Chunk},
+    server::{conn::AddrIncoming, Server as HyperServer},
     upgrade::Upgraded,
 };
-use izanami::App;
-use std::{marker::PhantomData, net::ToSocketAddrs, pin::Pin};
+#[cfg(feature = "metrics")]
+use izanami::metrics::Metrics;
+use izanami::{
+    access_log::{AccessLog, Record},
+    limit::{Guard, Limiter, Limits, LoadControl, Shed},
+    state::{ConnectionState, StateMap},
+    timeout::{Timeouts, Watchdog},
+    App, Reason, RemoteAddr,
+};
+use std::{
+    mem,
+    net::{SocketAddr, ToSocketAddrs},
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::sync::oneshot;
 use tower_service::Service;
+use tracing::{field, Instrument, Span};
 
 #[derive(Debug)]
 pub struct Server {
-    builder: ServerBuilder<AddrIncoming>,
+    incoming: AddrIncoming,
+    timeouts: Timeouts,
+    limits: Limits,
+    metrics: LoadMetrics,
+    access_log: Option<Arc<AccessLog>>,
+    proxy_protocol: bool,
+    http1_half_close: bool,
+    states: StateMap,
+    #[cfg(feature = "metrics")]
+    recorder: Option<Metrics>,
 }
 
 impl Server {
@@ -27,19 +61,185 @@ impl Server {
     {
         let addr = addr.to_socket_addrs().unwrap().next().unwrap();
         Ok(Self {
-            builder: HyperServer::try_bind(&addr)?,
+            incoming: AddrIncoming::bind(&addr)?,
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            metrics: LoadMetrics::default(),
+            access_log: None,
+            proxy_protocol: false,
+            http1_half_close: true,
+            states: StateMap::new(),
+            #[cfg(feature = "metrics")]
+            recorder: None,
         })
     }
 
+    /// Return the local address that this server is bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.incoming.local_addr()
+    }
+
+    /// Set the maximum duration from the start of a request until
+    /// the response head is sent.
+    ///
+    /// If the limit is exceeded, the server sends `503 Service Unavailable`
+    /// (or `408 Request Timeout` if the application is waiting for the
+    /// request body) instead of the application.
+    pub fn response_head_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.response_head = Some(timeout);
+        self
+    }
+
+    /// Set the maximum duration of a whole request.
+    ///
+    /// If the limit is exceeded after the response head has been sent,
+    /// the response body is aborted.
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.request = Some(timeout);
+        self
+    }
+
+    /// Set the maximum duration between the progress of a request, that is,
+    /// between the calls of `data` and `send_data`.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.idle = Some(timeout);
+        self
+    }
+
+    /// Set the maximum number of open connections.
+    ///
+    /// While the limit is reached, the server stops accepting new connections.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.limits.max_connections = Some(max);
+        self
+    }
+
+    /// Set the maximum number of requests processed concurrently by the application.
+    pub fn max_concurrent_requests(mut self, max: usize) -> Self {
+        self.limits.max_requests = Some(max);
+        self
+    }
+
+    /// Set the maximum number of requests processed concurrently on each connection.
+    ///
+    /// On HTTP/2 connections, the value is also advertised to the clients
+    /// as `SETTINGS_MAX_CONCURRENT_STREAMS`.
+    pub fn max_concurrent_requests_per_connection(mut self, max: usize) -> Self {
+        self.limits.max_requests_per_connection = Some(max);
+        self
+    }
+
+    /// Set the behavior when the number of concurrent requests reaches the limit.
+    ///
+    /// The default is `Overload::Reject` with `Retry-After` of one second.
+    pub fn overload(mut self, overload: Overload) -> Self {
+        self.limits.overload = overload;
+        self
+    }
+
+    /// Return the handle of the counters that describe the load of this server.
+    pub fn metrics(&self) -> LoadMetrics {
+        self.metrics.clone()
+    }
+
+    /// Write a line to `access_log` for each completed request.
+    ///
+    /// Regardless of this setting, each connection and request is processed
+    /// within a `tracing` span that carries the same information.
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(Arc::new(access_log));
+        self
+    }
+
+    /// Specify whether to require the PROXY protocol header (version 1 or 2)
+    /// at the beginning of each connection.
+    ///
+    /// If enabled, the client address in the header is used as `RemoteAddr`
+    /// and in the logs, and the connections without a valid header are closed.
+    /// The idle timeout also limits the time to receive the header.
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Specify whether the HTTP/1 connections may be half-closed by the clients
+    /// while the response is being sent.
+    ///
+    /// If disabled, the connection is closed as soon as the client shuts down
+    /// its write side, so that `Events::cancelled` can detect the disconnection
+    /// of the clients. The default is `true`.
+    pub fn http1_half_close(mut self, enabled: bool) -> Self {
+        self.http1_half_close = enabled;
+        self
+    }
+
+    /// Insert `value` into the extensions of each request as `izanami::state::State<T>`.
+    pub fn state<T>(mut self, value: T) -> Self
+    where
+        T: Send + Sync + 'static,
+    {
+        self.states.insert(value);
+        self
+    }
+
+    /// Build a value with `f` for each accepted connection, and insert it into
+    /// the extensions of the requests on that connection as `izanami::state::State<T>`.
+    pub fn connection_state<F, T>(mut self, f: F) -> Self
+    where
+        F: Fn(&RemoteAddr) -> T + Send + Sync + 'static,
+        T: Send + Sync + 'static,
+    {
+        self.states.insert_per_connection(f);
+        self
+    }
+
+    /// Record the server and application events to `metrics`.
+    ///
+    /// Use `izanami::metrics::MetricsApp` to serve the recorded values.
+    #[cfg(feature = "metrics")]
+    pub fn record_metrics(mut self, metrics: Metrics) -> Self {
+        self.recorder = Some(metrics);
+        self
+    }
+
     pub async fn serve<T>(self, app: T) -> hyper::Result<()>
     where
         T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
     {
-        let server = self
-            .builder
-            .serve(hyper::service::make_service_fn(move |_| {
-                let app = app.clone();
-                async move { Ok::<_, std::convert::Infallible>(AppService(app)) }
+        let timeouts = self.timeouts;
+        let max_streams = self
+            .limits
+            .max_requests_per_connection
+            .map(|max| max as u32);
+        let control = LoadControl::new(self.limits, self.metrics);
+        let access_log = self.access_log;
+        let states = self.states;
+        #[cfg(feature = "metrics")]
+        let recorder = self.recorder;
+        let incoming = Incoming::new(self.incoming, control.clone())
+            .proxy_protocol(self.proxy_protocol, timeouts.idle);
+        #[cfg(feature = "metrics")]
+        let incoming = incoming.record_metrics(recorder.clone());
+        let server = HyperServer::builder(incoming)
+            .http1_half_close(self.http1_half_close)
+            .http2_max_concurrent_streams(max_streams)
+            .serve(hyper::service::make_service_fn(move |conn: &Conn| {
+                let remote_addr = conn.remote_addr();
+                let service = AppService {
+                    app: app.clone(),
+                    cx: ConnContext {
+                        remote_addr,
+                        timeouts,
+                        limiter: control.connection(),
+                        control: control.clone(),
+                        access_log: access_log.clone(),
+                        state: states.connect(&RemoteAddr(remote_addr)),
+                        #[cfg(feature = "metrics")]
+                        recorder: recorder.clone(),
+                        span: tracing::info_span!("connection", peer = %remote_addr),
+                    },
+                };
+                async move { Ok::<_, std::convert::Infallible>(service) }
             }));
         server.await
     }
@@ -47,40 +247,54 @@ impl Server {
 
 #[derive(Debug)]
 pub struct Events<'a> {
-    req_body: Option<Body>,
-    response_sender: Option<oneshot::Sender<Response<Body>>>,
-    state: State,
-    _marker: PhantomData<&'a mut ()>,
+    req_body: &'a mut Option<Body>,
+    response_sender: &'a mut Option<oneshot::Sender<Response<ResponseBody>>>,
+    state: &'a mut State,
+    watchdog: &'a Watchdog,
+    record: &'a mut Record,
+    span: &'a Span,
 }
 
 #[derive(Debug)]
 enum State {
     Init,
     Streaming(BodySender),
-    Upgraded(Upgraded),
+    Upgraded(#[allow(dead_code)] Upgraded),
     Done,
+    Reset(Reason),
 }
 
 impl Events<'_> {
+    fn record_head(&mut self, status: StatusCode) {
+        self.record.status = Some(status);
+        self.record.head_duration = self.record.started.elapsed().ok();
+        self.span.record("status", status.as_u16());
+    }
+
     pub async fn data(&mut self) -> Option<hyper::Result<Chunk>> {
         let req_body = self.req_body.as_mut().unwrap();
-        poll_fn(|cx| Pin::new(&mut *req_body).poll_data(cx)).await
+        self.watchdog.start_reading();
+        let data = poll_fn(|cx| Pin::new(&mut *req_body).poll_data(cx)).await;
+        self.watchdog.touch();
+        if let Some(Ok(ref data)) = data {
+            self.record.bytes_in += data.len() as u64;
+        }
+        data
     }
 
     pub async fn trailers(&mut self) -> hyper::Result<Option<HeaderMap>> {
         let req_body = self.req_body.as_mut().unwrap();
-        poll_fn(|cx| Pin::new(&mut *req_body).poll_trailers(cx)).await
+        self.watchdog.start_reading();
+        let trailers = poll_fn(|cx| Pin::new(&mut *req_body).poll_trailers(cx)).await;
+        self.watchdog.touch();
+        trailers
     }
 
-    pub async fn send_response<T>(&mut self, response: Response<T>) -> hyper::Result<()>
-    where
-        T: Into<Body>,
-    {
-        let sender = self.response_sender.take().unwrap();
-        let _ = sender.send(response.map(Into::into));
-        self.state = State::Done;
-
-        Ok(())
+    fn ensure_not_reset(&self) -> hyper::Result<()> {
+        match *self.state {
+            State::Reset(..) => Err(closed_error()),
+            _ => Ok(()),
+        }
     }
 
     pub async fn start_send_response(
@@ -88,24 +302,27 @@ impl Events<'_> {
         response: Response<()>,
         end_of_stream: bool,
     ) -> hyper::Result<()> {
+        self.ensure_not_reset()?;
         let sender = self.response_sender.take().unwrap();
+        self.watchdog.head_sent();
+        self.record_head(response.status());
 
         if response.status() == StatusCode::SWITCHING_PROTOCOLS {
             debug_assert!(!end_of_stream);
 
-            let _ = sender.send(response.map(|_| Body::empty()));
+            let _ = sender.send(response.map(|_| ResponseBody::empty()));
 
             let req_body = self.req_body.take().unwrap();
             let upgraded = req_body.on_upgrade().await?;
-            self.state = State::Upgraded(upgraded);
+            *self.state = State::Upgraded(upgraded);
         } else if !end_of_stream {
-            let (body_sender, body) = hyper::Body::channel();
+            let (body_sender, body) = ResponseBody::channel();
             let _ = sender.send(response.map(|_| body));
 
-            self.state = State::Streaming(body_sender);
+            *self.state = State::Streaming(body_sender);
         } else {
-            let _ = sender.send(response.map(|_| Body::empty()));
-            self.state = State::Done;
+            let _ = sender.send(response.map(|_| ResponseBody::empty()));
+            *self.state = State::Done;
         }
 
         Ok(())
@@ -115,19 +332,77 @@ impl Events<'_> {
     where
         T: Into<Chunk>,
     {
-        match &mut self.state {
+        match &mut *self.state {
             State::Streaming(sender) => {
-                sender.send_data(data.into()).await?;
+                let data = data.into();
+                self.record.bytes_out += data.len() as u64;
+                sender.send_data(data).await?;
             }
+            State::Reset(..) => return Err(closed_error()),
             _ => panic!("unexpected call"),
         }
+        self.watchdog.touch();
 
         if is_end_stream {
-            self.state = State::Done;
+            *self.state = State::Done;
+        }
+
+        Ok(())
+    }
+
+    /// Send the trailers and end the response stream.
+    ///
+    /// Note that hyper sends the trailers only on HTTP/2 connections.
+    pub async fn send_trailers(&mut self, trailers: HeaderMap) -> hyper::Result<()> {
+        self.ensure_not_reset()?;
+        match mem::replace(&mut *self.state, State::Done) {
+            State::Streaming(sender) => sender.send_trailers(trailers),
+            _ => panic!("unexpected call"),
         }
+        self.watchdog.touch();
 
         Ok(())
     }
+
+    /// Abort the exchange with the client.
+    ///
+    /// hyper cannot specify the reason, so the stream is reset with `INTERNAL_ERROR`
+    /// on HTTP/2, and the connection is closed on HTTP/1.x.
+    /// The subsequent calls to send the response fail.
+    pub fn reset(&mut self, reason: Reason) {
+        // Dropping the sender of the response head fails the service.
+        self.response_sender.take();
+        if let State::Streaming(sender) = mem::replace(&mut *self.state, State::Reset(reason)) {
+            sender.abort();
+        }
+    }
+
+    /// Poll whether the next chunk of the response body can be sent without waiting.
+    ///
+    /// hyper accepts a chunk of any size once the previous one is taken, so the
+    /// capacity is `usize::MAX` when it is ready.
+    pub fn poll_send_capacity(&mut self, cx: &mut task::Context<'_>) -> Poll<hyper::Result<usize>> {
+        match &mut *self.state {
+            State::Streaming(sender) => sender.poll_ready(cx).map_ok(|()| usize::MAX),
+            State::Reset(..) => Poll::Ready(Err(closed_error())),
+            _ => Poll::Ready(Ok(usize::MAX)),
+        }
+    }
+
+    /// Wait until the client cancels the request, and return the reason.
+    ///
+    /// The cancellation is detected when hyper discards the response, that is,
+    /// when the stream is reset or the connection is closed (see `Server::http1_half_close`).
+    /// The reason is always `CANCEL` since hyper does not tell it.
+    pub async fn cancelled(&mut self) -> Reason {
+        match (&mut *self.response_sender, &mut *self.state) {
+            (Some(sender), _) => sender.closed().await,
+            (None, State::Streaming(sender)) => sender.closed().await,
+            (None, State::Reset(reason)) => return *reason,
+            (None, _) => future::pending().await,
+        }
+        Reason::CANCEL
+    }
 }
 
 #[async_trait]
@@ -168,35 +443,194 @@ impl<'a> izanami::Events for Events<'a> {
     async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error> {
         self.send_trailers(trailers).await
     }
+
+    #[inline]
+    fn reset(&mut self, reason: Reason) {
+        self.reset(reason)
+    }
+
+    #[inline]
+    async fn cancelled(&mut self) -> Reason {
+        self.cancelled().await
+    }
+
+    #[inline]
+    fn poll_send_capacity(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<usize, Self::Error>> {
+        self.poll_send_capacity(cx)
+    }
+
+    async fn send_response<T>(&mut self, response: Response<T>) -> Result<(), Self::Error>
+    where
+        T: Into<Self::Data> + Send,
+    {
+        self.ensure_not_reset()?;
+        let sender = self.response_sender.take().unwrap();
+        self.watchdog.head_sent();
+        self.record_head(response.status());
+        let response = response.map(|body| body.into());
+        self.record.bytes_out += response.body().len() as u64;
+        let _ = sender.send(response.map(|body| Body::from(body).into()));
+        *self.state = State::Done;
+
+        Ok(())
+    }
 }
 
-struct AppService<T>(T);
+/// Create the error returned by the calls after the exchange has been reset.
+///
+/// hyper does not expose the constructors of its error, so the one reported
+/// by a body channel whose receiver has been dropped is used instead.
+fn closed_error() -> hyper::Error {
+    let (mut sender, body) = Body::channel();
+    drop(body);
+    let mut cx = task::Context::from_waker(futures::task::noop_waker_ref());
+    match sender.poll_ready(&mut cx) {
+        Poll::Ready(Err(err)) => err,
+        _ => unreachable!("the receiver has been dropped"),
+    }
+}
+
+/// The values shared by the requests on a connection.
+#[derive(Debug, Clone)]
+struct ConnContext {
+    remote_addr: SocketAddr,
+    timeouts: Timeouts,
+    control: LoadControl,
+    limiter: Limiter,
+    access_log: Option<Arc<AccessLog>>,
+    state: ConnectionState,
+    #[cfg(feature = "metrics")]
+    recorder: Option<Metrics>,
+    span: Span,
+}
 
-impl<T> AppService<T>
+struct AppService<T> {
+    app: T,
+    cx: ConnContext,
+}
+
+fn spawn_background<T>(
+    app: T,
+    parts: http::request::Parts,
+    req_body: Body,
+    mut record: Record,
+    started: Instant,
+    guard: Guard,
+    cx: ConnContext,
+) -> oneshot::Receiver<Response<ResponseBody>>
 where
-    T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
+    T: for<'a> App<Events<'a>> + Send + Sync + 'static,
 {
-    fn spawn_background(&self, request: Request<Body>) -> oneshot::Receiver<Response<Body>> {
-        let (parts, req_body) = request.into_parts();
-        let app = self.0.clone();
-        let (tx, rx) = oneshot::channel();
-        tokio::spawn(async move {
-            if let Err(err) = app
-                .call(Request::from_parts(
-                    parts,
-                    Events {
-                        req_body: Some(req_body),
-                        response_sender: Some(tx),
-                        state: State::Init,
-                        _marker: PhantomData,
-                    },
-                ))
+    let (tx, rx) = oneshot::channel();
+    let span = Span::current();
+    tokio::spawn(
+        async move {
+            let span = Span::current();
+            let mut req_body = Some(req_body);
+            let mut response_sender = Some(tx);
+            let mut state = State::Init;
+            let watchdog = Watchdog::new(cx.timeouts);
+
+            // The status of the response sent instead of the application if it failed,
+            // or `None` if the response head has already been sent.
+            let failed = match watchdog
+                .run(
+                    AssertUnwindSafe(app.call(Request::from_parts(
+                        parts,
+                        Events {
+                            req_body: &mut req_body,
+                            response_sender: &mut response_sender,
+                            state: &mut state,
+                            watchdog: &watchdog,
+                            record: &mut record,
+                            span: &span,
+                        },
+                    )))
+                    .catch_unwind(),
+                )
                 .await
             {
-                eprintln!("app error: {}", err.into());
+                Ok(Ok(Ok(()))) => None,
+                Ok(Ok(Err(err))) => {
+                    tracing::error!("app error: {}", err.into());
+                    #[cfg(feature = "metrics")]
+                    {
+                        if let Some(ref recorder) = cx.recorder {
+                            recorder.app_error();
+                        }
+                    }
+                    None
+                }
+                Ok(Err(..)) => {
+                    tracing::error!("app panicked");
+                    #[cfg(feature = "metrics")]
+                    {
+                        if let Some(ref recorder) = cx.recorder {
+                            recorder.app_panic();
+                        }
+                    }
+                    Some(Some(StatusCode::INTERNAL_SERVER_ERROR))
+                }
+                Err(expired) => {
+                    tracing::debug!("request timed out");
+                    Some(expired.status())
+                }
+            };
+
+            if let Some(status) = failed {
+                match (status, response_sender.take()) {
+                    (Some(status), Some(sender)) => {
+                        let mut response = Response::new(ResponseBody::empty());
+                        *response.status_mut() = status;
+                        response
+                            .headers_mut()
+                            .insert(header::CONNECTION, HeaderValue::from_static("close"));
+                        let _ = sender.send(response);
+                        record.status = Some(status);
+                    }
+                    _ => {
+                        // Aborting the body closes the connection (or resets the stream in HTTP/2).
+                        if let State::Streaming(sender) = mem::replace(&mut state, State::Done) {
+                            sender.abort();
+                        }
+                    }
+                }
             }
-        });
-        rx
+
+            // The slot of the request is held until the application completes.
+            drop(guard);
+            finish_request(&mut record, started, &cx);
+        }
+        .instrument(span),
+    );
+    rx
+}
+
+/// Record the summary of a completed request to the current span, the access log
+/// and the metrics.
+fn finish_request(record: &mut Record, started: Instant, cx: &ConnContext) {
+    record.duration = started.elapsed();
+
+    let span = Span::current();
+    span.record("bytes_in", record.bytes_in);
+    span.record("bytes_out", record.bytes_out);
+    span.record("duration_ms", record.duration.as_secs_f64() * 1000.0);
+    if let Some(status) = record.status {
+        span.record("status", status.as_u16());
+    }
+    tracing::debug!("request completed");
+
+    if let Some(ref access_log) = cx.access_log {
+        access_log.log(record);
+    }
+    #[cfg(feature = "metrics")]
+    {
+        if let Some(ref recorder) = cx.recorder {
+            recorder.request_completed(record);
+        }
     }
 }
 
@@ -204,8 +638,8 @@ impl<T> Service<Request<Body>> for AppService<T>
 where
     T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
 {
-    type Response = Response<Body>;
-    type Error = hyper::Error;
+    type Response = Response<ResponseBody>;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
     #[allow(clippy::type_complexity)]
     type Future =
         Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
@@ -215,7 +649,59 @@ where
     }
 
     fn call(&mut self, request: Request<hyper::Body>) -> Self::Future {
-        let rx = self.spawn_background(request);
-        Box::pin(async move { Ok(rx.await.unwrap()) })
+        let app = self.app.clone();
+        let cx = self.cx.clone();
+        let span = tracing::info_span!(
+            parent: &cx.span,
+            "request",
+            peer = %cx.remote_addr,
+            method = %request.method(),
+            uri = %request.uri(),
+            version = ?request.version(),
+            status = field::Empty,
+            bytes_in = field::Empty,
+            bytes_out = field::Empty,
+            duration_ms = field::Empty,
+            request_id = field::Empty,
+        );
+        Box::pin(
+            async move {
+                let started = Instant::now();
+                let (mut parts, body) = request.into_parts();
+                parts.extensions.insert(RemoteAddr(cx.remote_addr));
+                cx.state.inject(&mut parts.extensions);
+                let mut record = Record::new(&parts);
+                record.remote_addr = Some(cx.remote_addr);
+
+                match cx.control.admit(&cx.limiter).await {
+                    Ok(guard) => {
+                        let rx = spawn_background(app, parts, body, record, started, guard, cx);
+                        // The sender is dropped if the exchange has been reset
+                        // before the response head.
+                        rx.await.map_err(|_| "the exchange has been reset".into())
+                    }
+                    Err(shed) => {
+                        tracing::debug!("request rejected by the concurrency limit");
+                        // The stream cannot be reset through hyper.
+                        let retry_after = match shed {
+                            Shed::Reject(retry_after) => retry_after,
+                            Shed::Refuse => None,
+                        };
+                        let mut response = Response::new(ResponseBody::empty());
+                        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                        if let Some(retry_after) = retry_after {
+                            response.headers_mut().insert(
+                                header::RETRY_AFTER,
+                                HeaderValue::from(retry_after.as_secs().max(1)),
+                            );
+                        }
+                        record.status = Some(StatusCode::SERVICE_UNAVAILABLE);
+                        finish_request(&mut record, started, &cx);
+                        Ok(response)
+                    }
+                }
+            }
+            .instrument(span),
+        )
     }
 }
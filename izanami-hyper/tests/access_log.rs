@@ -0,0 +1,91 @@
+mod support;
+
+use async_trait::async_trait;
+use http::{Request, Response};
+use izanami::{
+    access_log::{AccessLog, Format},
+    App, Events as _,
+};
+use izanami_hyper::{Events, Server};
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+use support::{run, yield_now};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+#[derive(Clone)]
+struct Hello;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Hello {
+    type Error = hyper::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        events.send_response(Response::new("hello")).await
+    }
+}
+
+/// A writer whose contents are shared with the test.
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuf {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn common_log_line() {
+    run(|_| async {
+        let buf = SharedBuf::default();
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .access_log(AccessLog::new(Format::Common, buf.clone()));
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            server.serve(Hello).await.unwrap();
+        });
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET /index.html HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut head = vec![0; 1024];
+        let n = stream.read(&mut head).await.unwrap();
+        assert!(head[..n].starts_with(b"HTTP/1.1 200 "));
+
+        // The line is written after the response has been passed to hyper.
+        while buf.contents().is_empty() {
+            yield_now().await;
+        }
+        let line = buf.contents();
+        assert!(line.starts_with("127.0.0.1 - - ["), "{:?}", line);
+        assert!(
+            line.ends_with("] \"GET /index.html HTTP/1.1\" 200 5\n"),
+            "{:?}",
+            line
+        );
+    });
+}
@@ -0,0 +1,140 @@
+mod support;
+
+use async_trait::async_trait;
+use futures::{channel::mpsc, future, StreamExt};
+use http::{Request, Response};
+use izanami::{App, Events as _};
+use izanami_hyper::{Events, LoadMetrics, Overload, Server};
+use std::{net::SocketAddr, time::Duration};
+use support::{run, yield_now};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// An app that notifies when it is called, and stalls unless `respond` is set.
+#[derive(Clone)]
+struct TestApp {
+    respond: bool,
+    notify: mpsc::UnboundedSender<()>,
+}
+
+#[async_trait]
+impl<'a> App<Events<'a>> for TestApp {
+    type Error = hyper::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        let _ = self.notify.unbounded_send(());
+        if self.respond {
+            events.send_response(Response::new("hello")).await
+        } else {
+            future::pending().await
+        }
+    }
+}
+
+fn spawn_server(
+    server: Server,
+    respond: bool,
+) -> (SocketAddr, LoadMetrics, mpsc::UnboundedReceiver<()>) {
+    let addr = server.local_addr();
+    let metrics = server.metrics();
+    let (notify, notified) = mpsc::unbounded();
+    tokio::spawn(async move {
+        server.serve(TestApp { respond, notify }).await.unwrap();
+    });
+    (addr, metrics, notified)
+}
+
+const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n";
+
+async fn read_head(stream: &mut TcpStream) -> String {
+    let mut buf = vec![0; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    String::from_utf8(buf[..n].to_vec()).unwrap()
+}
+
+#[test]
+fn reject_over_limit() {
+    run(|_| async {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .max_concurrent_requests(1)
+            .overload(Overload::Reject(Duration::from_secs(5)));
+        let (addr, metrics, mut notified) = spawn_server(server, false);
+
+        let mut first = TcpStream::connect(&addr).await.unwrap();
+        first.write_all(REQUEST).await.unwrap();
+        notified.next().await.unwrap();
+        assert_eq!(metrics.active_requests(), 1);
+
+        let mut second = TcpStream::connect(&addr).await.unwrap();
+        second.write_all(REQUEST).await.unwrap();
+        let response = read_head(&mut second).await;
+        assert!(response.starts_with("HTTP/1.1 503 "), "{:?}", response);
+        assert!(response.contains("retry-after: 5\r\n"), "{:?}", response);
+        assert_eq!(metrics.requests_rejected(), 1);
+        assert_eq!(metrics.active_requests(), 1);
+    });
+}
+
+#[test]
+fn queue_until_timeout() {
+    run(|clock| async move {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .max_concurrent_requests(1)
+            .overload(Overload::Queue(Duration::from_secs(10)));
+        let (addr, metrics, mut notified) = spawn_server(server, false);
+
+        let mut first = TcpStream::connect(&addr).await.unwrap();
+        first.write_all(REQUEST).await.unwrap();
+        notified.next().await.unwrap();
+
+        let mut second = TcpStream::connect(&addr).await.unwrap();
+        second.write_all(REQUEST).await.unwrap();
+        while metrics.requests_queued() == 0 {
+            yield_now().await;
+        }
+        clock.advance(Duration::from_secs(11));
+
+        let response = read_head(&mut second).await;
+        assert!(response.starts_with("HTTP/1.1 503 "), "{:?}", response);
+        assert!(!response.contains("retry-after"), "{:?}", response);
+        assert_eq!(metrics.requests_queued(), 1);
+        assert_eq!(metrics.requests_rejected(), 1);
+    });
+}
+
+#[test]
+fn defer_connections_over_limit() {
+    run(|_| async {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .max_connections(1);
+        let (addr, metrics, mut notified) = spawn_server(server, true);
+
+        let mut first = TcpStream::connect(&addr).await.unwrap();
+        first.write_all(REQUEST).await.unwrap();
+        let response = read_head(&mut first).await;
+        assert!(response.starts_with("HTTP/1.1 200 "), "{:?}", response);
+        notified.next().await.unwrap();
+        assert_eq!(metrics.active_connections(), 1);
+        assert_eq!(metrics.connections_deferred(), 1);
+
+        let mut second = TcpStream::connect(&addr).await.unwrap();
+        second.write_all(REQUEST).await.unwrap();
+        drop(first);
+
+        let response = read_head(&mut second).await;
+        assert!(response.starts_with("HTTP/1.1 200 "), "{:?}", response);
+        assert_eq!(metrics.active_connections(), 1);
+    });
+}
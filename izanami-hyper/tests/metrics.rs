@@ -0,0 +1,90 @@
+#![cfg(feature = "metrics")]
+
+mod support;
+
+use async_trait::async_trait;
+use http::{Request, Response};
+use izanami::{metrics::Metrics, App, Events as _};
+use izanami_hyper::{Events, Server};
+use std::io;
+use support::{run, yield_now};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// An app that fails depending on the request path.
+#[derive(Clone)]
+struct TestApp;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for TestApp {
+    type Error = io::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+        match parts.uri.path() {
+            "/error" => Err(io::Error::other("error")),
+            "/panic" => panic!("explicit panic"),
+            _ => events
+                .send_response(Response::new("hello"))
+                .await
+                .map_err(io::Error::other),
+        }
+    }
+}
+
+async fn get(stream: &mut TcpStream, path: &str) -> String {
+    let request = format!("GET {} HTTP/1.1\r\nhost: localhost\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut buf = vec![0; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    String::from_utf8(buf[..n].to_vec()).unwrap()
+}
+
+#[test]
+fn record_requests() {
+    run(|_| async {
+        let metrics = Metrics::new();
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .record_metrics(metrics.clone());
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            server.serve(TestApp).await.unwrap();
+        });
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        let response = get(&mut stream, "/").await;
+        assert!(response.starts_with("HTTP/1.1 200 "), "{:?}", response);
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        let response = get(&mut stream, "/panic").await;
+        assert!(response.starts_with("HTTP/1.1 500 "), "{:?}", response);
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET /error HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        // The requests are recorded after the application completes.
+        while !metrics.render().contains("izanami_app_errors_total 1\n") {
+            yield_now().await;
+        }
+        let text = metrics.render();
+        for line in &[
+            "izanami_connections_accepted_total 3\n",
+            "izanami_requests_total{method=\"GET\",status=\"200\"} 1\n",
+            "izanami_requests_total{method=\"GET\",status=\"500\"} 1\n",
+            "izanami_response_body_bytes_total 5\n",
+            "izanami_app_panics_total 1\n",
+        ] {
+            assert!(text.contains(line), "{:?} not in {}", line, text);
+        }
+    });
+}
@@ -0,0 +1,89 @@
+mod support;
+
+use async_trait::async_trait;
+use http::{Request, Response};
+use izanami::{App, Events as _, RemoteAddr};
+use izanami_hyper::{Events, Server};
+use std::io;
+use support::run;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Respond with the remote address of the request.
+#[derive(Clone)]
+struct Peer;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Peer {
+    type Error = hyper::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+        let RemoteAddr(addr) = parts.extensions.get::<RemoteAddr>().cloned().unwrap();
+        events.send_response(Response::new(addr.to_string())).await
+    }
+}
+
+#[test]
+fn use_source_address() {
+    run(|_| async {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .proxy_protocol(true);
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            server.serve(Peer).await.unwrap();
+        });
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"PROXY TCP4 203.0.113.7 192.0.2.1 56324 80\r\n")
+            .await
+            .unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 "), "{:?}", response);
+        assert!(
+            response.ends_with("\r\n\r\n203.0.113.7:56324"),
+            "{:?}",
+            response
+        );
+    });
+}
+
+#[test]
+fn close_without_header() {
+    run(|_| async {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .proxy_protocol(true);
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            server.serve(Peer).await.unwrap();
+        });
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        // The connection is closed with the unread request, which may cause a reset.
+        let mut response = vec![];
+        match stream.read_to_end(&mut response).await {
+            Ok(..) => assert!(response.is_empty()),
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::ConnectionReset),
+        }
+    });
+}
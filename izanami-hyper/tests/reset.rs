@@ -0,0 +1,124 @@
+mod support;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{HeaderMap, Request, Response};
+use izanami::{App, Reason};
+use izanami_hyper::{Events, Server};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use support::run;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::oneshot,
+};
+
+/// Stream the response until the client cancels the request, and report the reason.
+#[derive(Clone)]
+struct WaitCancel(Arc<Mutex<Option<oneshot::Sender<Reason>>>>);
+
+#[async_trait]
+impl<'a> App<Events<'a>> for WaitCancel {
+    type Error = hyper::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        events.start_send_response(Response::new(()), false).await?;
+        events.send_data(Bytes::from("partial"), false).await?;
+        let reason = events.cancelled().await;
+        let _ = self.0.lock().unwrap().take().unwrap().send(reason);
+        Ok(())
+    }
+}
+
+/// Abort every request without the response, and report whether
+/// the subsequent calls to send the response fail.
+#[derive(Clone)]
+struct Abort(Arc<Mutex<Option<oneshot::Sender<bool>>>>);
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Abort {
+    type Error = hyper::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        events.reset(Reason::ENHANCE_YOUR_CALM);
+        let failed = events
+            .start_send_response(Response::new(()), false)
+            .await
+            .is_err()
+            && events.send_data(Bytes::from("data"), false).await.is_err()
+            && events.send_trailers(HeaderMap::new()).await.is_err()
+            && izanami::Events::send_response(&mut events, Response::new(Bytes::new()))
+                .await
+                .is_err();
+        let _ = self.0.lock().unwrap().take().unwrap().send(failed);
+        Ok(())
+    }
+}
+
+async fn start_server<T>(app: T) -> SocketAddr
+where
+    T: for<'a> App<Events<'a>> + Clone + Send + Sync + 'static,
+{
+    let server = Server::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .http1_half_close(false);
+    let addr = server.local_addr();
+    tokio::spawn(async move {
+        server.serve(app).await.unwrap();
+    });
+    addr
+}
+
+#[test]
+fn cancelled_by_closing_connection() {
+    run(|_| async {
+        let (tx, rx) = oneshot::channel();
+        let addr = start_server(WaitCancel(Arc::new(Mutex::new(Some(tx))))).await;
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0; 4096];
+        let mut received = vec![];
+        while !received.ends_with(b"partial\r\n") {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0);
+            received.extend_from_slice(&buf[..n]);
+        }
+
+        drop(stream);
+        assert_eq!(rx.await.unwrap(), Reason::CANCEL);
+    });
+}
+
+#[test]
+fn reset_closes_connection() {
+    run(|_| async {
+        let (tx, rx) = oneshot::channel();
+        let addr = start_server(Abort(Arc::new(Mutex::new(Some(tx))))).await;
+
+        let mut stream = TcpStream::connect(&addr).await.unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = vec![];
+        let _ = stream.read_to_end(&mut received).await;
+        assert!(received.is_empty(), "{:?}", received);
+        assert!(rx.await.unwrap());
+    });
+}
@@ -0,0 +1,85 @@
+mod support;
+
+use async_trait::async_trait;
+use http::{Request, Response};
+use izanami::{state::State, App, Events as _, RemoteAddr};
+use izanami_hyper::{Events, Server};
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use support::run;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+struct ConnId(usize);
+
+/// Respond with the shared and per-connection states.
+#[derive(Clone)]
+struct Echo;
+
+#[async_trait]
+impl<'a> App<Events<'a>> for Echo {
+    type Error = hyper::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let name = State::<&'static str>::from_request(&request).unwrap();
+        let conn_id = State::<ConnId>::from_request(&request).unwrap();
+        let mut events = request.into_body();
+        events
+            .send_response(Response::new(format!("{}/{}", *name, conn_id.0)))
+            .await
+    }
+}
+
+async fn pipeline(addr: &SocketAddr, requests: usize) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    for _ in 1..requests {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+    }
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = vec![];
+    stream.read_to_end(&mut response).await.unwrap();
+    String::from_utf8(response).unwrap()
+}
+
+#[test]
+fn inject_states() {
+    run(|_| async {
+        let next_id = AtomicUsize::new(1);
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .state("example")
+            .connection_state(move |remote_addr: &RemoteAddr| {
+                assert!(remote_addr.0.ip().is_loopback());
+                ConnId(next_id.fetch_add(1, Ordering::SeqCst))
+            });
+        let addr = server.local_addr();
+        tokio::spawn(async move {
+            server.serve(Echo).await.unwrap();
+        });
+
+        let response = pipeline(&addr, 2).await;
+        assert_eq!(
+            response.matches("\r\n\r\nexample/1").count(),
+            2,
+            "{:?}",
+            response
+        );
+
+        let response = pipeline(&addr, 1).await;
+        assert!(response.ends_with("\r\n\r\nexample/2"), "{:?}", response);
+    });
+}
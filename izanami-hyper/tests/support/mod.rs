@@ -0,0 +1,54 @@
+#![allow(dead_code)]
+
+use futures::{future::poll_fn, task::Poll};
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::runtime::current_thread;
+use tokio_timer::clock::{Clock, Now};
+
+/// A clock that advances only when the test says so.
+#[derive(Debug, Clone)]
+pub struct MockClock(Arc<Mutex<Instant>>);
+
+impl Now for MockClock {
+    fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl MockClock {
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+pub fn run<F, Fut>(f: F)
+where
+    F: FnOnce(MockClock) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let clock = MockClock(Arc::new(Mutex::new(Instant::now())));
+    let mut rt = current_thread::Builder::new()
+        .clock(Clock::new_with_now(clock.clone()))
+        .build()
+        .unwrap();
+    rt.block_on(f(clock));
+}
+
+/// Yield to the other tasks and the I/O driver once.
+pub async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
@@ -0,0 +1,130 @@
+mod support;
+
+use async_trait::async_trait;
+use futures::{channel::mpsc, future, StreamExt};
+use http::{Request, Response};
+use izanami::App;
+use izanami_hyper::{Events, Server};
+use std::time::Duration;
+use support::{run, MockClock};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+#[derive(Debug, Copy, Clone)]
+enum Behavior {
+    /// Never send the response.
+    Stall,
+    /// Read the first chunk of the request body, and wait for the rest.
+    ReadBody,
+    /// Send the response head, and never send the body.
+    StallBody,
+}
+
+/// An app that notifies when it reaches the point to stall.
+#[derive(Clone)]
+struct TestApp {
+    behavior: Behavior,
+    notify: mpsc::UnboundedSender<()>,
+}
+
+#[async_trait]
+impl<'a> App<Events<'a>> for TestApp {
+    type Error = hyper::Error;
+
+    async fn call(&self, request: Request<Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        match self.behavior {
+            Behavior::Stall => {}
+            Behavior::ReadBody => {
+                let _ = events.data().await;
+                let _ = self.notify.unbounded_send(());
+                while let Some(data) = events.data().await {
+                    data?;
+                }
+            }
+            Behavior::StallBody => {
+                events.start_send_response(Response::new(()), false).await?;
+            }
+        }
+        let _ = self.notify.unbounded_send(());
+        future::pending().await
+    }
+}
+
+async fn request(server: Server, behavior: Behavior, request: &[u8], clock: MockClock) -> String {
+    let addr = server.local_addr();
+    let (notify, mut notified) = mpsc::unbounded();
+    tokio::spawn(async move {
+        server.serve(TestApp { behavior, notify }).await.unwrap();
+    });
+
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+
+    notified.next().await.unwrap();
+    clock.advance(Duration::from_secs(60));
+
+    let mut response = vec![];
+    let _ = stream.read_to_end(&mut response).await;
+    String::from_utf8(response).unwrap()
+}
+
+#[test]
+fn response_head_timeout() {
+    run(|clock| async move {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .response_head_timeout(Duration::from_secs(30));
+        let response = request(
+            server,
+            Behavior::Stall,
+            b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n",
+            clock,
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 503 "), "{:?}", response);
+    });
+}
+
+#[test]
+fn idle_timeout_while_reading_body() {
+    run(|clock| async move {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .idle_timeout(Duration::from_secs(10));
+        let response = request(
+            server,
+            Behavior::ReadBody,
+            b"POST / HTTP/1.1\r\nhost: localhost\r\ncontent-length: 10\r\n\r\nhello",
+            clock,
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 408 "), "{:?}", response);
+    });
+}
+
+#[test]
+fn request_timeout_after_response_head() {
+    run(|clock| async move {
+        let server = Server::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .request_timeout(Duration::from_secs(30));
+        let response = request(
+            server,
+            Behavior::StallBody,
+            b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n",
+            clock,
+        )
+        .await;
+        assert!(response.starts_with("HTTP/1.1 200 "), "{:?}", response);
+        assert!(!response.ends_with("0\r\n\r\n"), "{:?}", response);
+    });
+}
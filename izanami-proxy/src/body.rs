@@ -0,0 +1,95 @@
+use futures::{
+    future::Future,
+    task::{self, Poll},
+};
+use http::HeaderMap;
+use http_body::{Body as HttpBody, SizeHint};
+use hyper::body::{Body, Chunk, Sender};
+use std::pin::Pin;
+use tokio::sync::oneshot;
+
+/// The body of the requests sent to the upstream, which can carry the trailers.
+#[derive(Debug)]
+pub struct ProxyBody {
+    body: Body,
+    trailers: Option<oneshot::Receiver<HeaderMap>>,
+}
+
+impl ProxyBody {
+    pub(crate) fn empty() -> Self {
+        Self {
+            body: Body::empty(),
+            trailers: None,
+        }
+    }
+
+    pub(crate) fn channel() -> (BodySender, Self) {
+        let (data, body) = Body::channel();
+        let (trailers, rx) = oneshot::channel();
+        let sender = BodySender {
+            data,
+            trailers: Some(trailers),
+        };
+        let body = Self {
+            body,
+            trailers: Some(rx),
+        };
+        (sender, body)
+    }
+}
+
+impl HttpBody for ProxyBody {
+    type Data = Chunk;
+    type Error = hyper::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.body).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = match self.trailers {
+            Some(ref mut trailers) => futures::ready!(Pin::new(trailers).poll(cx)).ok(),
+            None => None,
+        };
+        self.trailers = None;
+        Poll::Ready(Ok(trailers))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.trailers.is_none() && self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+/// The sending half of `ProxyBody::channel`.
+#[derive(Debug)]
+pub(crate) struct BodySender {
+    data: Sender,
+    trailers: Option<oneshot::Sender<HeaderMap>>,
+}
+
+impl BodySender {
+    pub(crate) async fn send_data(&mut self, chunk: Chunk) -> hyper::Result<()> {
+        self.data.send_data(chunk).await
+    }
+
+    pub(crate) fn send_trailers(mut self, trailers: HeaderMap) {
+        if let Some(sender) = self.trailers.take() {
+            let _ = sender.send(trailers);
+        }
+    }
+
+    /// Abort the stream with an error.
+    pub(crate) fn abort(self) {
+        self.data.abort();
+    }
+}
@@ -0,0 +1,474 @@
+//! An `App` that forwards the requests to an upstream HTTP server.
+
+#![deny(
+    missing_debug_implementations,
+    nonstandard_style,
+    rust_2018_idioms,
+    rust_2018_compatibility,
+    unused
+)]
+
+mod body;
+
+pub use crate::body::ProxyBody;
+
+use crate::body::BodySender;
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use http::{
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    uri::{self, PathAndQuery, Uri},
+    Request, Response, StatusCode, Version,
+};
+use http_body::Body as _;
+use hyper::{
+    body::{Body, Chunk},
+    client::{Client, HttpConnector, ResponseFuture},
+};
+use izanami::{App, Events, RemoteAddr};
+use std::{error, fmt, mem, net::SocketAddr};
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+/// The headers that only apply to a single connection, which are not forwarded.
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// An `App` that forwards the requests to an upstream HTTP server.
+///
+/// The request and response bodies are streamed in both directions,
+/// including the trailers. The connections to the upstream are pooled
+/// and shared among the clones of this value.
+#[derive(Clone)]
+pub struct Proxy {
+    upstream: Uri,
+    client: Client<HttpConnector, ProxyBody>,
+    http2_only: bool,
+    max_idle_per_host: usize,
+    preserve_host: bool,
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("upstream", &self.upstream)
+            .field("http2_only", &self.http2_only)
+            .field("max_idle_per_host", &self.max_idle_per_host)
+            .field("preserve_host", &self.preserve_host)
+            .finish()
+    }
+}
+
+impl Proxy {
+    /// Create a `Proxy` that forwards the requests to `upstream` over HTTP/1.1.
+    ///
+    /// The path of `upstream`, if any, is prepended to the request paths.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `upstream` does not have the scheme and authority.
+    pub fn new(upstream: Uri) -> Self {
+        assert!(
+            upstream.scheme_part().is_some() && upstream.authority_part().is_some(),
+            "the upstream URI must be absolute"
+        );
+        let mut proxy = Self {
+            upstream,
+            client: Client::builder().build_http(),
+            http2_only: false,
+            max_idle_per_host: usize::MAX,
+            preserve_host: false,
+        };
+        proxy.rebuild_client();
+        proxy
+    }
+
+    fn rebuild_client(&mut self) {
+        self.client = Client::builder()
+            .http2_only(self.http2_only)
+            .max_idle_per_host(self.max_idle_per_host)
+            .build_http();
+    }
+
+    /// Specify whether to connect to the upstream with HTTP/2 (prior knowledge).
+    ///
+    /// The default value is `false`.
+    pub fn http2_only(mut self, enabled: bool) -> Self {
+        self.http2_only = enabled;
+        self.rebuild_client();
+        self
+    }
+
+    /// Set the maximum number of idle connections kept for the upstream.
+    pub fn max_idle_per_host(mut self, max: usize) -> Self {
+        self.max_idle_per_host = max;
+        self.rebuild_client();
+        self
+    }
+
+    /// Specify whether to forward the `Host` header of the request as is.
+    ///
+    /// The default value is `false`, in which case the authority of
+    /// the upstream is used. The original value is always available
+    /// to the upstream as `X-Forwarded-Host`.
+    pub fn preserve_host(mut self, enabled: bool) -> Self {
+        self.preserve_host = enabled;
+        self
+    }
+
+    fn upstream_uri(&self, uri: &Uri) -> Result<Uri, http::Error> {
+        let prefix = self.upstream.path().trim_end_matches('/');
+        let path_and_query = uri
+            .path_and_query()
+            .map_or("/", PathAndQuery::as_str)
+            .to_owned();
+
+        let mut parts = uri::Parts::default();
+        parts.scheme = self.upstream.scheme_part().cloned();
+        parts.authority = self.upstream.authority_part().cloned();
+        parts.path_and_query = Some(format!("{}{}", prefix, path_and_query).parse()?);
+        Ok(Uri::from_parts(parts)?)
+    }
+}
+
+#[async_trait]
+impl<E> App<E> for Proxy
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    type Error = BoxError;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+        let version = parts.version;
+
+        let has_body = match parts.headers.get(header::CONTENT_LENGTH) {
+            Some(len) => len != "0",
+            None => {
+                version >= Version::HTTP_2 || parts.headers.contains_key(header::TRANSFER_ENCODING)
+            }
+        };
+        let (sender, body) = if has_body {
+            let (sender, body) = ProxyBody::channel();
+            (Some(sender), body)
+        } else {
+            (None, ProxyBody::empty())
+        };
+
+        let mut upstream = Request::new(body);
+        *upstream.method_mut() = parts.method.clone();
+        *upstream.uri_mut() = self.upstream_uri(&parts.uri)?;
+        *upstream.version_mut() = if self.http2_only {
+            Version::HTTP_2
+        } else {
+            Version::HTTP_11
+        };
+        *upstream.headers_mut() = forwarded_headers(
+            &parts.headers,
+            &parts.uri,
+            parts.extensions.get::<RemoteAddr>().map(|addr| addr.0),
+            self.preserve_host,
+        );
+
+        // The request body is pumped while the response is forwarded, since
+        // the upstream may respond before it receives the whole request body.
+        let manual_release = sender.is_some() && events.set_manual_release(true);
+        let mut input = match sender {
+            Some(sender) => Input::Receiving(sender),
+            None => Input::Closed,
+        };
+        let mut output = Output::Waiting(self.client.request(upstream));
+        loop {
+            if let (Input::Closed, Output::Done) = (&input, &output) {
+                return Ok(());
+            }
+
+            let event = {
+                // The future of `data` is dropped if the upstream comes first, which
+                // loses nothing since the received data is buffered by the backends.
+                let receiving = async {
+                    match input {
+                        Input::Receiving(..) => Event::Received(events.data().await.map(|data| {
+                            data.map(|data| Chunk::from(data.collect::<Bytes>()))
+                                .map_err(Into::into)
+                        })),
+                        Input::Sending(ref mut sending) => {
+                            let (sender, len, sent) = sending.await;
+                            Event::Sent(sender, len, sent)
+                        }
+                        Input::Trailing(..) => {
+                            Event::Trailers(events.trailers().await.map_err(Into::into))
+                        }
+                        Input::Closed => future::pending().await,
+                    }
+                };
+                let forwarding = async {
+                    match output {
+                        Output::Waiting(ref mut response) => Event::Response(response.await),
+                        Output::Streaming(ref mut body) => Event::Chunk(body.next().await),
+                        Output::Done => future::pending().await,
+                    }
+                };
+                futures::pin_mut!(receiving, forwarding);
+                match future::select(receiving, forwarding).await {
+                    Either::Left((event, _)) | Either::Right((event, _)) => event,
+                }
+            };
+
+            match event {
+                Event::Received(Some(Ok(chunk))) => {
+                    let sender = input.take_sender();
+                    input = Input::Sending(send_chunk(sender, chunk));
+                }
+                Event::Received(Some(Err(err))) => {
+                    input.take_sender().abort();
+                    return Err(err);
+                }
+                Event::Received(None) => input = Input::Trailing(input.take_sender()),
+                Event::Sent(sender, len, Ok(())) => {
+                    // The capacity is released after the upstream takes the data,
+                    // so that the client sends the body as fast as the upstream receives it.
+                    if manual_release {
+                        if let Err(err) = events.release_capacity(len) {
+                            sender.abort();
+                            return Err(err.into());
+                        }
+                    }
+                    input = Input::Receiving(sender);
+                }
+                // The upstream has stopped receiving the body, so the rest is not read.
+                Event::Sent(_, _, Err(..)) => input = Input::Closed,
+                Event::Trailers(trailers) => {
+                    let sender = input.take_sender();
+                    input = Input::Closed;
+                    match trailers {
+                        Ok(Some(trailers)) => sender.send_trailers(trailers),
+                        Ok(None) => {}
+                        Err(err) => {
+                            sender.abort();
+                            return Err(err);
+                        }
+                    }
+                }
+                Event::Response(Ok(response)) => {
+                    let (mut parts, body) = response.into_parts();
+                    remove_hop_by_hop(&mut parts.headers);
+                    parts.version = version;
+                    let end_of_stream = body.is_end_stream();
+                    events
+                        .start_send_response(Response::from_parts(parts, ()), end_of_stream)
+                        .await
+                        .map_err(Into::into)?;
+                    output = if end_of_stream {
+                        Output::Done
+                    } else {
+                        Output::Streaming(body)
+                    };
+                }
+                Event::Response(Err(..)) => {
+                    let response = Response::builder()
+                        .status(StatusCode::BAD_GATEWAY)
+                        .header(header::CONTENT_LENGTH, "0")
+                        .body(())?;
+                    return events
+                        .start_send_response(response, true)
+                        .await
+                        .map_err(Into::into);
+                }
+                Event::Chunk(Some(chunk)) => {
+                    events
+                        .send_data(chunk?.into_bytes().into(), false)
+                        .await
+                        .map_err(Into::into)?;
+                }
+                Event::Chunk(None) => {
+                    let trailers = match output {
+                        Output::Streaming(ref mut body) => body.trailers().await?,
+                        _ => unreachable!(),
+                    };
+                    output = Output::Done;
+                    match trailers {
+                        Some(trailers) => events.send_trailers(trailers).await,
+                        None => events.send_data(Bytes::new().into(), true).await,
+                    }
+                    .map_err(Into::into)?;
+                }
+            }
+        }
+    }
+}
+
+type Sending = BoxFuture<'static, (BodySender, usize, hyper::Result<()>)>;
+
+/// The progress of pumping the request body to the upstream.
+enum Input {
+    /// Waiting for the next chunk of the request body.
+    Receiving(BodySender),
+    /// Sending a chunk to the upstream.
+    Sending(Sending),
+    /// Waiting for the trailers of the request.
+    Trailing(BodySender),
+    /// The request body has been forwarded, or the upstream has stopped receiving it.
+    Closed,
+}
+
+impl Input {
+    fn take_sender(&mut self) -> BodySender {
+        match mem::replace(self, Input::Closed) {
+            Input::Receiving(sender) | Input::Trailing(sender) => sender,
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// The progress of forwarding the response from the upstream.
+enum Output {
+    /// Waiting for the response head.
+    Waiting(ResponseFuture),
+    /// Streaming the response body.
+    Streaming(Body),
+    /// The response has been forwarded.
+    Done,
+}
+
+/// What has happened while forwarding the request.
+enum Event {
+    Received(Option<Result<Chunk, BoxError>>),
+    Sent(BodySender, usize, hyper::Result<()>),
+    Trailers(Result<Option<HeaderMap>, BoxError>),
+    Response(hyper::Result<Response<Body>>),
+    Chunk(Option<hyper::Result<Chunk>>),
+}
+
+/// Send a chunk of the request body to the upstream.
+///
+/// The sender is moved into the future and returned with the result,
+/// so that the future is kept while the response is being forwarded.
+fn send_chunk(mut sender: BodySender, chunk: Chunk) -> Sending {
+    async move {
+        let len = chunk.len();
+        let sent = sender.send_data(chunk).await;
+        (sender, len, sent)
+    }
+    .boxed()
+}
+
+/// Build the headers of the upstream request.
+fn forwarded_headers(
+    headers: &HeaderMap,
+    uri: &Uri,
+    remote_addr: Option<SocketAddr>,
+    preserve_host: bool,
+) -> HeaderMap {
+    let mut headers = headers.clone();
+
+    let te_trailers = headers
+        .get_all(header::TE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case("trailers"));
+    remove_hop_by_hop(&mut headers);
+    if te_trailers {
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+    }
+
+    let host = headers.get(header::HOST).cloned().or_else(|| {
+        uri.authority_part()
+            .and_then(|authority| HeaderValue::from_str(authority.as_str()).ok())
+    });
+    if !preserve_host {
+        headers.remove(header::HOST);
+    }
+    let proto = uri.scheme_str().unwrap_or("http");
+
+    let mut forwarded = format!(
+        "for={}",
+        remote_addr.map_or_else(|| "unknown".to_owned(), forwarded_node)
+    );
+    if let Some(host) = host.as_ref().and_then(|host| host.to_str().ok()) {
+        forwarded.push_str(";host=");
+        forwarded.push_str(&forwarded_value(host));
+    }
+    forwarded.push_str(";proto=");
+    forwarded.push_str(proto);
+    append(&mut headers, header::FORWARDED, &forwarded);
+
+    if let Some(addr) = remote_addr {
+        append(
+            &mut headers,
+            HeaderName::from_static("x-forwarded-for"),
+            &addr.ip().to_string(),
+        );
+    }
+    if let Ok(proto) = HeaderValue::from_str(proto) {
+        headers.insert(HeaderName::from_static("x-forwarded-proto"), proto);
+    }
+    if let Some(host) = host {
+        headers.insert(HeaderName::from_static("x-forwarded-host"), host);
+    }
+
+    headers
+}
+
+/// Append a value to the comma-separated list in the header.
+fn append(headers: &mut HeaderMap, name: HeaderName, value: &str) {
+    let value = match headers.get(&name).and_then(|v| v.to_str().ok()) {
+        Some(existing) if !existing.is_empty() => format!("{}, {}", existing, value),
+        _ => value.to_owned(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.insert(name, value);
+    }
+}
+
+/// Format the address of a node in `Forwarded`, as specified in RFC 7239.
+fn forwarded_node(addr: SocketAddr) -> String {
+    match addr {
+        SocketAddr::V4(addr) => addr.ip().to_string(),
+        SocketAddr::V6(addr) => format!("\"[{}]\"", addr.ip()),
+    }
+}
+
+/// Quote the value in `Forwarded` unless it is a token.
+fn forwarded_value(value: &str) -> String {
+    let is_token = !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if is_token {
+        value.to_owned()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+}
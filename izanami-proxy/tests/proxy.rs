@@ -0,0 +1,221 @@
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::future;
+use http::{HeaderMap, Request, Response, StatusCode, Uri};
+use http_body::Body as _;
+use hyper::{body::Chunk, Body, Client};
+use izanami::{App, Events as _};
+use izanami_proxy::Proxy;
+use std::net::SocketAddr;
+
+/// An upstream which echoes the request body and reports the request headers.
+#[derive(Clone)]
+struct Echo;
+
+#[async_trait]
+impl<'a> App<izanami_hyper::Events<'a>> for Echo {
+    type Error = hyper::Error;
+
+    async fn call(&self, request: Request<izanami_hyper::Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+
+        let mut body = Vec::new();
+        while let Some(chunk) = events.data().await {
+            body.extend_from_slice(chunk?.bytes());
+        }
+
+        let mut response = Response::builder();
+        response.header("x-path", parts.uri.path_and_query().unwrap().as_str());
+        response.header("connection", "x-hop");
+        response.header("x-hop", "dropped");
+        for (name, value) in &parts.headers {
+            response.header(format!("x-echo-{}", name).as_str(), value.clone());
+        }
+        events.send_response(response.body(body).unwrap()).await
+    }
+}
+
+/// An HTTP/2 upstream which echoes the request trailers in the response trailers.
+#[derive(Clone)]
+struct Trailers;
+
+#[async_trait]
+impl<'a> App<izanami_h2::Events<'a>> for Trailers {
+    type Error = h2::Error;
+
+    async fn call(&self, request: Request<izanami_h2::Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        let mut len = 0;
+        while let Some(data) = events.data().await {
+            len += data?.remaining();
+        }
+        let mut trailers = events.trailers().await?.unwrap_or_default();
+        trailers.insert("x-len", len.to_string().parse().unwrap());
+
+        events.start_send_response(Response::new(()), false).await?;
+        events.send_data(Bytes::from("done"), false).await?;
+        events.send_trailers(trailers).await
+    }
+}
+
+/// An upstream which echoes each chunk of the request body as soon as it is received.
+#[derive(Clone)]
+struct Duplex;
+
+#[async_trait]
+impl<'a> App<izanami_hyper::Events<'a>> for Duplex {
+    type Error = hyper::Error;
+
+    async fn call(&self, request: Request<izanami_hyper::Events<'a>>) -> Result<(), Self::Error>
+    where
+        'a: 'async_trait,
+    {
+        let mut events = request.into_body();
+        events.start_send_response(Response::new(()), false).await?;
+        while let Some(chunk) = events.data().await {
+            events.send_data(chunk?, false).await?;
+        }
+        events.send_data(Chunk::from(Bytes::new()), true).await
+    }
+}
+
+async fn start_proxy(proxy: Proxy) -> SocketAddr {
+    let server = izanami_hyper::Server::bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr();
+    tokio::spawn(async move {
+        server.serve(proxy).await.unwrap();
+    });
+    addr
+}
+
+fn upstream_uri(addr: SocketAddr, path: &str) -> Uri {
+    format!("http://{}{}", addr, path).parse().unwrap()
+}
+
+#[tokio::test]
+async fn forward_http1() {
+    let upstream = izanami_hyper::Server::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr();
+    tokio::spawn(async move {
+        upstream.serve(Echo).await.unwrap();
+    });
+    let addr = start_proxy(Proxy::new(upstream_uri(upstream_addr, "/api/"))).await;
+
+    let client = Client::new();
+    let request = Request::post(format!("http://{}/items?id=1", addr))
+        .header("connection", "x-custom")
+        .header("x-custom", "hop")
+        .header("x-forwarded-for", "192.0.2.1")
+        .body(Body::from("hello, proxy"))
+        .unwrap();
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let headers = response.headers().clone();
+    assert_eq!(headers["x-path"], "/api/items?id=1");
+    assert!(!headers.contains_key("x-hop"));
+    assert!(!headers.contains_key("x-echo-x-custom"));
+    assert!(!headers.contains_key("x-echo-connection"));
+    assert_eq!(headers["x-echo-host"], upstream_addr.to_string().as_str());
+    assert_eq!(headers["x-echo-x-forwarded-for"], "192.0.2.1, 127.0.0.1");
+    assert_eq!(headers["x-echo-x-forwarded-proto"], "http");
+    assert_eq!(
+        headers["x-echo-x-forwarded-host"],
+        addr.to_string().as_str()
+    );
+    assert_eq!(
+        headers["x-echo-forwarded"],
+        format!("for=127.0.0.1;host=\"{}\";proto=http", addr).as_str()
+    );
+
+    let mut body = response.into_body();
+    let mut received = Vec::new();
+    while let Some(chunk) = body.next().await {
+        received.extend_from_slice(&chunk.unwrap());
+    }
+    assert_eq!(received, b"hello, proxy");
+
+    // The following requests go through the same pooled client.
+    let response = client
+        .get(format!("http://{}/", addr).parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["x-path"], "/api/");
+}
+
+#[tokio::test]
+async fn forward_trailers_over_h2() {
+    let upstream = izanami_h2::Server::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr().unwrap();
+    tokio::spawn(async move {
+        upstream.serve(Trailers).await.unwrap();
+    });
+    let proxy = Proxy::new(upstream_uri(upstream_addr, "")).http2_only(true);
+    let addr = start_proxy(proxy).await;
+
+    let client = Client::builder().http2_only(true).build_http();
+    let (mut sender, body) = Body::channel();
+    let request = Request::post(format!("http://{}/", addr))
+        .body(body)
+        .unwrap();
+    let response = client.request(request);
+    tokio::spawn(async move {
+        sender.send_data("0123456789".into()).await.unwrap();
+    });
+    let response = response.await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let mut body = response.into_body();
+    let mut received = Vec::new();
+    while let Some(chunk) = body.next().await {
+        received.extend_from_slice(&chunk.unwrap());
+    }
+    assert_eq!(received, b"done");
+
+    let trailers: Option<HeaderMap> = body.trailers().await.unwrap();
+    assert_eq!(trailers.unwrap()["x-len"], "10");
+}
+
+/// Receive the response body until `len` bytes arrive.
+async fn receive(body: &mut Body, len: usize) -> Vec<u8> {
+    let mut received = Vec::new();
+    while received.len() < len {
+        received.extend_from_slice(&body.next().await.unwrap().unwrap());
+    }
+    received
+}
+
+#[tokio::test]
+async fn forward_response_while_receiving_request_body() {
+    let upstream = izanami_hyper::Server::bind("127.0.0.1:0").await.unwrap();
+    let upstream_addr = upstream.local_addr();
+    tokio::spawn(async move {
+        upstream.serve(Duplex).await.unwrap();
+    });
+    let addr = start_proxy(Proxy::new(upstream_uri(upstream_addr, ""))).await;
+
+    let client = Client::new();
+    let (mut sender, body) = Body::channel();
+    let request = Request::post(format!("http://{}/", addr))
+        .body(body)
+        .unwrap();
+    let (response, sent) =
+        future::join(client.request(request), sender.send_data("ping".into())).await;
+    sent.unwrap();
+    let response = response.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Each chunk is echoed back before the next one is sent.
+    let mut body = response.into_body();
+    assert_eq!(receive(&mut body, 4).await, b"ping");
+    sender.send_data("pong".into()).await.unwrap();
+    assert_eq!(receive(&mut body, 4).await, b"pong");
+    drop(sender);
+    assert!(body.next().await.is_none());
+}
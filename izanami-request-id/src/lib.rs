@@ -0,0 +1,304 @@
+//! A middleware that assigns an ID to each request.
+
+#![deny(
+    missing_debug_implementations,
+    nonstandard_style,
+    rust_2018_idioms,
+    rust_2018_compatibility,
+    unused
+)]
+
+use async_trait::async_trait;
+use http::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Request, Response,
+};
+use izanami::{App, Events, Reason};
+use std::{
+    error, fmt,
+    task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing::Span;
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+/// The maximum length of the request IDs accepted from the client.
+const MAX_LEN: usize = 128;
+
+/// The ID assigned to a request, stored in the request extensions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId(HeaderValue);
+
+impl RequestId {
+    /// Return the string representation of the ID.
+    pub fn as_str(&self) -> &str {
+        // The value is validated to be visible ASCII.
+        self.0.to_str().unwrap_or_default()
+    }
+
+    /// Return the ID as a header value.
+    pub fn header_value(&self) -> &HeaderValue {
+        &self.0
+    }
+
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let bytes = value.as_bytes();
+        let valid = !bytes.is_empty()
+            && bytes.len() <= MAX_LEN
+            && bytes.iter().all(|&b| (0x21..=0x7e).contains(&b));
+        if valid {
+            Some(RequestId(value.clone()))
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The format of the generated request IDs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Format {
+    /// A random UUID (version 4), such as `5f1b0a4e-7c3d-4e2a-9b8f-0d6c1e2f3a4b`.
+    #[default]
+    UuidV4,
+
+    /// A ULID, such as `01ARZ3NDEKTSV4RRFFQ69G5FAV`, which sorts by the generation time.
+    Ulid,
+}
+
+impl Format {
+    /// Generate a new ID.
+    pub fn generate(self) -> RequestId {
+        let id = match self {
+            Format::UuidV4 => uuid_v4(),
+            Format::Ulid => ulid(),
+        };
+        RequestId(HeaderValue::from_str(&id).expect("the generated ID is valid"))
+    }
+}
+
+fn uuid_v4() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let mut id = String::with_capacity(36);
+    for (i, b) in bytes.iter().enumerate() {
+        if i == 4 || i == 6 || i == 8 || i == 10 {
+            id.push('-');
+        }
+        id.push_str(&format!("{:02x}", b));
+    }
+    id
+}
+
+fn ulid() -> String {
+    const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64);
+    let random = rand::random::<u128>() & ((1 << 80) - 1);
+    let value = (u128::from(millis & ((1 << 48) - 1)) << 80) | random;
+
+    // 26 characters of 5 bits each, where the first one carries only 3 bits.
+    (0..26)
+        .rev()
+        .map(|i| ALPHABET[((value >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+/// A middleware that assigns an ID to each request.
+///
+/// The ID is taken from the `X-Request-Id` header of the request, or generated
+/// if the header is missing or invalid. It is stored in the request extensions as
+/// `RequestId`, recorded to the `request_id` field of the current `tracing` span,
+/// and added to the response head.
+#[derive(Debug, Clone)]
+pub struct SetRequestId<A> {
+    app: A,
+    header_name: HeaderName,
+    format: Format,
+    trust_incoming: bool,
+}
+
+impl<A> SetRequestId<A> {
+    /// Wrap an `App` with the default configuration.
+    pub fn new(app: A) -> Self {
+        Self {
+            app,
+            header_name: HeaderName::from_static("x-request-id"),
+            format: Format::default(),
+            trust_incoming: true,
+        }
+    }
+
+    /// Set the name of the header that carries the ID.
+    ///
+    /// The default value is `x-request-id`.
+    pub fn header_name(mut self, header_name: HeaderName) -> Self {
+        self.header_name = header_name;
+        self
+    }
+
+    /// Set the format of the generated IDs.
+    ///
+    /// The default value is `Format::UuidV4`.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Specify whether to use the ID sent by the client.
+    ///
+    /// The default value is `true`. If disabled, a new ID is always generated.
+    pub fn trust_incoming(mut self, enabled: bool) -> Self {
+        self.trust_incoming = enabled;
+        self
+    }
+}
+
+#[async_trait]
+impl<A, E> App<E> for SetRequestId<A>
+where
+    A: App<RequestIdEvents<E>> + Send + Sync,
+    E: Events + Send,
+    E::Data: Send,
+{
+    type Error = BoxError;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (mut parts, events) = request.into_parts();
+
+        let request_id = match parts.headers.get(&self.header_name) {
+            Some(value) if self.trust_incoming => RequestId::from_header(value),
+            _ => None,
+        }
+        .unwrap_or_else(|| self.format.generate());
+
+        Span::current().record("request_id", request_id.as_str());
+        parts.extensions.insert(request_id.clone());
+
+        let events = RequestIdEvents {
+            inner: events,
+            header_name: self.header_name.clone(),
+            request_id,
+        };
+
+        self.app
+            .call(Request::from_parts(parts, events))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// The `Events` passed to the inner `App` of `SetRequestId`.
+#[derive(Debug)]
+pub struct RequestIdEvents<E> {
+    inner: E,
+    header_name: HeaderName,
+    request_id: RequestId,
+}
+
+impl<E> RequestIdEvents<E> {
+    /// Return the ID assigned to the request.
+    pub fn request_id(&self) -> &RequestId {
+        &self.request_id
+    }
+
+    /// Return a reference to the underlying `Events`.
+    pub fn get_ref(&self) -> &E {
+        &self.inner
+    }
+
+    /// Return a mutable reference to the underlying `Events`.
+    pub fn get_mut(&mut self) -> &mut E {
+        &mut self.inner
+    }
+}
+
+#[async_trait]
+impl<E> Events for RequestIdEvents<E>
+where
+    E: Events + Send,
+    E::Data: Send,
+{
+    type Data = E::Data;
+    type Error = E::Error;
+
+    #[inline]
+    async fn data(&mut self) -> Option<Result<Self::Data, Self::Error>> {
+        self.inner.data().await
+    }
+
+    #[inline]
+    async fn trailers(&mut self) -> Result<Option<HeaderMap>, Self::Error> {
+        self.inner.trailers().await
+    }
+
+    async fn start_send_response(
+        &mut self,
+        mut response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        response
+            .headers_mut()
+            .entry(&self.header_name)
+            .expect("valid header name")
+            .or_insert_with(|| self.request_id.0.clone());
+        self.inner
+            .start_send_response(response, end_of_stream)
+            .await
+    }
+
+    #[inline]
+    async fn send_data(
+        &mut self,
+        data: Self::Data,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        self.inner.send_data(data, end_of_stream).await
+    }
+
+    #[inline]
+    async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error> {
+        self.inner.send_trailers(trailers).await
+    }
+
+    fn reset(&mut self, reason: Reason) {
+        self.inner.reset(reason)
+    }
+
+    async fn cancelled(&mut self) -> Reason {
+        self.inner.cancelled().await
+    }
+
+    fn reserve_send_capacity(&mut self, capacity: usize) {
+        self.inner.reserve_send_capacity(capacity)
+    }
+
+    fn poll_send_capacity(&mut self, cx: &mut Context<'_>) -> Poll<Result<usize, Self::Error>> {
+        self.inner.poll_send_capacity(cx)
+    }
+
+    fn set_manual_release(&mut self, enabled: bool) -> bool {
+        self.inner.set_manual_release(enabled)
+    }
+
+    fn release_capacity(&mut self, len: usize) -> Result<(), Self::Error> {
+        self.inner.release_capacity(len)
+    }
+
+    fn is_push_supported(&self) -> bool {
+        self.inner.is_push_supported()
+    }
+}
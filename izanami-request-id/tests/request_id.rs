@@ -0,0 +1,102 @@
+use async_trait::async_trait;
+use futures::executor::block_on;
+use http::{Request, Response};
+use izanami::{App, Events};
+use izanami_request_id::{Format, RequestId, SetRequestId};
+use izanami_test::MockEvents;
+
+/// Respond with the request ID in `x-seen`.
+struct Inner;
+
+#[async_trait]
+impl<E> App<E> for Inner
+where
+    E: Events + Send,
+{
+    type Error = E::Error;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+        let request_id = parts.extensions.get::<RequestId>().unwrap();
+        let response = Response::builder()
+            .header("x-seen", request_id.header_value().clone())
+            .body(())
+            .unwrap();
+        events.start_send_response(response, true).await
+    }
+}
+
+fn call(app: &SetRequestId<Inner>, request_id: Option<&str>) -> Response<()> {
+    let mut events = MockEvents::default();
+    let mut request = Request::get("/").body(&mut events).unwrap();
+    if let Some(request_id) = request_id {
+        request
+            .headers_mut()
+            .insert("x-request-id", request_id.parse().unwrap());
+    }
+    block_on(app.call(request)).unwrap();
+    events.response.unwrap()
+}
+
+#[test]
+fn propagate_incoming() {
+    let app = SetRequestId::new(Inner);
+    let response = call(&app, Some("abc-123"));
+    assert_eq!(response.headers()["x-request-id"], "abc-123");
+    assert_eq!(response.headers()["x-seen"], "abc-123");
+
+    let app = SetRequestId::new(Inner).trust_incoming(false);
+    let response = call(&app, Some("abc-123"));
+    assert_ne!(response.headers()["x-request-id"], "abc-123");
+    assert_eq!(
+        response.headers()["x-request-id"],
+        response.headers()["x-seen"]
+    );
+}
+
+#[test]
+fn generate_uuid() {
+    let app = SetRequestId::new(Inner);
+    for request_id in &[None, Some(""), Some("has space")] {
+        let response = call(&app, *request_id);
+        let id = response.headers()["x-request-id"].to_str().unwrap();
+        assert_eq!(response.headers()["x-seen"], id);
+        assert_eq!(id.len(), 36, "{}", id);
+        let groups: Vec<_> = id.split('-').map(str::len).collect();
+        assert_eq!(groups, [8, 4, 4, 4, 12]);
+        assert_eq!(&id[14..15], "4");
+        assert!("89ab".contains(&id[19..20]), "{}", id);
+    }
+}
+
+#[test]
+fn generate_ulid() {
+    let first = Format::Ulid.generate();
+    std::thread::sleep(std::time::Duration::from_millis(2));
+    let second = Format::Ulid.generate();
+    for id in &[&first, &second] {
+        assert_eq!(id.as_str().len(), 26);
+        assert!(id
+            .as_str()
+            .bytes()
+            .all(|b| b"0123456789ABCDEFGHJKMNPQRSTVWXYZ".contains(&b)));
+    }
+    assert!(first.as_str() < second.as_str());
+
+    let app = SetRequestId::new(Inner).format(Format::Ulid);
+    let response = call(&app, None);
+    assert_eq!(response.headers()["x-request-id"].len(), 26);
+}
+
+#[test]
+fn usable_with_both_backends() {
+    fn assert_hyper<T: for<'a> App<izanami_hyper::Events<'a>>>(_: &T) {}
+    fn assert_h2<T: for<'a> App<izanami_h2::Events<'a>>>(_: &T) {}
+
+    let app = SetRequestId::new(Inner);
+    assert_hyper(&app);
+    assert_h2(&app);
+}
@@ -0,0 +1,518 @@
+//! An `App` that serves static files from a directory.
+
+#![deny(
+    missing_debug_implementations,
+    nonstandard_style,
+    rust_2018_idioms,
+    rust_2018_compatibility,
+    unused
+)]
+
+mod range;
+
+use crate::range::Ranges;
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{
+    header::{self, HeaderMap, HeaderValue},
+    request::Parts,
+    Method, Response, StatusCode,
+};
+use izanami::{App, Events};
+use percent_encoding::percent_decode_str;
+use std::{
+    error,
+    io::{self, SeekFrom},
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs::File, io::AsyncReadExt};
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The precompressed variants, in the order of preference.
+const ENCODINGS: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+
+/// An `App` that serves the files in a directory.
+#[derive(Debug, Clone)]
+pub struct ServeDir {
+    root: PathBuf,
+    index_files: Vec<String>,
+    chunk_size: usize,
+    precompressed: bool,
+}
+
+impl ServeDir {
+    /// Create a new `ServeDir` that serves the files under `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            index_files: vec!["index.html".into()],
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            precompressed: false,
+        }
+    }
+
+    /// Set the file names that are searched when a directory is requested.
+    ///
+    /// The default value is `["index.html"]`.
+    pub fn index_files<I>(mut self, index_files: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.index_files = index_files.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set the maximum size of the chunks passed to `send_data`.
+    ///
+    /// The default value is 64 KiB.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `chunk_size` is zero.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "the chunk size must be greater than zero");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Specify whether to serve the precompressed siblings (`*.br` and `*.gz`)
+    /// when the client accepts the corresponding encoding.
+    ///
+    /// The default value is `false`.
+    pub fn precompressed(mut self, enabled: bool) -> Self {
+        self.precompressed = enabled;
+        self
+    }
+
+    async fn resolve(&self, parts: &Parts) -> Result<Resolved, io::Error> {
+        let mut path = match resolve_path(&self.root, parts.uri.path()) {
+            Some(path) => path,
+            None => return Ok(Resolved::Status(StatusCode::NOT_FOUND)),
+        };
+
+        let mut metadata = tokio::fs::metadata(&path).await?;
+        if metadata.is_dir() {
+            if !parts.uri.path().ends_with('/') {
+                let mut location = format!("{}/", parts.uri.path());
+                if let Some(query) = parts.uri.query() {
+                    location.push('?');
+                    location.push_str(query);
+                }
+                return Ok(Resolved::Redirect(location));
+            }
+
+            let mut found = None;
+            for index_file in &self.index_files {
+                let index_path = path.join(index_file);
+                match tokio::fs::metadata(&index_path).await {
+                    Ok(ref m) if m.is_file() => {
+                        found = Some((index_path, m.clone()));
+                        break;
+                    }
+                    _ => continue,
+                }
+            }
+            match found {
+                Some((index_path, index_metadata)) => {
+                    path = index_path;
+                    metadata = index_metadata;
+                }
+                None => return Ok(Resolved::Status(StatusCode::NOT_FOUND)),
+            }
+        }
+
+        let content_type = mime_guess::from_path(&path).first_or_octet_stream();
+
+        let mut encoding = None;
+        if self.precompressed {
+            for &(coding, ext) in ENCODINGS {
+                if !accepts_encoding(&parts.headers, coding) {
+                    continue;
+                }
+                let mut file_name = path.file_name().unwrap_or_default().to_owned();
+                file_name.push(".");
+                file_name.push(ext);
+                let encoded_path = path.with_file_name(file_name);
+                if let Ok(m) = tokio::fs::metadata(&encoded_path).await {
+                    if m.is_file() {
+                        path = encoded_path;
+                        metadata = m;
+                        encoding = Some(coding);
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(Resolved::File(FileInfo {
+            path,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            content_type: content_type.to_string(),
+            encoding,
+        }))
+    }
+}
+
+#[derive(Debug)]
+enum Resolved {
+    Status(StatusCode),
+    Redirect(String),
+    File(FileInfo),
+}
+
+#[derive(Debug)]
+struct FileInfo {
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    content_type: String,
+    encoding: Option<&'static str>,
+}
+
+impl FileInfo {
+    fn etag(&self) -> String {
+        let mtime = self
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_secs());
+        match self.encoding {
+            Some(encoding) => format!("\"{:x}-{:x}-{}\"", self.len, mtime, encoding),
+            None => format!("\"{:x}-{:x}\"", self.len, mtime),
+        }
+    }
+
+    fn last_modified(&self) -> Option<String> {
+        self.modified.map(httpdate::fmt_http_date)
+    }
+
+    fn is_not_modified(&self, headers: &HeaderMap, etag: &str) -> bool {
+        if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+            return range::etag_matches(if_none_match, etag);
+        }
+        match (
+            header_str(headers, header::IF_MODIFIED_SINCE)
+                .and_then(|date| httpdate::parse_http_date(date).ok()),
+            self.modified,
+        ) {
+            (Some(since), Some(modified)) => truncate(modified) <= since,
+            _ => false,
+        }
+    }
+
+    fn ranges(&self, headers: &HeaderMap, etag: &str) -> Ranges {
+        let range = match header_str(headers, header::RANGE) {
+            Some(range) => range,
+            None => return Ranges::Ignore,
+        };
+
+        if let Some(if_range) = header_str(headers, header::IF_RANGE) {
+            let fresh = if if_range.starts_with('"') {
+                if_range == etag
+            } else {
+                match (httpdate::parse_http_date(if_range), self.modified) {
+                    (Ok(date), Some(modified)) => truncate(modified) == date,
+                    _ => false,
+                }
+            };
+            if !fresh {
+                return Ranges::Ignore;
+            }
+        }
+
+        range::parse(range, self.len)
+    }
+}
+
+#[async_trait]
+impl<E> App<E> for ServeDir
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    type Error = BoxError;
+
+    async fn call(&self, request: http::Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+
+        if parts.method != Method::GET && parts.method != Method::HEAD {
+            let response = Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(header::ALLOW, "GET, HEAD")
+                .body(())?;
+            return events
+                .start_send_response(response, true)
+                .await
+                .map_err(Into::into);
+        }
+
+        let info = match self.resolve(&parts).await {
+            Ok(Resolved::File(info)) => info,
+            Ok(Resolved::Redirect(location)) => {
+                let response = Response::builder()
+                    .status(StatusCode::MOVED_PERMANENTLY)
+                    .header(header::LOCATION, location)
+                    .body(())?;
+                return events
+                    .start_send_response(response, true)
+                    .await
+                    .map_err(Into::into);
+            }
+            Ok(Resolved::Status(status)) => return send_status(&mut events, status).await,
+            Err(err) => return send_status(&mut events, io_error_status(&err)).await,
+        };
+
+        let etag = info.etag();
+
+        let mut response = Response::builder();
+        response
+            .header(header::ETAG, etag.as_str())
+            .header(header::ACCEPT_RANGES, "bytes");
+        if let Some(last_modified) = info.last_modified() {
+            response.header(header::LAST_MODIFIED, last_modified);
+        }
+        if self.precompressed {
+            response.header(header::VARY, "accept-encoding");
+        }
+        if let Some(encoding) = info.encoding {
+            response.header(header::CONTENT_ENCODING, encoding);
+        }
+
+        if info.is_not_modified(&parts.headers, &etag) {
+            let response = response.status(StatusCode::NOT_MODIFIED).body(())?;
+            return events
+                .start_send_response(response, true)
+                .await
+                .map_err(Into::into);
+        }
+
+        let is_head = parts.method == Method::HEAD;
+        let mut file = match File::open(&info.path).await {
+            Ok(file) => file,
+            Err(err) => return send_status(&mut events, io_error_status(&err)).await,
+        };
+
+        match info.ranges(&parts.headers, &etag) {
+            Ranges::Ignore => {
+                let response = response
+                    .header(header::CONTENT_TYPE, info.content_type.as_str())
+                    .header(header::CONTENT_LENGTH, info.len)
+                    .body(())?;
+                let end_of_stream = is_head || info.len == 0;
+                events
+                    .start_send_response(response, end_of_stream)
+                    .await
+                    .map_err(Into::into)?;
+                if !end_of_stream {
+                    self.send_range(&mut events, &mut file, 0..info.len, true)
+                        .await?;
+                }
+            }
+
+            Ranges::Unsatisfiable => {
+                let response = response
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", info.len))
+                    .body(())?;
+                events
+                    .start_send_response(response, true)
+                    .await
+                    .map_err(Into::into)?;
+            }
+
+            Ranges::Satisfiable(ref ranges) if ranges.len() == 1 => {
+                let range = ranges[0].clone();
+                let response = response
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(header::CONTENT_TYPE, info.content_type.as_str())
+                    .header(header::CONTENT_LENGTH, range.end - range.start)
+                    .header(header::CONTENT_RANGE, content_range(&range, info.len))
+                    .body(())?;
+                events
+                    .start_send_response(response, is_head)
+                    .await
+                    .map_err(Into::into)?;
+                if !is_head {
+                    self.send_range(&mut events, &mut file, range, true).await?;
+                }
+            }
+
+            Ranges::Satisfiable(ranges) => {
+                let boundary = format!("izanami-{}", etag.trim_matches('"'));
+                let part_headers: Vec<Bytes> = ranges
+                    .iter()
+                    .map(|range| {
+                        Bytes::from(format!(
+                            "\r\n--{}\r\ncontent-type: {}\r\ncontent-range: {}\r\n\r\n",
+                            boundary,
+                            info.content_type,
+                            content_range(range, info.len),
+                        ))
+                    })
+                    .collect();
+                let closing = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+                let content_length = part_headers.iter().map(|h| h.len() as u64).sum::<u64>()
+                    + ranges.iter().map(|r| r.end - r.start).sum::<u64>()
+                    + closing.len() as u64;
+
+                let response = response
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(
+                        header::CONTENT_TYPE,
+                        format!("multipart/byteranges; boundary={}", boundary),
+                    )
+                    .header(header::CONTENT_LENGTH, content_length)
+                    .body(())?;
+                events
+                    .start_send_response(response, is_head)
+                    .await
+                    .map_err(Into::into)?;
+                if !is_head {
+                    for (range, part_header) in ranges.into_iter().zip(part_headers) {
+                        events
+                            .send_data(part_header.into(), false)
+                            .await
+                            .map_err(Into::into)?;
+                        self.send_range(&mut events, &mut file, range, false)
+                            .await?;
+                    }
+                    events
+                        .send_data(closing.into(), true)
+                        .await
+                        .map_err(Into::into)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ServeDir {
+    async fn send_range<E>(
+        &self,
+        events: &mut E,
+        file: &mut File,
+        range: Range<u64>,
+        end_of_stream: bool,
+    ) -> Result<(), BoxError>
+    where
+        E: Events + Send,
+        E::Data: From<Bytes> + Send,
+    {
+        file.seek(SeekFrom::Start(range.start)).await?;
+
+        let mut remaining = range.end - range.start;
+        while remaining > 0 {
+            let len = remaining.min(self.chunk_size as u64) as usize;
+            let mut buf = vec![0u8; len];
+            file.read_exact(&mut buf[..]).await?;
+            remaining -= len as u64;
+
+            events
+                .send_data(Bytes::from(buf).into(), end_of_stream && remaining == 0)
+                .await
+                .map_err(Into::into)?;
+        }
+
+        Ok(())
+    }
+}
+
+async fn send_status<E>(events: &mut E, status: StatusCode) -> Result<(), BoxError>
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    let body = Bytes::from(status.canonical_reason().unwrap_or_default());
+    let response = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(E::Data::from(body))?;
+    events.send_response(response).await.map_err(Into::into)
+}
+
+fn io_error_status(err: &io::Error) -> StatusCode {
+    match err.kind() {
+        io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+        io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Map the request path to a file system path under `root`.
+///
+/// This function returns `None` if the path contains the segments that
+/// may escape from `root`, such as `..` or absolute paths.
+fn resolve_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+
+    let mut resolved = root.to_path_buf();
+    for segment in decoded.split('/') {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        if segment.contains('\\') || segment.contains('\0') {
+            return None;
+        }
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(component)), None) => resolved.push(component),
+            _ => return None,
+        }
+    }
+
+    Some(resolved)
+}
+
+fn accepts_encoding(headers: &HeaderMap, coding: &str) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or("").trim();
+            let q = params
+                .filter_map(|param| {
+                    let param = param.trim();
+                    if param.starts_with("q=") || param.starts_with("Q=") {
+                        param[2..].parse::<f32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+            name.eq_ignore_ascii_case(coding) && q > 0.0
+        })
+}
+
+fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers
+        .get(name)
+        .and_then(|value: &HeaderValue| value.to_str().ok())
+}
+
+/// Truncate the sub-second part since HTTP dates have the resolution of seconds.
+fn truncate(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => UNIX_EPOCH + std::time::Duration::from_secs(duration.as_secs()),
+        Err(..) => time,
+    }
+}
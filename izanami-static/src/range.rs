@@ -0,0 +1,102 @@
+use std::ops::Range;
+
+/// The maximum number of ranges accepted in a `Range` header.
+const MAX_RANGES: usize = 16;
+
+/// The result of interpreting the `Range` header.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Ranges {
+    /// The header is missing or cannot be interpreted.
+    /// The whole content should be sent.
+    Ignore,
+
+    /// None of the ranges overlap the content.
+    Unsatisfiable,
+
+    /// The satisfiable ranges.
+    Satisfiable(Vec<Range<u64>>),
+}
+
+/// Parse the value of `Range` header for the content of length `len`.
+pub(crate) fn parse(header: &str, len: u64) -> Ranges {
+    let specs = match split_prefix(header.trim(), "bytes=") {
+        Some(specs) => specs,
+        None => return Ranges::Ignore,
+    };
+
+    let mut ranges = vec![];
+    let mut count = 0;
+    for spec in specs.split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        count += 1;
+        if count > MAX_RANGES {
+            return Ranges::Ignore;
+        }
+
+        let mut bounds = spec.splitn(2, '-');
+        let first = bounds.next().map(str::trim).unwrap_or("");
+        let last = match bounds.next() {
+            Some(last) => last.trim(),
+            None => return Ranges::Ignore,
+        };
+
+        let range = if first.is_empty() {
+            // suffix-byte-range-spec
+            let suffix = match last.parse::<u64>() {
+                Ok(suffix) => suffix,
+                Err(..) => return Ranges::Ignore,
+            };
+            // An empty content has no byte to select.
+            if suffix == 0 || len == 0 {
+                continue;
+            }
+            len.saturating_sub(suffix)..len
+        } else {
+            let first = match first.parse::<u64>() {
+                Ok(first) => first,
+                Err(..) => return Ranges::Ignore,
+            };
+            let last = if last.is_empty() {
+                len.saturating_sub(1)
+            } else {
+                match last.parse::<u64>() {
+                    Ok(last) if last >= first => last.min(len.saturating_sub(1)),
+                    _ => return Ranges::Ignore,
+                }
+            };
+            if first >= len {
+                continue;
+            }
+            first..last + 1
+        };
+        ranges.push(range);
+    }
+
+    if count == 0 {
+        Ranges::Ignore
+    } else if ranges.is_empty() {
+        Ranges::Unsatisfiable
+    } else {
+        Ranges::Satisfiable(ranges)
+    }
+}
+
+/// Check whether the value of `If-None-Match` matches `etag`,
+/// by using the weak comparison.
+pub(crate) fn etag_matches(header: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    header
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+fn split_prefix<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    match s.get(..prefix.len()) {
+        Some(head) if head.eq_ignore_ascii_case(prefix) => Some(&s[prefix.len()..]),
+        _ => None,
+    }
+}
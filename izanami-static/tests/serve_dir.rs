@@ -0,0 +1,186 @@
+use http::{Request, StatusCode};
+use izanami::App;
+use izanami_static::ServeDir;
+use izanami_test::MockEvents;
+use std::{borrow::BorrowMut, path::PathBuf};
+
+fn fixture_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("izanami-static-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("hello.txt"), "Hello, world!\n").unwrap();
+    std::fs::write(dir.join("hello.txt.gz"), "compressed").unwrap();
+    std::fs::write(dir.join("sub/index.html"), "<p>index</p>").unwrap();
+    std::fs::write(dir.join("empty.txt"), "").unwrap();
+    dir
+}
+
+async fn get<B>(app: &ServeDir, mut builder: B) -> MockEvents
+where
+    B: BorrowMut<http::request::Builder>,
+{
+    let mut events = MockEvents::default();
+    let request = builder.borrow_mut().body(&mut events).unwrap();
+    app.call(request).await.unwrap();
+    assert!(events.end_of_stream);
+    events
+}
+
+#[tokio::test]
+async fn test_serve_files() {
+    let app = ServeDir::new(fixture_dir()).chunk_size(4);
+
+    let events = get(&app, Request::get("/hello.txt")).await;
+    assert_eq!(events.status(), StatusCode::OK);
+    assert_eq!(events.header("content-type"), Some("text/plain"));
+    assert_eq!(events.header("content-length"), Some("14"));
+    assert_eq!(events.body(), b"Hello, world!\n");
+    assert!(events.chunks.iter().all(|chunk| chunk.len() <= 4));
+
+    let events = get(&app, Request::head("/hello.txt")).await;
+    assert_eq!(events.status(), StatusCode::OK);
+    assert!(events.chunks.is_empty());
+
+    let events = get(&app, Request::get("/sub")).await;
+    assert_eq!(events.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(events.header("location"), Some("/sub/"));
+
+    let events = get(&app, Request::get("/sub/")).await;
+    assert_eq!(events.header("content-type"), Some("text/html"));
+    assert_eq!(events.body(), b"<p>index</p>");
+
+    let events = get(&app, Request::get("/missing.txt")).await;
+    assert_eq!(events.status(), StatusCode::NOT_FOUND);
+
+    let events = get(&app, Request::post("/hello.txt")).await;
+    assert_eq!(events.status(), StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+async fn test_path_traversal() {
+    let app = ServeDir::new(fixture_dir().join("sub"));
+    for path in &[
+        "/../hello.txt",
+        "/%2e%2e/hello.txt",
+        "/..%2fhello.txt",
+        "/..%5chello.txt",
+    ] {
+        let events = get(&app, Request::get(*path)).await;
+        assert_eq!(events.status(), StatusCode::NOT_FOUND, "path = {}", path);
+    }
+}
+
+#[tokio::test]
+async fn test_conditional_requests() {
+    let app = ServeDir::new(fixture_dir());
+
+    let events = get(&app, Request::get("/hello.txt")).await;
+    let etag = events.header("etag").unwrap().to_owned();
+    let last_modified = events.header("last-modified").unwrap().to_owned();
+
+    let events = get(
+        &app,
+        Request::get("/hello.txt").header("if-none-match", &*etag),
+    )
+    .await;
+    assert_eq!(events.status(), StatusCode::NOT_MODIFIED);
+    assert!(events.chunks.is_empty());
+
+    let events = get(
+        &app,
+        Request::get("/hello.txt").header("if-modified-since", &*last_modified),
+    )
+    .await;
+    assert_eq!(events.status(), StatusCode::NOT_MODIFIED);
+
+    let events = get(
+        &app,
+        Request::get("/hello.txt").header("if-none-match", "\"other\""),
+    )
+    .await;
+    assert_eq!(events.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_range_requests() {
+    let app = ServeDir::new(fixture_dir());
+
+    let events = get(
+        &app,
+        Request::get("/hello.txt").header("range", "bytes=7-11"),
+    )
+    .await;
+    assert_eq!(events.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(events.header("content-range"), Some("bytes 7-11/14"));
+    assert_eq!(events.body(), b"world");
+
+    let events = get(&app, Request::get("/hello.txt").header("range", "bytes=-2")).await;
+    assert_eq!(events.body(), b"!\n");
+
+    let events = get(
+        &app,
+        Request::get("/hello.txt").header("range", "bytes=100-"),
+    )
+    .await;
+    assert_eq!(events.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(events.header("content-range"), Some("bytes */14"));
+
+    for range in &["bytes=-5", "bytes=0-", "bytes=0-0"] {
+        let events = get(&app, Request::get("/empty.txt").header("range", *range)).await;
+        assert_eq!(events.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(events.header("content-range"), Some("bytes */0"));
+    }
+
+    let events = get(
+        &app,
+        Request::get("/hello.txt").header("range", "bytes=0-4,7-11"),
+    )
+    .await;
+    assert_eq!(events.status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = events.header("content-type").unwrap();
+    let (_, boundary) = content_type.split_once("boundary=").unwrap();
+    let body = String::from_utf8(events.body()).unwrap();
+    assert_eq!(
+        events.header("content-length"),
+        Some(&*body.len().to_string())
+    );
+    assert_eq!(
+        body,
+        format!(
+            "\r\n--{b}\r\ncontent-type: text/plain\r\ncontent-range: bytes 0-4/14\r\n\r\nHello\
+             \r\n--{b}\r\ncontent-type: text/plain\r\ncontent-range: bytes 7-11/14\r\n\r\nworld\
+             \r\n--{b}--\r\n",
+            b = boundary
+        )
+    );
+
+    let events = get(
+        &app,
+        Request::get("/hello.txt")
+            .header("range", "bytes=0-4")
+            .header("if-range", "\"stale\""),
+    )
+    .await;
+    assert_eq!(events.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_precompressed() {
+    let app = ServeDir::new(fixture_dir()).precompressed(true);
+
+    let events = get(
+        &app,
+        Request::get("/hello.txt").header("accept-encoding", "br, gzip"),
+    )
+    .await;
+    assert_eq!(events.header("content-encoding"), Some("gzip"));
+    assert_eq!(events.header("content-type"), Some("text/plain"));
+    assert_eq!(events.body(), b"compressed");
+
+    let events = get(
+        &app,
+        Request::get("/hello.txt").header("accept-encoding", "gzip;q=0"),
+    )
+    .await;
+    assert_eq!(events.header("content-encoding"), None);
+    assert_eq!(events.body(), b"Hello, world!\n");
+}
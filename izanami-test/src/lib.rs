@@ -0,0 +1,147 @@
+//! The helpers shared by the tests of the izanami crates.
+
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::channel::oneshot;
+use http::{HeaderMap, Response, StatusCode};
+use izanami::{Events, Reason};
+use std::{collections::VecDeque, io};
+
+/// A chunk of the body passed through `MockEvents`.
+#[derive(Debug)]
+pub struct Data(io::Cursor<Bytes>);
+
+impl From<Bytes> for Data {
+    fn from(bytes: Bytes) -> Self {
+        Self(io::Cursor::new(bytes))
+    }
+}
+
+impl From<&'static str> for Data {
+    fn from(s: &'static str) -> Self {
+        Bytes::from(s).into()
+    }
+}
+
+impl Buf for Data {
+    fn remaining(&self) -> usize {
+        self.0.remaining()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.0.bytes()
+    }
+
+    fn advance(&mut self, amt: usize) {
+        self.0.advance(amt)
+    }
+}
+
+/// An `Events` that replays the predefined request body and
+/// records the sent response.
+///
+/// The tests that inspect the response after calling the app pass
+/// `&mut MockEvents` as the request body.
+#[derive(Debug, Default)]
+pub struct MockEvents {
+    /// The chunks of the request body returned from `data`.
+    pub request_body: VecDeque<Bytes>,
+    pub response: Option<Response<()>>,
+    /// The chunks passed to `send_data`.
+    pub chunks: Vec<Bytes>,
+    pub trailers: Option<HeaderMap>,
+    pub end_of_stream: bool,
+    /// The number of chunks accepted before the client disconnects.
+    pub max_chunks: Option<usize>,
+    /// The client disconnects once the sender is used or dropped.
+    pub disconnected: Option<oneshot::Receiver<()>>,
+}
+
+impl MockEvents {
+    pub fn new<T: AsRef<[u8]>>(chunks: &[T]) -> Self {
+        Self {
+            request_body: chunks
+                .iter()
+                .map(|chunk| Bytes::from(chunk.as_ref()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.response.as_ref().unwrap().status()
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.response
+            .as_ref()
+            .unwrap()
+            .headers()
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+    }
+
+    /// Return the concatenation of the sent chunks.
+    pub fn body(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.iter().cloned())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Events for MockEvents {
+    type Data = Data;
+    type Error = io::Error;
+
+    async fn data(&mut self) -> Option<Result<Self::Data, Self::Error>> {
+        self.request_body.pop_front().map(|chunk| Ok(chunk.into()))
+    }
+
+    async fn trailers(&mut self) -> Result<Option<HeaderMap>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn start_send_response(
+        &mut self,
+        response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        assert!(self.response.is_none());
+        self.response = Some(response);
+        self.end_of_stream = end_of_stream;
+        Ok(())
+    }
+
+    async fn send_data(
+        &mut self,
+        data: Self::Data,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        assert!(!self.end_of_stream);
+        if self.max_chunks == Some(self.chunks.len()) {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+        self.chunks.push(Bytes::from(data.bytes()));
+        self.end_of_stream = end_of_stream;
+        Ok(())
+    }
+
+    async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error> {
+        assert!(!self.end_of_stream);
+        self.trailers = Some(trailers);
+        self.end_of_stream = true;
+        Ok(())
+    }
+
+    async fn cancelled(&mut self) -> Reason {
+        match self.disconnected {
+            Some(ref mut disconnected) => {
+                let _ = disconnected.await;
+                Reason::CANCEL
+            }
+            None => futures::future::pending().await,
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use std::fmt;
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+const B3: &str = "b3";
+const B3_TRACE_ID: &str = "x-b3-traceid";
+const B3_SPAN_ID: &str = "x-b3-spanid";
+const B3_SAMPLED: &str = "x-b3-sampled";
+const B3_FLAGS: &str = "x-b3-flags";
+
+/// The format of the headers that carry the trace context.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Propagation {
+    /// The W3C Trace Context headers, `traceparent` and `tracestate`.
+    #[default]
+    TraceContext,
+
+    /// The B3 headers, either the single `b3` header or the `X-B3-*` headers.
+    B3,
+}
+
+/// The 16-byte identifier of a trace.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TraceId(pub [u8; 16]);
+
+/// The 8-byte identifier of a span.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SpanId(pub [u8; 8]);
+
+macro_rules! impl_id {
+    ($name:ident, $len:expr) => {
+        impl $name {
+            /// Generate a random, valid identifier.
+            pub fn random() -> Self {
+                loop {
+                    let id = $name(rand::random());
+                    if id.is_valid() {
+                        return id;
+                    }
+                }
+            }
+
+            /// Return whether the identifier is valid, i.e. not all zeros.
+            pub fn is_valid(&self) -> bool {
+                self.0.iter().any(|&b| b != 0)
+            }
+
+            /// Parse the lowercase hexadecimal representation.
+            pub fn from_hex(s: &str) -> Option<Self> {
+                let mut id = [0; $len];
+                decode_hex(s, &mut id)?;
+                Some($name(id))
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                for b in &self.0 {
+                    write!(f, "{:02x}", b)?;
+                }
+                Ok(())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), self)
+            }
+        }
+    };
+}
+
+impl_id!(TraceId, 16);
+impl_id!(SpanId, 8);
+
+fn decode_hex(s: &str, out: &mut [u8]) -> Option<()> {
+    if s.len() != out.len() * 2 {
+        return None;
+    }
+    let digit = |c: u8| match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    };
+    for (b, pair) in out.iter_mut().zip(s.as_bytes().chunks(2)) {
+        *b = digit(pair[0])? << 4 | digit(pair[1])?;
+    }
+    Some(())
+}
+
+/// The identity of a span, propagated across the services.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanContext {
+    /// The identifier of the trace that the span belongs to.
+    pub trace_id: TraceId,
+
+    /// The identifier of the span.
+    pub span_id: SpanId,
+
+    /// Whether the trace is sampled.
+    pub sampled: bool,
+
+    /// The vendor-specific values carried by `tracestate`.
+    pub trace_state: Option<String>,
+}
+
+impl SpanContext {
+    /// Create the context of a new root span.
+    pub fn new_root() -> Self {
+        Self {
+            trace_id: TraceId::random(),
+            span_id: SpanId::random(),
+            sampled: true,
+            trace_state: None,
+        }
+    }
+
+    /// Create the context of a new child span.
+    pub fn new_child(&self) -> Self {
+        Self {
+            span_id: SpanId::random(),
+            ..self.clone()
+        }
+    }
+
+    /// Extract the context from the request headers.
+    ///
+    /// This method returns `None` if the headers are missing or malformed.
+    pub fn extract(headers: &HeaderMap, propagation: Propagation) -> Option<Self> {
+        match propagation {
+            Propagation::TraceContext => extract_trace_context(headers),
+            Propagation::B3 => extract_b3(headers),
+        }
+    }
+
+    /// Insert the headers that carry this context, e.g. into an outgoing request.
+    pub fn inject(&self, headers: &mut HeaderMap, propagation: Propagation) {
+        let mut insert = |name: &'static str, value: String| {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        };
+        match propagation {
+            Propagation::TraceContext => {
+                insert(
+                    TRACEPARENT,
+                    format!(
+                        "00-{}-{}-{:02x}",
+                        self.trace_id, self.span_id, self.sampled as u8
+                    ),
+                );
+                if let Some(ref trace_state) = self.trace_state {
+                    insert(TRACESTATE, trace_state.clone());
+                }
+            }
+            Propagation::B3 => insert(
+                B3,
+                format!("{}-{}-{}", self.trace_id, self.span_id, self.sampled as u8),
+            ),
+        }
+    }
+}
+
+fn extract_trace_context(headers: &HeaderMap) -> Option<SpanContext> {
+    let traceparent = headers.get(TRACEPARENT)?.to_str().ok()?.trim();
+    let mut fields = traceparent.split('-');
+    let version = fields.next()?;
+    let trace_id = TraceId::from_hex(fields.next()?)?;
+    let span_id = SpanId::from_hex(fields.next()?)?;
+    let mut flags = [0; 1];
+    decode_hex(fields.next()?, &mut flags)?;
+
+    // Future versions may append fields, but version 00 has exactly four.
+    let mut version_byte = [0; 1];
+    decode_hex(version, &mut version_byte)?;
+    match version_byte[0] {
+        0xff => return None,
+        0x00 if fields.next().is_some() => return None,
+        _ => {}
+    }
+    if !trace_id.is_valid() || !span_id.is_valid() {
+        return None;
+    }
+
+    let trace_state: Vec<&str> = headers
+        .get_all(TRACESTATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect();
+
+    Some(SpanContext {
+        trace_id,
+        span_id,
+        sampled: flags[0] & 0x01 != 0,
+        trace_state: if trace_state.is_empty() {
+            None
+        } else {
+            Some(trace_state.join(","))
+        },
+    })
+}
+
+fn extract_b3(headers: &HeaderMap) -> Option<SpanContext> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+    let (trace_id, span_id, sampled) = match header(B3) {
+        Some(b3) => {
+            let mut fields = b3.trim().split('-');
+            let trace_id = fields.next()?;
+            let span_id = fields.next()?;
+            (trace_id, span_id, fields.next())
+        }
+        None => (
+            header(B3_TRACE_ID)?,
+            header(B3_SPAN_ID)?,
+            b3_multi_sampled(headers),
+        ),
+    };
+
+    let trace_id = b3_trace_id(trace_id.trim())?;
+    let span_id = SpanId::from_hex(span_id.trim())?;
+    if !trace_id.is_valid() || !span_id.is_valid() {
+        return None;
+    }
+    let sampled = match sampled {
+        Some(sampled) => b3_sampled(sampled)?,
+        None => true,
+    };
+
+    Some(SpanContext {
+        trace_id,
+        span_id,
+        sampled,
+        trace_state: None,
+    })
+}
+
+/// Return the sampling decision sent without the trace and span IDs.
+///
+/// B3 allows the caller to propagate only the decision, e.g. `b3: 0` to deny
+/// the sampling of the whole trace.
+pub(crate) fn extract_sampling(headers: &HeaderMap, propagation: Propagation) -> Option<bool> {
+    match propagation {
+        Propagation::TraceContext => None,
+        Propagation::B3 => match headers.get(B3) {
+            Some(b3) => b3_sampled(b3.to_str().ok()?),
+            None => b3_sampled(b3_multi_sampled(headers)?),
+        },
+    }
+}
+
+fn b3_multi_sampled(headers: &HeaderMap) -> Option<&str> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    match header(B3_FLAGS) {
+        Some("1") => Some("d"),
+        _ => header(B3_SAMPLED),
+    }
+}
+
+fn b3_sampled(s: &str) -> Option<bool> {
+    match s.trim() {
+        "1" | "d" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse a B3 trace ID, which may be 64 or 128 bits long.
+fn b3_trace_id(s: &str) -> Option<TraceId> {
+    match s.len() {
+        16 => {
+            let mut id = [0; 16];
+            decode_hex(s, &mut id[8..])?;
+            Some(TraceId(id))
+        }
+        _ => TraceId::from_hex(s),
+    }
+}
@@ -0,0 +1,105 @@
+use crate::context::{SpanContext, SpanId};
+use http::{Method, StatusCode, Uri, Version};
+use std::{
+    fmt,
+    sync::{Arc, Mutex, PoisonError},
+    time::SystemTime,
+};
+
+/// A finished span, passed to `SpanExporter`.
+#[derive(Debug, Clone)]
+pub struct SpanData {
+    /// The context of the span.
+    pub context: SpanContext,
+
+    /// The identifier of the parent span, if the request carried a trace context.
+    pub parent_span_id: Option<SpanId>,
+
+    /// The name of the span.
+    pub name: String,
+
+    /// The method of the request.
+    pub method: Method,
+
+    /// The request target.
+    pub uri: Uri,
+
+    /// The HTTP version of the request.
+    pub version: Version,
+
+    /// The status code of the response, or `None` if the response head was not sent.
+    pub status: Option<StatusCode>,
+
+    /// The message of the error returned from the application, if any.
+    pub error: Option<String>,
+
+    /// The time when the application was called.
+    pub start_time: SystemTime,
+
+    /// The time when the application completed.
+    pub end_time: SystemTime,
+}
+
+/// A destination of the finished spans.
+pub trait SpanExporter: Send + Sync + 'static {
+    /// Export a finished span.
+    ///
+    /// This method is called on the task that processed the request,
+    /// so it should not block.
+    fn export(&self, span: SpanData);
+}
+
+impl<F> SpanExporter for F
+where
+    F: Fn(SpanData) + Send + Sync + 'static,
+{
+    fn export(&self, span: SpanData) {
+        (*self)(span)
+    }
+}
+
+/// A `SpanExporter` that keeps the spans in memory, mainly for testing.
+#[derive(Clone, Default)]
+pub struct InMemoryExporter {
+    spans: Arc<Mutex<Vec<SpanData>>>,
+}
+
+impl fmt::Debug for InMemoryExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InMemoryExporter")
+            .field("spans", &self.spans().len())
+            .finish()
+    }
+}
+
+impl InMemoryExporter {
+    /// Create an empty exporter.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the exported spans, in the order of completion.
+    pub fn spans(&self) -> Vec<SpanData> {
+        self.spans
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Remove all exported spans.
+    pub fn reset(&self) {
+        self.spans
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+impl SpanExporter for InMemoryExporter {
+    fn export(&self, span: SpanData) {
+        self.spans
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(span);
+    }
+}
@@ -0,0 +1,269 @@
+//! A middleware that propagates the distributed trace context.
+//!
+//! The context of the caller is read from the W3C Trace Context headers
+//! (or optionally the B3 headers), and a child span is created for each call
+//! of the inner `App`. The finished spans are passed to a `SpanExporter`.
+//!
+//! This crate only implements the propagation formats and a minimal span
+//! model. It does not depend on the OpenTelemetry API or SDK; an application
+//! that uses them can forward the `SpanData` from its own `SpanExporter`.
+
+#![deny(
+    missing_debug_implementations,
+    nonstandard_style,
+    rust_2018_idioms,
+    rust_2018_compatibility,
+    unused
+)]
+
+mod context;
+mod export;
+
+pub use crate::{
+    context::{Propagation, SpanContext, SpanId, TraceId},
+    export::{InMemoryExporter, SpanData, SpanExporter},
+};
+
+use async_trait::async_trait;
+use http::{HeaderMap, Request, Response, StatusCode};
+use izanami::{App, Events, Reason};
+use std::{
+    error, fmt,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::SystemTime,
+};
+use tracing::{field, Instrument, Span};
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+/// A middleware that creates a span for each call of the inner `App`.
+///
+/// The context of the created span is available to the inner `App`
+/// as the `SpanContext` in the request extensions. The call is also
+/// instrumented with a `tracing` span named `"trace_context"` that
+/// carries the trace and span IDs.
+///
+/// Only the sampled spans are exported. A request without a valid
+/// trace context starts a new trace, which is sampled unless the caller
+/// denied it with a B3 header carrying only the sampling decision (`b3: 0`).
+#[derive(Clone)]
+pub struct Trace<A> {
+    app: A,
+    exporter: Arc<dyn SpanExporter>,
+    propagation: Propagation,
+}
+
+impl<A: fmt::Debug> fmt::Debug for Trace<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Trace")
+            .field("app", &self.app)
+            .field("propagation", &self.propagation)
+            .finish()
+    }
+}
+
+impl<A> Trace<A> {
+    /// Wrap an `App` and export its spans to `exporter`.
+    pub fn new<T>(app: A, exporter: T) -> Self
+    where
+        T: SpanExporter,
+    {
+        Self {
+            app,
+            exporter: Arc::new(exporter),
+            propagation: Propagation::default(),
+        }
+    }
+
+    /// Set the format of the headers that carry the trace context.
+    ///
+    /// The default value is `Propagation::TraceContext`.
+    pub fn propagation(mut self, propagation: Propagation) -> Self {
+        self.propagation = propagation;
+        self
+    }
+}
+
+#[async_trait]
+impl<A, E> App<E> for Trace<A>
+where
+    A: App<TraceEvents<E>> + Send + Sync,
+    E: Events + Send,
+    E::Data: Send,
+{
+    type Error = BoxError;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (mut parts, events) = request.into_parts();
+
+        let parent = SpanContext::extract(&parts.headers, self.propagation);
+        let context = match parent {
+            Some(ref parent) => parent.new_child(),
+            None => SpanContext {
+                sampled: context::extract_sampling(&parts.headers, self.propagation)
+                    .unwrap_or(true),
+                ..SpanContext::new_root()
+            },
+        };
+        parts.extensions.insert(context.clone());
+
+        let span = tracing::info_span!(
+            "trace_context",
+            trace_id = %context.trace_id,
+            span_id = %context.span_id,
+            status = field::Empty,
+        );
+        let status = Arc::new(AtomicU16::new(0));
+        let events = TraceEvents {
+            inner: events,
+            context: context.clone(),
+            status: status.clone(),
+            span: span.clone(),
+        };
+
+        let mut data = SpanData {
+            name: format!("{} {}", parts.method, parts.uri.path()),
+            method: parts.method.clone(),
+            uri: parts.uri.clone(),
+            version: parts.version,
+            context,
+            parent_span_id: parent.map(|parent| parent.span_id),
+            status: None,
+            error: None,
+            start_time: SystemTime::now(),
+            end_time: SystemTime::now(),
+        };
+
+        let result = self
+            .app
+            .call(Request::from_parts(parts, events))
+            .instrument(span)
+            .await
+            .map_err(Into::into);
+
+        if data.context.sampled {
+            data.end_time = SystemTime::now();
+            data.status = StatusCode::from_u16(status.load(Ordering::Acquire)).ok();
+            data.error = result.as_ref().err().map(ToString::to_string);
+            self.exporter.export(data);
+        }
+
+        result
+    }
+}
+
+/// The `Events` passed to the inner `App` of `Trace`.
+pub struct TraceEvents<E> {
+    inner: E,
+    context: SpanContext,
+    status: Arc<AtomicU16>,
+    span: Span,
+}
+
+impl<E: fmt::Debug> fmt::Debug for TraceEvents<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceEvents")
+            .field("inner", &self.inner)
+            .field("context", &self.context)
+            .finish()
+    }
+}
+
+impl<E> TraceEvents<E> {
+    /// Return the context of the span created for this request.
+    pub fn context(&self) -> &SpanContext {
+        &self.context
+    }
+
+    /// Return a reference to the underlying `Events`.
+    pub fn get_ref(&self) -> &E {
+        &self.inner
+    }
+
+    /// Return a mutable reference to the underlying `Events`.
+    pub fn get_mut(&mut self) -> &mut E {
+        &mut self.inner
+    }
+}
+
+#[async_trait]
+impl<E> Events for TraceEvents<E>
+where
+    E: Events + Send,
+    E::Data: Send,
+{
+    type Data = E::Data;
+    type Error = E::Error;
+
+    #[inline]
+    async fn data(&mut self) -> Option<Result<Self::Data, Self::Error>> {
+        self.inner.data().await
+    }
+
+    #[inline]
+    async fn trailers(&mut self) -> Result<Option<HeaderMap>, Self::Error> {
+        self.inner.trailers().await
+    }
+
+    async fn start_send_response(
+        &mut self,
+        response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        let status = response.status().as_u16();
+        self.status.store(status, Ordering::Release);
+        self.span.record("status", status);
+        self.inner
+            .start_send_response(response, end_of_stream)
+            .await
+    }
+
+    #[inline]
+    async fn send_data(
+        &mut self,
+        data: Self::Data,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        self.inner.send_data(data, end_of_stream).await
+    }
+
+    #[inline]
+    async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error> {
+        self.inner.send_trailers(trailers).await
+    }
+
+    fn reset(&mut self, reason: Reason) {
+        self.inner.reset(reason)
+    }
+
+    async fn cancelled(&mut self) -> Reason {
+        self.inner.cancelled().await
+    }
+
+    fn reserve_send_capacity(&mut self, capacity: usize) {
+        self.inner.reserve_send_capacity(capacity)
+    }
+
+    fn poll_send_capacity(&mut self, cx: &mut Context<'_>) -> Poll<Result<usize, Self::Error>> {
+        self.inner.poll_send_capacity(cx)
+    }
+
+    fn set_manual_release(&mut self, enabled: bool) -> bool {
+        self.inner.set_manual_release(enabled)
+    }
+
+    fn release_capacity(&mut self, len: usize) -> Result<(), Self::Error> {
+        self.inner.release_capacity(len)
+    }
+
+    fn is_push_supported(&self) -> bool {
+        self.inner.is_push_supported()
+    }
+}
@@ -0,0 +1,206 @@
+use async_trait::async_trait;
+use futures::executor::block_on;
+use http::{HeaderMap, Request, Response, StatusCode};
+use izanami::{App, Events};
+use izanami_test::MockEvents;
+use izanami_trace_context::{
+    InMemoryExporter, Propagation, SpanContext, SpanData, SpanId, Trace, TraceEvents, TraceId,
+};
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+/// Respond with `202 Accepted` and remember the context in the request extensions.
+#[derive(Clone, Default)]
+struct Inner {
+    seen: Arc<Mutex<Option<SpanContext>>>,
+}
+
+#[async_trait]
+impl App<TraceEvents<MockEvents>> for Inner {
+    type Error = io::Error;
+
+    async fn call(&self, request: Request<TraceEvents<MockEvents>>) -> Result<(), Self::Error>
+    where
+        TraceEvents<MockEvents>: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+        let context = parts.extensions.get::<SpanContext>().cloned().unwrap();
+        assert_eq!(context, *events.context());
+        *self.seen.lock().unwrap() = Some(context);
+
+        let mut response = Response::new(());
+        *response.status_mut() = StatusCode::ACCEPTED;
+        events.start_send_response(response, true).await
+    }
+}
+
+fn call(
+    propagation: Propagation,
+    headers: &[(&'static str, &str)],
+) -> (Option<SpanData>, SpanContext) {
+    let exporter = InMemoryExporter::new();
+    let inner = Inner::default();
+    let app = Trace::new(inner.clone(), exporter.clone()).propagation(propagation);
+
+    let mut request = Request::get("/path?query")
+        .body(MockEvents::default())
+        .unwrap();
+    for (name, value) in headers {
+        request.headers_mut().insert(*name, value.parse().unwrap());
+    }
+    block_on(app.call(request)).unwrap();
+
+    let mut spans = exporter.spans();
+    assert!(spans.len() <= 1);
+    let seen = inner.seen.lock().unwrap().take().unwrap();
+    (spans.pop(), seen)
+}
+
+#[test]
+fn trace_context_child() {
+    let (span, seen) = call(
+        Propagation::TraceContext,
+        &[
+            (
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ),
+            ("tracestate", "congo=t61rcWkgMzE"),
+        ],
+    );
+    let span = span.unwrap();
+
+    assert_eq!(
+        span.context.trace_id,
+        TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+    );
+    assert_eq!(
+        span.parent_span_id,
+        Some(SpanId::from_hex("00f067aa0ba902b7").unwrap())
+    );
+    assert_ne!(span.context.span_id, span.parent_span_id.unwrap());
+    assert_eq!(
+        span.context.trace_state.as_deref(),
+        Some("congo=t61rcWkgMzE")
+    );
+    assert_eq!(span.context, seen);
+    assert_eq!(span.name, "GET /path");
+    assert_eq!(span.status, Some(StatusCode::ACCEPTED));
+    assert!(span.error.is_none());
+    assert!(span.start_time <= span.end_time);
+
+    let mut headers = HeaderMap::new();
+    seen.inject(&mut headers, Propagation::TraceContext);
+    assert_eq!(
+        headers["traceparent"],
+        format!("00-{}-{}-01", seen.trace_id, seen.span_id)
+    );
+    assert_eq!(
+        SpanContext::extract(&headers, Propagation::TraceContext),
+        Some(seen)
+    );
+}
+
+#[test]
+fn trace_context_root() {
+    for traceparent in &[
+        None,
+        Some("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+        Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra"),
+        Some("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        Some("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"),
+    ] {
+        let headers: Vec<_> = traceparent.iter().map(|v| ("traceparent", *v)).collect();
+        let (span, seen) = call(Propagation::TraceContext, &headers);
+        let span = span.unwrap();
+        assert!(span.parent_span_id.is_none(), "{:?}", traceparent);
+        assert!(span.context.trace_id.is_valid());
+        assert!(span.context.sampled);
+        assert_eq!(span.context, seen);
+    }
+}
+
+#[test]
+fn not_sampled() {
+    let (span, seen) = call(
+        Propagation::TraceContext,
+        &[(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+        )],
+    );
+    assert!(span.is_none());
+    assert!(!seen.sampled);
+}
+
+#[test]
+fn b3() {
+    let (span, _) = call(
+        Propagation::B3,
+        &[("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1")],
+    );
+    let span = span.unwrap();
+    assert_eq!(
+        span.context.trace_id,
+        TraceId::from_hex("80f198ee56343ba864fe8b2a57d3eff7").unwrap()
+    );
+    assert_eq!(
+        span.parent_span_id,
+        Some(SpanId::from_hex("e457b5a2e4d86bd1").unwrap())
+    );
+
+    let (span, _) = call(
+        Propagation::B3,
+        &[
+            ("x-b3-traceid", "463ac35c9f6413ad"),
+            ("x-b3-spanid", "a2fb4a1d1a96d312"),
+            ("x-b3-sampled", "1"),
+        ],
+    );
+    let span = span.unwrap();
+    assert_eq!(
+        span.context.trace_id,
+        TraceId::from_hex("0000000000000000463ac35c9f6413ad").unwrap()
+    );
+    assert_eq!(
+        span.parent_span_id,
+        Some(SpanId::from_hex("a2fb4a1d1a96d312").unwrap())
+    );
+
+    let (span, _) = call(
+        Propagation::B3,
+        &[("b3", "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-0")],
+    );
+    assert!(span.is_none());
+
+    // The W3C headers are ignored when B3 is selected.
+    let (span, _) = call(
+        Propagation::B3,
+        &[(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        )],
+    );
+    assert!(span.unwrap().parent_span_id.is_none());
+}
+
+#[test]
+fn b3_sampling_only() {
+    for headers in &[&[("b3", "0")][..], &[("x-b3-sampled", "0")][..]] {
+        let (span, seen) = call(Propagation::B3, headers);
+        assert!(span.is_none());
+        assert!(!seen.sampled);
+
+        let mut outgoing = HeaderMap::new();
+        seen.inject(&mut outgoing, Propagation::B3);
+        assert!(outgoing["b3"].to_str().unwrap().ends_with("-0"));
+    }
+
+    for headers in &[&[("b3", "d")][..], &[("x-b3-flags", "1")][..]] {
+        let (span, seen) = call(Propagation::B3, headers);
+        assert!(span.unwrap().parent_span_id.is_none());
+        assert!(seen.sampled);
+    }
+}
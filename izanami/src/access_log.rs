@@ -0,0 +1,321 @@
+//! Access logging in Common Log Format, Combined Log Format or JSON lines.
+
+use http::{header, request, HeaderValue, Method, StatusCode, Uri, Version};
+use std::{
+    fmt::{self, Write as _},
+    io::{self, Write},
+    net::SocketAddr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The summary of a request, reported to `AccessLog` after it completes.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// The address of the peer, if known.
+    pub remote_addr: Option<SocketAddr>,
+
+    /// The method of the request.
+    pub method: Method,
+
+    /// The request target.
+    pub uri: Uri,
+
+    /// The HTTP version of the request.
+    pub version: Version,
+
+    /// The value of the `Referer` header.
+    pub referer: Option<HeaderValue>,
+
+    /// The value of the `User-Agent` header.
+    pub user_agent: Option<HeaderValue>,
+
+    /// The status code of the response, or `None` if the response head was not sent.
+    pub status: Option<StatusCode>,
+
+    /// The number of bytes received in the request body.
+    pub bytes_in: u64,
+
+    /// The number of bytes sent in the response body.
+    pub bytes_out: u64,
+
+    /// The time when the request was received.
+    pub started: SystemTime,
+
+    /// The time taken until the response head was sent, or `None` if it was not sent.
+    pub head_duration: Option<Duration>,
+
+    /// The time taken to process the request.
+    pub duration: Duration,
+}
+
+impl Record {
+    /// Create a `Record` from the head of a request, starting at the current time.
+    pub fn new(parts: &request::Parts) -> Self {
+        Self {
+            remote_addr: None,
+            method: parts.method.clone(),
+            uri: parts.uri.clone(),
+            version: parts.version,
+            referer: parts.headers.get(header::REFERER).cloned(),
+            user_agent: parts.headers.get(header::USER_AGENT).cloned(),
+            status: None,
+            bytes_in: 0,
+            bytes_out: 0,
+            started: SystemTime::now(),
+            head_duration: None,
+            duration: Duration::default(),
+        }
+    }
+}
+
+/// The format of access log lines.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// The Common Log Format.
+    ///
+    /// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326`
+    Common,
+
+    /// The Combined Log Format, which appends `Referer` and `User-Agent` to `Common`.
+    Combined,
+
+    /// A JSON object per line.
+    Json,
+}
+
+/// A logger that writes a line for each completed request.
+pub struct AccessLog {
+    format: Format,
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .finish()
+    }
+}
+
+impl AccessLog {
+    /// Create an `AccessLog` that writes the lines to `writer`.
+    pub fn new<W>(format: Format, writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Self {
+            format,
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    /// Create an `AccessLog` that writes the lines to the standard output.
+    pub fn stdout(format: Format) -> Self {
+        Self::new(format, io::stdout())
+    }
+
+    /// Return the format of the lines.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Write a line for `record`.
+    ///
+    /// The errors while writing are ignored so that the logging never
+    /// affects the requests.
+    pub fn log(&self, record: &Record) {
+        let line = format_line(self.format, record);
+        if let Ok(mut writer) = self.writer.lock() {
+            let _ = writer.write_all(line.as_bytes());
+        }
+    }
+}
+
+/// Format `record` as a line, including the trailing newline.
+pub fn format_line(format: Format, record: &Record) -> String {
+    let mut line = String::new();
+    match format {
+        Format::Common | Format::Combined => {
+            let host = record
+                .remote_addr
+                .map_or_else(|| "-".to_owned(), |addr| addr.ip().to_string());
+            let status = record
+                .status
+                .map_or_else(|| "-".to_owned(), |status| status.as_u16().to_string());
+            let bytes = match record.bytes_out {
+                0 => "-".to_owned(),
+                n => n.to_string(),
+            };
+            let _ = write!(
+                line,
+                "{} - - [{}] \"{}\" {} {}",
+                host,
+                clf_time(record.started),
+                escape_clf(&format!(
+                    "{} {} {:?}",
+                    record.method, record.uri, record.version
+                )),
+                status,
+                bytes,
+            );
+            if format == Format::Combined {
+                let _ = write!(
+                    line,
+                    " \"{}\" \"{}\"",
+                    clf_header(record.referer.as_ref()),
+                    clf_header(record.user_agent.as_ref()),
+                );
+            }
+        }
+        Format::Json => {
+            line.push('{');
+            let _ = write!(line, "\"time\":\"{}\"", rfc3339_time(record.started));
+            line.push_str(",\"remote_addr\":");
+            match record.remote_addr {
+                Some(addr) => json_string(&mut line, &addr.to_string()),
+                None => line.push_str("null"),
+            }
+            line.push_str(",\"method\":");
+            json_string(&mut line, record.method.as_str());
+            line.push_str(",\"uri\":");
+            json_string(&mut line, &record.uri.to_string());
+            let _ = write!(line, ",\"version\":\"{:?}\"", record.version);
+            line.push_str(",\"status\":");
+            match record.status {
+                Some(status) => {
+                    let _ = write!(line, "{}", status.as_u16());
+                }
+                None => line.push_str("null"),
+            }
+            let _ = write!(
+                line,
+                ",\"bytes_in\":{},\"bytes_out\":{},\"duration_ms\":{:.3}",
+                record.bytes_in,
+                record.bytes_out,
+                record.duration.as_secs_f64() * 1000.0,
+            );
+            for (name, value) in &[
+                ("referer", &record.referer),
+                ("user_agent", &record.user_agent),
+            ] {
+                let _ = write!(line, ",\"{}\":", name);
+                match value {
+                    Some(value) => {
+                        json_string(&mut line, &String::from_utf8_lossy(value.as_bytes()))
+                    }
+                    None => line.push_str("null"),
+                }
+            }
+            line.push('}');
+        }
+    }
+    line.push('\n');
+    line
+}
+
+fn clf_header(value: Option<&HeaderValue>) -> String {
+    match value {
+        Some(value) => escape_clf(&String::from_utf8_lossy(value.as_bytes())),
+        None => "-".to_owned(),
+    }
+}
+
+/// Escape the double quotes, backslashes and control characters
+/// in a quoted field of CLF.
+fn escape_clf(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                let _ = write!(escaped, "\\x{:02X}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_string(buf: &mut String, s: &str) {
+    buf.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            '\t' => buf.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(buf, "\\u{:04x}", c as u32);
+            }
+            c => buf.push(c),
+        }
+    }
+    buf.push('"');
+}
+
+/// The broken-down UTC time.
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u64,
+    minute: u64,
+    second: u64,
+}
+
+impl DateTime {
+    fn from_system_time(time: SystemTime) -> Self {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let (days, rem) = (secs / 86400, secs % 86400);
+
+        // Convert the number of days since the epoch into the civil date.
+        // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = days as i64 + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        Self {
+            year,
+            month,
+            day,
+            hour: rem / 3600,
+            minute: rem / 60 % 60,
+            second: rem % 60,
+        }
+    }
+}
+
+fn clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+        t.day,
+        MONTHS[t.month as usize - 1],
+        t.year,
+        t.hour,
+        t.minute,
+        t.second
+    )
+}
+
+fn rfc3339_time(time: SystemTime) -> String {
+    let t = DateTime::from_system_time(time);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        t.year, t.month, t.day, t.hour, t.minute, t.second
+    )
+}
@@ -0,0 +1,384 @@
+//! Helpers for building and boxing `App`s without implementing the trait by hand.
+
+use crate::{App, BoxFuture, Events, Reason};
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures::task::{self, Poll};
+use http::{HeaderMap, Request, Response};
+use std::{error, fmt, future::Future};
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+/// Create an `App` from a function that takes a request and returns a `Future`.
+///
+/// ```ignore
+/// let app = izanami::app_fn(|request: Request<MyEvents>| async move {
+///     let mut events = request.into_body();
+///     events.send_response(Response::new("Hello, world!\n")).await
+/// });
+/// ```
+///
+/// A closure cannot return a future that borrows its argument, so the
+/// `Events` that borrow the connection, such as those of `izanami-hyper`,
+/// are handled with an `async fn` instead:
+///
+/// ```ignore
+/// async fn hello(request: Request<Events<'_>>) -> Result<(), hyper::Error> {
+///     let mut events = request.into_body();
+///     events.send_response(Response::new("Hello, world!\n")).await
+/// }
+///
+/// server.serve(izanami::app_fn(hello)).await?;
+/// ```
+pub fn app_fn<F>(f: F) -> AppFn<F> {
+    AppFn { f }
+}
+
+/// An `App` created by `app_fn`.
+#[derive(Clone)]
+pub struct AppFn<F> {
+    f: F,
+}
+
+impl<F> fmt::Debug for AppFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppFn").finish()
+    }
+}
+
+mod sealed {
+    use http::Request;
+
+    /// The function passed to `app_fn`.
+    ///
+    /// The output is named as an associated type, so that it is known to
+    /// outlive the lifetimes that both the function and the events outlive.
+    pub trait Handler<E> {
+        type Future;
+
+        fn call(&self, request: Request<E>) -> Self::Future;
+    }
+
+    impl<F, E, Fut> Handler<E> for F
+    where
+        F: Fn(Request<E>) -> Fut,
+    {
+        type Future = Fut;
+
+        #[inline]
+        fn call(&self, request: Request<E>) -> Self::Future {
+            (self)(request)
+        }
+    }
+}
+
+impl<F, E, R> App<E> for AppFn<F>
+where
+    F: sealed::Handler<E>,
+    F::Future: Future<Output = Result<(), R>> + Send,
+    E: Events,
+    R: Into<BoxError>,
+{
+    type Error = R;
+
+    #[inline]
+    fn call<'l1, 'async_trait>(
+        &'l1 self,
+        request: Request<E>,
+    ) -> BoxFuture<'async_trait, Result<(), Self::Error>>
+    where
+        'l1: 'async_trait,
+        E: 'async_trait,
+    {
+        Box::pin(sealed::Handler::call(&self.f, request))
+    }
+}
+
+/// A type-erased `Events` passed to `BoxApp`.
+///
+/// By default, the data is `Data` and the error is boxed, so that the
+/// `App`s taking `BoxEvents<'_>` run on any backend through `DynApp`.
+pub type BoxEvents<'a, D = Data, Err = BoxError> =
+    Box<dyn Events<Data = D, Error = Err> + Send + 'a>;
+
+/// A chunk of data exchanged through `DynEvents`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Data(Bytes);
+
+impl Data {
+    /// Consume itself and return the remaining bytes.
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+}
+
+impl<T: Into<Bytes>> From<T> for Data {
+    fn from(bytes: T) -> Self {
+        Self(bytes.into())
+    }
+}
+
+impl AsRef<[u8]> for Data {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl Buf for Data {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    fn bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    #[inline]
+    fn advance(&mut self, amt: usize) {
+        self.0.advance(amt);
+    }
+}
+
+/// An `Events` that converts the data of the inner `Events` from and into
+/// `Data`, and boxes its error.
+///
+/// The received data is copied into a contiguous `Bytes`, since the data
+/// types of the backends are not convertible into it in general.
+#[derive(Debug)]
+pub struct DynEvents<E> {
+    inner: E,
+}
+
+impl<E> DynEvents<E> {
+    /// Wrap the specified `Events`.
+    pub fn new(inner: E) -> Self {
+        Self { inner }
+    }
+
+    /// Return a reference to the inner `Events`.
+    pub fn get_ref(&self) -> &E {
+        &self.inner
+    }
+
+    /// Return a mutable reference to the inner `Events`.
+    pub fn get_mut(&mut self) -> &mut E {
+        &mut self.inner
+    }
+
+    /// Consume itself and return the inner `Events`.
+    pub fn into_inner(self) -> E {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<E> Events for DynEvents<E>
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    type Data = Data;
+    type Error = BoxError;
+
+    async fn data(&mut self) -> Option<Result<Self::Data, Self::Error>> {
+        match self.inner.data().await? {
+            Ok(data) => Some(Ok(Data(data.collect()))),
+            Err(err) => Some(Err(err.into())),
+        }
+    }
+
+    async fn trailers(&mut self) -> Result<Option<HeaderMap>, Self::Error> {
+        self.inner.trailers().await.map_err(Into::into)
+    }
+
+    async fn start_send_response(
+        &mut self,
+        response: Response<()>,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        self.inner
+            .start_send_response(response, end_of_stream)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn send_data(
+        &mut self,
+        data: Self::Data,
+        end_of_stream: bool,
+    ) -> Result<(), Self::Error> {
+        self.inner
+            .send_data(data.0.into(), end_of_stream)
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error> {
+        self.inner.send_trailers(trailers).await.map_err(Into::into)
+    }
+
+    fn reset(&mut self, reason: Reason) {
+        self.inner.reset(reason)
+    }
+
+    async fn cancelled(&mut self) -> Reason {
+        self.inner.cancelled().await
+    }
+
+    fn reserve_send_capacity(&mut self, capacity: usize) {
+        self.inner.reserve_send_capacity(capacity)
+    }
+
+    fn poll_send_capacity(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<usize, Self::Error>> {
+        self.inner.poll_send_capacity(cx).map_err(Into::into)
+    }
+
+    fn set_manual_release(&mut self, enabled: bool) -> bool {
+        self.inner.set_manual_release(enabled)
+    }
+
+    fn release_capacity(&mut self, len: usize) -> Result<(), Self::Error> {
+        self.inner.release_capacity(len).map_err(Into::into)
+    }
+
+    fn is_push_supported(&self) -> bool {
+        self.inner.is_push_supported()
+    }
+}
+
+/// An `App` that runs the inner `App` taking `BoxEvents<'_>` on any backend,
+/// by wrapping the events of the backend with `DynEvents`.
+///
+/// ```ignore
+/// async fn hello(request: Request<BoxEvents<'_>>) -> Result<(), BoxError> {
+///     let mut events = request.into_body();
+///     events.send_response(Response::new("Hello, world!\n")).await
+/// }
+///
+/// let app = DynApp::new(izanami::app_fn(hello));
+/// izanami_hyper::Server::bind(addr).await?.serve(app.clone()).await?;
+/// izanami_h2::Server::bind(addr).await?.serve(app).await?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct DynApp<A> {
+    app: A,
+}
+
+impl<A> DynApp<A> {
+    /// Wrap the specified `App`.
+    pub fn new(app: A) -> Self {
+        Self { app }
+    }
+}
+
+impl<A, E, R> App<E> for DynApp<A>
+where
+    A: for<'a> App<BoxEvents<'a>, Error = R>,
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+    R: Into<BoxError>,
+{
+    type Error = R;
+
+    #[inline]
+    fn call<'l1, 'async_trait>(
+        &'l1 self,
+        request: Request<E>,
+    ) -> BoxFuture<'async_trait, Result<(), Self::Error>>
+    where
+        'l1: 'async_trait,
+        E: 'async_trait,
+    {
+        let request =
+            request.map(|events| Box::new(DynEvents::new(events)) as BoxEvents<'async_trait>);
+        self.app.call(request)
+    }
+}
+
+/// A type-erased `App` that accepts any `Events` with the data type `D`
+/// and the error type `Err`, which are `Data` and the boxed error by default.
+///
+/// The events are boxed into `BoxEvents` before being passed to the inner
+/// `App`, so that the `App`s of different types can be stored in a collection.
+///
+/// ```ignore
+/// let routes: Vec<(Regex, BoxApp)> = vec![
+///     (Regex::new("^/$")?, BoxApp::new(Index)),
+///     (Regex::new("^/users/")?, BoxApp::new(Users::new(db))),
+/// ];
+/// ```
+pub struct BoxApp<D = Data, Err = BoxError> {
+    inner: Box<dyn for<'a> App<BoxEvents<'a, D, Err>, Error = BoxError> + Send + Sync>,
+}
+
+impl<D, Err> fmt::Debug for BoxApp<D, Err> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxApp").finish()
+    }
+}
+
+impl<D, Err> BoxApp<D, Err>
+where
+    D: Buf,
+    Err: Into<BoxError>,
+{
+    /// Box the specified `App`.
+    pub fn new<A>(app: A) -> Self
+    where
+        A: for<'a> App<BoxEvents<'a, D, Err>> + Send + Sync + 'static,
+    {
+        Self {
+            inner: Box::new(ErrInto(app)),
+        }
+    }
+}
+
+impl<E, D, Err> App<E> for BoxApp<D, Err>
+where
+    E: Events<Data = D, Error = Err> + Send,
+    D: Buf,
+    Err: Into<BoxError>,
+{
+    type Error = BoxError;
+
+    #[inline]
+    fn call<'l1, 'async_trait>(
+        &'l1 self,
+        request: Request<E>,
+    ) -> BoxFuture<'async_trait, Result<(), Self::Error>>
+    where
+        'l1: 'async_trait,
+        E: 'async_trait,
+    {
+        let request = request.map(|events| Box::new(events) as BoxEvents<'async_trait, D, Err>);
+        self.inner.call(request)
+    }
+}
+
+/// An `App` that converts the error of the inner `App` into `BoxError`.
+struct ErrInto<A>(A);
+
+impl<A, E> App<E> for ErrInto<A>
+where
+    A: App<E> + Sync,
+    E: Events + Send,
+{
+    type Error = BoxError;
+
+    fn call<'l1, 'async_trait>(
+        &'l1 self,
+        request: Request<E>,
+    ) -> BoxFuture<'async_trait, Result<(), Self::Error>>
+    where
+        'l1: 'async_trait,
+        E: 'async_trait,
+    {
+        let future = self.0.call(request);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
+}
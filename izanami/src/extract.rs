@@ -0,0 +1,245 @@
+//! Extractors for JSON bodies, urlencoded forms and query strings.
+//!
+//! This module is available only when the `serde` feature is enabled.
+
+use crate::Events;
+use async_trait::async_trait;
+use bytes::{Buf, Bytes, BytesMut};
+use http::{header, HeaderMap, Request, Response, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{error, fmt};
+
+/// The maximum size of a request body read by `form`.
+pub const DEFAULT_FORM_LIMIT: usize = 64 * 1024;
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+/// The error type returned from the extractors.
+///
+/// Each rejection corresponds to an HTTP status code that can be
+/// sent back to the client by using `to_response`.
+#[derive(Debug)]
+pub struct Rejection {
+    kind: RejectionKind,
+    source: Option<BoxError>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum RejectionKind {
+    ReadBody,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    Malformed,
+    Unprocessable,
+}
+
+impl Rejection {
+    fn new(kind: RejectionKind, source: Option<BoxError>) -> Self {
+        Self { kind, source }
+    }
+
+    fn from_json(err: serde_json::Error) -> Self {
+        let kind = if err.is_data() {
+            RejectionKind::Unprocessable
+        } else {
+            RejectionKind::Malformed
+        };
+        Self::new(kind, Some(err.into()))
+    }
+
+    /// Return the status code corresponding to this rejection.
+    pub fn status(&self) -> StatusCode {
+        match self.kind {
+            RejectionKind::ReadBody | RejectionKind::Malformed => StatusCode::BAD_REQUEST,
+            RejectionKind::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            RejectionKind::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            RejectionKind::Unprocessable => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    /// Create a plain text response that describes this rejection.
+    pub fn to_response(&self) -> Response<String> {
+        let body = format!("{}\n", self);
+        Response::builder()
+            .status(self.status())
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(body)
+            .expect("should be a valid response")
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self.kind {
+            RejectionKind::ReadBody => "failed to read the request body",
+            RejectionKind::PayloadTooLarge => "the request body is too large",
+            RejectionKind::UnsupportedMediaType => "unsupported content type",
+            RejectionKind::Malformed => "malformed request",
+            RejectionKind::Unprocessable => "failed to deserialize the request",
+        };
+        match self.source {
+            Some(ref source) => write!(f, "{}: {}", msg, source),
+            None => f.write_str(msg),
+        }
+    }
+}
+
+impl error::Error for Rejection {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.source.as_ref().map(|source| &**source as _)
+    }
+}
+
+/// Extension methods for `Events` that extract or send serialized values.
+#[async_trait]
+pub trait EventsExt: Events {
+    /// Read the request body as JSON, up to `limit` bytes.
+    async fn json<T>(&mut self, limit: usize) -> Result<T, Rejection>
+    where
+        T: DeserializeOwned;
+
+    /// Read the request body as `application/x-www-form-urlencoded`.
+    ///
+    /// The size of the body is limited to `DEFAULT_FORM_LIMIT`.
+    async fn form<T>(&mut self) -> Result<T, Rejection>
+    where
+        T: DeserializeOwned;
+
+    /// Send a response whose body is the JSON representation of `value`.
+    async fn send_json<T>(&mut self, status: StatusCode, value: &T) -> Result<(), BoxError>
+    where
+        T: Serialize + Sync;
+}
+
+#[async_trait]
+impl<E> EventsExt for E
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    async fn json<T>(&mut self, limit: usize) -> Result<T, Rejection>
+    where
+        T: DeserializeOwned,
+    {
+        let body = read_to_end(self, limit).await?;
+        serde_json::from_slice(&body).map_err(Rejection::from_json)
+    }
+
+    async fn form<T>(&mut self) -> Result<T, Rejection>
+    where
+        T: DeserializeOwned,
+    {
+        let body = read_to_end(self, DEFAULT_FORM_LIMIT).await?;
+        serde_urlencoded::from_bytes(&body)
+            .map_err(|err| Rejection::new(RejectionKind::Unprocessable, Some(err.into())))
+    }
+
+    async fn send_json<T>(&mut self, status: StatusCode, value: &T) -> Result<(), BoxError>
+    where
+        T: Serialize + Sync,
+    {
+        let body = Bytes::from(serde_json::to_vec(value)?);
+        let response = Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(E::Data::from(body))?;
+        self.send_response(response).await.map_err(Into::into)
+    }
+}
+
+/// Extension methods for `Request` that extract the typed values.
+#[async_trait]
+pub trait RequestExt {
+    /// Deserialize the query string of the request URI.
+    fn query<T>(&self) -> Result<T, Rejection>
+    where
+        T: DeserializeOwned;
+
+    /// Check that the content type is JSON and read the request body as JSON.
+    async fn json<T>(&mut self, limit: usize) -> Result<T, Rejection>
+    where
+        T: DeserializeOwned;
+
+    /// Check that the content type is `application/x-www-form-urlencoded`
+    /// and read the request body as a form.
+    async fn form<T>(&mut self) -> Result<T, Rejection>
+    where
+        T: DeserializeOwned;
+}
+
+#[async_trait]
+impl<E> RequestExt for Request<E>
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    fn query<T>(&self) -> Result<T, Rejection>
+    where
+        T: DeserializeOwned,
+    {
+        let query = self.uri().query().unwrap_or("");
+        serde_urlencoded::from_str(query)
+            .map_err(|err| Rejection::new(RejectionKind::Malformed, Some(err.into())))
+    }
+
+    async fn json<T>(&mut self, limit: usize) -> Result<T, Rejection>
+    where
+        T: DeserializeOwned,
+    {
+        if !has_content_type(self.headers(), is_json) {
+            return Err(Rejection::new(RejectionKind::UnsupportedMediaType, None));
+        }
+        self.body_mut().json(limit).await
+    }
+
+    async fn form<T>(&mut self) -> Result<T, Rejection>
+    where
+        T: DeserializeOwned,
+    {
+        if !has_content_type(self.headers(), |mime| {
+            mime.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        }) {
+            return Err(Rejection::new(RejectionKind::UnsupportedMediaType, None));
+        }
+        self.body_mut().form().await
+    }
+}
+
+fn has_content_type(headers: &HeaderMap, f: impl FnOnce(&str) -> bool) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| f(mime.trim()))
+}
+
+fn is_json(mime: &str) -> bool {
+    let mime = mime.to_ascii_lowercase();
+    mime == "application/json" || (mime.starts_with("application/") && mime.ends_with("+json"))
+}
+
+async fn read_to_end<E>(events: &mut E, limit: usize) -> Result<Bytes, Rejection>
+where
+    E: Events + Send + ?Sized,
+    E::Data: Send,
+{
+    let mut buf = BytesMut::new();
+    while let Some(data) = events.data().await {
+        let mut data =
+            data.map_err(|err| Rejection::new(RejectionKind::ReadBody, Some(err.into())))?;
+        if buf.len() + data.remaining() > limit {
+            return Err(Rejection::new(RejectionKind::PayloadTooLarge, None));
+        }
+        while data.has_remaining() {
+            let len = {
+                let bytes = data.bytes();
+                buf.extend_from_slice(bytes);
+                bytes.len()
+            };
+            data.advance(len);
+        }
+    }
+    Ok(buf.freeze())
+}
@@ -0,0 +1,221 @@
+//! I/O adapters built on top of `Events`.
+
+use crate::{BoxFuture, Events};
+use bytes::{Bytes, BytesMut};
+use futures::{
+    io::AsyncWrite,
+    task::{self, Poll},
+};
+use std::{cmp, fmt, io, pin::Pin};
+
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+const DEFAULT_CHUNK_SIZE: usize = 16 * 1024;
+
+/// An `AsyncWrite` that sends the written bytes as the response body.
+///
+/// The response head must be sent with `start_send_response` (and
+/// `end_of_stream = false`) before writing to this value.
+/// The written bytes are accumulated in an internal buffer and sent
+/// to the client via `send_data` as soon as the buffer is filled, or
+/// when the writer is flushed. Closing the writer ends the response stream.
+///
+/// ```ignore
+/// events.start_send_response(Response::new(()), false).await?;
+///
+/// let mut writer = ResponseWriter::new(&mut events);
+/// writer.write_all(b"Hello, world!\n").await?;
+/// writer.close().await?;
+/// ```
+pub struct ResponseWriter<'a, E: Events> {
+    state: State<'a, E>,
+    buf: BytesMut,
+    buffer_size: usize,
+    chunk_size: usize,
+}
+
+enum State<'a, E: Events> {
+    Idle(E),
+    Sending(BoxFuture<'a, (E, Result<(), E::Error>)>, bool),
+    Closed(E),
+    Poisoned,
+}
+
+// The `Events` is never pinned since it is moved into the boxed future while sending.
+impl<'a, E: Events> Unpin for ResponseWriter<'a, E> {}
+
+impl<'a, E> fmt::Debug for ResponseWriter<'a, E>
+where
+    E: Events,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.state {
+            State::Idle(..) => "Idle",
+            State::Sending(..) => "Sending",
+            State::Closed(..) => "Closed",
+            State::Poisoned => "Poisoned",
+        };
+        f.debug_struct("ResponseWriter")
+            .field("state", &state)
+            .field("buffered", &self.buf.len())
+            .field("buffer_size", &self.buffer_size)
+            .field("chunk_size", &self.chunk_size)
+            .finish()
+    }
+}
+
+impl<'a, E> ResponseWriter<'a, E>
+where
+    E: Events + Send + 'a,
+    E::Data: From<Bytes>,
+{
+    /// Create a new `ResponseWriter` with the default configuration.
+    pub fn new(events: E) -> Self {
+        Self {
+            state: State::Idle(events),
+            buf: BytesMut::new(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Set the number of bytes accumulated before sending them to the client.
+    ///
+    /// If the value is zero, the bytes are sent on each write, in chunks
+    /// of at most `chunk_size`. The default value is 8 KiB.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Set the maximum size of a chunk passed to `send_data` at once.
+    ///
+    /// The default value is 16 KiB.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `chunk_size` is zero.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "the chunk size must be greater than zero");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Consume this writer and return the underlying `Events`.
+    ///
+    /// The bytes remaining in the internal buffer are discarded.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if a chunk of data is being sent.
+    /// Flush the writer before calling this method.
+    pub fn into_inner(self) -> E {
+        match self.state {
+            State::Idle(events) | State::Closed(events) => events,
+            _ => panic!("the writer is sending a chunk of data"),
+        }
+    }
+
+    fn poll_sending(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        if let State::Sending(ref mut future, end_of_stream) = self.state {
+            let (events, result) = futures::ready!(future.as_mut().poll(cx));
+            self.state = if end_of_stream {
+                State::Closed(events)
+            } else {
+                State::Idle(events)
+            };
+            result.map_err(|err| io::Error::other(err.into()))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_sending(&mut self, end_of_stream: bool) -> io::Result<()> {
+        let mut events = match std::mem::replace(&mut self.state, State::Poisoned) {
+            State::Idle(events) => events,
+            State::Closed(events) => {
+                self.state = State::Closed(events);
+                return Err(closed_error());
+            }
+            _ => unreachable!(),
+        };
+        let len = cmp::min(self.buf.len(), self.chunk_size);
+        let chunk = self.buf.split_to(len).freeze();
+        let future = Box::pin(async move {
+            let result = events.send_data(chunk.into(), end_of_stream).await;
+            (events, result)
+        });
+        self.state = State::Sending(future, end_of_stream);
+        Ok(())
+    }
+}
+
+impl<'a, E> AsyncWrite for ResponseWriter<'a, E>
+where
+    E: Events + Send + 'a,
+    E::Data: From<Bytes>,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        loop {
+            futures::ready!(me.poll_sending(cx))?;
+            if let State::Closed(..) = me.state {
+                return Poll::Ready(Err(closed_error()));
+            }
+            if !me.buf.is_empty() && me.buf.len() >= me.buffer_size {
+                me.start_sending(false)?;
+                continue;
+            }
+            break;
+        }
+
+        let len = if me.buffer_size == 0 {
+            cmp::min(data.len(), me.chunk_size)
+        } else {
+            cmp::min(data.len(), me.buffer_size - me.buf.len())
+        };
+        me.buf.extend_from_slice(&data[..len]);
+
+        // Start sending the filled buffer without waiting for the next write.
+        if !me.buf.is_empty() && me.buf.len() >= me.buffer_size {
+            me.start_sending(false)?;
+            if let Poll::Ready(Err(err)) = me.poll_sending(cx) {
+                return Poll::Ready(Err(err));
+            }
+        }
+
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        loop {
+            futures::ready!(me.poll_sending(cx))?;
+            if me.buf.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            me.start_sending(false)?;
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        loop {
+            futures::ready!(me.poll_sending(cx))?;
+            if let State::Closed(..) = me.state {
+                return Poll::Ready(Ok(()));
+            }
+            let end_of_stream = me.buf.len() <= me.chunk_size;
+            me.start_sending(end_of_stream)?;
+        }
+    }
+}
+
+fn closed_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::BrokenPipe,
+        "the response stream has already been closed",
+    )
+}
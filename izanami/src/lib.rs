@@ -11,10 +11,28 @@
 #![forbid(clippy::unimplemented)]
 #![cfg_attr(test, deny(warnings))]
 
+pub mod access_log;
+mod app;
+#[cfg(feature = "serde")]
+pub mod extract;
+pub mod io;
+#[cfg(feature = "server")]
+pub mod limit;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod multipart;
+pub mod proxy_protocol;
+pub mod state;
+#[cfg(feature = "server")]
+pub mod timeout;
+
 use async_trait::async_trait;
 use bytes::Buf;
+use futures::task::{self, Poll};
 use http::{HeaderMap, Request, Response};
-use std::{error, future::Future, pin::Pin};
+use std::{error, future::Future, net::SocketAddr, pin::Pin};
+
+pub use crate::app::{app_fn, AppFn, BoxApp, BoxEvents, Data, DynApp, DynEvents};
 
 type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 
@@ -57,7 +75,7 @@ pub trait App<E: Events> {
         E: 'async_trait;
 }
 
-impl<'a, T: ?Sized, E> App<E> for &'a T
+impl<T: ?Sized, E> App<E> for &T
 where
     T: App<E>,
     E: Events,
@@ -137,11 +155,99 @@ pub trait Events {
         -> Result<(), Self::Error>;
 
     async fn send_trailers(&mut self, trailers: HeaderMap) -> Result<(), Self::Error>;
+
+    /// Send a complete response to the client.
+    ///
+    /// The default implementation sends the response head and then
+    /// the body as a single chunk of data that ends the stream.
+    async fn send_response<T>(&mut self, response: Response<T>) -> Result<(), Self::Error>
+    where
+        T: Into<Self::Data> + Send,
+        Self: Sized,
+    {
+        let (parts, body) = response.into_parts();
+        self.start_send_response(Response::from_parts(parts, ()), false)
+            .await?;
+        self.send_data(body.into(), true).await
+    }
+
+    /// Abort the exchange with the client.
+    ///
+    /// On HTTP/2, the stream is reset with `reason`. The servers that cannot
+    /// convey the reason, such as on HTTP/1.x, close the connection instead.
+    /// The subsequent calls to send the response fail.
+    ///
+    /// The default implementation does nothing, for the servers unable to
+    /// abort the exchange.
+    fn reset(&mut self, reason: Reason) {
+        let _ = reason;
+    }
+
+    /// Wait until the client cancels the request, and return the reason.
+    ///
+    /// The cancellation is signalled by `RST_STREAM` on HTTP/2, or by closing
+    /// the connection on HTTP/1.x. It never completes if the server cannot
+    /// detect the cancellation, which is the default.
+    fn cancelled<'l1, 'async_trait>(&'l1 mut self) -> BoxFuture<'async_trait, Reason>
+    where
+        'l1: 'async_trait,
+        Self: 'async_trait,
+    {
+        // Not written as `async fn`, whose default would require `Self: Send`.
+        Box::pin(futures::future::pending())
+    }
+
+    /// Request the capacity to send `capacity` bytes of the response body.
+    ///
+    /// The default implementation does nothing, for the servers without
+    /// flow control.
+    fn reserve_send_capacity(&mut self, capacity: usize) {
+        let _ = capacity;
+    }
+
+    /// Poll the number of bytes that can be sent without waiting for the client.
+    ///
+    /// The application can stop producing the data until the capacity is available,
+    /// instead of being blocked in `send_data`. The default implementation returns
+    /// `usize::MAX`, for the servers without flow control.
+    fn poll_send_capacity(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<usize, Self::Error>> {
+        let _ = cx;
+        Poll::Ready(Ok(usize::MAX))
+    }
+
+    /// Specify whether to release the capacity of the received data manually
+    /// by `release_capacity`, instead of when the data is received by `data`.
+    ///
+    /// It allows to keep the client from sending more data until the received
+    /// data is consumed. Returns `false` if the server does not support it,
+    /// which is the default.
+    fn set_manual_release(&mut self, enabled: bool) -> bool {
+        let _ = enabled;
+        false
+    }
+
+    /// Release the capacity of `len` bytes of the received data, so that
+    /// the client can send more data.
+    fn release_capacity(&mut self, len: usize) -> Result<(), Self::Error> {
+        let _ = len;
+        Ok(())
+    }
+
+    /// Return whether the server is able to push responses to the client.
+    ///
+    /// The servers supporting HTTP/2 server push provide the method to promise
+    /// a request on their own `Events`. The default implementation returns `false`.
+    fn is_push_supported(&self) -> bool {
+        false
+    }
 }
 
-impl<'a, E: ?Sized> Events for &'a mut E
+impl<E> Events for &mut E
 where
-    E: Events,
+    E: Events + Send,
 {
     type Data = E::Data;
     type Error = E::Error;
@@ -200,11 +306,64 @@ where
     {
         (**self).send_trailers(trailers)
     }
+
+    #[inline]
+    fn send_response<'l1, 'async_trait, T>(
+        &'l1 mut self,
+        response: Response<T>,
+    ) -> BoxFuture<'async_trait, Result<(), Self::Error>>
+    where
+        'l1: 'async_trait,
+        T: Into<Self::Data> + Send + 'async_trait,
+    {
+        (**self).send_response(response)
+    }
+
+    #[inline]
+    fn reset(&mut self, reason: Reason) {
+        (**self).reset(reason)
+    }
+
+    #[inline]
+    fn cancelled<'l1, 'async_trait>(&'l1 mut self) -> BoxFuture<'async_trait, Reason>
+    where
+        'l1: 'async_trait,
+    {
+        (**self).cancelled()
+    }
+
+    #[inline]
+    fn reserve_send_capacity(&mut self, capacity: usize) {
+        (**self).reserve_send_capacity(capacity)
+    }
+
+    #[inline]
+    fn poll_send_capacity(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<usize, Self::Error>> {
+        (**self).poll_send_capacity(cx)
+    }
+
+    #[inline]
+    fn set_manual_release(&mut self, enabled: bool) -> bool {
+        (**self).set_manual_release(enabled)
+    }
+
+    #[inline]
+    fn release_capacity(&mut self, len: usize) -> Result<(), Self::Error> {
+        (**self).release_capacity(len)
+    }
+
+    #[inline]
+    fn is_push_supported(&self) -> bool {
+        (**self).is_push_supported()
+    }
 }
 
-impl<E: ?Sized> Events for Box<E>
+impl<E> Events for Box<E>
 where
-    E: Events,
+    E: Events + Send,
 {
     type Data = E::Data;
     type Error = E::Error;
@@ -263,4 +422,207 @@ where
     {
         (**self).send_trailers(trailers)
     }
+
+    #[inline]
+    fn send_response<'l1, 'async_trait, T>(
+        &'l1 mut self,
+        response: Response<T>,
+    ) -> BoxFuture<'async_trait, Result<(), Self::Error>>
+    where
+        'l1: 'async_trait,
+        T: Into<Self::Data> + Send + 'async_trait,
+    {
+        (**self).send_response(response)
+    }
+
+    #[inline]
+    fn reset(&mut self, reason: Reason) {
+        (**self).reset(reason)
+    }
+
+    #[inline]
+    fn cancelled<'l1, 'async_trait>(&'l1 mut self) -> BoxFuture<'async_trait, Reason>
+    where
+        'l1: 'async_trait,
+    {
+        (**self).cancelled()
+    }
+
+    #[inline]
+    fn reserve_send_capacity(&mut self, capacity: usize) {
+        (**self).reserve_send_capacity(capacity)
+    }
+
+    #[inline]
+    fn poll_send_capacity(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<usize, Self::Error>> {
+        (**self).poll_send_capacity(cx)
+    }
+
+    #[inline]
+    fn set_manual_release(&mut self, enabled: bool) -> bool {
+        (**self).set_manual_release(enabled)
+    }
+
+    #[inline]
+    fn release_capacity(&mut self, len: usize) -> Result<(), Self::Error> {
+        (**self).release_capacity(len)
+    }
+
+    #[inline]
+    fn is_push_supported(&self) -> bool {
+        (**self).is_push_supported()
+    }
+}
+
+/// The `Events` erased by `BoxEvents`.
+///
+/// `send_response` is not callable on the trait object, so the default
+/// implementation is used instead of the one of the erased `Events`.
+impl<'a, D, Err> Events for Box<dyn Events<Data = D, Error = Err> + Send + 'a>
+where
+    D: Buf,
+    Err: Into<Box<dyn error::Error + Send + Sync + 'static>>,
+{
+    type Data = D;
+    type Error = Err;
+
+    #[inline]
+    fn data<'l1, 'async_trait>(
+        &'l1 mut self,
+    ) -> BoxFuture<'async_trait, Option<Result<Self::Data, Self::Error>>>
+    where
+        'l1: 'async_trait,
+    {
+        (**self).data()
+    }
+
+    #[inline]
+    fn trailers<'l1, 'async_trait>(
+        &'l1 mut self,
+    ) -> BoxFuture<'async_trait, Result<Option<HeaderMap>, Self::Error>>
+    where
+        'l1: 'async_trait,
+    {
+        (**self).trailers()
+    }
+
+    #[inline]
+    fn start_send_response<'l1, 'async_trait>(
+        &'l1 mut self,
+        response: Response<()>,
+        end_of_stream: bool,
+    ) -> BoxFuture<'async_trait, Result<(), Self::Error>>
+    where
+        'l1: 'async_trait,
+    {
+        (**self).start_send_response(response, end_of_stream)
+    }
+
+    #[inline]
+    fn send_data<'l1, 'async_trait>(
+        &'l1 mut self,
+        data: Self::Data,
+        end_of_stream: bool,
+    ) -> BoxFuture<'async_trait, Result<(), Self::Error>>
+    where
+        'l1: 'async_trait,
+    {
+        (**self).send_data(data, end_of_stream)
+    }
+
+    #[inline]
+    fn send_trailers<'l1, 'async_trait>(
+        &'l1 mut self,
+        trailers: HeaderMap,
+    ) -> BoxFuture<'async_trait, Result<(), Self::Error>>
+    where
+        'l1: 'async_trait,
+    {
+        (**self).send_trailers(trailers)
+    }
+
+    #[inline]
+    fn reset(&mut self, reason: Reason) {
+        (**self).reset(reason)
+    }
+
+    #[inline]
+    fn cancelled<'l1, 'async_trait>(&'l1 mut self) -> BoxFuture<'async_trait, Reason>
+    where
+        'l1: 'async_trait,
+    {
+        (**self).cancelled()
+    }
+
+    #[inline]
+    fn reserve_send_capacity(&mut self, capacity: usize) {
+        (**self).reserve_send_capacity(capacity)
+    }
+
+    #[inline]
+    fn poll_send_capacity(
+        &mut self,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<usize, Self::Error>> {
+        (**self).poll_send_capacity(cx)
+    }
+
+    #[inline]
+    fn set_manual_release(&mut self, enabled: bool) -> bool {
+        (**self).set_manual_release(enabled)
+    }
+
+    #[inline]
+    fn release_capacity(&mut self, len: usize) -> Result<(), Self::Error> {
+        (**self).release_capacity(len)
+    }
+
+    #[inline]
+    fn is_push_supported(&self) -> bool {
+        (**self).is_push_supported()
+    }
+}
+
+/// The address of the peer that sent the request.
+///
+/// The servers insert this value into the extensions of each request.
+/// If the server accepts the PROXY protocol, it is the address of the
+/// client conveyed by the load balancer instead of the peer of the connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct RemoteAddr(pub SocketAddr);
+
+/// The reason to abort an exchange, which corresponds to the error codes of HTTP/2.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Reason(u32);
+
+impl Reason {
+    pub const NO_ERROR: Self = Self(0x0);
+    pub const PROTOCOL_ERROR: Self = Self(0x1);
+    pub const INTERNAL_ERROR: Self = Self(0x2);
+    pub const FLOW_CONTROL_ERROR: Self = Self(0x3);
+    pub const SETTINGS_TIMEOUT: Self = Self(0x4);
+    pub const STREAM_CLOSED: Self = Self(0x5);
+    pub const FRAME_SIZE_ERROR: Self = Self(0x6);
+    pub const REFUSED_STREAM: Self = Self(0x7);
+    pub const CANCEL: Self = Self(0x8);
+    pub const COMPRESSION_ERROR: Self = Self(0x9);
+    pub const CONNECT_ERROR: Self = Self(0xa);
+    pub const ENHANCE_YOUR_CALM: Self = Self(0xb);
+    pub const INADEQUATE_SECURITY: Self = Self(0xc);
+    pub const HTTP_1_1_REQUIRED: Self = Self(0xd);
+}
+
+impl From<u32> for Reason {
+    fn from(code: u32) -> Self {
+        Self(code)
+    }
+}
+
+impl From<Reason> for u32 {
+    fn from(reason: Reason) -> Self {
+        reason.0
+    }
 }
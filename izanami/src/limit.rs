@@ -0,0 +1,285 @@
+//! Limits on the number of connections and concurrent requests, shared by
+//! the server implementations.
+
+use futures::future::{self, poll_fn, Either};
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio_sync::semaphore::{Permit, Semaphore};
+
+/// The behavior when the number of concurrent requests reaches the limit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Overload {
+    /// Wait until one of the running requests completes, up to the specified duration.
+    ///
+    /// If no slot becomes available in time, the server responds with
+    /// `503 Service Unavailable`.
+    Queue(Duration),
+
+    /// Respond with `503 Service Unavailable` immediately, with the `Retry-After`
+    /// header set to the specified duration.
+    Reject(Duration),
+
+    /// Reset the stream with `REFUSED_STREAM`.
+    ///
+    /// This tells the client that the request has not been processed
+    /// and can be safely retried. The servers that cannot reset the stream,
+    /// such as on HTTP/1.x, respond with `503 Service Unavailable` instead.
+    RefuseStream,
+}
+
+impl Default for Overload {
+    fn default() -> Self {
+        Overload::Reject(Duration::from_secs(1))
+    }
+}
+
+/// The counters that describe the load of a server and how often it sheds load.
+///
+/// The values are shared among the clones of this handle.
+#[derive(Debug, Clone, Default)]
+pub struct LoadMetrics {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    active_connections: AtomicUsize,
+    active_requests: AtomicUsize,
+    connections_deferred: AtomicU64,
+    requests_queued: AtomicU64,
+    requests_rejected: AtomicU64,
+    streams_refused: AtomicU64,
+}
+
+impl LoadMetrics {
+    /// Return the number of currently open connections.
+    pub fn active_connections(&self) -> usize {
+        self.inner.active_connections.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of requests currently being processed by the application.
+    pub fn active_requests(&self) -> usize {
+        self.inner.active_requests.load(Ordering::Relaxed)
+    }
+
+    /// Return how many times accepting a connection was deferred
+    /// because of the connection limit.
+    pub fn connections_deferred(&self) -> u64 {
+        self.inner.connections_deferred.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of requests that had to wait for a slot.
+    pub fn requests_queued(&self) -> u64 {
+        self.inner.requests_queued.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of requests rejected with `503 Service Unavailable`.
+    pub fn requests_rejected(&self) -> u64 {
+        self.inner.requests_rejected.load(Ordering::Relaxed)
+    }
+
+    /// Return the number of streams reset with `REFUSED_STREAM`.
+    pub fn streams_refused(&self) -> u64 {
+        self.inner.streams_refused.load(Ordering::Relaxed)
+    }
+
+    fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The configured limits of a server.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// The maximum number of open connections.
+    pub max_connections: Option<usize>,
+    /// The maximum number of concurrent requests in the whole server.
+    pub max_requests: Option<usize>,
+    /// The maximum number of concurrent requests on a connection.
+    pub max_requests_per_connection: Option<usize>,
+    /// The behavior when one of the request limits is reached.
+    pub overload: Overload,
+}
+
+/// The reason why a request is not passed to the application.
+#[derive(Debug, Copy, Clone)]
+pub enum Shed {
+    /// Respond with `503 Service Unavailable`, with the optional `Retry-After`.
+    Reject(Option<Duration>),
+    /// Reset the stream with `REFUSED_STREAM`.
+    Refuse,
+}
+
+/// Enforces the limits shared by all connections of a server.
+#[derive(Debug, Clone)]
+pub struct LoadControl {
+    limits: Limits,
+    connections: Limiter,
+    requests: Limiter,
+    metrics: LoadMetrics,
+}
+
+impl LoadControl {
+    pub fn new(limits: Limits, metrics: LoadMetrics) -> Self {
+        Self {
+            connections: Limiter::new(limits.max_connections),
+            requests: Limiter::new(limits.max_requests),
+            limits,
+            metrics,
+        }
+    }
+
+    /// Wait until a new connection can be accepted.
+    pub async fn reserve(&self) -> Reservation {
+        let slot = match self.connections.try_acquire() {
+            Some(slot) => slot,
+            None => {
+                LoadMetrics::increment(&self.metrics.inner.connections_deferred);
+                self.connections.acquire().await
+            }
+        };
+        Reservation {
+            slot,
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// Create the limiter for the requests on a connection.
+    pub fn connection(&self) -> Limiter {
+        Limiter::new(self.limits.max_requests_per_connection)
+    }
+
+    /// Decide whether to pass a request on `connection` to the application.
+    pub async fn admit(&self, connection: &Limiter) -> Result<Guard, Shed> {
+        if let Some(slot) = connection.try_acquire() {
+            if let Some(global) = self.requests.try_acquire() {
+                return Ok(self.request_guard(slot, global));
+            }
+        }
+
+        let counters = &self.metrics.inner;
+        match self.limits.overload {
+            Overload::Queue(timeout) => {
+                LoadMetrics::increment(&counters.requests_queued);
+                let acquire = async {
+                    let slot = connection.acquire().await;
+                    let global = self.requests.acquire().await;
+                    (slot, global)
+                };
+                futures::pin_mut!(acquire);
+                match future::select(acquire, tokio::timer::delay_for(timeout)).await {
+                    Either::Left(((slot, global), _)) => Ok(self.request_guard(slot, global)),
+                    Either::Right(..) => {
+                        LoadMetrics::increment(&counters.requests_rejected);
+                        Err(Shed::Reject(None))
+                    }
+                }
+            }
+            Overload::Reject(retry_after) => {
+                LoadMetrics::increment(&counters.requests_rejected);
+                Err(Shed::Reject(Some(retry_after)))
+            }
+            Overload::RefuseStream => {
+                LoadMetrics::increment(&counters.streams_refused);
+                Err(Shed::Refuse)
+            }
+        }
+    }
+
+    fn request_guard(&self, slot: Slot, global: Slot) -> Guard {
+        Guard::new(vec![slot, global], &self.metrics, |c| &c.active_requests)
+    }
+}
+
+/// The slot reserved for a connection that has not been accepted yet.
+#[derive(Debug)]
+pub struct Reservation {
+    slot: Slot,
+    metrics: LoadMetrics,
+}
+
+impl Reservation {
+    /// Assign the slot to an accepted connection.
+    pub fn accepted(self) -> Guard {
+        Guard::new(vec![self.slot], &self.metrics, |c| &c.active_connections)
+    }
+}
+
+/// A counting semaphore, or no limit at all.
+#[derive(Debug, Clone)]
+pub struct Limiter(Option<Arc<Semaphore>>);
+
+impl Limiter {
+    fn new(max: Option<usize>) -> Self {
+        Limiter(max.map(|max| Arc::new(Semaphore::new(max))))
+    }
+
+    fn slot(&self) -> Slot {
+        Slot {
+            semaphore: self.0.clone(),
+            permit: Permit::new(),
+        }
+    }
+
+    fn try_acquire(&self) -> Option<Slot> {
+        let mut slot = self.slot();
+        if let Some(ref semaphore) = self.0 {
+            slot.permit.try_acquire(semaphore).ok()?;
+        }
+        Some(slot)
+    }
+
+    async fn acquire(&self) -> Slot {
+        let mut slot = self.slot();
+        if let Some(ref semaphore) = self.0 {
+            // The semaphore is never closed.
+            let _ = poll_fn(|cx| slot.permit.poll_acquire(cx, semaphore)).await;
+        }
+        slot
+    }
+}
+
+/// A permit acquired from `Limiter`, released when dropped.
+#[derive(Debug)]
+struct Slot {
+    semaphore: Option<Arc<Semaphore>>,
+    permit: Permit,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(ref semaphore) = self.semaphore {
+            self.permit.release(semaphore);
+        }
+    }
+}
+
+/// Holds the slots of a connection or a request while it is active.
+#[derive(Debug)]
+pub struct Guard {
+    _slots: Vec<Slot>,
+    metrics: LoadMetrics,
+    gauge: fn(&Counters) -> &AtomicUsize,
+}
+
+impl Guard {
+    fn new(slots: Vec<Slot>, metrics: &LoadMetrics, gauge: fn(&Counters) -> &AtomicUsize) -> Self {
+        gauge(&metrics.inner).fetch_add(1, Ordering::Relaxed);
+        Self {
+            _slots: slots,
+            metrics: metrics.clone(),
+            gauge,
+        }
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        (self.gauge)(&self.metrics.inner).fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,325 @@
+//! Metrics of the server and application events, exported in Prometheus text format.
+
+use crate::{access_log::Record, App, Events};
+use async_trait::async_trait;
+use bytes::Bytes;
+use http::{header, Method, Request, Response, StatusCode};
+use std::{
+    collections::HashMap,
+    error,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+/// The upper bounds of the latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The methods labelled by their name.
+///
+/// The extension methods are counted under `OTHER`, so that a client cannot
+/// create an unbounded number of series.
+const METHODS: [&str; 9] = [
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// A registry of the metrics recorded by a server.
+///
+/// The values are shared among the clones of this handle, so the same
+/// registry can be passed to a server and to `MetricsApp`.
+#[derive(Debug, Clone, Default)]
+pub struct Metrics {
+    inner: Arc<Registry>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    connections_accepted: AtomicU64,
+    connections_active: AtomicUsize,
+    requests: Mutex<HashMap<(&'static str, Option<StatusCode>), u64>>,
+    request_body_bytes: AtomicU64,
+    response_body_bytes: AtomicU64,
+    response_head_duration: Histogram,
+    request_duration: Histogram,
+    stream_resets: AtomicU64,
+    app_errors: AtomicU64,
+    app_panics: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        for (bucket, &le) in self.buckets.iter().zip(&BUCKETS) {
+            if secs <= le {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (bucket, le) in self.buckets.iter().zip(&BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                le,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        );
+        let _ = writeln!(out, "{}_count {}", name, count);
+    }
+}
+
+impl Metrics {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that a connection has been accepted.
+    ///
+    /// The connection is counted as active until the returned guard is dropped.
+    pub fn connection(&self) -> ConnectionGuard {
+        self.inner
+            .connections_accepted
+            .fetch_add(1, Ordering::Relaxed);
+        self.inner
+            .connections_active
+            .fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard {
+            metrics: self.clone(),
+        }
+    }
+
+    /// Record a completed request.
+    pub fn request_completed(&self, record: &Record) {
+        let inner = &*self.inner;
+        *inner
+            .requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry((method_label(&record.method), record.status))
+            .or_insert(0) += 1;
+        inner
+            .request_body_bytes
+            .fetch_add(record.bytes_in, Ordering::Relaxed);
+        inner
+            .response_body_bytes
+            .fetch_add(record.bytes_out, Ordering::Relaxed);
+        if let Some(head_duration) = record.head_duration {
+            inner.response_head_duration.observe(head_duration);
+        }
+        inner.request_duration.observe(record.duration);
+    }
+
+    /// Record that the server has reset an HTTP/2 stream.
+    pub fn stream_reset(&self) {
+        self.inner.stream_resets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that the application has returned an error.
+    pub fn app_error(&self) {
+        self.inner.app_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that the application has panicked.
+    pub fn app_panic(&self) {
+        self.inner.app_panics.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render the current values in Prometheus text format.
+    pub fn render(&self) -> String {
+        let inner = &*self.inner;
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "izanami_connections_accepted_total",
+            "The number of accepted connections.",
+            inner.connections_accepted.load(Ordering::Relaxed),
+        );
+        let _ = writeln!(
+            out,
+            "# HELP izanami_connections_active The number of open connections.\n\
+             # TYPE izanami_connections_active gauge\n\
+             izanami_connections_active {}",
+            inner.connections_active.load(Ordering::Relaxed)
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP izanami_requests_total The number of completed requests.\n\
+             # TYPE izanami_requests_total counter"
+        );
+        let mut requests: Vec<_> = inner
+            .requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|((method, status), &count)| {
+                let status = status.map_or_else(String::new, |s| s.as_u16().to_string());
+                (*method, status, count)
+            })
+            .collect();
+        requests.sort();
+        for (method, status, count) in requests {
+            let _ = writeln!(
+                out,
+                "izanami_requests_total{{method=\"{}\",status=\"{}\"}} {}",
+                method, status, count
+            );
+        }
+
+        counter(
+            &mut out,
+            "izanami_request_body_bytes_total",
+            "The number of bytes received in request bodies.",
+            inner.request_body_bytes.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "izanami_response_body_bytes_total",
+            "The number of bytes sent in response bodies.",
+            inner.response_body_bytes.load(Ordering::Relaxed),
+        );
+        inner.response_head_duration.render(
+            &mut out,
+            "izanami_response_head_duration_seconds",
+            "The time until the response head was sent.",
+        );
+        inner.request_duration.render(
+            &mut out,
+            "izanami_request_duration_seconds",
+            "The time until the response stream was completed.",
+        );
+        counter(
+            &mut out,
+            "izanami_stream_resets_total",
+            "The number of HTTP/2 streams reset by the server.",
+            inner.stream_resets.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "izanami_app_errors_total",
+            "The number of errors returned from the application.",
+            inner.app_errors.load(Ordering::Relaxed),
+        );
+        counter(
+            &mut out,
+            "izanami_app_panics_total",
+            "The number of panics in the application.",
+            inner.app_panics.load(Ordering::Relaxed),
+        );
+
+        out
+    }
+}
+
+fn method_label(method: &Method) -> &'static str {
+    METHODS
+        .iter()
+        .find(|&&name| name == method.as_str())
+        .map_or("OTHER", |&name| name)
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Counts a connection as active while alive.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    metrics: Metrics,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics
+            .inner
+            .connections_active
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// An `App` that serves the values of `Metrics` in Prometheus text format.
+#[derive(Debug, Clone)]
+pub struct MetricsApp {
+    metrics: Metrics,
+}
+
+impl MetricsApp {
+    /// Create an `App` that serves the values of `metrics`.
+    pub fn new(metrics: Metrics) -> Self {
+        Self { metrics }
+    }
+}
+
+#[async_trait]
+impl<E> App<E> for MetricsApp
+where
+    E: Events + Send,
+    E::Data: From<Bytes> + Send,
+{
+    type Error = Box<dyn error::Error + Send + Sync + 'static>;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let (parts, mut events) = request.into_parts();
+
+        if parts.method != Method::GET && parts.method != Method::HEAD {
+            let response = Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(header::ALLOW, "GET, HEAD")
+                .body(())?;
+            return events
+                .start_send_response(response, true)
+                .await
+                .map_err(Into::into);
+        }
+
+        let body = Bytes::from(self.metrics.render());
+        let response = Response::builder()
+            .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(())?;
+        if parts.method == Method::HEAD {
+            return events
+                .start_send_response(response, true)
+                .await
+                .map_err(Into::into);
+        }
+        events
+            .send_response(response.map(|()| E::Data::from(body)))
+            .await
+            .map_err(Into::into)
+    }
+}
@@ -0,0 +1,458 @@
+//! Streaming parser for `multipart/form-data` request bodies.
+
+use crate::Events;
+use bytes::{Buf, Bytes, BytesMut};
+use http::{
+    header::{self, HeaderName, HeaderValue},
+    HeaderMap, StatusCode,
+};
+use std::{error, fmt};
+
+const DEFAULT_FIELD_LIMIT: u64 = 16 * 1024 * 1024;
+const DEFAULT_TOTAL_LIMIT: u64 = 64 * 1024 * 1024;
+const DEFAULT_HEADERS_LIMIT: usize = 8 * 1024;
+
+type BoxError = Box<dyn error::Error + Send + Sync + 'static>;
+
+/// Extract the boundary parameter from the `Content-Type` header.
+///
+/// This function returns `None` if the content type is not `multipart/form-data`
+/// or it does not have the boundary parameter.
+pub fn boundary(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    let mut params = content_type.split(';');
+
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+
+    params.find_map(|param| {
+        let mut kv = param.splitn(2, '=');
+        let key = kv.next()?.trim();
+        let value = kv.next()?.trim();
+        if !key.eq_ignore_ascii_case("boundary") {
+            return None;
+        }
+        let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            &value[1..value.len() - 1]
+        } else {
+            value
+        };
+        if value.is_empty() || value.len() > 70 {
+            return None;
+        }
+        Some(value.to_owned())
+    })
+}
+
+/// The error type returned from `Multipart` and `Field`.
+#[derive(Debug)]
+pub struct MultipartError {
+    kind: ErrorKind,
+    source: Option<BoxError>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ErrorKind {
+    ReadBody,
+    Malformed(&'static str),
+    HeadersTooLarge,
+    FieldTooLarge,
+    TotalTooLarge,
+}
+
+impl MultipartError {
+    fn new(kind: ErrorKind) -> Self {
+        Self { kind, source: None }
+    }
+
+    fn malformed(msg: &'static str) -> Self {
+        Self::new(ErrorKind::Malformed(msg))
+    }
+
+    /// Return the status code corresponding to this error.
+    pub fn status(&self) -> StatusCode {
+        match self.kind {
+            ErrorKind::ReadBody | ErrorKind::Malformed(..) => StatusCode::BAD_REQUEST,
+            ErrorKind::HeadersTooLarge => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            ErrorKind::FieldTooLarge | ErrorKind::TotalTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ErrorKind::ReadBody => f.write_str("failed to read the request body")?,
+            ErrorKind::Malformed(msg) => write!(f, "malformed multipart body: {}", msg)?,
+            ErrorKind::HeadersTooLarge => f.write_str("the part headers are too large")?,
+            ErrorKind::FieldTooLarge => f.write_str("the field is too large")?,
+            ErrorKind::TotalTooLarge => f.write_str("the request body is too large")?,
+        }
+        if let Some(ref source) = self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for MultipartError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.source.as_ref().map(|source| &**source as _)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum State {
+    Preamble,
+    Delimiter,
+    Headers,
+    Body,
+    End,
+}
+
+/// A streaming reader of `multipart/form-data` body.
+///
+/// The fields are read from `Events::data` one by one, and the content of
+/// each field is yielded as chunks without buffering the whole body.
+///
+/// ```ignore
+/// let boundary = multipart::boundary(req.headers()).unwrap();
+/// let mut multipart = Multipart::new(req.body_mut(), &boundary);
+/// while let Some(mut field) = multipart.next_field().await? {
+///     println!("name = {:?}", field.name());
+///     while let Some(chunk) = field.chunk().await? {
+///         println!("recv: {:?}", chunk);
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Multipart<E> {
+    events: E,
+    delimiter: Bytes,
+    buf: BytesMut,
+    state: State,
+    eof: bool,
+    field_size: u64,
+    total_size: u64,
+    field_limit: u64,
+    total_limit: u64,
+    headers_limit: usize,
+}
+
+impl<E> Multipart<E>
+where
+    E: Events,
+{
+    /// Create a new `Multipart` that reads the body from `events`.
+    pub fn new(events: E, boundary: &str) -> Self {
+        let mut delimiter = BytesMut::with_capacity(boundary.len() + 4);
+        delimiter.extend_from_slice(b"\r\n--");
+        delimiter.extend_from_slice(boundary.as_bytes());
+
+        // The first delimiter may not be preceded by CRLF,
+        // so a virtual CRLF is placed at the beginning of the buffer.
+        let mut buf = BytesMut::with_capacity(8 * 1024);
+        buf.extend_from_slice(b"\r\n");
+
+        Self {
+            events,
+            delimiter: delimiter.freeze(),
+            buf,
+            state: State::Preamble,
+            eof: false,
+            field_size: 0,
+            total_size: 0,
+            field_limit: DEFAULT_FIELD_LIMIT,
+            total_limit: DEFAULT_TOTAL_LIMIT,
+            headers_limit: DEFAULT_HEADERS_LIMIT,
+        }
+    }
+
+    /// Set the maximum number of bytes in the content of a field.
+    ///
+    /// The default value is 16 MiB.
+    pub fn field_limit(mut self, limit: u64) -> Self {
+        self.field_limit = limit;
+        self
+    }
+
+    /// Set the maximum number of bytes in the whole request body.
+    ///
+    /// The default value is 64 MiB.
+    pub fn total_limit(mut self, limit: u64) -> Self {
+        self.total_limit = limit;
+        self
+    }
+
+    /// Set the maximum number of bytes in the header section of a part.
+    ///
+    /// The default value is 8 KiB.
+    pub fn headers_limit(mut self, limit: usize) -> Self {
+        self.headers_limit = limit;
+        self
+    }
+
+    /// Consume this reader and return the underlying `Events`.
+    pub fn into_inner(self) -> E {
+        self.events
+    }
+
+    /// Read the next field.
+    ///
+    /// The remaining content of the previous field is skipped.
+    pub async fn next_field(&mut self) -> Result<Option<Field<'_, E>>, MultipartError> {
+        loop {
+            match self.state {
+                State::Preamble => {
+                    if let Some(pos) = find(&self.buf, &self.delimiter) {
+                        self.buf.advance(pos + self.delimiter.len());
+                        self.state = State::Delimiter;
+                        continue;
+                    }
+                    let keep = self.delimiter.len() - 1;
+                    if self.buf.len() > keep {
+                        let len = self.buf.len() - keep;
+                        self.buf.advance(len);
+                    }
+                    if !self.fill().await? {
+                        return Err(MultipartError::malformed("missing the boundary"));
+                    }
+                }
+
+                State::Delimiter => {
+                    // transport-padding followed by CRLF, or the close delimiter.
+                    let pos = self
+                        .buf
+                        .iter()
+                        .position(|&b| b != b' ' && b != b'\t')
+                        .filter(|&pos| pos + 2 <= self.buf.len());
+                    match pos {
+                        Some(0) if self.buf.starts_with(b"--") => {
+                            self.state = State::End;
+                        }
+                        Some(pos) if self.buf[pos..].starts_with(b"\r\n") => {
+                            self.buf.advance(pos);
+                            self.state = State::Headers;
+                        }
+                        Some(..) => {
+                            return Err(MultipartError::malformed("invalid boundary line"));
+                        }
+                        None => {
+                            if self.buf.len() > self.headers_limit {
+                                return Err(MultipartError::malformed("invalid boundary line"));
+                            }
+                            if !self.fill().await? {
+                                return Err(MultipartError::malformed("unexpected end of body"));
+                            }
+                        }
+                    }
+                }
+
+                State::Headers => {
+                    // The buffer starts with the CRLF that terminates the boundary line.
+                    if let Some(pos) = find(&self.buf, b"\r\n\r\n") {
+                        if pos > self.headers_limit {
+                            return Err(MultipartError::new(ErrorKind::HeadersTooLarge));
+                        }
+                        let headers = parse_headers(&self.buf[2.min(pos)..pos])?;
+                        self.buf.advance(pos + 4);
+                        self.state = State::Body;
+                        self.field_size = 0;
+                        return Ok(Some(Field {
+                            multipart: self,
+                            headers,
+                        }));
+                    }
+                    if self.buf.len() > self.headers_limit + 4 {
+                        return Err(MultipartError::new(ErrorKind::HeadersTooLarge));
+                    }
+                    if !self.fill().await? {
+                        return Err(MultipartError::malformed("unexpected end of body"));
+                    }
+                }
+
+                State::Body => while self.read_chunk().await?.is_some() {},
+
+                State::End => return Ok(None),
+            }
+        }
+    }
+
+    async fn read_chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        loop {
+            if self.state != State::Body {
+                return Ok(None);
+            }
+
+            let chunk = match find(&self.buf, &self.delimiter) {
+                Some(pos) => {
+                    let chunk = self.buf.split_to(pos).freeze();
+                    self.buf.advance(self.delimiter.len());
+                    self.state = State::Delimiter;
+                    chunk
+                }
+                None => {
+                    // Keep the bytes that may be a prefix of the delimiter.
+                    let keep = self.delimiter.len() - 1;
+                    let len = self.buf.len().saturating_sub(keep);
+                    self.buf.split_to(len).freeze()
+                }
+            };
+
+            if !chunk.is_empty() {
+                self.field_size += chunk.len() as u64;
+                if self.field_size > self.field_limit {
+                    return Err(MultipartError::new(ErrorKind::FieldTooLarge));
+                }
+                return Ok(Some(chunk));
+            }
+
+            if self.state == State::Body && !self.fill().await? {
+                return Err(MultipartError::malformed("unexpected end of body"));
+            }
+        }
+    }
+
+    async fn fill(&mut self) -> Result<bool, MultipartError> {
+        if self.eof {
+            return Ok(false);
+        }
+        match self.events.data().await {
+            Some(Ok(mut data)) => {
+                self.total_size += data.remaining() as u64;
+                if self.total_size > self.total_limit {
+                    return Err(MultipartError::new(ErrorKind::TotalTooLarge));
+                }
+                while data.has_remaining() {
+                    let len = {
+                        let bytes = data.bytes();
+                        self.buf.extend_from_slice(bytes);
+                        bytes.len()
+                    };
+                    data.advance(len);
+                }
+                Ok(true)
+            }
+            Some(Err(err)) => Err(MultipartError {
+                kind: ErrorKind::ReadBody,
+                source: Some(err.into()),
+            }),
+            None => {
+                self.eof = true;
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// A field in the `multipart/form-data` body.
+#[derive(Debug)]
+pub struct Field<'a, E> {
+    multipart: &'a mut Multipart<E>,
+    headers: HeaderMap,
+}
+
+impl<E> Field<'_, E>
+where
+    E: Events,
+{
+    /// Return the headers of this field.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Return the value of `name` parameter in `Content-Disposition`.
+    pub fn name(&self) -> Option<&str> {
+        self.disposition_param("name")
+    }
+
+    /// Return the value of `filename` parameter in `Content-Disposition`.
+    pub fn file_name(&self) -> Option<&str> {
+        self.disposition_param("filename")
+    }
+
+    /// Return the value of `Content-Type` of this field.
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    /// Read the next chunk of the content of this field.
+    ///
+    /// This method returns `None` at the end of the field.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        self.multipart.read_chunk().await
+    }
+
+    fn disposition_param(&self, name: &str) -> Option<&str> {
+        let disposition = self
+            .headers
+            .get(header::CONTENT_DISPOSITION)?
+            .to_str()
+            .ok()?;
+        disposition.split(';').skip(1).find_map(|param| {
+            let mut kv = param.splitn(2, '=');
+            let key = kv.next()?.trim();
+            let value = kv.next()?.trim();
+            if !key.eq_ignore_ascii_case(name) {
+                return None;
+            }
+            if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                Some(&value[1..value.len() - 1])
+            } else {
+                Some(value)
+            }
+        })
+    }
+}
+
+fn parse_headers(mut buf: &[u8]) -> Result<HeaderMap, MultipartError> {
+    let mut headers = HeaderMap::new();
+    while !buf.is_empty() {
+        let (line, rest) = match find(buf, b"\r\n") {
+            Some(pos) => (&buf[..pos], &buf[pos + 2..]),
+            None => (buf, &[][..]),
+        };
+        buf = rest;
+
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or_else(|| MultipartError::malformed("invalid header line"))?;
+        let name = HeaderName::from_bytes(&line[..colon])
+            .map_err(|_| MultipartError::malformed("invalid header name"))?;
+        let value = trim(&line[colon + 1..]);
+        let value = HeaderValue::from_bytes(value)
+            .map_err(|_| MultipartError::malformed("invalid header value"))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+fn trim(mut bytes: &[u8]) -> &[u8] {
+    while let Some((&b, rest)) = bytes.split_first() {
+        if b != b' ' && b != b'\t' {
+            break;
+        }
+        bytes = rest;
+    }
+    while let Some((&b, rest)) = bytes.split_last() {
+        if b != b' ' && b != b'\t' {
+            break;
+        }
+        bytes = rest;
+    }
+    bytes
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
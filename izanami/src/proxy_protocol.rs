@@ -0,0 +1,191 @@
+//! Parser of the PROXY protocol header (version 1 and 2) sent by load balancers.
+//!
+//! See <https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt> for the specification.
+
+use std::{
+    error, fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str,
+};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+
+/// The shortest possible header, `PROXY UNKNOWN\r\n`.
+const MIN_LEN: usize = 15;
+
+/// The addresses of the original connection, conveyed by the PROXY protocol header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ProxyHeader {
+    /// The address of the client, or `None` if the proxy did not provide it
+    /// (e.g. `UNKNOWN` in version 1, or the `LOCAL` command in version 2).
+    pub source: Option<SocketAddr>,
+
+    /// The address that the client connected to.
+    pub destination: Option<SocketAddr>,
+}
+
+/// The result of `parse`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Status {
+    /// The header has been parsed, and consists of the whole input.
+    Complete(ProxyHeader),
+
+    /// The header needs at least the specified number of bytes more.
+    ///
+    /// The requested bytes never go beyond the end of the header, so the caller
+    /// can read exactly that many bytes without consuming the data after it.
+    Partial(usize),
+}
+
+/// An error that the input is not a valid PROXY protocol header.
+#[derive(Debug)]
+pub struct InvalidHeader(&'static str);
+
+impl fmt::Display for InvalidHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid PROXY protocol header: {}", self.0)
+    }
+}
+
+impl error::Error for InvalidHeader {}
+
+/// Parse the PROXY protocol header at the beginning of a connection.
+///
+/// The caller starts with an empty buffer and appends as many bytes as
+/// requested by `Status::Partial`, until the header is complete.
+pub fn parse(buf: &[u8]) -> Result<Status, InvalidHeader> {
+    if buf.len() < MIN_LEN {
+        let prefix_len = buf.len().min(V2_SIGNATURE.len());
+        if !V1_PREFIX.starts_with(&buf[..prefix_len.min(V1_PREFIX.len())])
+            && !V2_SIGNATURE.starts_with(&buf[..prefix_len])
+        {
+            return Err(InvalidHeader("unknown signature"));
+        }
+        return Ok(Status::Partial(MIN_LEN - buf.len()));
+    }
+
+    if buf.starts_with(V1_PREFIX) {
+        parse_v1(buf)
+    } else if buf.starts_with(V2_SIGNATURE) {
+        parse_v2(buf)
+    } else {
+        Err(InvalidHeader("unknown signature"))
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> Result<Status, InvalidHeader> {
+    if !buf.ends_with(b"\r\n") {
+        if buf.len() >= V1_MAX_LEN {
+            return Err(InvalidHeader("the line is too long"));
+        }
+        if buf[..buf.len() - 1].contains(&b'\n') {
+            return Err(InvalidHeader("unexpected line feed"));
+        }
+        return Ok(Status::Partial(1));
+    }
+
+    let line = str::from_utf8(&buf[V1_PREFIX.len()..buf.len() - 2])
+        .map_err(|_| InvalidHeader("the line is not ASCII"))?;
+    let mut fields = line.split(' ');
+    let protocol = fields.next().unwrap_or_default();
+    if protocol == "UNKNOWN" {
+        return Ok(Status::Complete(ProxyHeader::default()));
+    }
+
+    let mut next = || fields.next().ok_or(InvalidHeader("missing fields"));
+    let (source, destination, source_port, destination_port) = (next()?, next()?, next()?, next()?);
+    if fields.next().is_some() {
+        return Err(InvalidHeader("too many fields"));
+    }
+
+    let parse_ip = |s: &str| -> Result<IpAddr, InvalidHeader> {
+        let ip = match protocol {
+            "TCP4" => s.parse::<Ipv4Addr>().map(IpAddr::V4),
+            "TCP6" => s.parse::<Ipv6Addr>().map(IpAddr::V6),
+            _ => return Err(InvalidHeader("unknown protocol")),
+        };
+        ip.map_err(|_| InvalidHeader("invalid address"))
+    };
+    let parse_port = |s: &str| -> Result<u16, InvalidHeader> {
+        if s.len() > 1 && s.starts_with('0') {
+            return Err(InvalidHeader("invalid port"));
+        }
+        s.parse().map_err(|_| InvalidHeader("invalid port"))
+    };
+
+    Ok(Status::Complete(ProxyHeader {
+        source: Some(SocketAddr::new(parse_ip(source)?, parse_port(source_port)?)),
+        destination: Some(SocketAddr::new(
+            parse_ip(destination)?,
+            parse_port(destination_port)?,
+        )),
+    }))
+}
+
+fn parse_v2(buf: &[u8]) -> Result<Status, InvalidHeader> {
+    if buf.len() < V2_HEADER_LEN {
+        return Ok(Status::Partial(V2_HEADER_LEN - buf.len()));
+    }
+
+    let version = buf[12] >> 4;
+    let command = buf[12] & 0x0f;
+    if version != 2 {
+        return Err(InvalidHeader("unsupported version"));
+    }
+    let len = V2_HEADER_LEN + usize::from(u16::from_be_bytes([buf[14], buf[15]]));
+    if buf.len() < len {
+        return Ok(Status::Partial(len - buf.len()));
+    }
+    let addresses = &buf[V2_HEADER_LEN..len];
+
+    match command {
+        // LOCAL: the connection was established by the proxy itself.
+        0x0 => return Ok(Status::Complete(ProxyHeader::default())),
+        // PROXY
+        0x1 => {}
+        _ => return Err(InvalidHeader("unknown command")),
+    }
+
+    // The upper half is the address family, and the lower one is the transport.
+    let header = match buf[13] >> 4 {
+        0x1 => {
+            if addresses.len() < 12 {
+                return Err(InvalidHeader("truncated addresses"));
+            }
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    addresses[at],
+                    addresses[at + 1],
+                    addresses[at + 2],
+                    addresses[at + 3],
+                ))
+            };
+            let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+            ProxyHeader {
+                source: Some(SocketAddr::new(ip(0), port(8))),
+                destination: Some(SocketAddr::new(ip(4), port(10))),
+            }
+        }
+        0x2 => {
+            if addresses.len() < 36 {
+                return Err(InvalidHeader("truncated addresses"));
+            }
+            let ip = |at: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&addresses[at..at + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+            ProxyHeader {
+                source: Some(SocketAddr::new(ip(0), port(32))),
+                destination: Some(SocketAddr::new(ip(16), port(34))),
+            }
+        }
+        // AF_UNSPEC, AF_UNIX or unknown families, whose addresses are ignored.
+        _ => ProxyHeader::default(),
+    };
+    Ok(Status::Complete(header))
+}
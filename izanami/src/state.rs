@@ -0,0 +1,200 @@
+//! Shared state injected into the extensions of each request.
+//!
+//! The servers hold a `StateMap` configured by the user, and insert the
+//! registered values into every request as `State<T>`. The applications
+//! then take the values out of the request without being cloned themselves.
+//!
+//! ```ignore
+//! let server = izanami_hyper::Server::bind("127.0.0.1:4000")
+//!     .await?
+//!     .state(db_pool)
+//!     .connection_state(|remote_addr: &RemoteAddr| RateLimit::for_peer(remote_addr.0));
+//!
+//! // within the application
+//! let db_pool = State::<DbPool>::from_request(&request)?;
+//! ```
+
+use crate::RemoteAddr;
+use http::{Extensions, Request};
+use std::{any, error, fmt, ops::Deref, sync::Arc};
+
+type Inserter = Arc<dyn Fn(&mut Extensions) + Send + Sync>;
+
+type Builder = Arc<dyn Fn(&RemoteAddr) -> Inserter + Send + Sync>;
+
+/// A shared value of type `T` taken from the extensions of a request.
+pub struct State<T: ?Sized>(Arc<T>);
+
+impl<T> State<T> {
+    /// Create a new `State` that holds `value`.
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(value))
+    }
+}
+
+impl<T: ?Sized> State<T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Take the value of type `T` from the extensions.
+    pub fn from_extensions(extensions: &Extensions) -> Result<Self, MissingState> {
+        extensions.get::<Self>().cloned().ok_or(MissingState {
+            type_name: any::type_name::<T>(),
+        })
+    }
+
+    /// Take the value of type `T` from the extensions of `request`.
+    pub fn from_request<E>(request: &Request<E>) -> Result<Self, MissingState> {
+        Self::from_extensions(request.extensions())
+    }
+}
+
+impl<T: ?Sized> State<T> {
+    /// Return the shared reference counted pointer to the value.
+    pub fn into_arc(self) -> Arc<T> {
+        self.0
+    }
+}
+
+impl<T: ?Sized> From<Arc<T>> for State<T> {
+    fn from(value: Arc<T>) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: ?Sized> Clone for State<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> Deref for State<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for State<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("State").field(&&*self.0).finish()
+    }
+}
+
+/// The error returned when the requested state has not been registered to the server.
+#[derive(Debug)]
+pub struct MissingState {
+    type_name: &'static str,
+}
+
+impl fmt::Display for MissingState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the state of type `{}` is not registered",
+            self.type_name
+        )
+    }
+}
+
+impl error::Error for MissingState {}
+
+/// A set of the values inserted into each request by the servers.
+///
+/// The values registered by `insert` are shared by all the requests.
+/// Those registered by `insert_per_connection` are built once for each
+/// accepted connection, and shared by the requests on that connection.
+#[derive(Clone, Default)]
+pub struct StateMap {
+    shared: Vec<Inserter>,
+    per_connection: Vec<Builder>,
+}
+
+impl fmt::Debug for StateMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StateMap")
+            .field("shared", &self.shared.len())
+            .field("per_connection", &self.per_connection.len())
+            .finish()
+    }
+}
+
+impl StateMap {
+    /// Create an empty `StateMap`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a value shared by all the requests.
+    ///
+    /// The value registered later replaces the one of the same type.
+    pub fn insert<T>(&mut self, value: T)
+    where
+        T: Send + Sync + 'static,
+    {
+        self.shared.push(inserter(State::new(value)));
+    }
+
+    /// Register a function that builds the value for each accepted connection.
+    ///
+    /// The function receives the address of the client, which is the one
+    /// conveyed by the PROXY protocol if the server accepts it.
+    pub fn insert_per_connection<F, T>(&mut self, f: F)
+    where
+        F: Fn(&RemoteAddr) -> T + Send + Sync + 'static,
+        T: Send + Sync + 'static,
+    {
+        self.per_connection.push(Arc::new(move |remote_addr| {
+            inserter(State::new(f(remote_addr)))
+        }));
+    }
+
+    /// Return whether no value is registered.
+    pub fn is_empty(&self) -> bool {
+        self.shared.is_empty() && self.per_connection.is_empty()
+    }
+
+    /// Build the values for a connection accepted from `remote_addr`.
+    pub fn connect(&self, remote_addr: &RemoteAddr) -> ConnectionState {
+        let mut inserters = self.shared.clone();
+        inserters.extend(self.per_connection.iter().map(|build| build(remote_addr)));
+        ConnectionState { inserters }
+    }
+}
+
+/// The values inserted into the requests on a connection, created by `StateMap::connect`.
+#[derive(Clone, Default)]
+pub struct ConnectionState {
+    inserters: Vec<Inserter>,
+}
+
+impl fmt::Debug for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionState")
+            .field("values", &self.inserters.len())
+            .finish()
+    }
+}
+
+impl ConnectionState {
+    /// Insert the values into `extensions` as `State<T>`.
+    pub fn inject(&self, extensions: &mut Extensions) {
+        for insert in &self.inserters {
+            insert(extensions);
+        }
+    }
+}
+
+fn inserter<T>(state: State<T>) -> Inserter
+where
+    T: Send + Sync + 'static,
+{
+    Arc::new(move |extensions| {
+        extensions.insert(state.clone());
+    })
+}
@@ -0,0 +1,139 @@
+//! Limits on the duration of requests, shared by the server implementations.
+
+use futures::future::{self, Either, Future};
+use http::StatusCode;
+use std::{
+    sync::{Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// The limits on the duration of a request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeouts {
+    /// The limit until the response head is sent.
+    pub response_head: Option<Duration>,
+    /// The limit on the whole request.
+    pub request: Option<Duration>,
+    /// The limit on the inactivity of the application.
+    pub idle: Option<Duration>,
+}
+
+/// Tracks the progress of a request and aborts it when one of the limits is exceeded.
+#[derive(Debug)]
+pub struct Watchdog {
+    timeouts: Timeouts,
+    started: Instant,
+    activity: Mutex<Activity>,
+}
+
+#[derive(Debug)]
+struct Activity {
+    last_active: Instant,
+    reading: bool,
+    head_sent: bool,
+}
+
+/// The state of the request at the time when a limit was exceeded.
+#[derive(Debug, Copy, Clone)]
+pub struct Expired {
+    reading: bool,
+    head_sent: bool,
+}
+
+impl Expired {
+    /// Return the status code of the response to be sent instead of
+    /// the application, or `None` if the response head has already been sent.
+    ///
+    /// `408 Request Timeout` is used if the application was waiting
+    /// for the request body, and `503 Service Unavailable` otherwise.
+    pub fn status(self) -> Option<StatusCode> {
+        if self.head_sent {
+            None
+        } else if self.reading {
+            Some(StatusCode::REQUEST_TIMEOUT)
+        } else {
+            Some(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+impl Watchdog {
+    pub fn new(timeouts: Timeouts) -> Self {
+        let now = tokio::clock::now();
+        Self {
+            timeouts,
+            started: now,
+            activity: Mutex::new(Activity {
+                last_active: now,
+                reading: false,
+                head_sent: false,
+            }),
+        }
+    }
+
+    fn activity(&self) -> MutexGuard<'_, Activity> {
+        self.activity.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Mark that the application started waiting for the request body.
+    pub fn start_reading(&self) {
+        self.activity().reading = true;
+    }
+
+    /// Mark that the application made progress, and restart the idle timer.
+    pub fn touch(&self) {
+        let mut activity = self.activity();
+        activity.reading = false;
+        activity.last_active = tokio::clock::now();
+    }
+
+    /// Mark that the response head has been sent.
+    pub fn head_sent(&self) {
+        let mut activity = self.activity();
+        activity.head_sent = true;
+        activity.last_active = tokio::clock::now();
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        let activity = self.activity();
+        let response_head = match self.timeouts.response_head {
+            Some(timeout) if !activity.head_sent => Some(self.started + timeout),
+            _ => None,
+        };
+        let request = self.timeouts.request.map(|timeout| self.started + timeout);
+        let idle = self
+            .timeouts
+            .idle
+            .map(|timeout| activity.last_active + timeout);
+        response_head.into_iter().chain(request).chain(idle).min()
+    }
+
+    /// Drive `future` to completion unless one of the limits is exceeded.
+    pub async fn run<F>(&self, future: F) -> Result<F::Output, Expired>
+    where
+        F: Future,
+    {
+        futures::pin_mut!(future);
+        loop {
+            let deadline = match self.deadline() {
+                Some(deadline) => deadline,
+                None => return Ok(future.await),
+            };
+
+            if deadline <= tokio::clock::now() {
+                let activity = self.activity();
+                return Err(Expired {
+                    reading: activity.reading,
+                    head_sent: activity.head_sent,
+                });
+            }
+
+            // The deadline may have been extended while waiting,
+            // so it is re-evaluated each time the timer fires.
+            match future::select(future.as_mut(), tokio::timer::delay(deadline)).await {
+                Either::Left((output, _)) => return Ok(output),
+                Either::Right(..) => continue,
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use http::{HeaderValue, Request, StatusCode};
+use izanami::access_log::{format_line, AccessLog, Format, Record};
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+
+fn record() -> Record {
+    let (parts, ()) = Request::get("/index.html?q=%22x%22")
+        .header("referer", "http://example.com/")
+        .header("user-agent", "curl/7.64.0")
+        .body(())
+        .unwrap()
+        .into_parts();
+    let mut record = Record::new(&parts);
+    record.remote_addr = Some("127.0.0.1:51234".parse().unwrap());
+    record.status = Some(StatusCode::OK);
+    record.bytes_in = 0;
+    record.bytes_out = 2326;
+    record.started = UNIX_EPOCH + Duration::from_secs(971_186_136);
+    record.duration = Duration::from_micros(1500);
+    record
+}
+
+#[test]
+fn common() {
+    assert_eq!(
+        format_line(Format::Common, &record()),
+        "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \
+         \"GET /index.html?q=%22x%22 HTTP/1.1\" 200 2326\n"
+    );
+}
+
+#[test]
+fn combined_without_response() {
+    let mut record = record();
+    record.remote_addr = None;
+    record.status = None;
+    record.bytes_out = 0;
+    record.referer = None;
+    record.user_agent = Some(HeaderValue::from_static("a \"quoted\" agent"));
+    assert_eq!(
+        format_line(Format::Combined, &record),
+        "- - - [10/Oct/2000:13:55:36 +0000] \
+         \"GET /index.html?q=%22x%22 HTTP/1.1\" - - \"-\" \"a \\\"quoted\\\" agent\"\n"
+    );
+}
+
+#[test]
+fn json() {
+    assert_eq!(
+        format_line(Format::Json, &record()),
+        "{\"time\":\"2000-10-10T13:55:36Z\",\"remote_addr\":\"127.0.0.1:51234\",\
+         \"method\":\"GET\",\"uri\":\"/index.html?q=%22x%22\",\"version\":\"HTTP/1.1\",\
+         \"status\":200,\"bytes_in\":0,\"bytes_out\":2326,\"duration_ms\":1.500,\
+         \"referer\":\"http://example.com/\",\"user_agent\":\"curl/7.64.0\"}\n"
+    );
+}
+
+#[derive(Clone, Default)]
+struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn log_to_writer() {
+    let buf = SharedBuf::default();
+    let log = AccessLog::new(Format::Common, buf.clone());
+    log.log(&record());
+    log.log(&record());
+    let written = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(written.lines().count(), 2);
+}
@@ -0,0 +1,98 @@
+use bytes::{Buf, Bytes};
+use futures::executor::block_on;
+use http::{Request, Response, StatusCode};
+use izanami::{app_fn, App, BoxApp, BoxEvents, DynApp, Events};
+use izanami_test::{Data, MockEvents};
+use std::{io, sync::mpsc};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+type Routes = Vec<(&'static str, BoxApp<Data, io::Error>)>;
+
+struct Status(StatusCode);
+
+#[async_trait::async_trait]
+impl<E> App<E> for Status
+where
+    E: Events + Send,
+{
+    type Error = E::Error;
+
+    async fn call(&self, request: Request<E>) -> Result<(), Self::Error>
+    where
+        E: 'async_trait,
+    {
+        let mut response = Response::new(());
+        *response.status_mut() = self.0;
+        request
+            .into_body()
+            .start_send_response(response, true)
+            .await
+    }
+}
+
+async fn echo(request: Request<BoxEvents<'_, Data, io::Error>>) -> io::Result<()> {
+    let mut events = request.into_body();
+    let mut body = vec![];
+    while let Some(data) = events.data().await {
+        body.extend_from_slice(Buf::bytes(&data?));
+    }
+    events
+        .send_response(Response::new(Data::from(Bytes::from(body))))
+        .await
+}
+
+async fn upper(request: Request<BoxEvents<'_>>) -> Result<(), BoxError> {
+    let mut events = request.into_body();
+    events.start_send_response(Response::new(()), false).await?;
+    while let Some(data) = events.data().await {
+        let data = data?.into_bytes().to_ascii_uppercase();
+        events.send_data(data.into(), false).await?;
+    }
+    events.send_data("!".into(), true).await
+}
+
+#[test]
+fn app_fn_closure() {
+    let (tx, rx) = mpsc::channel();
+    let app = app_fn(move |request: Request<MockEvents>| {
+        let tx = tx.clone();
+        async move {
+            let mut events = request.into_body();
+            events.send_response(Response::new("hello")).await?;
+            tx.send(events).unwrap();
+            Ok::<_, io::Error>(())
+        }
+    });
+    block_on(app.call(Request::new(MockEvents::default()))).unwrap();
+    let events = rx.recv().unwrap();
+    assert_eq!(events.body(), b"hello");
+    assert!(events.end_of_stream);
+}
+
+#[test]
+fn box_app_routes() {
+    let routes: Routes = vec![
+        ("/status", BoxApp::new(Status(StatusCode::NO_CONTENT))),
+        ("/echo", BoxApp::new(app_fn(echo))),
+    ];
+    let route = |path| &routes.iter().find(|(p, _)| *p == path).unwrap().1;
+
+    let mut events = MockEvents::default();
+    block_on(route("/status").call(Request::new(&mut events))).unwrap();
+    assert_eq!(events.response.unwrap().status(), StatusCode::NO_CONTENT);
+    assert!(events.end_of_stream);
+
+    let mut events = MockEvents::new(&["foo", "bar"]);
+    block_on(route("/echo").call(Request::new(&mut events))).unwrap();
+    assert_eq!(events.body(), b"foobar");
+}
+
+#[test]
+fn dyn_app_on_other_events() {
+    let app = DynApp::new(app_fn(upper));
+    let mut events = MockEvents::new(&["foo", "bar"]);
+    block_on(app.call(Request::new(&mut events))).unwrap();
+    assert_eq!(events.body(), b"FOOBAR!");
+    assert!(events.end_of_stream);
+}
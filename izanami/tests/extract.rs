@@ -0,0 +1,73 @@
+#![cfg(feature = "serde")]
+
+use futures::executor::block_on;
+use http::{Request, StatusCode};
+use izanami::extract::{EventsExt, RequestExt};
+use izanami_test::MockEvents;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Params {
+    name: String,
+    age: u32,
+}
+
+#[test]
+fn test_json() {
+    let mut events = MockEvents::new(&[r#"{"name":"alice","#, r#""age":20}"#]);
+    let params: Params = block_on(events.json(1024)).unwrap();
+    assert_eq!(
+        params,
+        Params {
+            name: "alice".into(),
+            age: 20
+        }
+    );
+}
+
+#[test]
+fn test_json_rejections() {
+    let mut events = MockEvents::new(&[r#"{"name":"alice","age":20}"#]);
+    let err = block_on(events.json::<Params>(8)).unwrap_err();
+    assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let mut events = MockEvents::new(&[r#"{"name":"alice""#]);
+    let err = block_on(events.json::<Params>(1024)).unwrap_err();
+    assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+    let mut events = MockEvents::new(&[r#"{"name":"alice"}"#]);
+    let err = block_on(events.json::<Params>(1024)).unwrap_err();
+    assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let mut request = Request::builder()
+        .header("content-type", "text/plain")
+        .body(MockEvents::new(&[r#"{"name":"alice","age":20}"#]))
+        .unwrap();
+    let err = block_on(request.json::<Params>(1024)).unwrap_err();
+    assert_eq!(err.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+#[test]
+fn test_form_and_query() {
+    let mut request = Request::builder()
+        .uri("/?name=bob&age=30")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(MockEvents::new(&["name=alice&age=20"]))
+        .unwrap();
+
+    let query: Params = request.query().unwrap();
+    assert_eq!(query.name, "bob");
+
+    let form: Params = block_on(request.form()).unwrap();
+    assert_eq!(form.name, "alice");
+}
+
+#[test]
+fn test_send_json() {
+    let mut events = MockEvents::default();
+    block_on(events.send_json(StatusCode::CREATED, &vec![1, 2, 3])).unwrap();
+
+    assert_eq!(events.status(), StatusCode::CREATED);
+    assert_eq!(events.header("content-type"), Some("application/json"));
+    assert_eq!(events.body(), b"[1,2,3]");
+}
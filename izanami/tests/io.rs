@@ -0,0 +1,139 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{executor::block_on, io::AsyncWriteExt};
+use http::{HeaderMap, Response};
+use izanami::{io::ResponseWriter, Events};
+use izanami_test::{Data, MockEvents};
+use std::io;
+
+#[test]
+fn buffered_until_full() {
+    block_on(async {
+        let mut events = MockEvents::default();
+        let mut writer = ResponseWriter::new(&mut events).buffer_size(8);
+        writer.write_all(b"Hello").await.unwrap();
+        writer.write_all(b", world").await.unwrap();
+        drop(writer);
+        assert_eq!(events.body(), b"Hello, w");
+        assert!(!events.end_of_stream);
+    });
+}
+
+#[test]
+fn flush_sends_buffered_bytes() {
+    block_on(async {
+        let mut events = MockEvents::default();
+        let mut writer = ResponseWriter::new(&mut events);
+        writer.write_all(b"Hello").await.unwrap();
+        writer.flush().await.unwrap();
+        drop(writer);
+        assert_eq!(events.body(), b"Hello");
+        assert!(!events.end_of_stream);
+    });
+}
+
+#[test]
+fn unbuffered_sends_each_write() {
+    block_on(async {
+        let mut events = MockEvents::default();
+        let mut writer = ResponseWriter::new(&mut events)
+            .buffer_size(0)
+            .chunk_size(4);
+        writer.write_all(b"Hello").await.unwrap();
+        drop(writer);
+        assert_eq!(events.body(), b"Hello");
+        assert_eq!(events.chunks, ["Hell", "o"]);
+    });
+}
+
+#[test]
+fn flush_in_chunks() {
+    block_on(async {
+        let mut events = MockEvents::default();
+        let mut writer = ResponseWriter::new(&mut events)
+            .buffer_size(100)
+            .chunk_size(4);
+        writer.write_all(b"Hello, world").await.unwrap();
+        writer.flush().await.unwrap();
+        drop(writer);
+        assert_eq!(events.body(), b"Hello, world");
+        assert_eq!(events.chunks, ["Hell", "o, w", "orld"]);
+    });
+}
+
+#[test]
+fn close_ends_stream() {
+    block_on(async {
+        let mut events = MockEvents::default();
+        let mut writer = ResponseWriter::new(&mut events).chunk_size(4);
+        writer.write_all(b"Hello, world").await.unwrap();
+        writer.close().await.unwrap();
+
+        let err = writer.write_all(b"!").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        drop(writer);
+        assert_eq!(events.body(), b"Hello, world");
+        assert_eq!(events.chunks, ["Hell", "o, w", "orld"]);
+        assert!(events.end_of_stream);
+    });
+}
+
+/// An `Events` that sends the complete responses on its own.
+#[derive(Debug, Default)]
+struct Whole {
+    sent_whole: bool,
+}
+
+#[async_trait]
+impl Events for Whole {
+    type Data = Data;
+    type Error = io::Error;
+
+    async fn data(&mut self) -> Option<Result<Self::Data, Self::Error>> {
+        None
+    }
+
+    async fn trailers(&mut self) -> Result<Option<HeaderMap>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn start_send_response(&mut self, _: Response<()>, _: bool) -> Result<(), Self::Error> {
+        unreachable!()
+    }
+
+    async fn send_data(&mut self, _: Self::Data, _: bool) -> Result<(), Self::Error> {
+        unreachable!()
+    }
+
+    async fn send_trailers(&mut self, _: HeaderMap) -> Result<(), Self::Error> {
+        unreachable!()
+    }
+
+    async fn send_response<T>(&mut self, _: Response<T>) -> Result<(), Self::Error>
+    where
+        T: Into<Self::Data> + Send,
+    {
+        self.sent_whole = true;
+        Ok(())
+    }
+}
+
+#[test]
+fn send_response_through_wrappers() {
+    block_on(async {
+        let mut events = Whole::default();
+        <&mut Whole as Events>::send_response(
+            &mut &mut events,
+            Response::new(Bytes::from("Hello")),
+        )
+        .await
+        .unwrap();
+        assert!(events.sent_whole);
+
+        let mut events = Box::new(Whole::default());
+        <Box<Whole> as Events>::send_response(&mut events, Response::new(Bytes::from("Hello")))
+            .await
+            .unwrap();
+        assert!(events.sent_whole);
+    });
+}
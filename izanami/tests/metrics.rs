@@ -0,0 +1,107 @@
+#![cfg(feature = "metrics")]
+
+use futures::executor::block_on;
+use http::{Method, Request, StatusCode};
+use izanami::{
+    access_log::Record,
+    metrics::{Metrics, MetricsApp},
+    App,
+};
+use izanami_test::MockEvents;
+use std::time::Duration;
+
+fn record(method: Method, status: Option<StatusCode>) -> Record {
+    let (parts, ()) = Request::builder()
+        .method(method)
+        .uri("/")
+        .body(())
+        .unwrap()
+        .into_parts();
+    let mut record = Record::new(&parts);
+    record.status = status;
+    record.bytes_in = 3;
+    record.bytes_out = 10;
+    record.head_duration = Some(Duration::from_millis(20));
+    record.duration = Duration::from_millis(300);
+    record
+}
+
+#[test]
+fn render_counters() {
+    let metrics = Metrics::new();
+    let first = metrics.connection();
+    let second = metrics.connection();
+    drop(first);
+
+    metrics.request_completed(&record(Method::GET, Some(StatusCode::OK)));
+    metrics.request_completed(&record(Method::GET, Some(StatusCode::OK)));
+    metrics.request_completed(&record(Method::POST, None));
+    metrics.stream_reset();
+    metrics.app_error();
+    metrics.app_panic();
+
+    let text = metrics.render();
+    for line in &[
+        "izanami_connections_accepted_total 2\n",
+        "izanami_connections_active 1\n",
+        "izanami_requests_total{method=\"GET\",status=\"200\"} 2\n",
+        "izanami_requests_total{method=\"POST\",status=\"\"} 1\n",
+        "izanami_request_body_bytes_total 9\n",
+        "izanami_response_body_bytes_total 30\n",
+        "izanami_response_head_duration_seconds_bucket{le=\"0.01\"} 0\n",
+        "izanami_response_head_duration_seconds_bucket{le=\"0.025\"} 3\n",
+        "izanami_request_duration_seconds_bucket{le=\"0.25\"} 0\n",
+        "izanami_request_duration_seconds_bucket{le=\"0.5\"} 3\n",
+        "izanami_request_duration_seconds_bucket{le=\"+Inf\"} 3\n",
+        "izanami_request_duration_seconds_sum 0.9\n",
+        "izanami_request_duration_seconds_count 3\n",
+        "izanami_stream_resets_total 1\n",
+        "izanami_app_errors_total 1\n",
+        "izanami_app_panics_total 1\n",
+    ] {
+        assert!(text.contains(line), "{:?} not in {}", line, text);
+    }
+    drop(second);
+}
+
+#[test]
+fn count_extension_methods_as_other() {
+    let metrics = Metrics::new();
+    for method in &["PURGE", "PROPFIND", "X-CUSTOM"] {
+        let method = Method::from_bytes(method.as_bytes()).unwrap();
+        metrics.request_completed(&record(method, Some(StatusCode::OK)));
+    }
+    metrics.request_completed(&record(Method::PATCH, Some(StatusCode::OK)));
+
+    let text = metrics.render();
+    assert!(text.contains("izanami_requests_total{method=\"OTHER\",status=\"200\"} 3\n"));
+    assert!(text.contains("izanami_requests_total{method=\"PATCH\",status=\"200\"} 1\n"));
+    assert!(!text.contains("PURGE"), "{}", text);
+}
+
+#[test]
+fn serve_metrics() {
+    let metrics = Metrics::new();
+    metrics.app_error();
+    let app = MetricsApp::new(metrics);
+
+    let mut events = MockEvents::default();
+    let request = Request::get("/metrics").body(&mut events).unwrap();
+    block_on(app.call(request)).unwrap();
+
+    assert_eq!(events.status(), StatusCode::OK);
+    assert_eq!(
+        events.header("content-type"),
+        Some("text/plain; version=0.0.4")
+    );
+    let body = String::from_utf8(events.body()).unwrap();
+    assert!(body.contains("izanami_app_errors_total 1\n"), "{}", body);
+
+    let mut events = MockEvents::default();
+    let request = Request::post("/metrics").body(&mut events).unwrap();
+    block_on(app.call(request)).unwrap();
+    assert_eq!(
+        events.response.unwrap().status(),
+        StatusCode::METHOD_NOT_ALLOWED
+    );
+}
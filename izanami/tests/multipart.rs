@@ -0,0 +1,167 @@
+use futures::executor::block_on;
+use http::{HeaderMap, StatusCode};
+use izanami::multipart::{self, Multipart, MultipartError};
+use izanami_test::MockEvents;
+
+const BODY: &str = "preamble\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"text\"\r\n\
+\r\n\
+hello\r\n\
+--XyZ  \r\n\
+Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+line 1\r\n--Xy\r\nline 2\r\n\
+--XyZ--\r\n\
+epilogue";
+
+#[derive(Debug, PartialEq)]
+struct Part {
+    name: Option<String>,
+    file_name: Option<String>,
+    content: Vec<u8>,
+}
+
+fn collect(multipart: &mut Multipart<MockEvents>) -> Result<Vec<Part>, MultipartError> {
+    block_on(async {
+        let mut parts = vec![];
+        while let Some(mut field) = multipart.next_field().await? {
+            let mut part = Part {
+                name: field.name().map(ToOwned::to_owned),
+                file_name: field.file_name().map(ToOwned::to_owned),
+                content: vec![],
+            };
+            while let Some(chunk) = field.chunk().await? {
+                part.content.extend_from_slice(&chunk);
+            }
+            parts.push(part);
+        }
+        Ok(parts)
+    })
+}
+
+fn chunked(body: &[u8], size: usize) -> MockEvents {
+    MockEvents::new(&body.chunks(size).collect::<Vec<_>>())
+}
+
+#[test]
+fn test_boundary() {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "content-type",
+        "multipart/form-data; charset=utf-8; boundary=\"a b\""
+            .parse()
+            .unwrap(),
+    );
+    assert_eq!(multipart::boundary(&headers).as_deref(), Some("a b"));
+
+    headers.insert("content-type", "text/plain; boundary=x".parse().unwrap());
+    assert_eq!(multipart::boundary(&headers), None);
+}
+
+#[test]
+fn test_parse_fields() {
+    for size in 1..=BODY.len() {
+        let mut multipart = Multipart::new(chunked(BODY.as_bytes(), size), "XyZ");
+        let parts = collect(&mut multipart).unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                Part {
+                    name: Some("text".into()),
+                    file_name: None,
+                    content: b"hello".to_vec(),
+                },
+                Part {
+                    name: Some("file".into()),
+                    file_name: Some("a.txt".into()),
+                    content: b"line 1\r\n--Xy\r\nline 2".to_vec(),
+                },
+            ],
+            "chunk size = {}",
+            size
+        );
+    }
+}
+
+#[test]
+fn test_skip_unread_fields() {
+    let mut multipart = Multipart::new(chunked(BODY.as_bytes(), 7), "XyZ");
+    let names = block_on(async {
+        let mut names = vec![];
+        while let Some(field) = multipart.next_field().await.unwrap() {
+            names.push(field.name().unwrap().to_owned());
+        }
+        names
+    });
+    assert_eq!(names, vec!["text", "file"]);
+}
+
+#[test]
+fn test_limits() {
+    let mut multipart = Multipart::new(chunked(BODY.as_bytes(), 16), "XyZ").field_limit(8);
+    let err = collect(&mut multipart).unwrap_err();
+    assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let mut multipart = Multipart::new(chunked(BODY.as_bytes(), 16), "XyZ").total_limit(64);
+    let err = collect(&mut multipart).unwrap_err();
+    assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[test]
+fn test_malformed() {
+    for body in &[
+        "",
+        "--XyZ",
+        "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno end",
+        "--XyZ\r\ninvalid header\r\n\r\ncontent\r\n--XyZ--",
+        "--XyZabc\r\n\r\ncontent\r\n--XyZ--",
+        "--xyz\r\n\r\ncontent\r\n--xyz--",
+    ] {
+        let mut multipart = Multipart::new(chunked(body.as_bytes(), 3), "XyZ");
+        let err = collect(&mut multipart).unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST, "body = {:?}", body);
+    }
+}
+
+/// Feed the parser with randomly corrupted bodies and random chunk sizes,
+/// and check that it terminates without panicking.
+#[test]
+fn fuzz_malformed_boundaries() {
+    let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+    let alphabet = b"-\r\n XyZ\t\"a";
+
+    for _ in 0..5000 {
+        let mut body = BODY.as_bytes().to_vec();
+        for _ in 0..rng.below(8) + 1 {
+            let pos = rng.below(body.len() + 1);
+            match rng.below(3) {
+                0 if pos < body.len() => {
+                    body.remove(pos);
+                }
+                1 if pos < body.len() => body[pos] = alphabet[rng.below(alphabet.len())],
+                _ => body.insert(pos, alphabet[rng.below(alphabet.len())]),
+            }
+        }
+        let size = rng.below(32) + 1;
+
+        let mut multipart = Multipart::new(chunked(&body, size), "XyZ")
+            .field_limit(64)
+            .headers_limit(128);
+        if let Ok(parts) = collect(&mut multipart) {
+            assert!(parts.len() <= body.len());
+        }
+    }
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
@@ -0,0 +1,69 @@
+use izanami::proxy_protocol::{parse, ProxyHeader, Status};
+use std::net::SocketAddr;
+
+/// Feed `input` to the parser as requested, and return the header
+/// with the number of bytes consumed.
+fn feed(input: &[u8]) -> (ProxyHeader, usize) {
+    let mut len = 0;
+    loop {
+        match parse(&input[..len]).unwrap() {
+            Status::Complete(header) => return (header, len),
+            Status::Partial(n) => {
+                len += n;
+                assert!(len <= input.len(), "requested beyond the input");
+            }
+        }
+    }
+}
+
+fn addr(s: &str) -> Option<SocketAddr> {
+    Some(s.parse().unwrap())
+}
+
+#[test]
+fn v1_tcp4() {
+    let input = b"PROXY TCP4 203.0.113.7 192.0.2.1 56324 443\r\nGET / HTTP/1.1\r\n";
+    let (header, len) = feed(input);
+    assert_eq!(len, 44);
+    assert_eq!(header.source, addr("203.0.113.7:56324"));
+    assert_eq!(header.destination, addr("192.0.2.1:443"));
+}
+
+#[test]
+fn v1_tcp6_and_unknown() {
+    let (header, _) = feed(b"PROXY TCP6 2001:db8::1 2001:db8::2 4711 80\r\n");
+    assert_eq!(header.source, addr("[2001:db8::1]:4711"));
+    assert_eq!(header.destination, addr("[2001:db8::2]:80"));
+
+    let (header, len) = feed(b"PROXY UNKNOWN\r\nGET");
+    assert_eq!(len, 15);
+    assert_eq!(header, ProxyHeader::default());
+}
+
+#[test]
+fn v2_tcp4_and_local() {
+    let mut input = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+    input.extend_from_slice(&[203, 0, 113, 7, 192, 0, 2, 1, 0xdc, 0x04, 0x01, 0xbb]);
+    input.extend_from_slice(b"PRI * HTTP/2.0\r\n");
+    let (header, len) = feed(&input);
+    assert_eq!(len, 28);
+    assert_eq!(header.source, addr("203.0.113.7:56324"));
+    assert_eq!(header.destination, addr("192.0.2.1:443"));
+
+    let (header, len) = feed(b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00GET");
+    assert_eq!(len, 16);
+    assert_eq!(header, ProxyHeader::default());
+}
+
+#[test]
+fn invalid_headers() {
+    assert!(parse(b"GET / HTTP/1.1\r\n").is_err());
+    assert!(parse(b"PROXY TCP4 203.0.113.7 192.0.2.1 56324\r\n").is_err());
+    assert!(parse(b"PROXY TCP4 2001:db8::1 192.0.2.1 56324 443\r\n").is_err());
+    assert!(parse(&[b'P'; 8]).is_err());
+    assert!(parse(b"\r\n\r\n\0\r\nQUIT\n\x31\x11\x00\x00").is_err());
+
+    let mut long = b"PROXY ".to_vec();
+    long.resize(107, b'1');
+    assert!(parse(&long).is_err());
+}
@@ -0,0 +1,54 @@
+use http::{Extensions, Request};
+use izanami::{
+    state::{State, StateMap},
+    RemoteAddr,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Debug, PartialEq)]
+struct ConnId(usize);
+
+#[test]
+fn inject_shared_and_per_connection() {
+    let next_id = AtomicUsize::new(1);
+    let mut states = StateMap::new();
+    states.insert("first");
+    states.insert("second");
+    states.insert_per_connection(move |remote_addr: &RemoteAddr| {
+        assert_eq!(remote_addr.0.port(), 4321);
+        ConnId(next_id.fetch_add(1, Ordering::SeqCst))
+    });
+    let remote_addr = RemoteAddr(([192, 0, 2, 1], 4321).into());
+
+    let conn1 = states.connect(&remote_addr);
+    let conn2 = states.connect(&remote_addr);
+    let mut requests = vec![];
+    for conn in &[&conn1, &conn1, &conn2] {
+        let mut request = Request::new(());
+        conn.inject(request.extensions_mut());
+        requests.push(request);
+    }
+
+    // The value registered later replaces the one of the same type.
+    let name = State::<&'static str>::from_request(&requests[0]).unwrap();
+    assert_eq!(*name, "second");
+
+    let ids: Vec<_> = requests
+        .iter()
+        .map(|request| State::<ConnId>::from_request(request).unwrap().0)
+        .collect();
+    assert_eq!(ids, vec![1, 1, 2]);
+
+    // The value built for a connection is shared by its requests.
+    let first = State::<ConnId>::from_request(&requests[0]).unwrap();
+    let second = State::<ConnId>::from_request(&requests[1]).unwrap();
+    assert!(std::ptr::eq(&*first, &*second));
+}
+
+#[test]
+fn missing_state() {
+    let err = State::<ConnId>::from_extensions(&Extensions::new()).unwrap_err();
+    let msg = err.to_string();
+    assert!(msg.contains("ConnId`"), "{}", msg);
+    assert!(msg.ends_with("is not registered"), "{}", msg);
+}